@@ -0,0 +1,105 @@
+use std::sync::LazyLock;
+
+use chrono::NaiveDate;
+use markdown::ParseOptions;
+use markdown::mdast::Node;
+use regex::Regex;
+
+use crate::{Result, Zettel};
+
+// TASK_REGEX matches markdown tasks, `- [ ] task` and `- [x] done`
+static TASK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s*[-*+] \[([ xX])\] (.+)$").expect("must compile"));
+
+// DUE_REGEX matches the due date of a task, `due:2024-05-01` or `📅 2024-05-01`
+static DUE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:due:|📅 ?)([0-9]{4}-[0-9]{2}-[0-9]{2})").expect("must compile")
+});
+
+// Task is a single markdown task within a note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub text: String,
+    pub done: bool,
+    pub due: Option<NaiveDate>,
+    pub line: usize,
+}
+
+impl Task {
+    // is_due returns true when the task is open and due on or before the date
+    pub fn is_due(&self, date: NaiveDate) -> bool {
+        !self.done && self.due.is_some_and(|due| due <= date)
+    }
+}
+
+// tasks returns the tasks found within the content
+pub fn tasks(content: &str) -> Vec<Task> {
+    TASK_REGEX
+        .captures_iter(content)
+        .map(|cap| {
+            let text = cap[2].trim().to_string();
+            let start = cap.get(0).expect("has match").start();
+            Task {
+                due: DUE_REGEX
+                    .captures(&text)
+                    .and_then(|due| NaiveDate::parse_from_str(&due[1], "%Y-%m-%d").ok()),
+                done: &cap[1] != " ",
+                line: content[..start].matches('\n').count() + 1,
+                text,
+            }
+        })
+        .collect()
+}
+
+// open_task_items returns the source of the unchecked task list items of the
+// content, the items nested within one are kept as part of it
+pub fn open_task_items(content: &str) -> Result<Vec<String>> {
+    let ast = markdown::to_mdast(content, &ParseOptions::gfm())?;
+    let mut items = Vec::new();
+    collect_open_task_items(&ast, content, &mut items);
+    Ok(items)
+}
+
+fn collect_open_task_items(node: &Node, content: &str, items: &mut Vec<String>) {
+    if let Node::ListItem(item) = node {
+        if item.checked == Some(false) {
+            if let Some(pos) = item.position.as_ref() {
+                // the nested lines are indented relative to the item
+                let indent = pos.start.column - 1;
+                let source: Vec<&str> = content[pos.start.offset..pos.end.offset]
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let spaces = line.len() - line.trim_start_matches(' ').len();
+                        if i == 0 {
+                            line
+                        } else {
+                            &line[spaces.min(indent)..]
+                        }
+                    })
+                    .collect();
+                items.push(source.join("\n").trim_end().to_string());
+            }
+            return;
+        }
+    }
+
+    for child in node.children().into_iter().flatten() {
+        collect_open_task_items(child, content, items);
+    }
+}
+
+// Agenda is everything that needs attention on a day
+pub struct Agenda {
+    pub date: NaiveDate,
+    // daily is the daily of the date
+    pub daily: Zettel,
+    // todo are the open tasks of the daily
+    pub todo: Vec<Task>,
+    // due are the open tasks of other notes due on or before the date
+    pub due: Vec<(Zettel, Task)>,
+    // meetings are the meetings dated on the date
+    pub meetings: Vec<Zettel>,
+    // review are the fleeting notes that are due for a sweep
+    pub review: Vec<Zettel>,
+}
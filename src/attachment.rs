@@ -0,0 +1,292 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf, StripPrefixError};
+use std::process::{Command, Stdio};
+
+use chrono::{DateTime, Datelike, Local};
+use tera::{Context, Tera};
+
+use crate::{Error, Result, ZettelIDBuilder, markdown_links, resolve_local_link, slug};
+
+// DEFAULT_ATTACHMENT_NAME_TEMPLATE names attachments after the slug of their
+// name and the hash, like the images
+pub const DEFAULT_ATTACHMENT_NAME_TEMPLATE: &str =
+    "{% if slug %}{{ slug }}_{% endif %}{{ hash }}{% if ext %}.{{ ext }}{% endif %}";
+
+// EMBEDDED_EXTENSIONS are the attachments linked as an embed, ![name](path),
+// rather than a link
+const EMBEDDED_EXTENSIONS: [&str; 8] = ["jpg", "jpeg", "png", "gif", "webp", "bmp", "svg", "tiff"];
+
+// DEFAULT_PDF_TEXT_COMMAND extracts the text of a pdf, it is run with the pdf
+// and - so the text is printed to stdout
+pub const DEFAULT_PDF_TEXT_COMMAND: &str = "pdftotext";
+
+// DEFAULT_TRANSCRIBE_COMMAND transcribes audio with whisper.cpp, it is run
+// with the recording and prints the transcript
+pub const DEFAULT_TRANSCRIBE_COMMAND: &str = "whisper-cli --no-timestamps --no-prints --file";
+
+// AUDIO_EXTENSIONS are the recordings attached as audio memos
+const AUDIO_EXTENSIONS: [&str; 7] = ["wav", "mp3", "m4a", "ogg", "opus", "flac", "webm"];
+
+// TEXT_SUFFIX is appended to the name of an attachment for the file holding
+// its extracted text, paper.pdf has its text in paper.pdf.txt
+pub const TEXT_SUFFIX: &str = ".txt";
+
+// AttachmentBuilder stores any file, like audio, csv or pdfs, as it is. The
+// files are named and put in date directories the way images are.
+#[derive(Clone)]
+pub struct AttachmentBuilder {
+    base: PathBuf,
+    name: Option<String>,
+    name_template: String,
+}
+
+impl AttachmentBuilder {
+    pub fn new<P: AsRef<Path>>(base: P) -> Self {
+        Self {
+            base: PathBuf::from(base.as_ref()),
+            name: None,
+            name_template: DEFAULT_ATTACHMENT_NAME_TEMPLATE.to_string(),
+        }
+    }
+
+    // with_date_directory will add the path [year]/[month]/[day] to the base
+    // when storing the attachment
+    pub fn with_date_directory(mut self, date: &DateTime<Local>) -> Self {
+        self.base.push(format!("{:02}", date.year()));
+        self.base.push(format!("{:02}", date.month()));
+        self.base.push(format!("{:02}", date.day()));
+        self
+    }
+
+    // name sets the name the slug in the filename is made from, by default
+    // the name of the file without its extension
+    pub fn name<S: Into<String>>(mut self, name: Option<S>) -> Self {
+        self.name = name.map(|v| v.into());
+        self
+    }
+
+    // name_template sets the tera template for the filename. The template has
+    // date, slug, hash and ext available.
+    pub fn name_template<S: Into<String>>(mut self, template: Option<S>) -> Self {
+        if let Some(template) = template {
+            self.name_template = template.into();
+        }
+        self
+    }
+
+    // build copies the file into the attachments, an attachment is never
+    // overwritten
+    pub fn build<P>(self, file: P) -> Result<Attachment>
+    where
+        P: AsRef<Path>,
+    {
+        let Self {
+            base,
+            name,
+            name_template,
+        } = self;
+
+        let file = file.as_ref();
+        if !file.is_file() {
+            return Err(Error::NotFound(format!("{:?} is not a file", file)));
+        }
+
+        let name = name.unwrap_or_else(|| {
+            file.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        let ext = file
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        fs::create_dir_all(base.as_path())?;
+
+        let hash: String = ZettelIDBuilder::new().with_hash().build()?.into();
+        let mut context = Context::new();
+        context.insert("date", &Local::now().format("%Y-%m-%d").to_string());
+        context.insert("slug", &slug(&name));
+        context.insert("hash", &hash);
+        context.insert("ext", &ext);
+        let filename = Tera::one_off(&name_template, &context, false)?;
+        let path = base.join(file_name(filename.trim())?);
+
+        // create_new fails rather than overwrite an attachment stored between
+        // the name being made and the copy
+        let mut target = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(target) => target,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::PKMError(format!("{:?} already exists", path)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Err(err) = io::copy(&mut File::open(file)?, &mut target) {
+            let _ = fs::remove_file(&path);
+            return Err(err.into());
+        }
+        Ok(Attachment { path, name })
+    }
+}
+
+// file_name checks the rendered name template is the name of a file, it must
+// not reach into other directories or hide the attachment
+fn file_name(name: &str) -> Result<&str> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.starts_with('.') => Ok(name),
+        _ => Err(Error::PKMError(format!(
+            "the attachment name template rendered {:?}, which is not a file name",
+            name
+        ))),
+    }
+}
+
+pub struct Attachment {
+    pub path: PathBuf,
+    pub name: String,
+}
+
+impl Attachment {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn rel_path<P: AsRef<Path>>(
+        &self,
+        parent: P,
+    ) -> std::result::Result<&Path, StripPrefixError> {
+        self.path.strip_prefix(parent)
+    }
+
+    // link is the markdown linking to the attachment from the root directory,
+    // images are embedded
+    pub fn link<P: AsRef<Path>>(&self, root: P) -> Result<String> {
+        let target = self.rel_path(root)?.to_string_lossy().replace(' ', "%20");
+        let embed = self.path.extension().is_some_and(|ext| {
+            EMBEDDED_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+        });
+
+        Ok(format!(
+            "{}[{}](/{})",
+            if embed { "!" } else { "" },
+            escape_link_text(&self.name),
+            target
+        ))
+    }
+}
+
+// escape_link_text escapes the characters that would end the text of a
+// markdown link early
+fn escape_link_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+// is_pdf is true for the files with a pdf extension
+pub fn is_pdf<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+// is_audio is true for the files with the extension of a recording
+pub fn is_audio<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|ext| {
+        AUDIO_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+// text_path is the file the extracted text of the attachment is kept in
+pub fn text_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_os_string();
+    name.push(TEXT_SUFFIX);
+    PathBuf::from(name)
+}
+
+// extract_pdf_text runs the command with the pdf and - appended, through the
+// shell so the command can carry its own arguments, and writes the text it
+// prints next to the pdf, returning the path of the text
+pub fn extract_pdf_text<P: AsRef<Path>>(command: &str, pdf: P) -> Result<PathBuf> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\" -", command))
+        .arg("sh")
+        .arg(pdf.as_ref())
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| Error::PKMError(format!("could not run {}: {}", command, err)))?;
+    if !output.status.success() {
+        return Err(Error::PKMError(format!(
+            "{} failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let path = text_path(pdf);
+    fs::write(&path, output.stdout)?;
+    Ok(path)
+}
+
+// transcribe runs the command with the recording appended and returns the
+// transcript it prints
+pub fn transcribe<P: AsRef<Path>>(command: &str, audio: P) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", command))
+        .arg("sh")
+        .arg(audio.as_ref())
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| Error::PKMError(format!("could not run {}: {}", command, err)))?;
+    if !output.status.success() {
+        return Err(Error::PKMError(format!(
+            "{} failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    // whisper prints every segment on its own line
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" "))
+}
+
+// attachment_text is the extracted text of every attachment the note links
+// to, attachments without extracted text are skipped
+pub fn attachment_text(root: &Path, note: &Path, content: &str) -> String {
+    markdown_links(content)
+        .iter()
+        .filter_map(|link| resolve_local_link(root, note, link))
+        .filter_map(|path| fs::read_to_string(text_path(path)).ok())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_text_commands_can_carry_arguments() {
+        let pdf = std::env::temp_dir().join(format!("pkm-attachment-{}.pdf", std::process::id()));
+        fs::write(&pdf, "").unwrap();
+
+        let text = extract_pdf_text("printf '%s %s %s' layout", &pdf).unwrap();
+        let extracted = fs::read_to_string(&text).unwrap();
+        fs::remove_file(&pdf).unwrap();
+        fs::remove_file(&text).unwrap();
+
+        assert_eq!(extracted, format!("layout {} -", pdf.to_string_lossy()));
+    }
+}
@@ -1,27 +1,42 @@
 use std::{
-    ffi::OsStr,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
     fs::{self, read_to_string},
-    io::stdout,
+    io::{IsTerminal, Read, stdout},
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf, absolute},
     process::{ExitCode, Stdio},
+    rc::Rc,
+    time::Duration,
 };
 
-use chrono::{DateTime, Local, TimeZone};
-use clap::{ArgAction, ArgMatches, Command, ValueHint, arg, value_parser};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command, ValueHint, arg, value_parser};
 use clap_complete::aot::{Shell, generate};
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate};
 use human_date_parser::ParseResult;
-use inquire::Text;
-use log::{LevelFilter, error};
+use indicatif::{ProgressBar, ProgressStyle};
+use inquire::{Confirm, Select, Text};
+use log::{LevelFilter, error, info, warn};
 use lsp_types::GotoDefinitionResponse::{Array, Link, Scalar};
 use markdown::{ParseOptions, mdast::Node};
+use pkm::lsp::{AsLocalPath, LSP, StandardRunner, hover_text, outline, symbol_locations};
 use pkm::{
-    Editor, Error, Finder, FinderItem, PKM, PKMBuilder, Result, Zettel, ZettelIDBuilder,
-    ZettelIndex, ZettelReference, first_node, first_within_child, path_to_id,
+    Bundle, DocIndexer, EXIT_CONFIG, EXIT_FAILURE, Editor, Embeddings, Error, FAVORITES_FILE,
+    FLEETING_TAG, FacetCount, Favorites, FinderAction, FinderItem, Highlighting, Hook, IndexConfig,
+    IndexReport, Llm, MEETING_TAG, Mention, NoteReader, PKM, PKMBuilder, Plugin, Result,
+    SUMMARY_HEADING, SUMMARY_KEY, SearchSort, Selection, Server, Table, TemplateInfo, VaultMetrics,
+    Zettel, ZettelID, ZettelIndex, ZettelReference, broken_links, changed_times, duplicate_titles,
+    expand_embeds, extract_pdf_text, first_node, first_within_child, heatmap, id_title,
+    image_paths, is_audio, is_pdf, link_mentions, needs_rebuild, path_to_id, plugins, rank_fusion,
+    similar_names, streaks, sync, templates, transcribe, unlinked_mentions, wikilinks, word_diffs,
+    write_textfile,
 };
 use regex::Regex;
+use serde_json::{Value, json};
 use tera::Context;
-use walkdir::WalkDir;
 
 const DATE_REGEX: &str = "[0-9]{4}-(0[0-9]|1[0-2])-([0-2][0-9]|3[01])";
 const ZETTEL_ICON: &str = "󰎚";
@@ -29,8 +44,12 @@ const MEETING_ICON: &str = "";
 const DATED_ICON: &str = "󰸗";
 const FLEETING_ICON: &str = "";
 
-const MEETING_TAG: &str = "meeting";
-const FLEETING_TAG: &str = "fleeting";
+// sweep_command is pkm fleeting sweep, which pkm review runs as well
+fn sweep_command(name: &'static str) -> Command {
+    Command::new(name)
+        .about("Promote, merge, archive or delete fleeting notes that have gone stale")
+        .arg(arg!(OLDER_THAN: --"older-than" <AGE> "Only go through the fleeting notes older than this (12h, 14d, 2w)").env("PKM_FLEETING_MAX_AGE").default_value("14d"))
+}
 
 fn cli() -> Command {
     let default_repo = if cfg!(debug_assertions) {
@@ -41,35 +60,124 @@ fn cli() -> Command {
 
     Command::new("pkm")
         .about("A PKM management CLI")
-        .arg(arg!(-v --verbose "-v Warning, -vv Info, -vvv Debug, -vvvv Trace; not specified logs Error").action(ArgAction::Count))
+        .arg(arg!(-v --verbose "-v Info, -vv Debug, -vvv Trace with the messages sent to the LSP; not specified logs warnings and errors. PKM_LOG takes filters like pkm::lsp=trace").action(ArgAction::Count))
+        .arg(arg!(QUIET: -q --quiet "Log nothing, only the output of the command and the error it fails with are printed").conflicts_with("verbose"))
         .arg(arg!(REPO: -r --repo <REPO> "The root directory of the pkm").env(default_repo))
         .arg(arg!(REFERENCE_FILE: --"repo-reference-file" <REFERENCE_FILE> "Find the root git repo from the reference file"))
         .arg(arg!(ZETTEL_DIR: --"zettel-dir" [ZETTEL_DIR] "The directory where zettels are stored relative to the repo directory").env("PKM_ZETTEL_DIR").default_value(pkm::DEFAULT_ZETTEL_DIR).value_hint(ValueHint::DirPath))
         .arg(arg!(TEMPLATE_DIR: --"template-dir" [TEMPLATE_DIR] "The directory where templates are stored relative to the repo directory").env("PKM_TEMPLATE_DIR").default_value(pkm::DEFAULT_TEMPLATE_DIR).value_hint(ValueHint::DirPath))
         .arg(arg!(DAILY_DIR: --"daily-dir" [DAILY_DIR] "The directory where dailys are stored relative to the repo directory").env("PKM_DAILY_DIR").default_value(pkm::DEFAULT_DAILY_DIR).value_hint(ValueHint::DirPath))
-        .arg(arg!(IMG_DIR: --"img-dir" [IMG_DIR] "The directory, relative to the root directory, where images are stored").env("PKM_DAILY_DIR").default_value(pkm::DEFAULT_IMAGE_DIR).value_hint(ValueHint::DirPath))
+        .arg(arg!(INBOX_DIR: --"inbox-dir" [INBOX_DIR] "The directory, relative to the root directory, where captures wait to be filed").env("PKM_INBOX_DIR").default_value(pkm::DEFAULT_INBOX_DIR).value_hint(ValueHint::DirPath))
+        .arg(arg!(ARCHIVE_DIR: --"archive-dir" [ARCHIVE_DIR] "The directory, relative to the root directory, where archived notes are stored").env("PKM_ARCHIVE_DIR").default_value(pkm::DEFAULT_ARCHIVE_DIR).value_hint(ValueHint::DirPath))
+        .arg(arg!(COLLECTION_DIR: --"collection-dir" [COLLECTION_DIR] "The directory, relative to the root directory, where collections are stored").env("PKM_COLLECTION_DIR").default_value(pkm::DEFAULT_COLLECTION_DIR).value_hint(ValueHint::DirPath))
+        .arg(arg!(ATTACHMENT_DIR: --"attachment-dir" [ATTACHMENT_DIR] "The directory, relative to the root directory, where attachments are stored").env("PKM_ATTACHMENT_DIR").default_value(pkm::DEFAULT_ATTACHMENT_DIR).value_hint(ValueHint::DirPath))
+        .arg(arg!(PDF_TEXT_COMMAND: --"pdf-text-command" <COMMAND> "The command extracting the text of attached pdfs, run with the pdf and - it prints the text").env("PKM_PDF_TEXT_COMMAND").default_value(pkm::DEFAULT_PDF_TEXT_COMMAND).value_hint(ValueHint::CommandName))
+        .arg(arg!(TRANSCRIBE_COMMAND: --"transcribe-command" <COMMAND> "The command transcribing audio memos, run with the recording it prints the transcript").env("PKM_TRANSCRIBE_COMMAND").default_value(pkm::DEFAULT_TRANSCRIBE_COMMAND).value_hint(ValueHint::CommandName))
+        .arg(arg!(JSON: --json "Print json with stable field names instead of text, for scripts and editor plugins, errors are json on stderr").env("PKM_JSON").global(true))
+        .arg(arg!(AUTO_COMMIT: --"auto-commit" "Commit the files changed by a command, like a new zettel and its daily, once the command is done").env("PKM_AUTO_COMMIT"))
+        .arg(arg!(COMMIT_TEMPLATE: --"commit-template" <TEMPLATE> "The tera template of the messages of auto commits and pkm repo sync, with actions, summary, notes, images, files, changed, titles and date").env("PKM_COMMIT_TEMPLATE"))
+        .arg(arg!(CHANGELOG: --changelog "Record every change made through pkm in the CHANGELOG.md note").env("PKM_CHANGELOG"))
+        .arg(arg!(FINDER_BIND: --"finder-bind" <BINDING> "Bind a key in the finder to an action, like ctrl-y:copy-link. Actions are open, print, copy-path, copy-link, daily and link").env("PKM_FINDER_BINDINGS").value_delimiter(',').action(ArgAction::Append).value_parser(|s: &str| pkm::parse_binding(s).map_err(|err| err.to_string())))
+        .arg(arg!(ID_SCHEME: --"id-scheme" <SCHEME> "How the unique part of new ids is made: hash, timestamp, ulid, nanoid or slug").env("PKM_ID_SCHEME").default_value("hash").value_parser(|s: &str| pkm::parse_id_scheme(s).map_err(|err| err.to_string())))
+        .arg(arg!(EMBEDDER: --embedder <EMBEDDER> "What embeds the notes for semantic search: the http or https url of an OpenAI compatible embeddings endpoint, or a command printing the vector of stdin as a json array").env("PKM_EMBEDDER").value_parser(|s: &str| pkm::parse_embedder(s).map_err(|err| err.to_string())))
+        .arg(arg!(EMBEDDER_MODEL: --"embedder-model" <MODEL> "The model asked for from the embeddings endpoint").env("PKM_EMBEDDER_MODEL").default_value(pkm::DEFAULT_EMBEDDING_MODEL))
+        .arg(arg!(LLM: --llm <URL> "The http or https url of the OpenAI compatible chat completions endpoint used by summarize and --suggest-title, overrides .pkm/llm.json").env("PKM_LLM").value_parser(|s: &str| pkm::parse_llm(s).map_err(|err| err.to_string())))
+        .arg(arg!(LLM_MODEL: --"llm-model" <MODEL> "The model asked for from the llm endpoint").env("PKM_LLM_MODEL"))
+        .arg(arg!(LLM_API_KEY: --"llm-api-key" <KEY> "The bearer token sent to the llm endpoint, prefer the environment variable over the flag").env("PKM_LLM_API_KEY").hide_env_values(true))
+        .arg(arg!(ROLLOVER: --rollover "Carry the open tasks of the previous daily over into a new daily").env("PKM_DAILY_ROLLOVER"))
+        .arg(arg!(ROUTE: --route <ROUTE> "Send the zettels with a tag to a template and directory, like meeting=meeting:meetings").env("PKM_ROUTES").value_delimiter(',').action(ArgAction::Append).value_parser(|s: &str| pkm::parse_route(s).map_err(|err| err.to_string())))
+        .arg(arg!(FINDER_PREVIEW: --"finder-preview" <POSITION> "Where the preview is shown in the finder").env("PKM_FINDER_PREVIEW").default_value("right").value_parser(["up", "down", "left", "right"]))
+        .arg(arg!(FINDER_PREVIEW_SIZE: --"finder-preview-size" <SIZE> "The size of the preview in the finder, like 40 or 50%").env("PKM_FINDER_PREVIEW_SIZE"))
+        .arg(arg!(FINDER_PREVIEW_WRAP: --"finder-preview-wrap" "Wrap long lines in the preview of the finder").env("PKM_FINDER_PREVIEW_WRAP"))
+        .arg(arg!(FINDER_PREVIEW_TOGGLE: --"finder-preview-toggle" <KEY> "The key that shows and hides the preview of the finder").env("PKM_FINDER_PREVIEW_TOGGLE").default_value("alt-p"))
+        .arg(arg!(FINDER_PLAIN: --"finder-plain" "Show the previews of the finder without colors").env("PKM_FINDER_PLAIN"))
+        .arg(arg!(THEME: --theme <THEME> "The syntax highlighting theme, a bundled one or a .tmTheme in .pkm/themes or ~/.config/pkm/themes. By default it matches the background of the terminal").env("PKM_THEME"))
+        .arg(arg!(LSP_COMMAND: --"lsp-command" <COMMAND> "The markdown language server to run").env("PKM_LSP_COMMAND").default_value(pkm::DEFAULT_LSP_COMMAND).value_hint(ValueHint::CommandName))
+        .arg(arg!(LSP_ARGS: --"lsp-args" <ARGS> "Space separated arguments passed to the language server").env("PKM_LSP_ARGS").value_delimiter(' ').allow_hyphen_values(true))
+        .arg(arg!(LSP_INIT_OPTIONS: --"lsp-init-options" <JSON> "The initialization options sent to the language server as json").env("PKM_LSP_INIT_OPTIONS").value_parser(|s: &str| serde_json::from_str::<serde_json::Value>(s)))
+        .arg(arg!(LSP_TIMEOUT: --"lsp-timeout" <SECONDS> "How long to wait on the LSP before giving up, 0 waits forever").env("PKM_LSP_TIMEOUT").default_value("30").value_parser(value_parser!(u64)))
+        .arg(arg!(LSP_INIT_RETRIES: --"lsp-init-retries" <RETRIES> "How many times to retry starting the LSP when it times out").env("PKM_LSP_INIT_RETRIES").default_value("0").value_parser(value_parser!(u32)))
+        .arg(arg!(IMG_DIR: --"img-dir" [IMG_DIR] "The directory, relative to the root directory, where images are stored").env("PKM_IMG_DIR").default_value(pkm::DEFAULT_IMAGE_DIR).value_hint(ValueHint::DirPath))
         .subcommand(
             Command::new("zettel")
                 .about("Create a new zettel")
                 .alias("ztl")
-                .arg(arg!(TEMPLATE: -t --template [TEMPLATE] "The template of the zettel").default_value("default"))
+                .arg(arg!(TEMPLATE: -t --template [TEMPLATE] "The template of the zettel").default_value("default").add(ArgValueCandidates::new(complete_templates)))
                 .arg(arg!(MEETING: --meeting "mark the zettel as notes for a meeting"))
                 .arg(arg!(FLEETING: --fleeting "mark the zettel as fleeting notes"))
+                .arg(arg!(ATTENDEE: --attendee <NAME> "An attendee of the meeting, kept in the frontmatter and passed to the template as attendees").action(ArgAction::Append).requires("MEETING"))
                 .arg(arg!(DATE: --date "put the date into the filename"))
                 .arg(arg!(HASH: --hash "put a hash in the filename"))
                 .arg(arg!(NO_EDIT: --"no-edit" "Do not open in an editor once created"))
-                .arg(arg!(TITLE: <TITLE> "The title of the zettel"))
+                .arg(arg!(CONTEXT: --context <HEADING> "Place the daily reference under this heading of the daily"))
+                .arg(arg!(ANCHOR: --anchor <HEADING> "Link the daily reference to this heading of the zettel"))
+                .arg(arg!(CHILD_OF: --"child-of" <ZTL> "Make the zettel a child of this note, the path, id or part of its title").value_hint(ValueHint::FilePath))
+                .arg(arg!(HERE: --here "File the zettel under the project linked to the code repo of the current directory"))
+                .arg(arg!(STDIN: --stdin "Use the text piped on stdin as the body of the zettel, the editor is not opened"))
+                .arg(arg!(FROM_FILE: --"from-file" <PATH> "Use the content of the file as the body of the zettel").value_hint(ValueHint::FilePath).conflicts_with("STDIN"))
+                .arg(arg!(SUGGEST_TITLE: --"suggest-title" "Ask the llm for the title of the body from --stdin or --from-file").conflicts_with("TITLE"))
+                .arg(arg!(TITLE: <TITLE> "The title of the zettel").required_unless_present("SUGGEST_TITLE"))
                 .arg(arg!(VARS: ... "variables for the template (title:\"Hello World\")"))
+                .subcommand_negates_reqs(true)
+                .args_conflicts_with_subcommands(true)
+                .subcommand(
+                    Command::new("from-selection")
+                        .about("Create a zettel from text on stdin, the first line may be a json header with the source of the text")
+                        .arg(arg!(TITLE: [TITLE] "The title of the zettel, defaults to the title in the header or the first line"))
+                        .arg(arg!(FLEETING: --fleeting "mark the zettel as fleeting notes"))
+                        .arg(arg!(NO_EDIT: --"no-edit" "Do not open in an editor once created"))
+                        .arg(arg!(CONTEXT: --context <HEADING> "Place the daily reference under this heading of the daily"))
+                )
         )
         .subcommand(
             Command::new("daily")
                 .about("open the daily file")
                 .alias("day")
-                .arg(arg!(TEMPLATE: -t --template [TEMPLATE] "The template of the zettel").default_value("daily"))
+                .arg(arg!(TEMPLATE: -t --template [TEMPLATE] "The template of the zettel").default_value("daily").add(ArgValueCandidates::new(complete_templates)))
                 .arg(arg!(DATE: [DATE] "Human representation of a date for the dailly").default_value("today"))
                 .arg(arg!(NO_EDIT: --"no-edit" "Do not open in an editor once created"))
                 .arg(arg!(VARS: ... "variables for the template (title:\"Hello World\")"))
         )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check the repo for problems, the zettel titles unless a check is given")
+                .args_conflicts_with_subcommands(true)
+                .arg(arg!(FIX_TITLES: --"fix-titles" "Offer to fix zettels whose H1 does not match the title in their id"))
+                .arg(arg!(FROM_H1: --"from-h1" "Rename every mismatched zettel from its H1 without prompting").requires("FIX_TITLES"))
+                .arg(arg!(FROM_ID: --"from-id" "Rewrite every mismatched H1 from the zettel id without prompting").requires("FIX_TITLES").conflicts_with("FROM_H1"))
+                .subcommand(
+                    Command::new("links")
+                        .about("Report the links and images that don't resolve to a file")
+                        .arg(arg!(SELECT: -s --select "Open the notes with broken links in the finder"))
+                )
+                .subcommand(
+                    Command::new("titles")
+                        .about("Report the zettels with the same or nearly the same title")
+                        .arg(arg!(SIMILARITY: --similarity <RATIO> "How alike titles must be to be reported, from 0 to 1").default_value("0.9").value_parser(value_parser!(f64)))
+                        .arg(arg!(SELECT: -s --select "Open the duplicate zettels in the finder"))
+                )
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Scaffold the directories, templates and files of a new repo")
+                .arg(arg!(DIR: [DIR] "The directory of the new repo, defaults to the repo").value_hint(ValueHint::DirPath))
+                .arg(arg!(GIT: --git "Make the repo a git repo as well"))
+        )
+        .subcommand(
+            Command::new("capture")
+                .about("Write a thought into the inbox to file it later")
+                .arg(arg!(TEXT: <TEXT> ... "The thought to capture"))
+        )
+        .subcommand(
+            Command::new("inbox")
+                .about("File the captures of the inbox as zettels or into existing notes")
+        )
+        .subcommand(
+            Command::new("log")
+                .about("Append a timestamped entry to today's daily")
+                .arg(arg!(MESSAGE: <MESSAGE> ... "The entry to log"))
+                .arg(arg!(CONTEXT: --context <HEADING> "Place the entry under this heading of the daily"))
+        )
         .subcommand(
             Command::new("repo")
                 .about("run git commands")
@@ -80,22 +188,79 @@ fn cli() -> Command {
                     .allow_hyphen_values(true)
                     .trailing_var_arg(true)
                 )
+                .args_conflicts_with_subcommands(true)
+                .subcommand(
+                    Command::new("sync")
+                        .about("Commit every change to the notes, rebase onto the remote and push")
+                        .arg(arg!(MESSAGE: -m --message <TEMPLATE> "The tera template of the commit message, overrides --commit-template").env("PKM_SYNC_MESSAGE"))
+                )
         )
         .subcommand(
             Command::new("favorites")
                 .about("A list of favorites")
                 .alias("fvt")
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a zettel to the favorites")
+                        .arg(arg!(ID: <ID> "The id of the zettel"))
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a zettel from the favorites")
+                        .alias("rm")
+                        .arg(arg!(ID: <ID> "The id of the zettel"))
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("Print the ids of the favorites")
+                        .alias("ls")
+                )
         )
         .subcommand(
             Command::new("index")
                 .about("Index the data")
+                .arg(arg!(EMBEDDINGS: --embeddings "Embed the new and changed notes for semantic search as well, needs --embedder"))
+                .arg(arg!(JOBS: -j --jobs <N> "How many notes to read and parse at once, defaults to the number of cpus").value_parser(value_parser!(usize)))
+                .arg(arg!(FULL: --full "Rebuild the whole index instead of only the notes that changed").conflicts_with("GC"))
+                .arg(arg!(GC: --gc "Only drop the notes that no longer exist from the index instead of rebuilding it").conflicts_with("EMBEDDINGS"))
+                .arg(arg!(LANGUAGE: --language <LANGUAGE> "Stem the words in the language from now on, like german or french. It is kept in .pkm/index.json").value_parser(pkm::LANGUAGES))
         )
         .subcommand(Command::new("search")
-            .about("Finds your relavent data"))
+            .about("Finds your relavent data")
+            .arg(arg!(COLLECTION: -c --collection <NAME> "Save the selected notes to the collection"))
+            .arg(arg!(QUERY: [QUERY] "Start with the query instead of prompting for one"))
+            .arg(arg!(NO_TUI: --"no-tui" "Print the best matches instead of opening the finder").requires("QUERY"))
+            .arg(arg!(INCLUDE_ARCHIVED: --"include-archived" "Search the archived notes as well"))
+            .arg(arg!(IN: --in <DIR> "Only search the notes within the directory, relative to the root directory, like daily or zettels/2024").action(ArgAction::Append))
+            .arg(arg!(SEMANTIC: --semantic "Blend in the notes nearest in meaning to the query, needs pkm index --embeddings"))
+            .arg(arg!(NO_RECENCY: --"no-recency" "Rank by relevance alone instead of favoring recently edited notes"))
+            .arg(arg!(SORT: --sort <SORT> "Order the matches by relevance, modified, created or title. Modified and created are newest first").default_value("relevance").value_parser(|s: &str| pkm::parse_search_sort(s).map_err(|err| err.to_string())).conflicts_with("SEMANTIC"))
+            .arg(arg!(SINCE: --since <DATE> "Only the notes dated on or after the date, like 2024-01-01 or \"last week\"").value_parser(|s: &str| pkm::parse_search_date(s).map_err(|err| err.to_string())))
+            .arg(arg!(UNTIL: --until <DATE> "Only the notes dated on or before the date").value_parser(|s: &str| pkm::parse_search_date(s).map_err(|err| err.to_string())))
+            .arg(arg!(PICK: --pick <N> "How many matches to print with --no-tui").default_value("1").value_parser(value_parser!(usize)).requires("NO_TUI"))
+            .arg(arg!(PICKER_FORMAT: --"picker-format" <FORMAT> "Print every match for a launcher like rofi, dmenu or Alfred instead of opening the finder: tsv prints the title and path separated by a tab, alfred prints script filter json").value_parser(["tsv", "alfred"]).conflicts_with_all(["NO_TUI", "COLLECTION"])))
+        .subcommand(Command::new("similar")
+            .about("Find the notes about the same thing as a note or text, ctrl-l in the finder links the note to the selection")
+            .arg(arg!(ZTL: [ZTL] "The path, id or part of the title of the note to find related notes for").value_hint(ValueHint::FilePath))
+            .arg(arg!(TEXT: --text <TEXT> "The text to find similar notes for"))
+            .arg(arg!(FILE: --file <FILE> "Read the text from the file, - reads stdin").value_hint(ValueHint::FilePath))
+            .group(ArgGroup::new("INPUT").args(["ZTL", "TEXT", "FILE"]).required(true))
+            .arg(arg!(LIMIT: --limit <N> "How many notes to find").default_value("10").value_parser(value_parser!(u64).range(1..)))
+            .arg(arg!(NO_TUI: --"no-tui" "Print the similar notes instead of opening the finder")))
+        .subcommand(
+            Command::new("collection")
+                .about("Manage named collections of notes")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("open")
+                        .about("Open every note in the collection")
+                        .arg(arg!(NAME: <NAME> "The name of the collection"))
+                )
+        )
 
         .subcommand(
             Command::new("script")
-                .about("run a helper script in pkm `/scripts` directory")
+                .about("run a helper script in pkm `/scripts` directory, the layout of the repo is in PKM_REPO, PKM_ZETTEL_DIR, PKM_DAILY_DIR, PKM_IMG_DIR, PKM_TEMPLATE_DIR and PKM_TODAY_PATH")
                 .alias("s")
                 .arg(
                     arg!(VARS: [VARS]) // Accept 1 or more args
@@ -104,21 +269,43 @@ fn cli() -> Command {
                     .trailing_var_arg(true)
             )
         )
+        .subcommand(
+            Command::new("docs")
+                .about("Generate the man pages or a markdown reference of the commands")
+                .arg(arg!(MAN: --man <DIR> "Write a man page for pkm and every subcommand into the directory").value_hint(ValueHint::DirPath))
+                .arg(arg!(MARKDOWN: --markdown "Print a markdown reference of every command"))
+                .group(ArgGroup::new("FORMAT").args(["MAN", "MARKDOWN"]).required(true))
+        )
         .subcommand(
             Command::new("completion")
                 .arg(
                     arg!(SHELL: --shell <SHELL>)
                     .value_parser(value_parser!(Shell))
                 )
-                .about("Generate shell completion")
+                .about("Generate shell completion. Completion of templates, tags and notes is loaded with source <(COMPLETE=bash pkm) instead")
         )
         .subcommand(
             Command::new("image")
                 .alias("img")
-                .arg(arg!(IMG: <IMG>).value_hint(ValueHint::FilePath))
+                .arg(arg!(IMG: <IMG> "The image, a directory of images or a glob like photos/*.png").value_hint(ValueHint::AnyPath))
                 .arg(arg!(MAX_WIDTH: --"max-width" <WIDTH>).required(false).default_value("1400").value_parser(clap::value_parser!(u32)))
                 .arg(arg!(MAX_HEIGHT: --"max-height" <HEIGHT>).required(false).default_value("1000").value_parser(clap::value_parser!(u32)))
-                .about("Add an image to the repo and echo the path")
+                .arg(arg!(NAME: --name <NAME> "A name for the image, slugged into the filename"))
+                .arg(arg!(NAME_TEMPLATE: --"name-template" <TEMPLATE> "The template for the filename, with date, slug, hash and ext available").env("PKM_IMAGE_NAME_TEMPLATE").default_value(pkm::DEFAULT_IMAGE_NAME_TEMPLATE))
+                .arg(arg!(GALLERY: --gallery "Print the embeds of a batch on one line instead of a list"))
+                .about("Add an image to the repo and echo the path, a batch echoes markdown embeds")
+        )
+        .subcommand(
+            Command::new("attach")
+                .about("Add a file to the attachments and echo a markdown link to it, the text of a pdf is extracted so search finds the notes linking to it")
+                .arg(arg!(FILE: <FILE> "The file to attach").value_hint(ValueHint::FilePath))
+                .arg(arg!(NAME: --name <NAME> "A name for the attachment, slugged into the filename. Defaults to the name of the file"))
+                .arg(arg!(NAME_TEMPLATE: --"name-template" <TEMPLATE> "The template for the filename, with date, slug, hash and ext available").env("PKM_ATTACHMENT_NAME_TEMPLATE").default_value(pkm::DEFAULT_ATTACHMENT_NAME_TEMPLATE))
+                .arg(arg!(NO_EXTRACT: --"no-extract" "Don't extract the text of a pdf"))
+                .arg(arg!(AUDIO: --audio "Attach the file as an audio memo, linked from today's daily"))
+                .arg(arg!(TRANSCRIBE: --transcribe "Add the transcript of the audio memo below its link").requires("AUDIO"))
+                .arg(arg!(ZETTEL: --zettel <TITLE> "Put the audio memo in a new zettel with the title rather than the daily").requires("AUDIO"))
+                .arg(arg!(CONTEXT: --context <HEADING> "Place the audio memo under this heading of the daily").requires("AUDIO").conflicts_with("ZETTEL"))
         )
         .subcommand(
             Command::new("move")
@@ -131,96 +318,518 @@ fn cli() -> Command {
                 .arg(arg!(ZTL: <ZTL>).value_hint(ValueHint::FilePath))
                 .about("return the path(s) to the defined things")
         )
+        .subcommand(
+            Command::new("rename")
+                .arg(arg!(ZTL: <ZTL>).value_hint(ValueHint::FilePath))
+                .arg(arg!(NEW_ID: <NEW_ID> "The new id of the zettel"))
+                .about("Rename a zettel and rewrite the links pointing at it")
+        )
+        .subcommand(
+            Command::new("mentions")
+                .arg(arg!(ZTL: <ZTL>).value_hint(ValueHint::FilePath))
+                .arg(arg!(LIST: -l --list "Only print the mentions instead of offering to link them"))
+                .about("Find notes mentioning the title of a zettel without linking to it")
+        )
+        .subcommand(
+            Command::new("outline")
+                .arg(arg!(ZTL: <ZTL>).value_hint(ValueHint::FilePath))
+                .arg(arg!(SELECT: -s --select "Pick a heading with the finder and open the editor at it"))
+                .about("Print the heading outline of a zettel")
+        )
+        .subcommand(
+            Command::new("toc")
+                .arg(arg!(ZTL: <ZTL> "The path, id or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(SELECT: -s --select "Pick a heading with the finder and open the editor at it"))
+                .about("Print the headings of a note with their line numbers, without the LSP")
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("List, rename and merge the tags of the notes")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the tags with how many notes have them, from the index"))
+                .subcommand(
+                    Command::new("rename")
+                        .about("Rename a tag in the ids and frontmatter of every note")
+                        .arg(arg!(OLD: <OLD> "The tag to rename").add(ArgValueCandidates::new(complete_tags)))
+                        .arg(arg!(NEW: <NEW> "The new name of the tag"))
+                )
+                .subcommand(
+                    Command::new("merge")
+                        .about("Merge tags into a single tag")
+                        .arg(arg!(TAGS: <TAGS>... "The tags to merge").add(ArgValueCandidates::new(complete_tags)))
+                        .arg(arg!(INTO: --into <TAG> "The tag they are merged into").required(true).add(ArgValueCandidates::new(complete_tags)))
+                )
+        )
+        .subcommand(
+            Command::new("mv")
+                .about("Move a note to another directory of the repo, rewriting the links to it")
+                .arg(arg!(ZTL: <ZTL> "The path, id or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(DEST: <DEST> "The directory, relative to the root directory, or the new path of the note").value_hint(ValueHint::AnyPath))
+        )
+        .subcommand(
+            Command::new("rm")
+                .about("Move a note into the trash and drop it from the index")
+                .arg(arg!(ZTL: <ZTL> "The path, id or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(IMAGES: --images "Trash the images no other note links to as well"))
+                .arg(arg!(HARD: --hard "Delete the note for good instead of trashing it"))
+        )
+        .subcommand(
+            Command::new("trash")
+                .about("Manage the removed notes")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the files in the trash"))
+                .subcommand(
+                    Command::new("restore")
+                        .about("Move files out of the trash to where they were removed from")
+                        .arg(arg!(PATH: [PATH] "The file within the trash, picked with the finder when left out").value_hint(ValueHint::FilePath))
+                )
+                .subcommand(Command::new("empty").about("Delete everything in the trash for good"))
+        )
+        .subcommand(
+            Command::new("archive")
+                .about("Move notes into the archive, leaving them out of searches")
+                .arg(arg!(ZTL: [ZTL] "The path, id or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(OLDER_THAN: --"older-than" <AGE> "Archive every zettel older than this (12h, 7d, 2w)"))
+                .group(ArgGroup::new("NOTES").args(["ZTL", "OLDER_THAN"]).required(true))
+        )
+        .subcommand(
+            Command::new("fleeting")
+                .about("Manage fleeting notes")
+                .subcommand_required(true)
+                .subcommand(sweep_command("sweep"))
+        )
+        .subcommand(
+            Command::new("meetings")
+                .about("Work with the meeting notes")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("actions")
+                        .about("List the open tasks of the meetings by the attendee they mention")
+                        .arg(arg!(ATTENDEE: --attendee <NAME> "Only list the tasks of this attendee"))
+                )
+        )
+        .subcommand(
+            Command::new("random")
+                .about("Open a random zettel to resurface old notes")
+                .arg(arg!(TAG: -t --tag <TAG> "Only pick notes with this tag").action(ArgAction::Append))
+                .arg(arg!(KIND: --kind <KIND> "Only pick notes of this kind, zettel when no tag is given").value_parser(["zettel", "daily", "meeting", "fleeting"]))
+                .arg(arg!(NO_EDIT: --"no-edit" "Print the note instead of opening it in an editor"))
+        )
+        .subcommand(
+            Command::new("streak")
+                .about("Print a heatmap of the days with a daily and the writing streaks")
+                .arg(arg!(WEEKS: --weeks <N> "How many weeks the heatmap shows").default_value("26").value_parser(value_parser!(u16).range(1..=520)))
+        )
+        .subcommand(
+            Command::new("recent")
+                .about("Open one of the notes modified last")
+                .arg(arg!(LIMIT: -n --limit <N> "How many notes to list").default_value("20").value_parser(value_parser!(usize)))
+                .arg(arg!(NO_TUI: --"no-tui" "Print the notes instead of opening the finder"))
+        )
+        .subcommand(sweep_command("review"))
+        .subcommand(
+            Command::new("status")
+                .about("Print an overview of the repo")
+                .arg(arg!(SWEEP: --sweep "Warn about fleeting notes that are due for a sweep").env("PKM_STATUS_SWEEP"))
+                .arg(arg!(OLDER_THAN: --"older-than" <AGE> "The age at which fleeting notes are due for a sweep").env("PKM_FLEETING_MAX_AGE").default_value("14d"))
+        )
+        .subcommand(
+            Command::new("table")
+                .about("Manage tables generated from data")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("import")
+                        .about("Insert or update a table in a zettel from a csv or json file")
+                        .arg(arg!(FILE: <FILE> "The csv or json file to import").value_hint(ValueHint::FilePath))
+                        .arg(arg!(INTO: --into <ZTL> "The zettel to put the table in").required(true))
+                        .arg(arg!(HEADING: --heading <HEADING> "The heading the table lives under").default_value("Data"))
+                        .arg(arg!(FORMAT: --format <FORMAT> "The format of the file, defaults to the file extension").value_parser(["csv", "json"]))
+                )
+        )
+        .subcommand(
+            Command::new("diagnostics")
+                .about("Print the problems the LSP reports for the notes, such as broken links")
+        )
+        .subcommand(
+            Command::new("symbols")
+                .arg(arg!(QUERY: [QUERY] "The symbols to search for").default_value(""))
+                .arg(arg!(SELECT: -s --select "Pick a symbol with the finder and open the editor at it"))
+                .about("Search the note titles and headings of the repo through the LSP")
+        )
+        .subcommand(
+            Command::new("serve")
+                .arg(arg!(LISTEN: -l --listen <ADDR> "The address to serve on").default_value("127.0.0.1:8080"))
+                .arg(arg!(EDIT: --edit "Allow notes to be edited from the browser"))
+                .about("Serve the notes over http")
+        )
+        .subcommand(
+            Command::new("template")
+                .about("Inspect the templates")
+                .alias("tmpl")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List the templates and the variables they use")
+                        .alias("ls")
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the source and the variables of the template")
+                        .arg(arg!(NAME: <NAME> "The name of the template, as given to --template"))
+                )
+                .subcommand(
+                    Command::new("new")
+                        .about("Create a template in the template dir and open it in the editor")
+                        .arg(arg!(NAME: <NAME> "The name of the template, as given to --template"))
+                        .arg(arg!(NO_EDIT: --"no-edit" "Do not open in an editor once created"))
+                )
+        )
+        .subcommand(
+            Command::new("bundle")
+                .about("Copy a note and the notes and images it links to into a directory or zip")
+                .arg(arg!(ZTL: <ZTL> "The id of the note"))
+                .arg(arg!(DEPTH: -d --depth <DEPTH> "How many links deep to follow").default_value("1").value_parser(value_parser!(usize)))
+                .arg(arg!(OUT: -o --out <OUT> "The directory or .zip file to write the bundle to").required(true).value_hint(ValueHint::AnyPath))
+        )
+        .subcommand(
+            Command::new("project")
+                .about("Manage the project notes of code repos")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("link")
+                        .about("Create or update the project note of the code repo and link the repo to it")
+                        .arg(arg!(GIT_DIR: [GIT_DIR] "A directory within the code repo").default_value(".").value_hint(ValueHint::DirPath))
+                )
+        )
+        .subcommand(
+            Command::new("metrics")
+                .about("Export metrics of the repo for prometheus")
+                .arg(arg!(LISTEN: -l --listen <ADDR> "Serve the metrics on the address, like :9877 for localhost or 0.0.0.0:9877 for every interface").conflicts_with("TEXTFILE"))
+                .arg(arg!(TEXTFILE: --textfile <PATH> "Write the metrics to the file for the node exporter textfile collector").value_hint(ValueHint::FilePath))
+        )
+        .subcommand(
+            Command::new("agenda")
+                .about("Show the daily, the tasks due, the meetings and the notes to review")
+                .arg(arg!(DATE: [DATE] "Human representation of the date of the agenda").default_value("today"))
+                .arg(arg!(MARKDOWN: --markdown "Print the agenda as markdown"))
+                .arg(arg!(OLDER_THAN: --"older-than" <AGE> "The age at which fleeting notes are due for a review").env("PKM_FLEETING_MAX_AGE").default_value("14d"))
+        )
+        .subcommand(
+            Command::new("open")
+                .about("Open a note in the editor by its path, id, alias or part of its title")
+                .arg(arg!(ZTL: <ZTL> "The path, id, alias or part of the title of the note").value_hint(ValueHint::FilePath).add(ArgValueCandidates::new(complete_notes)))
+        )
+        .subcommand(
+            Command::new("summarize")
+                .about("Ask the llm for a summary of the note and write it into the summary frontmatter field")
+                .arg(arg!(ZTL: <ZTL> "The path, id, alias or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(SECTION: --section "Write the summary into a Summary section instead of the frontmatter"))
+                .arg(arg!(PRINT: --print "Print the summary without writing it into the note"))
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Print the note with syntax highlighting")
+                .alias("render")
+                .arg(arg!(ZTL: <ZTL> "The path, id or part of the title of the note").value_hint(ValueHint::FilePath))
+                .arg(arg!(RAW: --raw "Print the note without highlighting, the default when not printing to a terminal"))
+                .arg(arg!(NO_EMBEDS: --"no-embeds" "Print ![[note]] embeds as they are instead of the embedded notes"))
+        )
+        .subcommand(
+            Command::new("plugins")
+                .about("List the rhai plugins in the .pkm/plugins directory, run them with pkm <plugin>")
+        )
+        .allow_external_subcommands(true)
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
+    // the shell asks for completions by running pkm with COMPLETE set
+    CompleteEnv::with_factory(cli).complete();
+
     let matches = cli().get_matches();
 
     colog::basic_builder()
         .filter_level(match matches.get_count("verbose") {
+            _ if matches.get_flag("QUIET") => LevelFilter::Off,
             0 => LevelFilter::Warn,
             1 => LevelFilter::Info,
             2 => LevelFilter::Debug,
             _ => LevelFilter::Trace,
         })
+        .parse_env("PKM_LOG")
         .init();
 
-    let repo = repo_from_reference(
-        matches
-            .get_one::<String>("REFERENCE_FILE")
-            .map(|s| s.as_str()),
-    )
-    .or_else(|| matches.get_one::<String>("REPO").map(PathBuf::from))
-    .expect("repo required");
+    // errors are json as well for scripts asking for json
+    let json = matches.get_flag("JSON");
+
+    // completions and docs describe pkm itself, they don't need a repo
+    let res = match matches.subcommand() {
+        Some(("completion", submatches)) => Some(run_completion(submatches)),
+        Some(("docs", submatches)) => Some(run_docs(submatches)),
+        _ => None,
+    };
+    match res {
+        Some(Ok(())) => return ExitCode::SUCCESS,
+        Some(Err(err)) => return fail(&err, err.exit_code(), json),
+        None => (),
+    }
+
+    // a new repo doesn't have to be configured as the repo yet
+    let init_dir = match matches.subcommand() {
+        Some(("init", sub_matches)) => sub_matches.get_one::<String>("DIR").map(PathBuf::from),
+        _ => None,
+    };
+
+    let repo = init_dir
+        .or_else(|| {
+            repo_from_reference(
+                matches
+                    .get_one::<String>("REFERENCE_FILE")
+                    .map(|s| s.as_str()),
+            )
+        })
+        .or_else(|| matches.get_one::<String>("REPO").map(PathBuf::from));
+    let Some(repo) = repo else {
+        let err = Error::PKMError(String::from(
+            "no repo, set --repo, PKM_REPO or --repo-reference-file",
+        ));
+        return fail(&err, EXIT_CONFIG, json);
+    };
 
     let pkm = match PKMBuilder::new(&repo) {
-        Err(err) => {
-            error!("{}", err);
-            return ExitCode::FAILURE;
-        }
+        Err(err) => return fail(&err, config_exit_code(&err), json),
         Ok(val) => val,
     };
 
+    // plugins hold on to the pkm while they run
     let pkm = match pkm.parse_args(&matches).build() {
-        Err(err) => {
-            error!("{}", err);
-            return ExitCode::FAILURE;
-        }
-        Ok(val) => val,
+        Err(err) => return fail(&err, config_exit_code(&err), json),
+        Ok(val) => Rc::new(val),
+    };
+
+    // the files changed before the command aren't committed with it, unless
+    // the command writes them again
+    let changed = match pkm.auto_commit {
+        true => match changed_times(&pkm.root) {
+            Ok(changed) => Some(changed),
+            Err(err) => {
+                warn!("auto commit disabled: {}", err);
+                None
+            }
+        },
+        false => None,
     };
 
     let res = match matches.subcommand() {
-        Some(("zettel", sub_matches)) => run_zettel(sub_matches, &pkm),
+        Some(("zettel", sub_matches)) => match sub_matches.subcommand() {
+            Some(("from-selection", sub_matches)) => run_zettel_from_selection(sub_matches, &pkm),
+            _ => run_zettel(sub_matches, &pkm),
+        },
         Some(("daily", sub_matches)) => run_daily(sub_matches, &pkm),
-        Some(("repo", sub_matches)) => run_repo(sub_matches, &pkm),
-        Some(("favorites", sub_matches)) => run_favorites(sub_matches, &pkm).await,
+        Some(("doctor", sub_matches)) => match sub_matches.subcommand() {
+            Some(("links", sub_matches)) => run_doctor_links(sub_matches, &pkm),
+            Some(("titles", sub_matches)) => run_doctor_titles(sub_matches, &pkm),
+            _ => run_doctor(sub_matches, &pkm),
+        },
+        Some(("init", sub_matches)) => run_init(sub_matches, &pkm),
+        Some(("capture", sub_matches)) => run_capture(sub_matches, &pkm),
+        Some(("inbox", sub_matches)) => run_inbox(sub_matches, &pkm),
+        Some(("log", sub_matches)) => run_log(sub_matches, &pkm),
+        Some(("repo", sub_matches)) => match sub_matches.subcommand() {
+            Some(("sync", sub_matches)) => run_repo_sync(sub_matches, &pkm),
+            _ => run_repo(sub_matches, &pkm),
+        },
+        Some(("favorites", sub_matches)) => match sub_matches.subcommand() {
+            Some(("add", sub_matches)) => run_favorites_add(sub_matches, &pkm),
+            Some(("remove", sub_matches)) => run_favorites_remove(sub_matches, &pkm),
+            Some(("list", _)) => run_favorites_list(&pkm),
+            _ => run_favorites(sub_matches, &pkm).await,
+        },
         Some(("index", sub_matches)) => run_index(sub_matches, &pkm),
         Some(("search", sub_matches)) => run_search(sub_matches, &pkm),
+        Some(("similar", sub_matches)) => run_similar(sub_matches, &pkm),
+        Some(("collection", submatches)) => match submatches.subcommand() {
+            Some(("open", submatches)) => run_collection_open(submatches, &pkm),
+            _ => unreachable!(),
+        },
         Some(("script", sub_matches)) => run_script(sub_matches, &pkm),
         Some(("image", submatches)) => run_image(submatches, &pkm),
         Some(("resolve", submatches)) => run_resolve(submatches, &pkm).await,
         Some(("move", submatches)) => run_move(submatches, &pkm).await,
-        Some(("completion", submatches)) => run_completion(submatches),
+        Some(("rename", submatches)) => run_rename(submatches, &pkm).await,
+        Some(("mentions", submatches)) => run_mentions(submatches, &pkm),
+        Some(("outline", submatches)) => run_outline(submatches, &pkm).await,
+        Some(("fleeting", submatches)) => match submatches.subcommand() {
+            Some(("sweep", submatches)) => run_fleeting_sweep(submatches, &pkm),
+            _ => unreachable!(),
+        },
+        Some(("meetings", submatches)) => match submatches.subcommand() {
+            Some(("actions", submatches)) => run_meeting_actions(submatches, &pkm),
+            _ => unreachable!(),
+        },
+        Some(("random", submatches)) => run_random(submatches, &pkm),
+        Some(("streak", submatches)) => run_streak(submatches, &pkm),
+        Some(("recent", submatches)) => run_recent(submatches, &pkm),
+        Some(("review", submatches)) => run_fleeting_sweep(submatches, &pkm),
+        Some(("status", submatches)) => run_status(submatches, &pkm),
+        Some(("table", submatches)) => match submatches.subcommand() {
+            Some(("import", submatches)) => run_table_import(submatches, &pkm).await,
+            _ => unreachable!(),
+        },
+        Some(("diagnostics", submatches)) => run_diagnostics(submatches, &pkm).await,
+        Some(("symbols", submatches)) => run_symbols(submatches, &pkm).await,
+        Some(("serve", submatches)) => run_serve(submatches, &pkm).await,
+        Some(("metrics", submatches)) => run_metrics(submatches, &pkm).await,
+        Some(("template", submatches)) => match submatches.subcommand() {
+            Some(("list", _)) => run_template_list(&pkm),
+            Some(("show", submatches)) => run_template_show(submatches, &pkm),
+            Some(("new", submatches)) => run_template_new(submatches, &pkm),
+            _ => unreachable!(),
+        },
+        Some(("bundle", submatches)) => run_bundle(submatches, &pkm),
+        Some(("project", submatches)) => match submatches.subcommand() {
+            Some(("link", submatches)) => run_project_link(submatches, &pkm),
+            _ => unreachable!(),
+        },
+        Some(("agenda", submatches)) => run_agenda(submatches, &pkm),
+        Some(("cat", submatches)) => run_cat(submatches, &pkm),
+        Some(("toc", submatches)) => run_toc(submatches, &pkm),
+        Some(("open", submatches)) => run_open(submatches, &pkm),
+        Some(("summarize", submatches)) => run_summarize(submatches, &pkm),
+        Some(("attach", submatches)) => run_attach(submatches, &pkm),
+        Some(("archive", submatches)) => run_archive(submatches, &pkm),
+        Some(("tag", submatches)) => match submatches.subcommand() {
+            Some(("list", _)) => run_tag_list(&pkm),
+            Some(("rename", submatches)) => run_tag_rename(submatches, &pkm),
+            Some(("merge", submatches)) => run_tag_merge(submatches, &pkm),
+            _ => unreachable!(),
+        },
+        Some(("mv", submatches)) => run_mv(submatches, &pkm),
+        Some(("rm", submatches)) => run_rm(submatches, &pkm),
+        Some(("trash", submatches)) => match submatches.subcommand() {
+            Some(("list", _)) => run_trash_list(&pkm),
+            Some(("restore", submatches)) => run_trash_restore(submatches, &pkm),
+            Some(("empty", _)) => run_trash_empty(&pkm),
+            _ => unreachable!(),
+        },
+        Some(("plugins", _)) => run_plugins(&pkm),
+        Some((name, submatches)) => run_plugin(name, submatches, &pkm),
         None => run_editor(&matches, &pkm),
-        _ => unreachable!(), // If all subcommands are defined above, anything else is unreachable!()
     };
 
     if let Err(err) = res {
-        error!("{}", err);
-        return ExitCode::FAILURE;
+        return fail(&err, err.exit_code(), json);
+    }
+
+    if let Some(changed) = changed {
+        match pkm.commit_changes(&changed) {
+            Ok(Some(message)) => {
+                info!("committed: {}", message.lines().next().unwrap_or_default())
+            }
+            Ok(None) => (),
+            Err(err) => warn!("could not commit the changes: {}", err),
+        }
     }
 
     ExitCode::SUCCESS
 }
 
+// config_exit_code is the exit code of an error setting up the repo, the
+// errors without a category of their own come from its configuration
+fn config_exit_code(err: &Error) -> u8 {
+    match err.exit_code() {
+        EXIT_FAILURE => EXIT_CONFIG,
+        code => code,
+    }
+}
+
+// fail prints the error to stderr, whatever the log level is, and returns the
+// exit code. With --json the error is printed as {"error": ..., "code": ...}.
+fn fail(err: &Error, code: u8, json: bool) -> ExitCode {
+    match json {
+        true => eprintln!("{}", json!({ "error": err.to_string(), "code": code })),
+        false => eprintln!("error: {}", err),
+    }
+    ExitCode::from(code)
+}
+
 async fn run_resolve(args: &ArgMatches, pkm: &PKM) -> Result<()> {
     let id = args.get_one::<String>("ZTL").expect("required field");
 
     let mut lsp = pkm.lsp().await?;
     let ztl_paths = pkm.resolve_path(id, &mut lsp).await?;
+    lsp.close().await?;
+
+    if pkm.json {
+        return print_json(Value::from(
+            ztl_paths
+                .iter()
+                .map(|path| zettel_json(pkm, &Zettel::new(path)?))
+                .collect::<Result<Vec<Value>>>()?,
+        ));
+    }
+
     for ztl_path in ztl_paths {
         println!("{}", ztl_path.as_path().to_string_lossy())
     }
     Ok(())
 }
 
-async fn run_move(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+// run_rename renames the zettel through the LSP, falling back to rewriting the
+// links ourselves when the LSP has nothing to say about it or changed nothing
+// on disk
+async fn run_rename(args: &ArgMatches, pkm: &PKM) -> Result<()> {
     let id = args.get_one::<String>("ZTL").expect("required field");
-    let remote = args
-        .get_one::<String>("REMOTE_REPO")
-        .expect("required field");
+    let new_id = ZettelID::from_path(args.get_one::<String>("NEW_ID").expect("required field"));
 
     let mut lsp = pkm.lsp().await?;
-    let mut ztls = pkm.resolve_path(id, &mut lsp).await?;
+    match pkm.lsp_rename(id, &new_id, &mut lsp).await? {
+        // an edit of nothing but the virtual note the rename was asked from
+        // is skipped when applied
+        Some(changed) if changed.iter().any(|(_, new)| new.is_file()) => {
+            lsp.close().await?;
+            pkm.record(&format!("renamed {} to", id), &new_id)?;
+            let (removed, mut notes): (Vec<PathBuf>, Vec<PathBuf>) = changed.into_iter().unzip();
+            notes.extend(pkm.find_by_id(&new_id));
+            return reindex(pkm, &removed, &notes);
+        }
+        Some(_) => log::debug!("the LSP renamed nothing on disk, renaming {} ourselves", id),
+        None => (),
+    }
 
-    let remote_pkm = PKMBuilder::new(remote)?.build()?;
+    let mut ztls = pkm.resolve_path(id, &mut lsp).await?;
+    lsp.close().await?;
+
+    match ztls.len() {
+        0 => Err(Error::NotFound(format!("{} is not a valid id", id))),
+        1 => {
+            let old = ztls[0].clone();
+            let (renamed, mut rewritten) =
+                pkm.rename_zettel(Zettel::new(ztls.remove(0))?, &new_id)?;
+            rewritten.push(renamed.path().to_path_buf());
+            reindex(pkm, &[old], &rewritten)
+        }
+        _ => Err(Error::PKMError(format!(
+            "{} matches more than one zettel",
+            id
+        ))),
+    }
+}
 
-    let ztl = match ztls.len() {
-        0 => return Err(Error::NotFound(format!("{} is not a valid id", id))),
-        1 => Zettel::new(ztls.remove(0))?,
+// select_zettel resolves the id to a single zettel, letting the user pick one
+// with the finder when the id is ambiguous. None is returned if nothing was picked.
+async fn select_zettel(
+    id: &str,
+    pkm: &PKM,
+    lsp: &mut LSP<StandardRunner>,
+) -> Result<Option<Zettel>> {
+    let mut ztls = pkm.resolve_path(id, lsp).await?;
+
+    match ztls.len() {
+        0 => Err(Error::NotFound(format!("{} is not a valid id", id))),
+        1 => Ok(Some(Zettel::new(ztls.remove(0))?)),
         _ => {
-            let mut finder = Finder::new(pkm.root.as_path());
+            let mut finder = pkm.finder()?;
             for ztl_path in ztls {
                 let ztl = Zettel::new(ztl_path)?;
                 finder.add(
@@ -234,156 +843,1159 @@ async fn run_move(args: &ArgMatches, pkm: &PKM) -> Result<()> {
                                 .map(Deref::deref)
                                 .unwrap_or("no content"),
                             Some("md"),
-                            None,
+                            pkm.theme.as_deref(),
                         )?,
                 )?;
             }
 
-            let item = match finder.select_one() {
-                Some(item) => item,
-                None => return Ok(()),
-            };
-
-            Zettel::new(item.text().to_string())?
+            match finder.select_one() {
+                Some(item) => Ok(Some(Zettel::new(item.text().to_string())?)),
+                None => Ok(None),
+            }
         }
-    };
+    }
+}
 
-    let newztl = ztl.swap_parent_dir(&pkm.zettel_dir, &remote_pkm.zettel_dir)?;
-    newztl.sync()?;
-    ztl.delete()?;
+// run_outline prints the heading outline of the zettel, or lets the user jump
+// to a heading with the finder
+async fn run_outline(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = args.get_one::<String>("ZTL").expect("required field");
 
-    Ok(())
-}
+    let mut lsp = pkm.lsp().await?;
+    let ztl = match select_zettel(id, pkm, &mut lsp).await? {
+        Some(ztl) => ztl,
+        None => return Ok(()),
+    };
 
-fn run_image(args: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let current_date = Local::now();
+    let content = ztl.content().map(|c| c.to_string()).unwrap_or_default();
+    lsp.did_open(ztl.path(), content.as_str(), "markdown")
+        .await?;
+    let items = match lsp.document_symbols(ztl.path()).await? {
+        Some(symbols) => outline(symbols),
+        None => Vec::new(),
+    };
+    lsp.close().await?;
+
+    if !args.get_flag("SELECT") {
+        for item in items {
+            println!(
+                "{}{}: {}",
+                "  ".repeat(item.depth),
+                item.line + 1,
+                item.name
+            );
+        }
+        return Ok(());
+    }
 
-    let img = pkm
-        .image()
-        .with_date_directory(&current_date)
-        .max_width(args.get_one::<u32>("MAX_WIDTH").copied())
-        .max_height(args.get_one::<u32>("MAX_HEIGHT").copied())
-        .build(args.get_one::<String>("IMG").expect("required"))?;
+    let lines: Vec<&str> = content.lines().collect();
+    let mut finder = pkm.finder()?;
+    for item in items {
+        // the text is the line so we can jump to it once selected
+        finder.add(
+            FinderItem::new((item.line + 1).to_string())
+                .with_display(Some(format!("{}{}", "  ".repeat(item.depth), item.name)))
+                .with_syntax_preview(
+                    &lines[(item.line as usize).min(lines.len())..].join("\n"),
+                    Some("md"),
+                    pkm.theme.as_deref(),
+                )?,
+        )?;
+    }
 
-    println!("{}", img.path().to_string_lossy());
-    Ok(())
-}
+    let line = match finder.select_one() {
+        Some(item) => item.text().parse::<u32>().unwrap_or(1),
+        None => return Ok(()),
+    };
+
+    Editor::new_from_env("EDITOR", pkm.root.as_path())
+        .at_line(line)
+        .file(ztl.rel_path(pkm.root.as_path())?)
+        .exec()?;
 
-fn run_completion(args: &ArgMatches) -> Result<()> {
-    let mut cmd = cli();
-    let generator: Shell = args.get_one("SHELL").copied().expect("Required Field");
-    let name = cmd.get_name().to_string();
-    generate(generator, &mut cmd, &name, &mut stdout());
     Ok(())
 }
 
-// build_zettel_context will build the context to create a new zettel from a template
-fn build_context_args(args: &ArgMatches) -> Context {
-    let mut context = tera::Context::new();
-    // add the title
+// run_toc prints the headings of the note from its markdown, without the LSP
+fn run_toc(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let ztl = match pick_note(pkm, ztl)? {
+        Some(path) => Zettel::new(path)?,
+        None => return Ok(()),
+    };
 
-    if let Some(vars) = args.get_many::<String>("VARS") {
-        for value in vars {
-            if let Some((key, value)) = value.split_once(":") {
-                context.insert(key, value);
-            }
+    let content = ztl.content().map(|c| c.to_string()).unwrap_or_default();
+    let headings = ztl
+        .content()
+        .map(|c| c.headings())
+        .transpose()?
+        .unwrap_or_default();
+    let top = headings.iter().map(|h| h.depth).min().unwrap_or(1);
+    let indent = |depth: u8| "  ".repeat((depth - top) as usize);
+
+    if !args.get_flag("SELECT") {
+        for heading in headings {
+            println!(
+                "{}{}: {}",
+                indent(heading.depth),
+                heading.line,
+                heading.text
+            );
         }
+        return Ok(());
     }
 
-    if let Some(title) = args.get_one::<String>("TITLE") {
-        context.insert("title", title)
+    let lines: Vec<&str> = content.lines().collect();
+    let mut finder = pkm.finder()?;
+    for heading in headings.iter() {
+        // the text is the line so we can jump to it once selected
+        finder.add(
+            FinderItem::new(heading.line.to_string())
+                .with_display(Some(format!("{}{}", indent(heading.depth), heading.text)))
+                .with_syntax_preview(
+                    &lines[(heading.line - 1).min(lines.len())..].join("\n"),
+                    Some("md"),
+                    pkm.theme.as_deref(),
+                )?,
+        )?;
     }
 
-    context
-}
+    let line = match finder.select_one() {
+        Some(item) => item.text().parse::<u32>().unwrap_or(1),
+        None => return Ok(()),
+    };
 
-fn run_editor(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
     Editor::new_from_env("EDITOR", pkm.root.as_path())
-        .file("README.md")
+        .at_line(line)
+        .file(ztl.rel_path(pkm.root.as_path())?)
         .exec()?;
+
     Ok(())
 }
 
-fn run_zettel(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let current_date = Local::now();
-    let mut context = build_context_args(sub_matches);
-    let date_reg = Regex::new(DATE_REGEX).expect("must compile");
-
-    let id = ZettelIDBuilder::new()
-        .parse_args(sub_matches, &current_date)
-        .build()?;
-
-    let mut reference_prefix = ZETTEL_ICON;
-
-    if let Some(date) = id.tag_regex(&date_reg) {
-        context.insert("daily", date);
-        reference_prefix = DATED_ICON;
-    }
+// run_symbols searches the workspace symbols of the LSP
+async fn run_symbols(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let query = args.get_one::<String>("QUERY").expect("defaulted");
 
-    if id.has_tag(FLEETING_TAG) {
-        reference_prefix = FLEETING_ICON;
+    let mut lsp = pkm.lsp().await?;
+    let symbols = match lsp.workspace_symbols(query.as_str()).await? {
+        Some(symbols) => symbol_locations(symbols),
+        None => Vec::new(),
+    };
+    lsp.close().await?;
+
+    if !args.get_flag("SELECT") {
+        for symbol in symbols {
+            println!(
+                "{}:{}: {}",
+                symbol.path.to_string_lossy(),
+                symbol.line + 1,
+                symbol.name
+            );
+        }
+        return Ok(());
     }
 
-    if id.has_tag(MEETING_TAG) {
-        reference_prefix = MEETING_ICON;
+    let mut finder = pkm.finder()?;
+    for symbol in symbols {
+        let content = read_to_string(&symbol.path).unwrap_or_default();
+        let lines: Vec<&str> = content.lines().collect();
+
+        // the text holds the path and line so we can jump to it once selected
+        finder.add(
+            FinderItem::new(format!(
+                "{}:{}",
+                symbol.path.to_string_lossy(),
+                symbol.line + 1
+            ))
+            .with_display(Some(symbol.name))
+            .with_syntax_preview(
+                &lines[(symbol.line as usize).min(lines.len())..].join("\n"),
+                Some("md"),
+                pkm.theme.as_deref(),
+            )?,
+        )?;
     }
 
-    let zettel = pkm
-        .zettel()
-        .with_year_month_day(&current_date)
-        .parse_args(sub_matches)
-        .id(&id)
-        .build(&pkm.tmpl, &context)?;
-
-    // add the reference to the daily
-    let reference = ZettelReference::new(&id, reference_prefix);
-    let reference: String = reference.into();
-    let mut daily = pkm.daily(&current_date)?;
-    daily.mut_content()?.append(&reference)?;
-    daily.sync()?;
+    let item = match finder.select_one() {
+        Some(item) => item,
+        None => return Ok(()),
+    };
 
-    if let Some(true) = sub_matches.get_one::<bool>("NO_EDIT") {
-        println!("{}", zettel.path().to_string_lossy())
-    } else {
-        Editor::new_from_env("EDITOR", pkm.root.as_path())
-            .file(zettel.rel_path(pkm.root.as_path())?)
-            .exec()?;
-    }
+    let text = item.text();
+    let (path, line) = text.rsplit_once(':').unwrap_or((text.as_ref(), "1"));
+    Editor::new_from_env("EDITOR", pkm.root.as_path())
+        .at_line(line.parse().unwrap_or(1))
+        .file(path)
+        .exec()?;
 
     Ok(())
 }
 
-fn parse_human_date(date: &str) -> Result<DateTime<Local>> {
-    // this library makes things hard
-    let current_date = human_date_parser::from_human_time(date, Local::now().naive_local())?;
-    let current_date = match current_date {
-        ParseResult::DateTime(datetime) => datetime,
-        ParseResult::Date(date) => date.into(),
-        ParseResult::Time(_) => Local::now().naive_local(),
+// run_table_import converts a csv or json file to a table within a zettel,
+// replacing the table generated by a previous import
+async fn run_table_import(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let file = PathBuf::from(args.get_one::<String>("FILE").expect("required field"));
+    let id = args.get_one::<String>("INTO").expect("required field");
+    let heading = args.get_one::<String>("HEADING").expect("defaulted");
+
+    let format = args
+        .get_one::<String>("FORMAT")
+        .map(String::as_str)
+        .or_else(|| file.extension().and_then(OsStr::to_str))
+        .unwrap_or("csv");
+
+    let content = read_to_string(&file)?;
+    let table = match format {
+        "json" => Table::from_json(&content)?,
+        _ => Table::from_csv(&content)?,
     };
 
-    Ok(Local.from_local_datetime(&current_date).unwrap())
+    let mut lsp = pkm.lsp().await?;
+    let ztl = select_zettel(id, pkm, &mut lsp).await?;
+    lsp.close().await?;
+    let mut ztl = match ztl {
+        Some(ztl) => ztl,
+        None => return Ok(()),
+    };
+
+    ztl.mut_content()?.upsert_marked_block(
+        heading,
+        &format!("pkm-table:{}", heading),
+        &table.to_markdown(),
+    )?;
+    let ztl = ztl.sync()?;
+    pkm.record(
+        &format!("imported {} into", file.to_string_lossy()),
+        &ztl.id(),
+    )?;
+
+    Ok(())
 }
 
-fn run_daily(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let current_date = parse_human_date(sub_matches.get_one::<String>("DATE").expect("defaulted"))?;
-    let daily = pkm.daily(&current_date)?;
+// run_diagnostics opens every note in the LSP and prints the diagnostics it
+// publishes for them
+async fn run_diagnostics(_args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let mut lsp = pkm.lsp().await?;
 
-    if let Some(true) = sub_matches.get_one::<bool>("NO_EDIT") {
-        println!("{}", daily.path().to_string_lossy())
-    } else {
-        Editor::new_from_env("EDITOR", pkm.root.as_path())
-            .file(daily.rel_path(pkm.root.as_path())?)
-            .exec()?;
+    let notes: Vec<PathBuf> = pkm.notes().collect();
+    for note in notes.iter() {
+        let content = read_to_string(note)?;
+        lsp.did_open(note, content, "markdown").await?;
+    }
+
+    let diagnostics = lsp.published_diagnostics(&notes).await?;
+    lsp.close().await?;
+
+    for published in diagnostics {
+        let path = published.uri.as_local_path();
+        let path = path.strip_prefix(&pkm.root).unwrap_or(&path);
+        for diagnostic in published.diagnostics {
+            println!(
+                "{}:{}:{}: {}",
+                path.to_string_lossy(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                diagnostic.message
+            );
+        }
     }
 
     Ok(())
 }
 
-fn run_repo(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    std::process::Command::new("git")
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
+async fn run_move(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = args.get_one::<String>("ZTL").expect("required field");
+    let remote = args
+        .get_one::<String>("REMOTE_REPO")
+        .expect("required field");
+
+    let mut lsp = pkm.lsp().await?;
+    let remote_pkm = PKMBuilder::new(remote)?.build()?;
+
+    let ztl = select_zettel(id, pkm, &mut lsp).await?;
+    lsp.close().await?;
+    let ztl = match ztl {
+        Some(ztl) => ztl,
+        None => return Ok(()),
+    };
+    let ztl_id = ztl.id();
+    let old = ztl.path().to_path_buf();
+
+    let newztl = ztl.swap_parent_dir(&pkm.zettel_dir, &remote_pkm.zettel_dir)?;
+    let newztl = newztl.sync()?;
+    ztl.delete()?;
+    pkm.record(&format!("moved to {}", remote), &ztl_id)?;
+
+    reindex(pkm, &[old], &[])?;
+    reindex(&remote_pkm, &[], &[newztl.path().to_path_buf()])?;
+
+    Ok(())
+}
+
+fn run_image(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let current_date = Local::now();
+    let input = args.get_one::<String>("IMG").expect("required");
+
+    let builder = pkm
+        .image()
+        .with_date_directory(&current_date)
+        .max_width(args.get_one::<u32>("MAX_WIDTH").copied())
+        .max_height(args.get_one::<u32>("MAX_HEIGHT").copied())
+        .name(args.get_one::<String>("NAME"))
+        .name_template(args.get_one::<String>("NAME_TEMPLATE"));
+
+    let paths = image_paths(input)?;
+    if paths.len() == 1 && paths[0] == Path::new(input) {
+        let img = builder.build(&paths[0])?;
+        pkm.hook(Hook::PostImage, img.path())?;
+        if pkm.json {
+            return print_json(json!({
+                "path": img.path(),
+                "uri": img.rel_path(&pkm.root)?,
+            }));
+        }
+        println!("{}", img.path().to_string_lossy());
+        return Ok(());
+    }
+
+    let mut embeds = Vec::new();
+    let mut images = Vec::new();
+    for (path, img) in paths.iter().zip(builder.build_all(&paths)) {
+        if let Ok(img) = img.as_ref() {
+            pkm.hook(Hook::PostImage, img.path())?;
+        }
+        match img {
+            Ok(img) if pkm.json => images.push(json!({
+                "source": path,
+                "path": img.path(),
+                "uri": img.rel_path(&pkm.root)?,
+            })),
+            Ok(img) => embeds.push(format!(
+                "![{}]({})",
+                path.file_stem().unwrap_or_default().to_string_lossy(),
+                img.rel_path(&pkm.root)?.to_string_lossy()
+            )),
+            Err(err) => error!("could not add {}: {}", path.to_string_lossy(), err),
+        }
+    }
+
+    if pkm.json {
+        print_json(Value::from(images))?;
+    } else if args.get_flag("GALLERY") {
+        println!("{}", embeds.join(" "));
+    } else {
+        for embed in embeds {
+            println!("- {}", embed);
+        }
+    }
+
+    Ok(())
+}
+
+// run_attach copies the file into the attachments and prints a link to it
+fn run_attach(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let file = args.get_one::<String>("FILE").expect("required");
+    let attachment = pkm
+        .attachment()
+        .with_date_directory(&Local::now())
+        .name(args.get_one::<String>("NAME"))
+        .name_template(args.get_one::<String>("NAME_TEMPLATE"))
+        .build(file)?;
+    let path = attachment.path();
+
+    // the attachment is kept when the text can't be extracted, pkm index
+    // just won't find its text
+    if is_pdf(path) && !args.get_flag("NO_EXTRACT") {
+        if let Err(err) = extract_pdf_text(&pkm.pdf_text_command, path) {
+            error!("could not extract the text of {}: {}", path.display(), err);
+        }
+    }
+
+    let link = attachment.link(&pkm.root)?;
+    if args.get_flag("AUDIO") {
+        return add_audio_memo(args, pkm, &link, path);
+    }
+
+    println!("{}", link);
+    Ok(())
+}
+
+// add_audio_memo links the recording from today's daily or a new zettel,
+// along with its transcript when asked for. The recording is kept when it
+// can't be transcribed.
+fn add_audio_memo(args: &ArgMatches, pkm: &PKM, link: &str, path: &Path) -> Result<()> {
+    let now = Local::now();
+    if !is_audio(path) {
+        warn!("{} doesn't look like a recording", path.display());
+    }
+
+    let transcript = if args.get_flag("TRANSCRIBE") {
+        match transcribe(&pkm.transcribe_command, path) {
+            Ok(transcript) => Some(transcript).filter(|t| !t.is_empty()),
+            Err(err) => {
+                error!("could not transcribe {}: {}", path.display(), err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let Some(title) = args.get_one::<String>("ZETTEL") else {
+        let mut entry = format!("- {} {}", now.format("%H:%M"), link);
+        if let Some(transcript) = &transcript {
+            entry.push_str(&format!("\n  {}", transcript));
+        }
+
+        let mut daily = pkm.daily(&now)?;
+        match args.get_one::<String>("CONTEXT") {
+            Some(heading) => daily.mut_content()?.append_to_section(heading, &entry)?,
+            None => daily.mut_content()?.append(&entry)?,
+        }
+        let daily = daily.sync()?;
+        println!("{}", daily.path().to_string_lossy());
+        return Ok(());
+    };
+
+    let id = pkm.id().title(Some(title), None).build()?;
+    let mut context = Context::new();
+    context.insert("title", title);
+    let mut zettel = pkm
+        .routed_zettel(&id)
+        .with_year_month_day(&now)
+        .id(&id)
+        .build(&pkm.tmpl, &context)?;
+
+    let rendered = zettel.content().as_deref().unwrap_or("").to_string();
+    let mut body = format!("{}\n\n{}\n", rendered.trim_end(), link);
+    if let Some(transcript) = &transcript {
+        body.push_str(&format!("\n{}\n", transcript));
+    }
+    zettel.set_content(body);
+    let zettel = zettel.sync()?;
+    pkm.created("created", &zettel)?;
+
+    add_daily_reference(pkm, &now, ZettelReference::new(&id, ZETTEL_ICON), None)?;
+    println!("{}", zettel.path().to_string_lossy());
+    Ok(())
+}
+
+fn run_completion(args: &ArgMatches) -> Result<()> {
+    let mut cmd = cli();
+    let generator: Shell = args.get_one("SHELL").copied().expect("Required Field");
+    let name = cmd.get_name().to_string();
+    generate(generator, &mut cmd, &name, &mut stdout());
+    Ok(())
+}
+
+// run_docs writes the man pages or prints the markdown reference, both are
+// generated from the definition of the commands
+fn run_docs(args: &ArgMatches) -> Result<()> {
+    if let Some(dir) = args.get_one::<String>("MAN") {
+        fs::create_dir_all(dir)?;
+        clap_mangen::generate_to(cli(), dir)?;
+        return Ok(());
+    }
+
+    let mut cmd = cli();
+    cmd.build();
+    let mut reference = String::from("# pkm command reference\n\n");
+    markdown_reference(&mut cmd, true, &mut reference);
+    print!("{}", reference);
+    Ok(())
+}
+
+// markdown_reference adds a section for the command and each of its
+// subcommands, the global arguments are only listed for the root
+fn markdown_reference(cmd: &mut Command, root: bool, out: &mut String) {
+    out.push_str(&format!(
+        "## {}\n\n",
+        cmd.get_bin_name().unwrap_or(cmd.get_name())
+    ));
+    if let Some(about) = cmd.get_about() {
+        out.push_str(&format!("{}\n\n", about));
+    }
+    out.push_str(&format!("```\n{}\n```\n\n", cmd.render_usage()));
+
+    let args: Vec<String> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && (root || !arg.is_global_set()))
+        .filter(|arg| !["help", "version"].contains(&arg.get_id().as_str()))
+        .map(markdown_arg)
+        .collect();
+    if !args.is_empty() {
+        out.push_str(&args.join("\n"));
+        out.push_str("\n\n");
+    }
+
+    for sub in cmd
+        .get_subcommands_mut()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+    {
+        markdown_reference(sub, false, out);
+    }
+}
+
+// markdown_arg is the list item of the argument, like
+// - `-t, --template <TEMPLATE>`: The template (default: default)
+fn markdown_arg(arg: &Arg) -> String {
+    let values = arg
+        .get_value_names()
+        .unwrap_or_default()
+        .iter()
+        .map(|value| format!("<{}>", value))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let mut names: Vec<String> = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("-{}", short));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("--{}", long));
+    }
+    let mut usage = names.join(", ");
+    if arg.get_action().takes_values() {
+        match values.is_empty() {
+            true if usage.is_empty() => usage = format!("<{}>", arg.get_id()),
+            true => usage.push_str(&format!(" <{}>", arg.get_id())),
+            false if usage.is_empty() => usage = values,
+            false => usage.push_str(&format!(" {}", values)),
+        }
+    }
+
+    let mut item = format!("- `{}`", usage);
+    if let Some(help) = arg.get_help() {
+        item.push_str(&format!(": {}", help));
+    }
+
+    let mut extra = Vec::new();
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().to_string())
+        .collect();
+    if !defaults.is_empty() {
+        extra.push(format!("default: {}", defaults.join(", ")));
+    }
+    if let Some(env) = arg.get_env() {
+        extra.push(format!("env: {}", env.to_string_lossy()));
+    }
+    if !extra.is_empty() {
+        item.push_str(&format!(" ({})", extra.join(", ")));
+    }
+    item
+}
+
+// RECENT_COMPLETIONS is how many of the most recently modified notes are
+// offered when completing a note
+const RECENT_COMPLETIONS: usize = 100;
+
+// completion_pkm opens the repo configured through the environment, a
+// completion is run without the flags of the command line
+fn completion_pkm() -> Result<PKM> {
+    let matches = cli().try_get_matches_from(["pkm"])?;
+    let repo = matches
+        .get_one::<String>("REPO")
+        .ok_or_else(|| Error::NotFound(String::from("no repo to complete from")))?;
+    PKMBuilder::new(repo)?.parse_args(&matches).build()
+}
+
+// complete_templates offers the templates of the repo
+fn complete_templates() -> Vec<CompletionCandidate> {
+    let Ok(templates) = completion_pkm().and_then(|pkm| templates(&pkm.tmpl)) else {
+        return Vec::new();
+    };
+
+    templates
+        .into_iter()
+        .map(|template| {
+            CompletionCandidate::new(template.name).help(Some(template.variables.join(", ").into()))
+        })
+        .collect()
+}
+
+// complete_tags offers the tags in the index, most used first. The index is
+// only read, a missing or outdated one offers nothing.
+fn complete_tags() -> Vec<CompletionCandidate> {
+    let tags = completion_pkm()
+        .and_then(|pkm| ZettelIndex::open(pkm.root))
+        .and_then(|index| index.doc_searcher()?.tags());
+    let Ok(mut tags) = tags else {
+        return Vec::new();
+    };
+
+    tags.sort_by(|a, b| b.count.cmp(&a.count));
+    tags.into_iter()
+        .map(|tag| CompletionCandidate::new(tag.value).help(Some(tag.count.to_string().into())))
+        .collect()
+}
+
+// complete_notes offers the ids of the most recently modified notes
+fn complete_notes() -> Vec<CompletionCandidate> {
+    let Ok(recent) = completion_pkm().and_then(|pkm| pkm.recent(RECENT_COMPLETIONS)) else {
+        return Vec::new();
+    };
+
+    recent
+        .into_iter()
+        .map(|ztl| {
+            let title = ztl.title().ok().flatten();
+            CompletionCandidate::new(ztl.id().to_string()).help(title.map(Into::into))
+        })
+        .collect()
+}
+
+// build_zettel_context will build the context to create a new zettel from a template
+fn build_context_args(args: &ArgMatches) -> Context {
+    let mut context = tera::Context::new();
+    // add the title
+
+    if let Some(vars) = args.get_many::<String>("VARS") {
+        for value in vars {
+            if let Some((key, value)) = value.split_once(":") {
+                context.insert(key, value);
+            }
+        }
+    }
+
+    if let Some(title) = args.get_one::<String>("TITLE") {
+        context.insert("title", title)
+    }
+
+    context
+}
+
+fn run_editor(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    Editor::new_from_env("EDITOR", pkm.root.as_path())
+        .file("README.md")
+        .exec()?;
+    Ok(())
+}
+
+fn run_zettel(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let current_date = Local::now();
+    let mut context = build_context_args(sub_matches);
+    let date_reg = Regex::new(DATE_REGEX).expect("must compile");
+
+    // the body is read up front so nothing is created when it can't be read
+    let body = if sub_matches.get_flag("STDIN") {
+        let mut body = String::new();
+        std::io::stdin().read_to_string(&mut body)?;
+        Some(body)
+    } else {
+        match sub_matches.get_one::<String>("FROM_FILE") {
+            Some(path) => Some(fs::read_to_string(path)?),
+            None => None,
+        }
+    };
+
+    let mut builder = pkm.id().parse_args(sub_matches, &current_date);
+    if sub_matches.get_flag("SUGGEST_TITLE") {
+        let body = body.as_deref().ok_or_else(|| {
+            Error::PKMError(String::from(
+                "--suggest-title needs a body from --stdin or --from-file",
+            ))
+        })?;
+        let title = require_llm(pkm)?.suggest_title(body)?;
+        context.insert("title", &title);
+        builder = builder.title(Some(&title), None);
+    }
+    let id = builder.build()?;
+
+    let mut reference_prefix = ZETTEL_ICON;
+
+    if let Some(date) = id.tag_regex(&date_reg) {
+        context.insert("daily", date);
+        reference_prefix = DATED_ICON;
+    }
+
+    if id.has_tag(FLEETING_TAG) {
+        reference_prefix = FLEETING_ICON;
+    }
+
+    if id.has_tag(MEETING_TAG) {
+        reference_prefix = MEETING_ICON;
+    }
+
+    let attendees: Vec<String> = sub_matches
+        .get_many::<String>("ATTENDEE")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if !attendees.is_empty() {
+        context.insert(pkm::ATTENDEES_KEY, &attendees);
+    }
+
+    // the parent is resolved up front so nothing is created without it
+    let parent = match sub_matches.get_one::<String>("CHILD_OF") {
+        Some(query) => match pick_note(pkm, query)? {
+            Some(path) => {
+                let parent = Zettel::new(path)?;
+                context.insert(pkm::PARENT_KEY, &parent.id().to_string());
+                Some(parent)
+            }
+            None => return Ok(()),
+        },
+        None => None,
+    };
+
+    let project = if sub_matches.get_flag("HERE") {
+        let dir = std::env::current_dir()?;
+        let project = pkm.project(&dir)?.ok_or_else(|| {
+            Error::NotFound(format!(
+                "no project is linked to {:?}, run `pkm project link`",
+                dir
+            ))
+        })?;
+        context.insert("project", &project.id().to_string());
+        Some(project)
+    } else {
+        None
+    };
+
+    // the template of the route is used unless one is asked for
+    let mut builder = pkm.routed_zettel(&id).with_year_month_day(&current_date);
+    if sub_matches.value_source("TEMPLATE") == Some(ValueSource::CommandLine) {
+        builder = builder.parse_args(sub_matches);
+    }
+    let mut zettel = builder.id(&id).build(&pkm.tmpl, &context)?;
+    if !attendees.is_empty() {
+        zettel
+            .mut_content()?
+            .set_frontmatter(pkm::ATTENDEES_KEY, &serde_json::to_string(&attendees)?)?;
+        zettel = zettel.sync()?;
+    }
+    if let Some(body) = body {
+        let rendered = zettel.content().as_deref().unwrap_or("").to_string();
+        zettel.set_content(format!("{}\n\n{}\n", rendered.trim_end(), body.trim_end()));
+        zettel = zettel.sync()?;
+    }
+    pkm.created("created", &zettel)?;
+
+    if let Some(parent) = parent {
+        zettel = pkm.adopt(zettel, parent)?;
+    }
+
+    if let Some(mut project) = project {
+        let reference: String = ZettelReference::new(&id, reference_prefix).into();
+        project
+            .mut_content()?
+            .append_to_section("Notes", &reference)?;
+        project.sync()?;
+    }
+
+    // add the reference to the daily
+    let reference = ZettelReference::new(&id, reference_prefix)
+        .heading(sub_matches.get_one::<String>("ANCHOR").map(String::as_str));
+    add_daily_reference(
+        pkm,
+        &current_date,
+        reference,
+        sub_matches.get_one::<String>("CONTEXT"),
+    )?;
+
+    if sub_matches.get_flag("NO_EDIT") || sub_matches.get_flag("STDIN") {
+        print_zettel(pkm, &zettel)?;
+    } else {
+        Editor::new_from_env("EDITOR", pkm.root.as_path())
+            .file(zettel.rel_path(pkm.root.as_path())?)
+            .exec()?;
+    }
+
+    // indexed once the editor is closed so the index has what was written,
+    // the zettel is there whether or not the index could be updated
+    if let Err(err) = reindex(pkm, &[], &[zettel.path().to_path_buf()]) {
+        error!("could not index {}: {}", zettel.path().display(), err);
+    }
+
+    Ok(())
+}
+
+// add_daily_reference adds the reference to the daily, under the context
+// heading when it is set
+fn add_daily_reference(
+    pkm: &PKM,
+    date: &DateTime<Local>,
+    reference: ZettelReference,
+    context: Option<&String>,
+) -> Result<()> {
+    let reference: String = reference.into();
+    let mut daily = pkm.daily(date)?;
+    match context {
+        Some(heading) => daily
+            .mut_content()?
+            .append_to_section(heading, &reference)?,
+        None => daily.mut_content()?.append(&reference)?,
+    }
+    daily.sync()?;
+    Ok(())
+}
+
+// run_zettel_from_selection creates a zettel from the selection piped in on
+// stdin, keeping the source of the selection in the frontmatter
+fn run_zettel_from_selection(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let current_date = Local::now();
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+
+    let selection = Selection::parse(&input);
+    let title = sub_matches
+        .get_one::<String>("TITLE")
+        .cloned()
+        .or_else(|| selection.title());
+
+    let fleeting = sub_matches.get_flag("FLEETING");
+    let mut builder = pkm.id().title(title.as_ref(), None);
+    if fleeting {
+        builder = builder.tag(FLEETING_TAG);
+    }
+    // selections often share a title, the hash keeps them apart
+    let id = builder.with_hash().build()?;
+
+    let mut context = Context::new();
+    context.insert("title", title.as_deref().unwrap_or(id.as_str()));
+    let mut zettel = pkm
+        .routed_zettel(&id)
+        .with_year_month_day(&current_date)
+        .id(&id)
+        .build(&pkm.tmpl, &context)?;
+
+    // the header goes into the frontmatter the template may already have,
+    // values are written as json which yaml understands
+    let mut content = zettel.mut_content()?;
+    for (key, value) in selection.meta.iter() {
+        content.set_frontmatter(key, &value.to_string())?;
+    }
+
+    let rendered = zettel.content().as_deref().unwrap_or("").to_string();
+    zettel.set_content(format!("{}\n\n{}\n", rendered.trim_end(), selection.text));
+    let zettel = zettel.sync()?;
+    pkm.created("captured", &zettel)?;
+
+    let prefix = if fleeting { FLEETING_ICON } else { ZETTEL_ICON };
+    add_daily_reference(
+        pkm,
+        &current_date,
+        ZettelReference::new(&id, prefix),
+        sub_matches.get_one::<String>("CONTEXT"),
+    )?;
+
+    if sub_matches.get_flag("NO_EDIT") {
+        print_zettel(pkm, &zettel)?;
+    } else {
+        Editor::new_from_env("EDITOR", pkm.root.as_path())
+            .file(zettel.rel_path(pkm.root.as_path())?)
+            .exec()?;
+    }
+
+    if let Err(err) = reindex(pkm, &[], &[zettel.path().to_path_buf()]) {
+        error!("could not index {}: {}", zettel.path().display(), err);
+    }
+
+    Ok(())
+}
+
+fn parse_human_date(date: &str) -> Result<DateTime<Local>> {
+    // this library makes things hard
+    let current_date = human_date_parser::from_human_time(date, Local::now().naive_local())?;
+    let current_date = match current_date {
+        ParseResult::DateTime(datetime) => datetime,
+        ParseResult::Date(date) => date.into(),
+        ParseResult::Time(_) => Local::now().naive_local(),
+    };
+
+    Ok(Local.from_local_datetime(&current_date).unwrap())
+}
+
+fn run_daily(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let current_date = parse_human_date(sub_matches.get_one::<String>("DATE").expect("defaulted"))?;
+    let daily = pkm.daily(&current_date)?;
+
+    if let Some(true) = sub_matches.get_one::<bool>("NO_EDIT") {
+        print_zettel(pkm, &daily)?;
+    } else {
+        Editor::new_from_env("EDITOR", pkm.root.as_path())
+            .file(daily.rel_path(pkm.root.as_path())?)
+            .exec()?;
+    }
+
+    Ok(())
+}
+
+// print_json prints the value on a single line, so the output of a command can
+// be read by scripts and editor plugins
+fn print_json(value: Value) -> Result<()> {
+    println!("{}", serde_json::to_string(&value)?);
+    Ok(())
+}
+
+// print_zettel prints the path of the zettel, or its path, uri, id and title
+// as json
+fn print_zettel(pkm: &PKM, zettel: &Zettel) -> Result<()> {
+    if !pkm.json {
+        println!("{}", zettel.path().to_string_lossy());
+        return Ok(());
+    }
+
+    print_json(zettel_json(pkm, zettel)?)
+}
+
+// zettel_json is the path, uri, id and title of the zettel
+fn zettel_json(pkm: &PKM, zettel: &Zettel) -> Result<Value> {
+    Ok(json!({
+        "path": zettel.path(),
+        "uri": zettel.rel_path(&pkm.root)?,
+        "id": zettel.id().to_string(),
+        "title": zettel.title()?,
+    }))
+}
+
+// doc_json is the path, uri, id and title of the document of the index
+fn doc_json(pkm: &PKM, doc: &HashMap<String, String>) -> Value {
+    let uri = doc.get("uri").expect("schema should have uri");
+    json!({
+        "path": pkm.root.join(uri),
+        "uri": uri,
+        "id": doc.get("id"),
+        "title": doc.get("title"),
+    })
+}
+
+// run_doctor_links prints every broken link as path:line: target
+fn run_doctor_links(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let broken = broken_links(pkm)?;
+
+    if sub_matches.get_flag("SELECT") {
+        if broken.is_empty() {
+            println!("no broken links");
+            return Ok(());
+        }
+
+        let mut finder = pkm.finder()?;
+        let mut seen = HashSet::new();
+        for link in broken.iter() {
+            if seen.insert(&link.note) {
+                finder.add_doc(link.note.strip_prefix(&pkm.root)?)?;
+            }
+        }
+        finder.run()?;
+        return Ok(());
+    }
+
+    if pkm.json {
+        let mut links = Vec::new();
+        for link in broken.iter() {
+            links.push(json!({
+                "uri": link.note.strip_prefix(&pkm.root)?,
+                "line": link.line,
+                "target": link.target,
+            }));
+        }
+        return print_json(Value::from(links));
+    }
+
+    for link in broken.iter() {
+        println!(
+            "{}:{}: {}",
+            link.note.strip_prefix(&pkm.root)?.to_string_lossy(),
+            link.line,
+            link.target
+        );
+    }
+
+    Ok(())
+}
+
+// run_doctor_titles prints every group of duplicate zettels, separated by an
+// empty line
+fn run_doctor_titles(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let similarity = *sub_matches
+        .get_one::<f64>("SIMILARITY")
+        .expect("similarity has a default");
+    let duplicates = duplicate_titles(pkm, similarity)?;
+
+    if sub_matches.get_flag("SELECT") {
+        if duplicates.is_empty() {
+            println!("no duplicate titles");
+            return Ok(());
+        }
+
+        let mut finder = pkm.finder()?;
+        for note in duplicates.iter().flatten() {
+            finder.add_doc(note.note.strip_prefix(&pkm.root)?)?;
+        }
+        finder.run()?;
+        return Ok(());
+    }
+
+    if pkm.json {
+        let mut groups = Vec::new();
+        for group in duplicates.iter() {
+            let mut notes = Vec::new();
+            for note in group.iter() {
+                notes.push(json!({
+                    "uri": note.note.strip_prefix(&pkm.root)?,
+                    "title": note.title,
+                }));
+            }
+            groups.push(Value::from(notes));
+        }
+        return print_json(Value::from(groups));
+    }
+
+    for (i, group) in duplicates.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+
+        for note in group.iter() {
+            println!(
+                "{}: {}",
+                note.note.strip_prefix(&pkm.root)?.to_string_lossy(),
+                note.title
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn run_init(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    for path in pkm.init()? {
+        println!(
+            "created {}",
+            path.strip_prefix(&pkm.root)?.to_string_lossy()
+        );
+    }
+
+    if sub_matches.get_flag("GIT") && pkm::git_init(pkm.root.as_path())? {
+        println!("initialized a git repo");
+    }
+
+    Ok(())
+}
+
+fn run_capture(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let text = sub_matches
+        .get_many::<String>("TEXT")
+        .expect("required")
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    let capture = pkm.capture(&text)?;
+    if pkm.json {
+        return print_json(zettel_json(pkm, &capture)?);
+    }
+    println!("{}", capture.rel_path(&pkm.root)?.to_string_lossy());
+    Ok(())
+}
+
+const INBOX_FILE: &str = "file as a new zettel";
+const INBOX_APPEND: &str = "append to an existing note";
+const INBOX_DELETE: &str = "delete";
+const INBOX_SKIP: &str = "skip";
+
+// run_inbox picks captures from the inbox with the finder until it is empty
+// or nothing is picked, filing each one
+fn run_inbox(_sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    loop {
+        let captures = pkm.inbox()?;
+        if captures.is_empty() {
+            println!("the inbox is empty");
+            return Ok(());
+        }
+
+        let mut finder = pkm.finder()?;
+        finder.header(Some(format!("{} captures to file", captures.len())));
+        for capture in captures.iter() {
+            finder.add_doc(capture.rel_path(&pkm.root)?)?;
+        }
+
+        let capture = match finder.select_one() {
+            Some(item) => Zettel::new(pkm.root.join(item.text().as_ref()))?,
+            None => return Ok(()),
+        };
+
+        let choice = Select::new(
+            "what should happen to this capture?",
+            vec![INBOX_FILE, INBOX_APPEND, INBOX_DELETE, INBOX_SKIP],
+        )
+        .prompt()?;
+
+        // a filed, appended or deleted capture is gone from the inbox and is
+        // dropped from the index along with it
+        let path = capture.path().to_path_buf();
+        match choice {
+            INBOX_FILE => {
+                let id = capture.id();
+                let title = Text::new("title")
+                    .with_initial_value(id.title().unwrap_or_default())
+                    .prompt()?;
+                let ztl = pkm.file_capture(capture, title.trim())?;
+                add_daily_reference(
+                    pkm,
+                    &Local::now(),
+                    ZettelReference::new(&ztl.id(), ZETTEL_ICON),
+                    None,
+                )?;
+                reindex(pkm, &[path], &[ztl.path().to_path_buf()])?;
+            }
+            INBOX_APPEND => {
+                let mut finder = pkm.finder()?;
+                finder.header(Some("append the capture to"));
+                for note in pkm.notes() {
+                    if !note.starts_with(&pkm.inbox_dir) {
+                        finder.add_doc(note.strip_prefix(&pkm.root)?)?;
+                    }
+                }
+
+                if let Some(item) = finder.select_one() {
+                    let into = Zettel::new(pkm.root.join(item.text().as_ref()))?;
+                    let (merged, mut rewritten) = pkm.merge_zettel(capture, into)?;
+                    rewritten.push(merged.path().to_path_buf());
+                    reindex(pkm, &[path], &rewritten)?;
+                }
+            }
+            INBOX_DELETE => {
+                let id = capture.id();
+                capture.delete()?;
+                pkm.record("deleted", &id)?;
+                reindex(pkm, &[path], &[])?;
+            }
+            _ => (),
+        }
+    }
+}
+
+// run_log appends the message as a `- HH:MM message` bullet to today's daily
+fn run_log(sub_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let now = Local::now();
+    let message = sub_matches
+        .get_many::<String>("MESSAGE")
+        .expect("required")
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let entry = format!("- {} {}", now.format("%H:%M"), message.trim());
+
+    let mut daily = pkm.daily(&now)?;
+    match sub_matches.get_one::<String>("CONTEXT") {
+        Some(heading) => daily.mut_content()?.append_to_section(heading, &entry)?,
+        None => daily.mut_content()?.append(&entry)?,
+    }
+    daily.sync()?;
+
+    Ok(())
+}
+
+fn run_repo(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    std::process::Command::new("git")
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .current_dir(pkm.root.as_path())
         .args(
@@ -396,143 +2008,1846 @@ fn run_repo(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
     Ok(())
 }
 
-fn run_script(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let mut arguments = matches
-        .get_many::<String>("VARS")
-        .expect("arguments required")
-        .into_iter();
+// run_repo_sync synchronizes the repo with its remote through git
+fn run_repo_sync(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let report = sync(
+        &pkm.root,
+        matches
+            .get_one::<String>("MESSAGE")
+            .unwrap_or(&pkm.commit_template),
+    )?;
+
+    if pkm.json {
+        return print_json(json!({
+            "committed": report.committed,
+            "upstream": report.upstream,
+            "pulled": report.pulled,
+            "pushed": report.pushed,
+        }));
+    }
+
+    match report.committed {
+        Some(message) => println!("committed: {}", message),
+        None => println!("nothing to commit"),
+    }
+    if !report.upstream {
+        println!("no upstream branch, nothing to push");
+    } else if report.pushed {
+        println!("pushed to the remote");
+    } else {
+        println!("nothing to push");
+    }
+
+    Ok(())
+}
+
+fn run_script(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let mut arguments = matches
+        .get_many::<String>("VARS")
+        .expect("arguments required")
+        .into_iter();
+
+    let mut command = String::from("./scripts/");
+    command.push_str(arguments.next().expect("required"));
+
+    std::process::Command::new(&command)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .current_dir(pkm.root.as_path())
+        .envs(pkm.script_env()?)
+        .args(arguments)
+        .status()?;
+
+    Ok(())
+}
+
+// run_random opens a note picked at random from the index
+fn run_random(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let mut filters: Vec<String> = args
+        .get_many::<String>("TAG")
+        .into_iter()
+        .flatten()
+        .map(|tag| format!("tag:{}", tag))
+        .collect();
+
+    match args.get_one::<String>("KIND") {
+        Some(kind) => filters.push(format!("kind:{}", kind)),
+        None if filters.is_empty() => filters.push(String::from("kind:zettel")),
+        None => (),
+    }
+
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let doc = index
+        .doc_searcher()?
+        .random(&filters)?
+        .ok_or_else(|| Error::NotFound(format!("no notes match {}", filters.join(" "))))?;
+    let uri = doc.get("uri").expect("schema should have uri");
+
+    if args.get_flag("NO_EDIT") {
+        println!("{}", uri);
+    } else {
+        Editor::new_from_env("EDITOR", pkm.root.as_path())
+            .file(uri)
+            .exec()?;
+    }
+
+    Ok(())
+}
+
+fn run_streak(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let weeks = *args.get_one::<u16>("WEEKS").expect("defaulted") as usize;
+    let today = Local::now().date_naive();
+    let days = pkm.daily_words()?;
+
+    let streaks = streaks(&days, today);
+    if pkm.json {
+        return print_json(json!({
+            "current": streaks.current,
+            "longest": streaks.longest,
+            "days": days.len(),
+        }));
+    }
+
+    print!("{}", heatmap(&days, today, weeks));
+    println!(
+        "\ncurrent streak: {} days\nlongest streak: {} days",
+        streaks.current, streaks.longest
+    );
+
+    Ok(())
+}
+
+fn run_recent(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let limit = *args.get_one::<usize>("LIMIT").expect("defaulted");
+    let recent = pkm.recent(limit)?;
+
+    if args.get_flag("NO_TUI") {
+        if pkm.json {
+            return print_json(Value::from(
+                recent
+                    .iter()
+                    .map(|ztl| zettel_json(pkm, ztl))
+                    .collect::<Result<Vec<Value>>>()?,
+            ));
+        }
+        for ztl in recent {
+            println!("{}", ztl.rel_path(&pkm.root)?.to_string_lossy());
+        }
+        return Ok(());
+    }
+
+    let mut finder = pkm.finder()?;
+    for ztl in recent {
+        finder.add_doc(ztl.rel_path(&pkm.root)?)?;
+    }
+    finder.run()?;
+
+    Ok(())
+}
+
+// run_meeting_actions prints the open tasks of the meetings grouped by the
+// attendee they mention
+fn run_meeting_actions(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let only = args.get_one::<String>("ATTENDEE").map(|a| a.to_lowercase());
+    let mut attendees = Vec::new();
+    for (attendee, actions) in pkm.meeting_actions()? {
+        if only
+            .as_ref()
+            .is_some_and(|only| *only != attendee.to_lowercase())
+        {
+            continue;
+        }
+
+        if pkm.json {
+            attendees.push(json!({
+                "attendee": attendee,
+                "tasks": actions
+                    .iter()
+                    .map(|(ztl, task)| json!({ "text": task.text, "id": ztl.id().to_string() }))
+                    .collect::<Vec<Value>>(),
+            }));
+            continue;
+        }
+
+        println!("## {}\n", attendee);
+        for (ztl, task) in actions {
+            println!("- [ ] {} ([[{}]])", task.text, ztl.id());
+        }
+        println!();
+    }
+
+    if pkm.json {
+        return print_json(Value::from(attendees));
+    }
+    Ok(())
+}
+
+// run_agenda prints the agenda of the day, either for the terminal or as
+// markdown for printing
+fn run_agenda(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let date = parse_human_date(args.get_one::<String>("DATE").expect("defaulted"))?;
+    let max_age = parse_age(args.get_one::<String>("OLDER_THAN").expect("defaulted"))?;
+    let agenda = pkm.agenda(&date, max_age)?;
+    let markdown = args.get_flag("MARKDOWN");
+
+    let title = |ztl: &Zettel| -> String {
+        ztl.title()
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| ztl.id().to_string())
+    };
+    let section = |name: &str, lines: Vec<String>| {
+        if lines.is_empty() {
+            return;
+        }
+
+        if markdown {
+            println!("\n## {}\n", name);
+        } else {
+            println!("\n{}", name);
+        }
+        for line in lines {
+            println!("{}", line);
+        }
+    };
+
+    if markdown {
+        println!("# Agenda {}", agenda.date.format("%A, %B %d, %Y"));
+    } else {
+        println!(
+            "{} {}  {}",
+            DATED_ICON,
+            agenda.date.format("%A, %B %d, %Y"),
+            agenda.daily.rel_path(&pkm.root)?.to_string_lossy()
+        );
+    }
+
+    section(
+        "Today",
+        agenda
+            .todo
+            .iter()
+            .map(|task| match markdown {
+                true => format!("- [ ] {}", task.text),
+                false => format!("  [ ] {}", task.text),
+            })
+            .collect(),
+    );
+
+    section(
+        "Due",
+        agenda
+            .due
+            .iter()
+            .map(|(ztl, task)| match markdown {
+                true => format!("- [ ] {} ([[{}]])", task.text, ztl.id()),
+                false => format!(
+                    "  [ ] {}  {}:{}",
+                    task.text,
+                    ztl.rel_path(&pkm.root)
+                        .unwrap_or(ztl.path())
+                        .to_string_lossy(),
+                    task.line
+                ),
+            })
+            .collect(),
+    );
+
+    section(
+        "Meetings",
+        agenda
+            .meetings
+            .iter()
+            .map(|ztl| match markdown {
+                true => format!("- [[{}]]", ztl.id()),
+                false => format!("  {} {}", MEETING_ICON, title(ztl)),
+            })
+            .collect(),
+    );
+
+    section(
+        "Review",
+        agenda
+            .review
+            .iter()
+            .map(|ztl| match markdown {
+                true => format!("- [[{}]]", ztl.id()),
+                false => format!("  {} {}", FLEETING_ICON, title(ztl)),
+            })
+            .collect(),
+    );
+
+    Ok(())
+}
+
+// pick_note resolves the path, id or title to a note, letting the finder
+// pick one when several match. None is returned when nothing was picked.
+fn pick_note(pkm: &PKM, query: &str) -> Result<Option<PathBuf>> {
+    let mut paths = match PathBuf::from(query) {
+        path if path.is_file() => vec![path],
+        _ => pkm.find_notes(query),
+    };
+
+    match paths.len() {
+        0 => Err(Error::NotFound(format!("could not find note {}", query))),
+        1 => Ok(Some(paths.remove(0))),
+        _ => {
+            let mut finder = pkm.finder()?;
+            for path in paths {
+                finder.add_doc(path.strip_prefix(&pkm.root)?)?;
+            }
+
+            Ok(finder
+                .select_one()
+                .map(|item| pkm.root.join(item.text().as_ref())))
+        }
+    }
+}
+
+// require_llm returns the llm of the repo, failing when none is configured
+fn require_llm(pkm: &PKM) -> Result<Llm> {
+    pkm.llm()?.ok_or_else(|| {
+        Error::PKMError(format!(
+            "no llm is configured, set --llm, PKM_LLM or {}",
+            pkm::LLM_CONFIG_FILE
+        ))
+    })
+}
+
+// run_summarize writes the summary of the note into its frontmatter or its
+// Summary section, a summary written before is replaced
+fn run_summarize(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let llm = require_llm(pkm)?;
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let path = match pick_note(pkm, ztl)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let summary = llm.summarize(&read_to_string(&path)?)?;
+    if args.get_flag("PRINT") {
+        println!("{}", summary);
+        return Ok(());
+    }
+
+    let mut zettel = Zettel::new(path)?;
+    let mut content = zettel.mut_content()?;
+    if args.get_flag("SECTION") {
+        if !content
+            .to_string()
+            .lines()
+            .any(|line| line.trim() == format!("## {}", SUMMARY_HEADING))
+        {
+            content.insert_section(SUMMARY_HEADING, "")?;
+        }
+        content.upsert_marked_block(SUMMARY_HEADING, SUMMARY_KEY, &summary)?;
+    } else {
+        content.set_frontmatter(SUMMARY_KEY, &serde_json::to_string(&summary)?)?;
+    }
+    let zettel = zettel.sync()?;
+    pkm.record("summarized", &zettel.id())?;
+
+    println!("{}", summary);
+    Ok(())
+}
+
+// run_open opens the note in the editor
+fn run_open(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let path = match pick_note(pkm, ztl)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    Editor::new_from_env("EDITOR", pkm.root.as_path())
+        .file(absolute(path)?)
+        .exec()?;
+    Ok(())
+}
+
+// run_cat prints the zettel highlighted with the theme
+fn run_cat(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let path = match pick_note(pkm, ztl)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let mut content = read_to_string(&path)?;
+    if !args.get_flag("NO_EMBEDS") {
+        let notes = NoteReader::new(&pkm.root);
+        content = expand_embeds(&content, |id| notes.read(id));
+    }
+
+    if args.get_flag("RAW") || !stdout().is_terminal() {
+        print!("{}", content);
+        return Ok(());
+    }
+
+    print!(
+        "{}",
+        Highlighting::new()
+            .syntax(Some("md"))
+            .theme(pkm.theme.as_deref())
+            .highlight(&content)?
+    );
+
+    Ok(())
+}
+
+// run_plugins lists the plugins of the repo with what they do
+fn run_plugins(pkm: &PKM) -> Result<()> {
+    for plugin in plugins(pkm.root.as_path())? {
+        let about = plugin.about()?;
+        println!(
+            "{}\t{}",
+            plugin.name,
+            about.unwrap_or_else(|| plugin.path.display().to_string())
+        );
+    }
+
+    Ok(())
+}
+
+// run_plugin runs the plugin for a subcommand pkm doesn't know about. A name
+// that is neither fails the way clap fails on an unknown subcommand.
+fn run_plugin(name: &str, matches: &ArgMatches, pkm: &Rc<PKM>) -> Result<()> {
+    let Some(plugin) = Plugin::find(pkm.root.as_path(), name)? else {
+        let mut cmd = cli();
+        let names: Vec<String> = cmd
+            .get_subcommands()
+            .map(|sub| sub.get_name().to_string())
+            .chain(plugins(pkm.root.as_path())?.into_iter().map(|p| p.name))
+            .collect();
+
+        let mut err = clap::Error::new(ErrorKind::InvalidSubcommand).with_cmd(&cmd);
+        err.insert(
+            ContextKind::InvalidSubcommand,
+            ContextValue::String(name.to_string()),
+        );
+        let similar = similar_names(name, names);
+        if !similar.is_empty() {
+            err.insert(
+                ContextKind::SuggestedSubcommand,
+                ContextValue::Strings(similar),
+            );
+        }
+        err.insert(
+            ContextKind::Usage,
+            ContextValue::StyledStr(cmd.render_usage()),
+        );
+        err.exit();
+    };
+
+    plugin.run(
+        pkm,
+        matches
+            .get_many::<OsString>("")
+            .into_iter()
+            .flatten()
+            .map(|arg| arg.to_string_lossy().to_string()),
+    )
+}
+
+// run_index creates/updates the index
+fn run_index(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    // a failing pre-index hook stops the index from changing at all
+    pkm.hook(Hook::PreIndex, &pkm.root)?;
+
+    let embedder = match (matches.get_flag("EMBEDDINGS"), pkm.embedder.as_ref()) {
+        (true, None) => {
+            return Err(Error::PKMError(String::from(
+                "--embeddings needs an embedder, set --embedder or PKM_EMBEDDER",
+            )));
+        }
+        (true, embedder) => embedder,
+        (false, _) => None,
+    };
+
+    if let Some(language) = matches.get_one::<String>("LANGUAGE") {
+        IndexConfig::open(&pkm.root)?
+            .with_language(Some(language))
+            .save(&pkm.root)?;
+    }
+
+    if matches.get_flag("GC") {
+        return run_index_gc(pkm);
+    }
+
+    let jobs = matches
+        .get_one::<usize>("JOBS")
+        .copied()
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|jobs| jobs.get())
+                .unwrap_or(1)
+        });
+
+    // an index that doesn't fit the config anymore can only be rebuilt
+    let report = match matches.get_flag("FULL") {
+        true => rebuild_index(pkm, jobs)?,
+        false => match ZettelIndex::new(pkm.root.as_path()) {
+            Ok(index) => update_index(pkm, &index, jobs)?,
+            Err(err) if needs_rebuild(&err) => {
+                info!("rebuilding the index: {}", err);
+                rebuild_index(pkm, jobs)?
+            }
+            Err(err) => return Err(err),
+        },
+    };
+
+    info!("indexed {} notes in {:.1?}", report.indexed, report.elapsed);
+    for (note, reason) in report.skipped.iter() {
+        warn!(
+            "skipped {}: {}",
+            note.strip_prefix(&pkm.root).unwrap_or(note).display(),
+            reason
+        );
+    }
+
+    if let Some(embedder) = embedder {
+        let mut embeddings = Embeddings::open(&pkm.root)?;
+        let embedded = embeddings.update(&pkm.root, pkm.notes(), embedder)?;
+        embeddings.save(&pkm.root)?;
+        info!("embedded {} notes", embedded);
+    }
+
+    Ok(())
+}
+
+// rebuild_index indexes every note into a staged index that replaces the
+// current one once it is complete, searches keep using the current index
+// until then
+fn rebuild_index(pkm: &PKM, jobs: usize) -> Result<IndexReport> {
+    let index = ZettelIndex::staging(pkm.root.as_path())?;
+    let mut writer = index.doc_indexer()?;
+    let notes: Vec<PathBuf> = pkm.notes().collect();
+    let report = index_notes(&mut writer, &notes, jobs);
+
+    writer.commit()?;
+    index.swap()?;
+    Ok(report)
+}
+
+// update_index indexes the notes modified since they were indexed and drops
+// the notes that are gone
+fn update_index(pkm: &PKM, index: &ZettelIndex<&Path>, jobs: usize) -> Result<IndexReport> {
+    let indexed = index.doc_searcher()?.indexed()?;
+    let notes: HashSet<PathBuf> = pkm.notes().collect();
+    let mut writer = index.doc_indexer()?;
+
+    // notes indexed before the uris were relative are indexed again
+    for uri in indexed.keys() {
+        if Path::new(uri).is_absolute() || !notes.contains(&pkm.root.join(uri)) {
+            writer.remove(uri)?;
+        }
+    }
+
+    let mut changed = Vec::new();
+    for note in notes {
+        let uri = note.strip_prefix(&pkm.root)?.to_string_lossy().to_string();
+        let modified = DateTime::<Local>::from(fs::metadata(&note)?.modified()?).timestamp();
+        if indexed.get(&uri) != Some(&modified) {
+            writer.remove(&note)?;
+            changed.push(note);
+        }
+    }
+
+    let report = index_notes(&mut writer, &changed, jobs);
+    writer.commit()?;
+    Ok(report)
+}
+
+// index_notes indexes the notes with as many jobs. The progress bar is drawn
+// on stderr and hidden when it isn't a terminal or pkm is quiet.
+fn index_notes(writer: &mut DocIndexer<&Path>, notes: &[PathBuf], jobs: usize) -> IndexReport {
+    let bar = match log::max_level() {
+        LevelFilter::Off => ProgressBar::hidden(),
+        _ => ProgressBar::new(notes.len() as u64),
+    }
+    .with_style(
+        ProgressStyle::with_template("{elapsed_precise} [{bar:40}] {pos}/{len} {msg}")
+            .expect("valid template")
+            .progress_chars("=> "),
+    );
+    let report = writer.process_all(notes, jobs, |progress| {
+        bar.set_position(progress.processed as u64);
+        if progress.errors > 0 {
+            bar.set_message(format!("{} skipped", progress.errors));
+        }
+    });
+    bar.finish_and_clear();
+    report
+}
+
+// run_index_gc compares the indexed notes with the repo and drops the notes
+// that were deleted, or are now ignored, printing them
+fn run_index_gc(pkm: &PKM) -> Result<()> {
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let notes: HashSet<PathBuf> = pkm.notes().collect();
+    let mut writer = index.doc_indexer()?;
+    let mut removed = 0;
+    for uri in index.doc_searcher()?.uris()? {
+        if notes.contains(&pkm.root.join(&uri)) {
+            continue;
+        }
+
+        println!("{}", uri);
+        writer.remove(&uri)?;
+        removed += 1;
+    }
+    writer.commit()?;
+
+    info!("dropped {} notes from the index", removed);
+    Ok(())
+}
+
+// semantic_docs fuses the hits of the query with the notes nearest to it in
+// meaning. within narrows the nearest notes the way the hits were, by the
+// facets, the directories and whether they are archived.
+fn semantic_docs<F>(
+    pkm: &PKM,
+    embeddings: &Embeddings,
+    query: &str,
+    docs: Vec<HashMap<String, String>>,
+    within: F,
+) -> Result<Vec<HashMap<String, String>>>
+where
+    F: Fn(&[String]) -> Result<Vec<HashMap<String, String>>>,
+{
+    let embedder = pkm.embedder.as_ref().ok_or_else(|| {
+        Error::PKMError(String::from(
+            "--semantic needs an embedder, set --embedder or PKM_EMBEDDER",
+        ))
+    })?;
+    if query.trim().is_empty() {
+        return Ok(docs);
+    }
+
+    let uri =
+        |doc: &HashMap<String, String>| doc.get("uri").expect("schema should have uri").clone();
+    let keyword: Vec<String> = docs.iter().map(uri).collect();
+    let nearest: Vec<String> = embeddings
+        .nearest(&embedder.embed(query)?, keyword.len().max(10))
+        .into_iter()
+        .map(|(uri, _)| uri)
+        .collect();
+
+    // the nearest notes outside of the filters have no document and are left
+    // out
+    let mut docs: HashMap<String, HashMap<String, String>> = docs
+        .into_iter()
+        .chain(within(&nearest)?)
+        .map(|doc| (uri(&doc), doc))
+        .collect();
+    Ok(rank_fusion([keyword, nearest])
+        .into_iter()
+        .filter_map(|uri| docs.remove(&uri))
+        .collect())
+}
+
+fn run_search(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let collection = matches.get_one::<String>("COLLECTION");
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let mut query = String::new();
+    let mut filters: Vec<String> = matches
+        .get_many::<String>("IN")
+        .into_iter()
+        .flatten()
+        .map(|dir| format!("dir:{}", dir.trim_matches('/')))
+        .collect();
+    let mut initial = matches.get_one::<String>("QUERY").cloned();
+    let include_archived = matches.get_flag("INCLUDE_ARCHIVED");
+    let since = matches.get_one::<NaiveDate>("SINCE").copied();
+    let until = matches.get_one::<NaiveDate>("UNTIL").copied();
+    let recency = !matches.get_flag("NO_RECENCY");
+    let sort = *matches.get_one::<SearchSort>("SORT").expect("has default");
+    let embeddings = match matches.get_flag("SEMANTIC") {
+        true => {
+            let embeddings = Embeddings::open(&pkm.root)?;
+            if embeddings.is_empty() {
+                return Err(Error::NotFound(String::from(
+                    "no embeddings, run pkm index --embeddings first",
+                )));
+            }
+            Some(embeddings)
+        }
+        false => None,
+    };
+    let search = |query: &str, filters: &[String]| {
+        index
+            .doc_searcher()?
+            .include_archived(include_archived)
+            .since(since)
+            .until(until)
+            .recency(recency)
+            .sort(sort)
+            .search(query, filters)
+    };
+    let within = |uris: &[String], filters: &[String]| {
+        index
+            .doc_searcher()?
+            .include_archived(include_archived)
+            .since(since)
+            .until(until)
+            .within(uris, filters)
+    };
+
+    if let Some(format) = matches.get_one::<String>("PICKER_FORMAT") {
+        let query = initial.unwrap_or_default();
+        let results = search(&query, &filters)?;
+        let docs = match embeddings.as_ref() {
+            Some(embeddings) => semantic_docs(pkm, embeddings, &query, results.docs, |uris| {
+                within(uris, &filters)
+            })?,
+            None => results.docs,
+        };
+        return print_picker(pkm, format, &docs);
+    }
+
+    loop {
+        let input = match initial.take() {
+            Some(input) => input,
+            None => Text::new(" >")
+                .with_placeholder("Query")
+                .with_help_message(
+                    "tag:<tag>, dir:<dir>, title:\"<text>\", after:<date>, -<term> and OR refine the query, :facet tag:<tag> or dir:<dir> narrows the results, :clear removes the facets",
+                )
+                .prompt()?,
+        };
+
+        match input.trim() {
+            ":clear" => filters.clear(),
+            input => match input.strip_prefix(":facet") {
+                Some(filter) => filters.push(filter.trim().to_string()),
+                None => query = input.to_string(),
+            },
+        }
+
+        if matches.get_flag("NO_TUI") {
+            let results = search(&query, &filters)?;
+            let docs = match embeddings.as_ref() {
+                Some(embeddings) => semantic_docs(pkm, embeddings, &query, results.docs, |uris| {
+                    within(uris, &filters)
+                })?,
+                None => results.docs,
+            };
+            let pick = *matches.get_one::<usize>("PICK").expect("has default");
+            let mut finder = pkm.finder()?;
+            let mut titles = HashMap::new();
+            for doc in docs {
+                if let Some(title) = doc.get("title") {
+                    titles.insert(
+                        doc.get("uri").expect("schema should have uri").clone(),
+                        title.clone(),
+                    );
+                }
+                finder.add(FinderItem::new(
+                    doc.get("uri").expect("schema should have uri"),
+                ))?;
+            }
+
+            let paths = finder.pick(pick);
+            if paths.is_empty() {
+                return Err(Error::NotFound(format!("nothing matches {}", query)));
+            }
+
+            if pkm.json {
+                return print_json(Value::from(
+                    paths
+                        .iter()
+                        .map(|path| {
+                            json!({
+                                "path": pkm.root.join(path),
+                                "uri": path,
+                                "title": titles.get(path.to_string_lossy().as_ref()),
+                            })
+                        })
+                        .collect::<Vec<Value>>(),
+                ));
+            }
+
+            for path in paths {
+                println!("{}", path.display());
+            }
+            return Ok(());
+        }
+
+        let results = match search(&query, &filters) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("oops: {}", err);
+                filters.pop_if(|filter| input.contains(filter.as_str()));
+                continue;
+            }
+        };
+
+        let docs = match embeddings.as_ref() {
+            Some(embeddings) => semantic_docs(pkm, embeddings, &query, results.docs, |uris| {
+                within(uris, &filters)
+            })?,
+            None => results.docs,
+        };
+
+        let diffs = word_diffs(
+            pkm.root.as_path(),
+            docs.iter().filter_map(|doc| doc.get("uri")),
+        );
+        let mut finder = pkm.finder()?;
+        finder.header(facet_summary(&filters, &results.facets));
+        for doc in docs {
+            let uri = doc.get("uri").expect("schema should have uri");
+            let mut full_path = PathBuf::from(pkm.root.as_path());
+            full_path.push(uri);
+
+            let content = read_to_string(&full_path)?;
+
+            finder.add(
+                FinderItem::new(uri)
+                    .with_display(doc.get("title"))
+                    .with_syntax_preview(&content, Some("md"), pkm.theme.as_deref())?
+                    .with_diff_preview(diffs.get(Path::new(uri)).cloned()),
+            )?;
+        }
+
+        if let Some(selection) = finder.run_selection()? {
+            if let Some(name) = collection {
+                pkm.collection(name)?
+                    .add(selection.iter().map(ZettelID::from_path))?;
+            }
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// print_picker prints the notes for a launcher, which passes the path of the
+// picked note to pkm open. tsv is a line of title and path per note, alfred is
+// the json of a script filter.
+fn print_picker(pkm: &PKM, format: &str, docs: &[HashMap<String, String>]) -> Result<()> {
+    let notes = docs.iter().map(|doc| {
+        let uri = doc.get("uri").expect("schema should have uri");
+        let title = doc
+            .get("title")
+            .filter(|title| !title.is_empty())
+            .unwrap_or(uri);
+        (uri, title.replace(['\t', '\n'], " "), pkm.root.join(uri))
+    });
+
+    if format == "alfred" {
+        let items: Vec<Value> = notes
+            .map(|(uri, title, path)| {
+                json!({
+                    "uid": uri,
+                    "type": "file",
+                    "title": title,
+                    "subtitle": uri,
+                    "arg": path,
+                })
+            })
+            .collect();
+        return print_json(json!({ "items": items }));
+    }
+
+    for (_, title, path) in notes {
+        println!("{}\t{}", title, path.to_string_lossy());
+    }
+    Ok(())
+}
+
+// run_similar finds the notes similar to the text and opens them in the
+// finder
+fn run_similar(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let limit = *matches.get_one::<u64>("LIMIT").expect("has default") as usize;
+
+    // a note is compared by its significant terms, leaving out the notes it
+    // already links to
+    let mut source = None;
+    let docs = if let Some(ztl) = matches.get_one::<String>("ZTL") {
+        let path = match pick_note(pkm, ztl)? {
+            Some(path) => absolute(path)?,
+            None => return Ok(()),
+        };
+        let content = read_to_string(&path)?;
+        let linked: Vec<String> = wikilinks(&content)
+            .into_iter()
+            .map(|link| link.id.to_lowercase())
+            .collect();
+
+        let docs =
+            index
+                .doc_searcher()?
+                .more_like(&path_to_id(&path), &content, limit + linked.len())?;
+        source = Some(path);
+        docs.into_iter()
+            .filter(|doc| {
+                doc.get("id")
+                    .is_none_or(|id| !linked.contains(&id.to_lowercase()))
+            })
+            .take(limit)
+            .collect()
+    } else {
+        let text = match (
+            matches.get_one::<String>("TEXT"),
+            matches.get_one::<String>("FILE"),
+        ) {
+            (Some(text), _) => text.clone(),
+            (None, Some(file)) if file == "-" => {
+                let mut text = String::new();
+                std::io::stdin().read_to_string(&mut text)?;
+                text
+            }
+            (None, Some(file)) => read_to_string(file)?,
+            (None, None) => unreachable!("the input group is required"),
+        };
+
+        index.doc_searcher()?.similar(&text, limit)?
+    };
+    if docs.is_empty() {
+        return Err(Error::NotFound(String::from("no similar notes")));
+    }
+
+    if matches.get_flag("NO_TUI") {
+        if pkm.json {
+            return print_json(Value::from(
+                docs.iter()
+                    .map(|doc| doc_json(pkm, doc))
+                    .collect::<Vec<Value>>(),
+            ));
+        }
+        for doc in docs {
+            println!("{}", doc.get("uri").expect("schema should have uri"));
+        }
+        return Ok(());
+    }
+
+    let mut finder = pkm.finder()?;
+    if let Some(source) = source {
+        finder.header(Some(format!("ctrl-l: link from {}", path_to_id(&source))));
+        finder.source(Some(source));
+        finder.bind("ctrl-l", FinderAction::LinkFromSource);
+    }
+    for doc in docs {
+        let mut full_path = PathBuf::from(pkm.root.as_path());
+        full_path.push(doc.get("uri").expect("schema should have uri"));
+
+        finder.add(
+            FinderItem::new(doc.get("uri").expect("schema should have uri"))
+                .with_display(doc.get("title"))
+                .with_syntax_preview(
+                    &read_to_string(&full_path)?,
+                    Some("md"),
+                    pkm.theme.as_deref(),
+                )?,
+        )?;
+    }
+    finder.run()?;
+
+    Ok(())
+}
+
+// facet_summary renders the active facets and the drill down counts of the
+// results, e.g. [tag:project] meeting (12), project/alpha (7), 2024 (15)
+fn facet_summary(filters: &[String], facets: &[FacetCount]) -> Option<String> {
+    let counts = facets
+        .iter()
+        .map(|facet| format!("{} ({})", facet.value, facet.count))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match (filters.is_empty(), counts.is_empty()) {
+        (true, true) => None,
+        (true, false) => Some(counts),
+        (false, _) => Some(format!("[{}] {}", filters.join(" "), counts)),
+    }
+}
+
+fn run_collection_open(matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let name = matches.get_one::<String>("NAME").expect("name is required");
+    let collection = pkm.collection(name)?;
+    if !collection.exists() {
+        return Err(Error::NotFound(format!(
+            "collection {} does not exist",
+            name
+        )));
+    }
+
+    let mut editor = Editor::new_from_env("EDITOR", pkm.root.as_path());
+    for id in collection.ids() {
+        match pkm.find_by_id(&id).into_iter().next() {
+            Some(path) => editor = editor.file(path),
+            None => log::warn!("{} in collection {} does not exist", id, name),
+        }
+    }
+
+    editor.exec()?;
+    Ok(())
+}
+
+async fn run_favorites(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let mut favorites = PathBuf::from(pkm.root.as_path());
+    favorites.push(FAVORITES_FILE);
+    let fcontent = fs::read_to_string(favorites.as_path())?;
+
+    let opts = ParseOptions::gfm();
+    let ast = markdown::to_mdast(&fcontent, &opts)?;
+    let table = first_node!(&ast, Node::Table).ok_or(Error::NotFound(String::from(
+        "could not find table in favorites",
+    )))?;
+
+    let mut iter = table.children.iter();
+    iter.next()
+        .ok_or(Error::NotFound(String::from("favorite expected a header")))?; // drop the header
+
+    let mut lsp = match pkm.lsp().await {
+        Ok(lsp) => lsp,
+        Err(err) => {
+            log::warn!(
+                "could not start the LSP, resolving favorites without it: {}",
+                err
+            );
+            return run_favorites_without_lsp(pkm);
+        }
+    };
+
+    let mut finder = pkm.finder()?;
+    for row in iter {
+        if let Node::TableRow(row) = row {
+            let zettel = first_within_child!(0, row, Node::Text).ok_or(Error::NotFound(
+                String::from("could not get zettel from favorites"),
+            ))?;
+
+            let line = zettel.position.as_ref().unwrap().start.line as u32 - 1;
+            let column = zettel.position.as_ref().unwrap().start.column as u32 - 1;
+
+            if let Ok(resp) = lsp.goto_defintion(favorites.as_path(), line, column).await {
+                // the hover of the link renders the linked note
+                let hover = match lsp.hover(favorites.as_path(), line, column).await {
+                    Ok(hover) => hover.map(hover_text),
+                    Err(err) => {
+                        log::debug!("could not hover favorite: {}", err);
+                        None
+                    }
+                };
+
+                match resp {
+                    Scalar(location) => {
+                        finder.add_fq_doc_with_preview(location.uri, hover.as_deref())?
+                    }
+                    Array(locations) => {
+                        for location in locations {
+                            finder.add_fq_doc(location.uri)?;
+                        }
+                    }
+                    Link(_) => (),
+                }
+            }
+        }
+    }
+
+    lsp.close().await?;
+    finder.run()?;
+
+    Ok(())
+}
+
+// run_favorites_without_lsp resolves the favorites by their filename instead
+// of asking the LSP
+fn run_favorites_without_lsp(pkm: &PKM) -> Result<()> {
+    let mut finder = pkm.finder()?;
+    for id in Favorites::open(&pkm.root)?.ids()? {
+        let paths = pkm.find_by_id(&id);
+        if paths.is_empty() {
+            log::warn!("favorite {} does not exist", id);
+        }
+
+        for path in paths {
+            finder.add_doc(path.strip_prefix(&pkm.root)?)?;
+        }
+    }
+
+    finder.run()?;
+    Ok(())
+}
+
+// favorite_id strips the brackets off an id given as a wikilink
+fn favorite_id(args: &ArgMatches) -> &str {
+    let id = args.get_one::<String>("ID").expect("required field");
+    id.trim_start_matches("[[").trim_end_matches("]]")
+}
+
+fn run_favorites_add(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = favorite_id(args);
+    if pkm.find_by_id(id).is_empty() {
+        return Err(Error::NotFound(format!("{} is not a valid id", id)));
+    }
+
+    let mut favorites = Favorites::open(&pkm.root)?;
+    if !favorites.add(id)? {
+        log::warn!("{} is already a favorite", id);
+        return Ok(());
+    }
+    favorites.save()
+}
+
+fn run_favorites_remove(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = favorite_id(args);
+    let mut favorites = Favorites::open(&pkm.root)?;
+    if !favorites.remove(id)? {
+        return Err(Error::NotFound(format!("{} is not a favorite", id)));
+    }
+    favorites.save()
+}
+
+fn run_favorites_list(pkm: &PKM) -> Result<()> {
+    let ids = Favorites::open(&pkm.root)?.ids()?;
+    if pkm.json {
+        return print_json(Value::from(
+            ids.iter()
+                .map(|id| json!({ "id": id }))
+                .collect::<Vec<Value>>(),
+        ));
+    }
+
+    for id in ids {
+        println!("{}", id);
+    }
+    Ok(())
+}
+
+async fn run_serve(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let edit = args.get_flag("EDIT");
+    let server = Server::new(pkm).edit(edit);
+    // the token is the only thing keeping other pages and machines from
+    // writing to the notes, it changes with every run
+    if edit {
+        println!("open /?token={} to sign in for editing", server.token());
+    }
+    server
+        .listen(args.get_one::<String>("LISTEN").expect("defaulted"))
+        .await
+}
 
-    let mut command = String::from("./scripts/");
-    command.push_str(arguments.next().expect("required"));
+fn run_template_list(pkm: &PKM) -> Result<()> {
+    let templates = templates(&pkm.tmpl)?;
+    if pkm.json {
+        return print_json(Value::from(
+            templates
+                .iter()
+                .map(|template| {
+                    json!({
+                        "name": template.name,
+                        "path": template.path,
+                        "variables": template.variables,
+                    })
+                })
+                .collect::<Vec<Value>>(),
+        ));
+    }
 
-    std::process::Command::new(&command)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .current_dir(pkm.root.as_path())
-        .args(arguments)
-        .status()?;
+    for template in templates {
+        println!("{}\t{}", template.name, template.variables.join(", "));
+    }
 
     Ok(())
 }
 
-// run_index creates/updates the index
-fn run_index(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let index = ZettelIndex::new(pkm.root.as_path())?;
-    let mut writer = index.doc_indexer()?;
+fn run_template_show(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let name = args.get_one::<String>("NAME").expect("required");
+    let template = TemplateInfo::from_tera(&pkm.tmpl, name)?;
 
-    // TODO: be smarter
-    writer.clear()?;
+    match template.path.as_ref() {
+        Some(path) => println!("{} ({})", template.name, path.to_string_lossy()),
+        None => println!("{} (built in)", template.name),
+    }
+    println!("variables: {}\n", template.variables.join(", "));
+    print!("{}", template.source);
 
-    for doc in WalkDir::new(pkm.root.as_path()) {
-        let doc = match doc {
-            Err(err) => {
-                error!("issue indexing {}", err);
+    Ok(())
+}
+
+fn run_template_new(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let path = pkm.new_template(args.get_one::<String>("NAME").expect("required"))?;
+
+    if args.get_flag("NO_EDIT") {
+        if pkm.json {
+            return print_json(json!({ "path": path }));
+        }
+        println!("{}", path.to_string_lossy());
+    } else {
+        Editor::new_from_env("EDITOR", pkm.root.as_path())
+            .file(path.strip_prefix(&pkm.root).unwrap_or(&path))
+            .exec()?;
+    }
+
+    Ok(())
+}
+
+// run_bundle writes the note and what it links to into the directory or zip
+fn run_bundle(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = args.get_one::<String>("ZTL").expect("required");
+    let depth = *args.get_one::<usize>("DEPTH").expect("defaulted");
+    let out = args.get_one::<String>("OUT").expect("required");
+
+    let bundle = Bundle::collect(
+        pkm,
+        id.trim_start_matches("[[").trim_end_matches("]]"),
+        depth,
+    )?;
+    if out.ends_with(".zip") {
+        bundle.write_zip(out)?;
+    } else {
+        bundle.write(out)?;
+    }
+
+    if pkm.json {
+        return print_json(json!({
+            "out": out,
+            "notes": bundle.notes.keys().collect::<Vec<_>>(),
+            "images": bundle.images.len(),
+        }));
+    }
+
+    println!(
+        "bundled {} notes and {} images into {}",
+        bundle.notes.len(),
+        bundle.images.len(),
+        out
+    );
+    Ok(())
+}
+
+// run_project_link creates or updates the project note of the code repo and
+// prints its path
+fn run_project_link(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let dir = args.get_one::<String>("GIT_DIR").expect("defaulted");
+    let project = pkm.link_project(dir)?;
+    print_zettel(pkm, &project)
+}
+
+// run_metrics serves the metrics, writes them to the textfile or prints them
+async fn run_metrics(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    if let Some(addr) = args.get_one::<String>("LISTEN") {
+        return Server::new(pkm).metrics_only(true).listen(addr).await;
+    }
+
+    if let Some(path) = args.get_one::<String>("TEXTFILE") {
+        return write_textfile(pkm, path);
+    }
+
+    print!("{}", VaultMetrics::collect(pkm)?.prometheus());
+    Ok(())
+}
+
+// parse_age parses a short age like 12h, 14d or 2w into a duration
+fn parse_age(age: &str) -> Result<Duration> {
+    let invalid = || Error::PKMError(format!("invalid age {:?}, expected 12h, 14d or 2w", age));
+    // the unit is split at its char, an age may end in any character
+    let (at, _) = age.char_indices().last().ok_or_else(invalid)?;
+    let (count, unit) = age.split_at(at);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+
+    let hours = match unit {
+        "h" => Some(count),
+        "d" => count.checked_mul(24),
+        "w" => count.checked_mul(24 * 7),
+        _ => return Err(invalid()),
+    };
+
+    hours
+        .and_then(|hours| hours.checked_mul(60 * 60))
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+const MENTION_LINK: &str = "link";
+const MENTION_SKIP: &str = "skip";
+const MENTION_QUIT: &str = "quit";
+
+// run_mentions finds the unlinked mentions of the title of the zettel in the
+// other notes and offers to turn each one into a link
+fn run_mentions(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let id = args.get_one::<String>("ZTL").expect("required field");
+    let list = args.get_flag("LIST");
+
+    let path = match pkm.find_by_id(id).into_iter().next() {
+        Some(path) => path,
+        None => return Err(Error::NotFound(format!("{} is not a valid id", id))),
+    };
+    let ztl = Zettel::new(&path)?;
+    let ztl_id = ztl.id();
+
+    let h1 = ztl.title()?;
+    let mut terms: Vec<&str> = Vec::new();
+    terms.extend(h1.as_deref());
+    terms.extend(ztl_id.title().ok());
+    terms.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+
+    for note in pkm.notes().filter(|note| *note != path) {
+        let content = read_to_string(&note)?;
+        let mentions = unlinked_mentions(&content, &terms);
+        if mentions.is_empty() {
+            continue;
+        }
+
+        let rel = note.strip_prefix(&pkm.root)?.to_string_lossy().to_string();
+        let mut accepted = Vec::new();
+        for mention in mentions {
+            println!("{}:{}: {}", rel, mention.line, mention.context);
+            if list {
                 continue;
             }
-            Ok(v) => v,
-        };
 
-        if doc.path().extension() != Some(OsStr::new("md")) {
-            continue;
+            match Select::new(
+                "link this mention?",
+                vec![MENTION_LINK, MENTION_SKIP, MENTION_QUIT],
+            )
+            .prompt()?
+            {
+                MENTION_LINK => accepted.push(mention),
+                MENTION_QUIT => {
+                    return write_mentions(pkm, &note, &content, &ztl_id, &accepted);
+                }
+                _ => (),
+            }
         }
 
-        let id = path_to_id(doc.path());
-        writer.process(&id, doc.path()).unwrap_or_else(|err| {
-            error!("could not index document {}", err);
-            ()
-        });
+        write_mentions(pkm, &note, &content, &ztl_id, &accepted)?;
     }
 
-    writer.commit()?;
+    Ok(())
+}
+
+fn write_mentions(
+    pkm: &PKM,
+    note: &Path,
+    content: &str,
+    id: &ZettelID,
+    mentions: &[Mention],
+) -> Result<()> {
+    if mentions.is_empty() {
+        return Ok(());
+    }
 
+    fs::write(note, link_mentions(content, id, mentions))?;
+    pkm.record("linked mentions of", id)?;
     Ok(())
 }
 
-fn run_search(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
+// run_tag_list prints the tags in the index with how many notes have them
+fn run_tag_list(pkm: &PKM) -> Result<()> {
     let index = ZettelIndex::new(pkm.root.as_path())?;
-    loop {
-        let query = Text::new(" >").with_placeholder("Query").prompt()?;
-        let docs = match index.doc_searcher()?.find(&query) {
-            Ok(v) => v,
-            Err(err) => {
-                error!("oops: {}", err);
-                continue;
-            }
-        };
+    let tags = index.doc_searcher()?.tags()?;
+    if pkm.json {
+        return print_json(Value::from(
+            tags.iter()
+                .map(|tag| json!({ "tag": tag.value, "count": tag.count }))
+                .collect::<Vec<Value>>(),
+        ));
+    }
 
-        let mut finder = Finder::new(pkm.root.as_path());
-        for doc in docs {
-            let mut full_path = PathBuf::from(pkm.root.as_path());
-            full_path.push(doc.get("uri").expect("schema should have uri"));
+    for tag in tags {
+        println!("{} ({})", tag.value, tag.count);
+    }
 
-            let content = read_to_string(&full_path)?;
+    Ok(())
+}
 
-            finder.add(
-                FinderItem::new(doc.get("uri").expect("schema should have uri"))
-                    .with_display(doc.get("title"))
-                    .with_syntax_preview(&content, Some("md"), None)?,
-            )?;
+// run_tag_rename renames the tag across the repo
+fn run_tag_rename(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let old = args.get_one::<String>("OLD").expect("required");
+    let new = args.get_one::<String>("NEW").expect("required");
+    let changed = pkm.rename_tag(old, new)?;
+    reindex_changed(pkm, &changed)
+}
+
+// run_tag_merge renames every tag to the tag they are merged into
+fn run_tag_merge(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let into = args.get_one::<String>("INTO").expect("required");
+    let mut changed = Vec::new();
+    for tag in args
+        .get_many::<String>("TAGS")
+        .expect("required")
+        .filter(|tag| *tag != into)
+    {
+        changed.extend(pkm.rename_tag(tag, into)?);
+    }
+    reindex_changed(pkm, &changed)
+}
+
+// reindex_changed prints the notes that changed and updates them within the
+// index, notes changed more than once are indexed at their last path
+fn reindex_changed(pkm: &PKM, changed: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let mut removed = Vec::new();
+    let mut notes = Vec::new();
+    for (old, new) in changed {
+        println!("{}", new.strip_prefix(&pkm.root)?.to_string_lossy());
+        removed.push(old.clone());
+        notes.push(new.clone());
+    }
+    reindex(pkm, &removed, &notes)
+}
+
+// reindex drops the notes that were at the removed paths from the index and
+// indexes the notes again, so the index keeps up without a rebuild. A note
+// that can't be indexed is logged and skipped, the trash is never indexed.
+fn reindex(pkm: &PKM, removed: &[PathBuf], notes: &[PathBuf]) -> Result<()> {
+    pkm.hook(Hook::PreIndex, &pkm.root)?;
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let mut writer = index.doc_indexer()?;
+    for path in removed {
+        writer.remove(path)?;
+    }
+
+    let trash = pkm.root.join(pkm::TRASH_DIR);
+    for note in notes
+        .iter()
+        .filter(|note| note.is_file() && !note.starts_with(&trash))
+    {
+        writer
+            .update(&path_to_id(note), note)
+            .unwrap_or_else(|err| error!("could not index document {}", err));
+    }
+    writer.commit()
+}
+
+// run_mv moves the note into the directory, or to the path when it ends in
+// .md, and indexes the moved note and the notes linking to it again
+fn run_mv(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let ztl = match pick_note(pkm, ztl)? {
+        Some(path) => Zettel::new(path)?,
+        None => return Ok(()),
+    };
+
+    let dest = pkm
+        .root
+        .join(args.get_one::<String>("DEST").expect("required"));
+    let to = match dest.extension() {
+        Some(ext) if ext == "md" && !dest.is_dir() => dest,
+        _ => dest.join(ztl.id().filename()),
+    };
+    if !absolute(&to)?.starts_with(absolute(&pkm.root)?) {
+        return Err(Error::PKMError(format!(
+            "{} is outside of the repo",
+            to.to_string_lossy()
+        )));
+    }
+
+    let old = ztl.path().to_path_buf();
+    let (moved, mut rewritten) = pkm.relocate_zettel(ztl, &to)?;
+    pkm.record("moved", &moved.id())?;
+    println!("{}", moved.rel_path(&pkm.root)?.to_string_lossy());
+
+    rewritten.push(moved.path().to_path_buf());
+    reindex(pkm, &[old], &rewritten)
+}
+
+// run_rm trashes the note, or deletes it for good with --hard after warning
+// about the notes still linking to it. Either way it is dropped from the
+// index.
+fn run_rm(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztl = args.get_one::<String>("ZTL").expect("required");
+    let ztl = match pick_note(pkm, ztl)? {
+        Some(path) => Zettel::new(path)?,
+        None => return Ok(()),
+    };
+    let path = ztl.path().to_path_buf();
+
+    if args.get_flag("HARD") {
+        let id = ztl.id();
+        if !confirm_delete(pkm, &ztl)? {
+            return Ok(());
         }
 
-        if finder.run()? {
-            break;
+        ztl.delete()?;
+        pkm.record("deleted", &id)?;
+    } else {
+        for trashed in pkm.trash_zettel(ztl, args.get_flag("IMAGES"))? {
+            println!("{}", trashed.strip_prefix(&pkm.root)?.to_string_lossy());
+        }
+    }
+
+    reindex(pkm, &[path], &[])
+}
+
+// confirm_delete warns about the notes still linking to the zettel and asks
+// whether to delete it anyway, it is true when nothing links to it
+fn confirm_delete(pkm: &PKM, ztl: &Zettel) -> Result<bool> {
+    let id = ztl.id();
+    let backlinks: Vec<PathBuf> = pkm
+        .backlinks(&id)?
+        .into_iter()
+        .filter(|note| note != ztl.path())
+        .collect();
+    if backlinks.is_empty() {
+        return Ok(true);
+    }
+
+    for note in backlinks.iter() {
+        log::warn!(
+            "{} links to {}",
+            note.strip_prefix(&pkm.root)?.to_string_lossy(),
+            id
+        );
+    }
+
+    Ok(Confirm::new(&format!(
+        "{} notes link to {}, delete it anyway?",
+        backlinks.len(),
+        id
+    ))
+    .with_default(false)
+    .prompt()?)
+}
+
+// run_trash_list prints the files within the trash
+fn run_trash_list(pkm: &PKM) -> Result<()> {
+    if pkm.json {
+        let mut trashed = Vec::new();
+        for path in pkm.trashed() {
+            trashed.push(json!({ "uri": path.strip_prefix(&pkm.root)? }));
         }
+        return print_json(Value::from(trashed));
+    }
+
+    for trashed in pkm.trashed() {
+        println!("{}", trashed.strip_prefix(&pkm.root)?.to_string_lossy());
     }
 
     Ok(())
 }
 
-async fn run_favorites(_matches: &ArgMatches, pkm: &PKM) -> Result<()> {
-    let mut favorites = PathBuf::from(pkm.root.as_path());
-    favorites.push("favorites.md");
-    let fcontent = fs::read_to_string(favorites.as_path())?;
+// run_trash_restore restores the file, or the files picked from the trash,
+// and indexes the restored notes again
+fn run_trash_restore(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let paths = match args.get_one::<String>("PATH") {
+        Some(path) => vec![match PathBuf::from(path) {
+            path if path.is_file() => absolute(path)?,
+            _ => pkm.root.join(pkm::TRASH_DIR).join(path),
+        }],
+        None => {
+            let mut finder = pkm.finder()?;
+            for trashed in pkm.trashed() {
+                finder.add_doc(trashed.strip_prefix(&pkm.root)?)?;
+            }
 
-    let opts = ParseOptions::gfm();
-    let ast = markdown::to_mdast(&fcontent, &opts)?;
-    let table = first_node!(&ast, Node::Table).ok_or(Error::NotFound(String::from(
-        "could not find table in favorites",
-    )))?;
+            match finder.select() {
+                Some(output) if !output.is_abort => output
+                    .selected_items
+                    .iter()
+                    .map(|item| pkm.root.join(item.text().as_ref()))
+                    .collect(),
+                _ => return Ok(()),
+            }
+        }
+    };
 
-    let mut iter = table.children.iter();
-    iter.next()
-        .ok_or(Error::NotFound(String::from("favorite expected a header")))?; // drop the header
+    let index = ZettelIndex::new(pkm.root.as_path())?;
+    let mut writer = index.doc_indexer()?;
+    for path in paths {
+        let restored = pkm.restore(&path)?;
+        println!("{}", restored.strip_prefix(&pkm.root)?.to_string_lossy());
+
+        if restored.extension() == Some(OsStr::new("md")) {
+            writer
+                .update(&path_to_id(&restored), &restored)
+                .unwrap_or_else(|err| error!("could not index document {}", err));
+        }
+    }
+    writer.commit()
+}
 
-    let mut lsp = pkm.lsp().await?;
+// run_trash_empty deletes everything in the trash after asking for
+// confirmation
+fn run_trash_empty(pkm: &PKM) -> Result<()> {
+    let trashed = pkm.trashed();
+    if trashed.is_empty() {
+        println!("the trash is empty");
+        return Ok(());
+    }
 
-    let mut finder = Finder::new(pkm.root.as_path());
-    for row in iter {
-        if let Node::TableRow(row) = row {
-            let zettel = first_within_child!(0, row, Node::Text).ok_or(Error::NotFound(
-                String::from("could not get zettel from favorites"),
-            ))?;
+    if Confirm::new(&format!(
+        "delete the {} files in the trash for good?",
+        trashed.len()
+    ))
+    .with_default(false)
+    .prompt()?
+    {
+        println!("deleted {} files", pkm.empty_trash()?);
+    }
 
-            if let Ok(resp) = lsp
-                .goto_defintion(
-                    favorites.as_path(),
-                    zettel.position.as_ref().unwrap().start.line as u32 - 1,
-                    zettel.position.as_ref().unwrap().start.column as u32 - 1,
-                )
-                .await
-            {
-                match resp {
-                    Scalar(location) => finder.add_fq_doc(location.uri)?,
-                    Array(locations) => {
-                        for location in locations {
-                            finder.add_fq_doc(location.uri)?;
-                        }
+    Ok(())
+}
+
+// run_archive archives the note, or every zettel older than the age after
+// asking for confirmation
+fn run_archive(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let ztls = match args.get_one::<String>("OLDER_THAN") {
+        Some(age) => pkm.older_zettels(parse_age(age)?)?,
+        None => {
+            let ztl = args.get_one::<String>("ZTL").expect("required");
+            match pick_note(pkm, ztl)? {
+                Some(path) => vec![Zettel::new(path)?],
+                None => return Ok(()),
+            }
+        }
+    };
+
+    if ztls.is_empty() {
+        println!("nothing to archive");
+        return Ok(());
+    }
+
+    if args.contains_id("OLDER_THAN") {
+        for ztl in ztls.iter() {
+            println!("{}", ztl.rel_path(&pkm.root)?.to_string_lossy());
+        }
+
+        if !Confirm::new(&format!("archive these {} zettels?", ztls.len()))
+            .with_default(false)
+            .prompt()?
+        {
+            return Ok(());
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut notes = Vec::new();
+    for ztl in ztls {
+        removed.push(ztl.path().to_path_buf());
+        let (archived, rewritten) = pkm.archive_zettel(ztl)?;
+        println!("{}", archived.rel_path(&pkm.root)?.to_string_lossy());
+        notes.push(archived.path().to_path_buf());
+        notes.extend(rewritten);
+    }
+
+    reindex(pkm, &removed, &notes)
+}
+
+const SWEEP_PROMOTE: &str = "promote to a zettel";
+const SWEEP_MERGE: &str = "merge into another zettel";
+const SWEEP_ARCHIVE: &str = "archive";
+const SWEEP_DELETE: &str = "delete";
+const SWEEP_SKIP: &str = "skip";
+
+// run_fleeting_sweep walks through the stale fleeting notes asking what to do
+// with each of them. Whatever is done is kept up in the index, deleted notes
+// go to the trash.
+fn run_fleeting_sweep(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let max_age = parse_age(args.get_one::<String>("OLDER_THAN").expect("defaulted"))?;
+
+    for ztl in pkm.fleeting(max_age)? {
+        println!(
+            "\n{}\n{}",
+            ztl.rel_path(&pkm.root)?.to_string_lossy(),
+            ztl.content().as_deref().unwrap_or("")
+        );
+
+        let choice = Select::new(
+            "what should happen to this note?",
+            vec![
+                SWEEP_PROMOTE,
+                SWEEP_MERGE,
+                SWEEP_ARCHIVE,
+                SWEEP_DELETE,
+                SWEEP_SKIP,
+            ],
+        )
+        .prompt()?;
+
+        let old = ztl.path().to_path_buf();
+        match choice {
+            SWEEP_PROMOTE => {
+                let (promoted, mut rewritten) = pkm.promote_zettel(ztl)?;
+                rewritten.push(promoted.path().to_path_buf());
+                reindex(pkm, &[old], &rewritten)?;
+            }
+            SWEEP_MERGE => {
+                let mut finder = pkm.finder()?;
+                for other in pkm.zettels()? {
+                    if other.path() != ztl.path() {
+                        finder.add_doc(other.rel_path(&pkm.root)?)?;
                     }
-                    Link(_) => (),
+                }
+
+                if let Some(item) = finder.select_one() {
+                    let mut into = pkm.root.clone();
+                    into.push(item.text().as_ref());
+                    let (merged, mut rewritten) = pkm.merge_zettel(ztl, Zettel::new(into)?)?;
+                    rewritten.push(merged.path().to_path_buf());
+                    reindex(pkm, &[old], &rewritten)?;
                 }
             }
+            SWEEP_ARCHIVE => {
+                let (archived, mut rewritten) = pkm.archive_zettel(ztl)?;
+                rewritten.push(archived.path().to_path_buf());
+                reindex(pkm, &[old], &rewritten)?;
+            }
+            SWEEP_DELETE => {
+                if confirm_delete(pkm, &ztl)? {
+                    pkm.trash_zettel(ztl, false)?;
+                    reindex(pkm, &[old], &[])?;
+                }
+            }
+            _ => (),
         }
     }
 
-    finder.run()?;
+    Ok(())
+}
+
+// run_status prints an overview of the repo
+fn run_status(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let zettels = pkm.zettels()?;
+    let fleeting = zettels
+        .iter()
+        .filter(|ztl| ztl.id().has_tag(FLEETING_TAG))
+        .count();
+
+    // the areas come from the index, the overview is still worth printing
+    // without one
+    let areas = match ZettelIndex::new(pkm.root.as_path())
+        .and_then(|index| index.doc_searcher()?.areas())
+    {
+        Ok(areas) => areas,
+        Err(err) => {
+            log::warn!("could not count the notes by area: {}", err);
+            Vec::new()
+        }
+    };
+
+    if pkm.json {
+        print_json(json!({
+            "repo": pkm.root,
+            "zettels": zettels.len(),
+            "fleeting": fleeting,
+            "areas": areas
+                .iter()
+                .map(|area| json!({ "dir": area.value, "count": area.count }))
+                .collect::<Vec<Value>>(),
+        }))?;
+    } else {
+        println!("repo: {}", pkm.root.to_string_lossy());
+        println!("zettels: {}", zettels.len());
+        println!("fleeting: {}", fleeting);
+        for area in &areas {
+            println!("notes in {}: {}", area.value, area.count);
+        }
+    }
+
+    if args.get_flag("SWEEP") {
+        let max_age = parse_age(args.get_one::<String>("OLDER_THAN").expect("defaulted"))?;
+        let stale = pkm.fleeting(max_age)?;
+        if !stale.is_empty() {
+            log::warn!(
+                "{} fleeting notes are due for a sweep, run `pkm fleeting sweep`",
+                stale.len()
+            );
+        }
+    }
 
     Ok(())
 }
 
+const FIX_FROM_H1: &str = "rename the file from the H1";
+const FIX_FROM_ID: &str = "rewrite the H1 from the id";
+const FIX_SKIP: &str = "skip";
+
+// run_doctor checks the zettels for problems, fixing them when requested
+fn run_doctor(args: &ArgMatches, pkm: &PKM) -> Result<()> {
+    let fix = args.get_flag("FIX_TITLES");
+    let bulk = if args.get_flag("FROM_H1") {
+        Some(FIX_FROM_H1)
+    } else if args.get_flag("FROM_ID") {
+        Some(FIX_FROM_ID)
+    } else {
+        None
+    };
+
+    let mut problems = Vec::new();
+    let (mut removed, mut renamed) = (Vec::new(), Vec::new());
+    for path in pkm.notes().filter(|note| note.starts_with(&pkm.zettel_dir)) {
+        let ztl = Zettel::new(&path)?;
+        let id = ztl.id();
+        let current = id.title().unwrap_or(&id);
+
+        let title = match ztl.title()? {
+            Some(title) => title,
+            None if pkm.json => {
+                problems.push(json!({
+                    "uri": ztl.rel_path(&pkm.root)?,
+                    "problem": "missing_h1",
+                }));
+                continue;
+            }
+            None => {
+                println!("{}: missing H1", ztl.rel_path(&pkm.root)?.to_string_lossy());
+                continue;
+            }
+        };
+
+        // the H1 is compared the way it would be written in an id, an H1
+        // like Client/Server can't be in a filename as is
+        if id_title(title.trim()) == current {
+            continue;
+        }
+
+        if pkm.json {
+            problems.push(json!({
+                "uri": ztl.rel_path(&pkm.root)?,
+                "problem": "title_mismatch",
+                "title": title,
+                "id_title": current,
+            }));
+        } else {
+            println!(
+                "{}: H1 \"{}\" does not match id title \"{}\"",
+                ztl.rel_path(&pkm.root)?.to_string_lossy(),
+                title,
+                current
+            );
+        }
+
+        if !fix {
+            continue;
+        }
+
+        let choice = match bulk {
+            Some(choice) => choice,
+            None => Select::new("fix with", vec![FIX_FROM_H1, FIX_FROM_ID, FIX_SKIP]).prompt()?,
+        };
+
+        match choice {
+            FIX_FROM_H1 => {
+                let new_id: ZettelID = id.with_title(title.trim())?;
+                let (ztl, rewritten) = pkm.rename_zettel(ztl, &new_id)?;
+                removed.push(path);
+                renamed.push(ztl.path().to_path_buf());
+                renamed.extend(rewritten);
+            }
+            FIX_FROM_ID => {
+                let current = current.to_string();
+                ztl.set_title(&current)?;
+                pkm.record("retitled", &id)?;
+            }
+            _ => (),
+        }
+    }
+
+    if pkm.json {
+        print_json(Value::from(problems))?;
+    }
+
+    if removed.is_empty() {
+        return Ok(());
+    }
+    reindex(pkm, &removed, &renamed)
+}
+
 fn repo_from_reference(refer: Option<&str>) -> Option<PathBuf> {
     let refer = if let Some(refer) = refer {
         refer
@@ -557,3 +3872,28 @@ fn repo_from_reference(refer: Option<&str>) -> Option<PathBuf> {
 
     return None;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_age_units() {
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(
+            parse_age("14d").unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_age("2w").unwrap(),
+            Duration::from_secs(2 * 7 * 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn parse_age_rejects_invalid() {
+        for age in ["", "d", "14", "14x", "3€", "-1d", "99999999999999999999w"] {
+            assert!(parse_age(age).is_err(), "{:?} should be invalid", age);
+        }
+    }
+}
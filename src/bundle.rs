@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::{Error, PKM, Result, path_to_id, resolve_local_link, unlink_wikilinks, wikilinks};
+
+// LINK_REGEX matches markdown links and images, [text](path) and
+// ![alt](path), capturing the ! of images, the text and the path
+static LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(!?)\[([^\]]*)\]\(([^)\s]+)\)").expect("must compile"));
+
+// BUNDLE_IMAGE_DIR is the directory within the bundle the images are put in
+pub const BUNDLE_IMAGE_DIR: &str = "imgs";
+
+// Bundle is a note and the notes and images it links to, with wikilinks or
+// markdown links. The images are kept with their file name within the bundle,
+// images sharing a name are numbered like photo-2.png. Nothing outside of the
+// repo is bundled.
+pub struct Bundle {
+    root: PathBuf,
+    pub notes: BTreeMap<String, PathBuf>,
+    pub images: BTreeMap<PathBuf, String>,
+}
+
+impl Bundle {
+    // collect gathers the note and everything it links to up to the depth,
+    // a depth of 0 is only the note itself
+    pub fn collect(pkm: &PKM, id: &str, depth: usize) -> Result<Self> {
+        let mut bundle = Bundle {
+            root: fs::canonicalize(&pkm.root)?,
+            notes: BTreeMap::new(),
+            images: BTreeMap::new(),
+        };
+
+        let mut queue = VecDeque::from([(id.to_string(), 0)]);
+        while let Some((id, level)) = queue.pop_front() {
+            if bundle.notes.contains_key(&id) {
+                continue;
+            }
+
+            let path = match pkm.find_by_id(&id).into_iter().next() {
+                Some(path) => path,
+                None if bundle.notes.is_empty() => {
+                    return Err(Error::NotFound(format!("could not find zettel {}", id)));
+                }
+                None => {
+                    log::warn!("{} is linked but does not exist", id);
+                    continue;
+                }
+            };
+
+            let content = fs::read_to_string(&path)?;
+            for link in LINK_REGEX.captures_iter(&content) {
+                if &link[1] == "!" {
+                    match resolve_image(&bundle.root, &path, &link[3]) {
+                        Some(image) => bundle.add_image(image),
+                        None => log::warn!("could not find image {} in {}", &link[3], id),
+                    }
+                } else if level < depth {
+                    if let Some(note) = resolve_note(&bundle.root, &path, &link[3]) {
+                        queue.push_back((path_to_id(&note), level + 1));
+                    }
+                }
+            }
+
+            if level < depth {
+                for link in wikilinks(&content) {
+                    queue.push_back((link.id, level + 1));
+                }
+            }
+
+            bundle.notes.insert(path_to_id(&path), path);
+        }
+
+        Ok(bundle)
+    }
+
+    // add_image names the image within the bundle, numbering it when another
+    // image already has its name
+    fn add_image(&mut self, image: PathBuf) {
+        if self.images.contains_key(&image) {
+            return;
+        }
+
+        let name = image
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("image"));
+        let mut unique = name.clone();
+        let mut n = 1;
+        while self
+            .images
+            .values()
+            .any(|taken| taken.eq_ignore_ascii_case(&unique))
+        {
+            n += 1;
+            unique = match name.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => format!("{}-{}.{}", stem, n, ext),
+                _ => format!("{}-{}", name, n),
+            };
+        }
+        self.images.insert(image, unique);
+    }
+
+    // rendered_notes are the file names and contents of the notes within the
+    // bundle. Notes are put next to each other so the links between them keep
+    // working, images point into imgs and links leaving the bundle are turned
+    // into plain text.
+    fn rendered_notes(&self) -> Result<Vec<(String, String)>> {
+        let ids: BTreeMap<String, &String> = self
+            .notes
+            .keys()
+            .map(|id| (id.to_lowercase(), id))
+            .collect();
+        let mut notes = Vec::new();
+        for (id, path) in self.notes.iter() {
+            let content = fs::read_to_string(path)?;
+            let content = unlink_wikilinks(&content, |link| ids.contains_key(&link.to_lowercase()));
+            let content = LINK_REGEX.replace_all(&content, |cap: &Captures| {
+                if &cap[1] == "!" {
+                    return match resolve_image(&self.root, path, &cap[3])
+                        .and_then(|image| self.images.get(&image))
+                    {
+                        Some(name) => format!("![{}]({}/{})", &cap[2], BUNDLE_IMAGE_DIR, name),
+                        None => cap[0].to_string(),
+                    };
+                }
+
+                let note = resolve_note(&self.root, path, &cap[3]).map(|note| path_to_id(&note));
+                match note.as_ref().and_then(|note| ids.get(&note.to_lowercase())) {
+                    Some(id) => {
+                        let anchor = cap[3].find('#').map(|idx| &cap[3][idx..]).unwrap_or("");
+                        format!("[{}]({}.md{})", &cap[2], id, anchor)
+                    }
+                    None if note.is_some() => cap[2].to_string(),
+                    None => cap[0].to_string(),
+                }
+            });
+            notes.push((format!("{}.md", id), content.to_string()));
+        }
+        Ok(notes)
+    }
+
+    // write copies the bundle into the directory
+    pub fn write<P: AsRef<Path>>(&self, out: P) -> Result<()> {
+        let out = out.as_ref();
+        fs::create_dir_all(out.join(BUNDLE_IMAGE_DIR))?;
+
+        for (name, content) in self.rendered_notes()? {
+            fs::write(out.join(name), content.as_bytes())?;
+        }
+        for (image, name) in self.images.iter() {
+            fs::copy(image, out.join(BUNDLE_IMAGE_DIR).join(name))?;
+        }
+
+        Ok(())
+    }
+
+    // write_zip writes the bundle into a new zip archive, replacing the file
+    // if it exists. A bundle that fails to write leaves no archive behind.
+    pub fn write_zip<P: AsRef<Path>>(&self, out: P) -> Result<()> {
+        let out = out.as_ref();
+        let result = self.zip(File::create(out)?);
+        if result.is_err() {
+            let _ = fs::remove_file(out);
+        }
+        result
+    }
+
+    fn zip(&self, file: File) -> Result<()> {
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, content) in self.rendered_notes()? {
+            zip.start_file(name, options)?;
+            zip.write_all(content.as_bytes())?;
+        }
+        for (image, name) in self.images.iter() {
+            zip.start_file(format!("{}/{}", BUNDLE_IMAGE_DIR, name), options)?;
+            io::copy(&mut File::open(image)?, &mut zip)?;
+        }
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+// resolve_image finds the image linked from the note, relative to the note
+// and then to the root of the repo. Remote images and files outside of the
+// repo are left alone.
+fn resolve_image(root: &Path, note: &Path, link: &str) -> Option<PathBuf> {
+    resolve_local_link(root, note, link)
+        .filter(|path| path.is_file())
+        .and_then(|path| fs::canonicalize(path).ok())
+        .filter(|path| path.starts_with(root))
+}
+
+// resolve_note finds the note a markdown link points at, like resolve_image
+fn resolve_note(root: &Path, note: &Path, link: &str) -> Option<PathBuf> {
+    resolve_image(root, note, link).filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_sharing_a_name_are_numbered() {
+        let mut bundle = Bundle {
+            root: PathBuf::from("/notes"),
+            notes: BTreeMap::new(),
+            images: BTreeMap::new(),
+        };
+        bundle.add_image(PathBuf::from("/notes/a/photo.png"));
+        bundle.add_image(PathBuf::from("/notes/b/Photo.png"));
+        bundle.add_image(PathBuf::from("/notes/c/photo.png"));
+        bundle.add_image(PathBuf::from("/notes/a/photo.png"));
+
+        assert_eq!(
+            bundle.images.values().collect::<Vec<_>>(),
+            vec!["photo.png", "Photo-2.png", "photo-3.png"]
+        );
+    }
+
+    #[test]
+    fn markdown_links_stay_within_the_bundle() {
+        let dir = std::env::temp_dir().join(format!("pkm-bundle-{}", std::process::id()));
+        let root = dir.join("notes");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.join("secret.png"), "").unwrap();
+        fs::write(root.join("photo.png"), "").unwrap();
+        fs::write(
+            root.join("a.md"),
+            "[b](b.md#part) [c](c.md) [site](https://example.com) ![p](photo.png) ![s](../secret.png)",
+        )
+        .unwrap();
+        fs::write(root.join("b.md"), "").unwrap();
+        fs::write(root.join("c.md"), "").unwrap();
+
+        let root = fs::canonicalize(&root).unwrap();
+        let mut bundle = Bundle {
+            root: root.clone(),
+            notes: BTreeMap::from([
+                (String::from("a"), root.join("a.md")),
+                (String::from("b"), root.join("b.md")),
+            ]),
+            images: BTreeMap::new(),
+        };
+        assert_eq!(
+            resolve_image(&root, &root.join("a.md"), "../secret.png"),
+            None
+        );
+        bundle.add_image(resolve_image(&root, &root.join("a.md"), "photo.png").unwrap());
+
+        let notes = bundle.rendered_notes().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            notes[0],
+            (
+                String::from("a.md"),
+                String::from(
+                    "[b](b.md#part) c [site](https://example.com) ![p](imgs/photo.png) ![s](../secret.png)"
+                )
+            )
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Result, Zettel, ZettelID, wikilinks};
+
+// Collection is a named list of zettels, stored as a markdown list of
+// wikilinks so it can be read and edited like any other note
+pub struct Collection {
+    name: String,
+    ztl: Zettel,
+}
+
+impl Collection {
+    // open opens the collection within the directory, the collection does not
+    // need to exist yet
+    pub fn open<P: AsRef<Path>>(dir: P, name: &str) -> Result<Collection> {
+        let mut path = PathBuf::from(dir.as_ref());
+        path.push(format!("{}.md", name));
+
+        Ok(Collection {
+            name: name.to_string(),
+            ztl: Zettel::new(path)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.ztl.path()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.ztl.content().is_some()
+    }
+
+    // ids returns the ids of the zettels within the collection in order
+    pub fn ids(&self) -> Vec<String> {
+        self.ztl
+            .content()
+            .map(|content| wikilinks(&content).into_iter().map(|l| l.id).collect())
+            .unwrap_or_default()
+    }
+
+    // add adds the zettels to the collection, skipping the ones already in it
+    pub fn add<I>(self, ids: I) -> Result<Collection>
+    where
+        I: IntoIterator<Item = ZettelID>,
+    {
+        let Self { name, mut ztl } = self;
+        let existing: Vec<String> = wikilinks(ztl.content().as_deref().unwrap_or(""))
+            .into_iter()
+            .map(|l| l.id)
+            .collect();
+
+        let mut content = ztl
+            .content()
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| format!("# {}\n", name));
+
+        for id in ids {
+            if existing.iter().any(|e| e.as_str() == id.as_str()) {
+                continue;
+            }
+
+            if !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&format!("- [[{}]]\n", id));
+        }
+
+        ztl.set_content(content);
+        Ok(Collection {
+            name,
+            ztl: ztl.sync()?,
+        })
+    }
+}
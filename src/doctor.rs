@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use markdown::ParseOptions;
+use markdown::mdast::Node;
+
+use crate::{
+    ALIASES_KEY, Frontmatter, PKM, Result, is_local_link, path_to_id, resolve_local_link,
+    wikilink_lines,
+};
+
+// BrokenLink is a link within a note whose target doesn't resolve to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    pub note: PathBuf,
+    pub line: usize,
+    pub target: String,
+}
+
+// TitledNote is a note along with the title it was compared by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitledNote {
+    pub note: PathBuf,
+    pub title: String,
+}
+
+// broken_links checks the wikilinks, markdown links and images of every note,
+// returning the ones that don't resolve to a file
+pub fn broken_links(pkm: &PKM) -> Result<Vec<BrokenLink>> {
+    // wikilinks point at the id or an alias of a note or the name of any
+    // other file
+    let mut targets: HashSet<String> = HashSet::new();
+    for path in pkm.walk() {
+        if let Some(name) = path.file_name() {
+            targets.insert(name.to_string_lossy().to_lowercase());
+        }
+        targets.insert(path_to_id(&path).to_lowercase());
+    }
+    for note in pkm.notes() {
+        let content = fs::read_to_string(&note)?;
+        for alias in Frontmatter::parse(&content).list(ALIASES_KEY) {
+            targets.insert(alias.to_lowercase());
+        }
+    }
+
+    let mut broken = Vec::new();
+    for note in pkm.notes() {
+        let content = fs::read_to_string(&note)?;
+
+        for (line, link) in wikilink_lines(&content) {
+            if !targets.contains(&link.id.to_lowercase()) {
+                broken.push(BrokenLink {
+                    note: note.clone(),
+                    line,
+                    target: link.id,
+                });
+            }
+        }
+
+        let ast = markdown::to_mdast(&content, &ParseOptions::gfm())?;
+        let mut check: Vec<&Node> = vec![&ast];
+        while let Some(node) = check.pop() {
+            if let Some(children) = node.children() {
+                check.extend(children.iter().rev());
+            }
+
+            let (url, position) = match node {
+                Node::Link(link) => (link.url.as_str(), link.position.as_ref()),
+                Node::Image(image) => (image.url.as_str(), image.position.as_ref()),
+                _ => continue,
+            };
+
+            if is_local_link(url) && resolve_local_link(&pkm.root, &note, url).is_none() {
+                broken.push(BrokenLink {
+                    note: note.clone(),
+                    line: position.map(|pos| pos.start.line).unwrap_or(1),
+                    target: url.to_string(),
+                });
+            }
+        }
+    }
+
+    broken.sort_by(|a, b| a.note.cmp(&b.note).then(a.line.cmp(&b.line)));
+    Ok(broken)
+}
+
+// duplicate_titles groups the zettels whose titles are the same or at least
+// similarity alike, from 0 to 1. The title is the H1 of the zettel, or the
+// title of its id when it has none. Only groups with more than one zettel are
+// returned.
+pub fn duplicate_titles(pkm: &PKM, similarity: f64) -> Result<Vec<Vec<TitledNote>>> {
+    let mut groups: Vec<Vec<(TitledNote, String)>> = Vec::new();
+    for ztl in pkm.zettels()? {
+        let title = match ztl.title()? {
+            Some(title) => title,
+            None => ztl.id().title().unwrap_or_default().to_string(),
+        };
+
+        let normalized = normalize_title(&title);
+        if normalized.is_empty() {
+            continue;
+        }
+
+        let note = TitledNote {
+            note: ztl.path().to_path_buf(),
+            title,
+        };
+        match groups.iter_mut().find(|group| {
+            group
+                .iter()
+                .any(|(_, other)| title_similarity(&normalized, other) >= similarity)
+        }) {
+            Some(group) => group.push((note, normalized)),
+            None => groups.push(vec![(note, normalized)]),
+        }
+    }
+
+    let mut duplicates: Vec<Vec<TitledNote>> = groups
+        .into_iter()
+        .filter(|group| group.len() > 1)
+        .map(|group| {
+            let mut notes: Vec<TitledNote> = group.into_iter().map(|(note, _)| note).collect();
+            notes.sort_by(|a, b| a.note.cmp(&b.note));
+            notes
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a[0].note.cmp(&b[0].note));
+    Ok(duplicates)
+}
+
+// normalize_title lowercases the title and keeps only its words, so case and
+// punctuation don't count as a difference
+fn normalize_title(title: &str) -> String {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// title_similarity is 1 minus the edit distance between the titles relative to
+// the longest of the two
+pub(crate) fn title_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
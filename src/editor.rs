@@ -43,6 +43,13 @@ impl Editor {
         self
     }
 
+    // at_line opens the next file at the line, using the +line argument most
+    // terminal editors understand
+    pub fn at_line(mut self, line: u32) -> Self {
+        self.command.arg(format!("+{}", line));
+        self
+    }
+
     pub fn exec(mut self) -> Result<ExitStatus> {
         let status = self.command.status()?;
         Ok(status)
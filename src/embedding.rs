@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::Hash;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha1::{Digest, Sha1};
+use ureq::Agent;
+
+use crate::{Error, Result};
+
+// EMBEDDINGS_FILE is the file, relative to the root directory, the vectors of
+// the notes are kept in next to the search index
+pub const EMBEDDINGS_FILE: &str = ".index.embeddings.json";
+
+// DEFAULT_EMBEDDING_MODEL is the model asked for from an endpoint
+pub const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+// CONNECT_TIMEOUT and REQUEST_TIMEOUT bound the requests to an endpoint, a
+// model may have to be loaded before it answers
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+// SAVE_EVERY is how many notes are embedded between saving the embeddings, an
+// update that fails part way keeps what it embedded
+const SAVE_EVERY: usize = 50;
+
+// RANK_FUSION_K keeps the top hits of one ranking from drowning out the
+// others, 60 is what reciprocal rank fusion usually goes with
+const RANK_FUSION_K: f32 = 60.0;
+
+// Embedder turns text into a vector, texts about the same thing end up close
+// to each other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Embedder {
+    // Endpoint posts the text to an OpenAI compatible embeddings endpoint,
+    // like the one of ollama or llama.cpp
+    Endpoint { url: String, model: String },
+    // Command runs the command with the text on stdin, it prints the vector
+    // as a json array
+    Command(String),
+}
+
+// parse_embedder parses the http(s) url of an embeddings endpoint, anything else
+// is a command
+pub fn parse_embedder(embedder: &str) -> Result<Embedder> {
+    let embedder = embedder.trim();
+    if embedder.is_empty() {
+        return Err(Error::PKMError(String::from(
+            "the embedder is empty, expected an http url or a command",
+        )));
+    }
+
+    if embedder.starts_with("http://") || embedder.starts_with("https://") {
+        return Ok(Embedder::Endpoint {
+            url: embedder.to_string(),
+            model: DEFAULT_EMBEDDING_MODEL.to_string(),
+        });
+    }
+
+    Ok(Embedder::Command(embedder.to_string()))
+}
+
+impl Embedder {
+    // with_model sets the model asked for from an endpoint, commands pick
+    // their own model
+    pub fn with_model<S: Into<String>>(self, model: Option<S>) -> Self {
+        match (self, model) {
+            (Embedder::Endpoint { url, .. }, Some(model)) => Embedder::Endpoint {
+                url,
+                model: model.into(),
+            },
+            (embedder, _) => embedder,
+        }
+    }
+
+    // embed turns the text into its vector
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        match self {
+            Embedder::Endpoint { url, model } => {
+                let body = json!({ "model": model, "input": text }).to_string();
+                let response: Value = serde_json::from_slice(&post_json(url, None, &body)?)?;
+                vector(&response["data"][0]["embedding"])
+            }
+            Embedder::Command(command) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                child
+                    .stdin
+                    .take()
+                    .expect("stdin is piped")
+                    .write_all(text.as_bytes())?;
+
+                let output = child.wait_with_output()?;
+                if !output.status.success() {
+                    return Err(Error::PKMError(format!(
+                        "embedding command {:?} failed with {}",
+                        command, output.status
+                    )));
+                }
+                vector(&serde_json::from_slice(&output.stdout)?)
+            }
+        }
+    }
+}
+
+// vector reads a json array of numbers
+fn vector(value: &Value) -> Result<Vec<f32>> {
+    value
+        .as_array()
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|value| value.as_f64().map(|v| v as f32))
+                .collect::<Option<Vec<f32>>>()
+        })
+        .filter(|vector| !vector.is_empty())
+        .ok_or_else(|| Error::PKMError(String::from("the embedder didn't return a vector")))
+}
+
+// post_json sends the json body to the http or https url and returns the
+// body of the response, the token is sent as a bearer token
+pub fn post_json(url: &str, token: Option<&str>, body: &str) -> Result<Vec<u8>> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .build()
+        .into();
+
+    let mut request = agent.post(url).header("Content-Type", "application/json");
+    if let Some(token) = token {
+        request = request.header("Authorization", &format!("Bearer {}", token));
+    }
+    let mut response = request.send(body)?;
+    Ok(response.body_mut().read_to_vec()?)
+}
+
+// Embeddings are the vectors of the notes, a note is only embedded again when
+// its content changes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Embeddings {
+    // notes are keyed by their path relative to the root directory
+    notes: HashMap<String, NoteEmbedding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoteEmbedding {
+    hash: String,
+    vector: Vec<f32>,
+}
+
+impl Embeddings {
+    // open reads the embeddings of the repo, there are none until the repo is
+    // indexed with embeddings
+    pub fn open(root: &Path) -> Result<Embeddings> {
+        let path = root.join(EMBEDDINGS_FILE);
+        if !path.exists() {
+            return Ok(Embeddings::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, root: &Path) -> Result<()> {
+        fs::write(root.join(EMBEDDINGS_FILE), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    // update embeds the notes that are new or changed since they were last
+    // embedded and drops the notes that are gone, returning how many notes
+    // were embedded. A note that can't be embedded is skipped with a warning
+    // and keeps its previous vector, the embeddings are saved as they go.
+    pub fn update<I>(&mut self, root: &Path, notes: I, embedder: &Embedder) -> Result<usize>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let mut seen = HashSet::new();
+        let mut embedded = 0;
+        for note in notes {
+            let key = note.strip_prefix(root)?.to_string_lossy().to_string();
+            seen.insert(key.clone());
+
+            let content = match fs::read_to_string(&note) {
+                Ok(content) => content,
+                Err(err) => {
+                    log::warn!("could not read {}: {}", key, err);
+                    continue;
+                }
+            };
+            let hash = hex::encode(Sha1::digest(content.as_bytes()));
+            if self.notes.get(&key).is_some_and(|note| note.hash == hash) {
+                continue;
+            }
+
+            match embedder.embed(&content) {
+                Ok(vector) => {
+                    self.notes.insert(key, NoteEmbedding { hash, vector });
+                    embedded += 1;
+                }
+                Err(err) => {
+                    log::warn!("could not embed {}: {}", key, err);
+                    continue;
+                }
+            }
+
+            if embedded % SAVE_EVERY == 0 {
+                self.save(root)?;
+            }
+        }
+
+        self.notes.retain(|key, _| seen.contains(key));
+        Ok(embedded)
+    }
+
+    // nearest returns the paths, relative to the root directory, of the notes
+    // closest to the vector along with their similarity, closest first
+    pub fn nearest(&self, vector: &[f32], limit: usize) -> Vec<(String, f32)> {
+        let mut nearest: Vec<(String, f32)> = self
+            .notes
+            .iter()
+            .map(|(key, note)| (key.clone(), cosine(vector, &note.vector)))
+            .collect();
+        nearest.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        nearest.truncate(limit);
+        nearest
+    }
+}
+
+// cosine is the cosine similarity of the vectors, from -1 to 1
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+// rank_fusion merges the rankings with reciprocal rank fusion, every item
+// scores 1 / (k + rank) for every ranking it is in
+pub fn rank_fusion<T, I>(rankings: I) -> Vec<T>
+where
+    T: Eq + Hash + Clone,
+    I: IntoIterator<Item = Vec<T>>,
+{
+    let mut scores: HashMap<T, f32> = HashMap::new();
+    let mut order: Vec<T> = Vec::new();
+    for ranking in rankings {
+        for (rank, item) in ranking.into_iter().enumerate() {
+            let score = scores.entry(item.clone()).or_insert_with(|| {
+                order.push(item.clone());
+                0.0
+            });
+            *score += 1.0 / (RANK_FUSION_K + rank as f32 + 1.0);
+        }
+    }
+
+    // the sort is stable, ties keep the order they were first seen in
+    order.sort_by(|a, b| scores[b].total_cmp(&scores[a]));
+    order
+}
@@ -36,6 +36,9 @@ pub enum Error {
     #[error("indexing error: {0}")]
     OpenDirectoryError(#[from] tantivy::directory::error::OpenDirectoryError),
 
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+
     #[error("indexing error: {0}")]
     QueryError(#[from] tantivy::query::QueryParserError),
 
@@ -57,12 +60,24 @@ pub enum Error {
     #[error("LSP Runtime Error: {0}")]
     LSPError(#[from] crate::lsp::Error),
 
+    #[error("LSP Server Error: {0}")]
+    LspServerError(#[from] crate::lsp::LspServerError),
+
     #[error("Serialization Error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
     #[error("Image Error: {0}")]
     ImageError(#[from] image::ImageError),
 
+    #[error("HTTP Error: {0}")]
+    HttpError(#[from] ureq::Error),
+
+    #[error("Zip Error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+
+    #[error("Plugin Error: {0}")]
+    PluginError(String),
+
     #[error("unknown data store error")]
     Unknown,
 }
@@ -74,3 +89,30 @@ impl From<markdown::message::Message> for Error {
         Self::MarkdownParserError(value)
     }
 }
+
+// EXIT_FAILURE is the exit code of the errors without a category of their
+// own, EXIT_USAGE is the one clap exits with for invalid arguments
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_NOT_FOUND: u8 = 3;
+pub const EXIT_CONFIG: u8 = 4;
+pub const EXIT_INDEX: u8 = 5;
+pub const EXIT_LSP: u8 = 6;
+
+impl Error {
+    // exit_code is the code pkm exits with on the error, so scripts can tell
+    // a missing note from a broken index or language server
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::NotFound(_) => EXIT_NOT_FOUND,
+            Self::CommandError(_) => EXIT_USAGE,
+            Self::TemplatingError(_) => EXIT_CONFIG,
+            Self::IndexError(_)
+            | Self::OpenDirectoryError(_)
+            | Self::InvalidQuery(_)
+            | Self::QueryError(_) => EXIT_INDEX,
+            Self::LSPError(_) | Self::LspServerError(_) => EXIT_LSP,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
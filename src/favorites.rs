@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use markdown::ParseOptions;
+use markdown::mdast::{Node, Table};
+
+use crate::{Result, first_node, wikilinks};
+
+pub const FAVORITES_FILE: &str = "favorites.md";
+
+// Favorites is the table of favorite zettels kept in favorites.md. The first
+// column of every row links the zettel.
+pub struct Favorites {
+    path: PathBuf,
+    content: String,
+}
+
+impl Favorites {
+    // open reads the favorites of the repo, which don't have to exist yet
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Favorites> {
+        let mut path = PathBuf::from(root.as_ref());
+        path.push(FAVORITES_FILE);
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Favorites { path, content })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // ids returns the ids of the favorites in the order of the table
+    pub fn ids(&self) -> Result<Vec<String>> {
+        Ok(self.rows()?.into_iter().map(|(id, _)| id).collect())
+    }
+
+    // add appends a row for the id to the table, creating the table when
+    // there isn't one. Returns false when the id is already a favorite.
+    pub fn add(&mut self, id: &str) -> Result<bool> {
+        if self.ids()?.iter().any(|fav| fav == id) {
+            return Ok(false);
+        }
+
+        let ast = markdown::to_mdast(&self.content, &ParseOptions::gfm())?;
+        match first_node!(&ast, Node::Table) {
+            Some(table) => {
+                let row = row(id, columns(table));
+                let end = table
+                    .position
+                    .as_ref()
+                    .map(|pos| pos.end.offset)
+                    .unwrap_or(self.content.len());
+                self.content.insert_str(end, &format!("\n{}", row));
+            }
+            None => {
+                if !self.content.is_empty() {
+                    if !self.content.ends_with('\n') {
+                        self.content.push('\n');
+                    }
+                    self.content.push('\n');
+                }
+                self.content
+                    .push_str(&format!("| Favorite |\n| --- |\n{}\n", row(id, 1)));
+            }
+        }
+
+        Ok(true)
+    }
+
+    // remove removes the row of the id from the table. Returns false when the
+    // id wasn't a favorite.
+    pub fn remove(&mut self, id: &str) -> Result<bool> {
+        let range = match self.rows()?.into_iter().find(|(fav, _)| fav == id) {
+            Some((_, range)) => range,
+            None => return Ok(false),
+        };
+
+        // take the line break of the row with it
+        let end = match self.content[range.end..].starts_with('\n') {
+            true => range.end + 1,
+            false => range.end,
+        };
+        self.content.replace_range(range.start..end, "");
+        Ok(true)
+    }
+
+    // save writes the favorites back to disk
+    pub fn save(self) -> Result<()> {
+        fs::write(&self.path, self.content)?;
+        Ok(())
+    }
+
+    // rows returns the id of every row along with the byte range of the row
+    fn rows(&self) -> Result<Vec<(String, std::ops::Range<usize>)>> {
+        let ast = markdown::to_mdast(&self.content, &ParseOptions::gfm())?;
+        let table = match first_node!(&ast, Node::Table) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(table
+            .children
+            .iter()
+            .skip(1) // the header
+            .filter_map(|row| {
+                let cell = row.children()?.first()?.to_string();
+                let id = wikilinks(&cell)
+                    .into_iter()
+                    .next()
+                    .map(|link| link.id)
+                    .unwrap_or_else(|| cell.trim().to_string());
+                let pos = row.position()?;
+                Some((id, pos.start.offset..pos.end.offset))
+            })
+            .collect())
+    }
+}
+
+fn columns(table: &Table) -> usize {
+    table
+        .children
+        .first()
+        .and_then(Node::children)
+        .map(Vec::len)
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn row(id: &str, columns: usize) -> String {
+    format!("| [[{}]] |{}", id, " |".repeat(columns - 1))
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{Datelike, Duration, NaiveDate};
+use convert_case::{Case, Casing};
+use tera::{Function, Tera, Value, to_value, try_get_value};
+
+use crate::{ZettelIndex, slug};
+
+// DATE_FORMATS are the date formats the date filters understand, the result
+// of date_add keeps the format of its input
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%A, %B %d, %Y", "%Y/%m/%d"];
+
+// register_filters adds the filters pkm provides to templates
+//
+//   {{ title | slug }}                 my-title, like the file names of pkm
+//   {{ title | train_case }}           My-Title
+//   {{ daily | date_add(days=-1) }}    the day before, in the same format
+//   {{ daily | weekday }}              Monday
+//   {{ daily | iso_week }}             2024-W05
+pub fn register_filters(tera: &mut Tera) {
+    // tera's own slugify is left as it is, templates written for it keep
+    // working
+    tera.register_filter("slug", slug_filter);
+    tera.register_filter("train_case", train_case);
+    tera.register_filter("date_add", date_add);
+    tera.register_filter("weekday", weekday);
+    tera.register_filter("iso_week", iso_week);
+}
+
+// register_functions adds the functions pkm provides to templates
+//
+//   {% for note in search(query="standup", facets=["kind:meeting"], limit=5) %}
+//   - [[{{ note.id }}]] {{ note.title }}
+//   {% endfor %}
+pub fn register_functions<P: Into<PathBuf>>(tera: &mut Tera, root: P) {
+    tera.register_function("search", SearchFunction { root: root.into() });
+}
+
+// SearchFunction searches the index of the repo when the template renders.
+// Every hit has the id, title, uri and content of the note.
+struct SearchFunction {
+    root: PathBuf,
+}
+
+impl Function for SearchFunction {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let query = match args.get("query") {
+            Some(query) => query
+                .as_str()
+                .ok_or_else(|| tera::Error::msg("search: query must be a string"))?,
+            None => "",
+        };
+        // the index can't collect the top 0 hits
+        let limit = match args.get("limit") {
+            Some(limit) => limit
+                .as_u64()
+                .filter(|limit| *limit > 0)
+                .ok_or_else(|| tera::Error::msg("search: limit must be a positive number"))?,
+            None => 10,
+        };
+        let facets: Vec<String> = match args.get("facets") {
+            Some(Value::String(facet)) => vec![facet.clone()],
+            Some(Value::Array(facets)) => facets
+                .iter()
+                .filter_map(|facet| facet.as_str().map(String::from))
+                .collect(),
+            Some(_) => return Err(tera::Error::msg("search: facets must be strings")),
+            None => Vec::new(),
+        };
+
+        let search = || -> crate::Result<_> {
+            let index = ZettelIndex::new(self.root.as_path())?;
+            let results = index
+                .doc_searcher()?
+                .limit(limit as usize)
+                .search(query, &facets)?;
+            Ok(results.docs)
+        };
+        let docs = search().map_err(|err| tera::Error::msg(format!("search: {}", err)))?;
+
+        Ok(to_value(docs)?)
+    }
+
+    // the index changes between renders
+    fn is_safe(&self) -> bool {
+        false
+    }
+}
+
+fn slug_filter(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("slug", "value", String, value);
+    Ok(to_value(slug(&s))?)
+}
+
+fn train_case(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("train_case", "value", String, value);
+    Ok(to_value(s.to_case(Case::Train))?)
+}
+
+fn date_add(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("date_add", "value", String, value);
+    let (date, format) = parse_date("date_add", &s)?;
+
+    let arg = |name: &str| -> tera::Result<i64> {
+        match args.get(name) {
+            Some(v) => v.as_i64().ok_or_else(|| {
+                tera::Error::msg(format!("date_add: {} must be a whole number", name))
+            }),
+            None => Ok(0),
+        }
+    };
+    let date = date + Duration::days(arg("days")? + arg("weeks")? * 7);
+
+    Ok(to_value(date.format(format).to_string())?)
+}
+
+fn weekday(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("weekday", "value", String, value);
+    let (date, _) = parse_date("weekday", &s)?;
+    Ok(to_value(date.format("%A").to_string())?)
+}
+
+fn iso_week(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("iso_week", "value", String, value);
+    let (date, _) = parse_date("iso_week", &s)?;
+    let week = date.iso_week();
+    Ok(to_value(format!("{}-W{:02}", week.year(), week.week()))?)
+}
+
+// parse_date parses the date in any of the known formats, returning the
+// format it was in
+fn parse_date(filter: &str, s: &str) -> tera::Result<(NaiveDate, &'static str)> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| {
+            NaiveDate::parse_from_str(s.trim(), format)
+                .ok()
+                .map(|date| (date, *format))
+        })
+        .ok_or_else(|| {
+            tera::Error::msg(format!("{}: {:?} is not a date like 2024-05-01", filter, s))
+        })
+}
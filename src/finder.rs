@@ -1,41 +1,282 @@
-use crate::{Editor, Error, Highlighting, Result, first_node};
+use crate::{
+    Editor, Error, Highlighting, NoteReader, Result, Zettel, expand_embeds, first_node, path_to_id,
+    wikilinks,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
 use std::{
     borrow::Cow,
-    fs::read_to_string,
+    fs::{OpenOptions, read_to_string},
+    io::Write,
     path::{Path, PathBuf},
-    sync::Arc,
+    str::FromStr,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use crossbeam_channel::{Receiver, Sender, unbounded};
+use crossbeam_channel::unbounded;
 use lsp_types::Uri;
 use markdown::{ParseOptions, mdast::Node};
-use skim::{ItemPreview, Skim, SkimItem, SkimOptions, SkimOutput, prelude::SkimOptionsBuilder};
+use regex::Regex;
+use skim::{
+    ItemPreview, Skim, SkimItem, SkimItemReceiver, SkimOptions, SkimOutput,
+    prelude::{Key, SkimOptionsBuilder},
+};
+
+// TTY is the terminal the clipboard escape sequence is written to
+const TTY: &str = "/dev/tty";
+
+// DIFF_TOGGLE_KEY switches the preview between the content and the diff
+// against the last commit for items that have a diff
+const DIFF_TOGGLE_KEY: &str = "alt-d";
+
+// ANSI_REGEX matches the escape sequences used to color the previews
+static ANSI_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*m").expect("must compile"));
+
+// PreviewLayout is how the preview is shown within the finder
+#[derive(Debug, Clone)]
+pub struct PreviewLayout {
+    position: String,
+    size: Option<String>,
+    wrap: bool,
+    toggle: Option<String>,
+    plain: bool,
+}
+
+impl Default for PreviewLayout {
+    fn default() -> Self {
+        Self {
+            position: String::from("right"),
+            size: None,
+            wrap: false,
+            toggle: None,
+            plain: false,
+        }
+    }
+}
+
+impl PreviewLayout {
+    // with_position sets where the preview is shown: up, down, left or right
+    pub fn with_position<S: Into<String>>(mut self, position: Option<S>) -> Self {
+        if let Some(position) = position {
+            self.position = position.into();
+        }
+        self
+    }
+
+    // with_size sets the size of the preview, in lines/columns or percent (50%)
+    pub fn with_size<S: Into<String>>(mut self, size: Option<S>) -> Self {
+        self.size = size.map(|s| s.into());
+        self
+    }
+
+    // with_wrap wraps the long lines of the preview
+    pub fn with_wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    // with_toggle sets the key that shows and hides the preview
+    pub fn with_toggle<S: Into<String>>(mut self, toggle: Option<S>) -> Self {
+        self.toggle = toggle.map(|t| t.into());
+        self
+    }
+
+    // with_plain strips the colors from the previews for terminals that mangle
+    // ANSI escapes
+    pub fn with_plain(mut self, plain: bool) -> Self {
+        self.plain = plain;
+        self
+    }
+
+    // window renders the layout as the preview window option of skim
+    fn window(&self) -> String {
+        let mut window = self.position.clone();
+        if let Some(size) = self.size.as_ref() {
+            window.push(':');
+            window.push_str(size);
+        }
+        if self.wrap {
+            window.push_str(":wrap");
+        }
+        window
+    }
+}
 
-pub struct Finder<P: AsRef<Path>> {
+// FinderAction is what happens to the selection of the finder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinderAction {
+    // Open opens the selection in the editor
+    Open,
+    // PrintPath prints the relative path of the selection
+    PrintPath,
+    // CopyPath copies the relative path of the selection to the clipboard
+    CopyPath,
+    // CopyWikilink copies a [[wikilink]] to the selection to the clipboard
+    CopyWikilink,
+    // AppendToDaily adds a reference to the selection to the daily
+    AppendToDaily,
+    // LinkFromSource adds a [[wikilink]] to the selection to the source note
+    LinkFromSource,
+}
+
+impl FromStr for FinderAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "open" => Ok(FinderAction::Open),
+            "print" => Ok(FinderAction::PrintPath),
+            "copy-path" => Ok(FinderAction::CopyPath),
+            "copy-link" => Ok(FinderAction::CopyWikilink),
+            "daily" => Ok(FinderAction::AppendToDaily),
+            "link" => Ok(FinderAction::LinkFromSource),
+            _ => Err(Error::PKMError(format!(
+                "unknown finder action {:?}, expected open, print, copy-path, copy-link, daily or link",
+                s
+            ))),
+        }
+    }
+}
+
+// parse_binding parses a key:action binding, like ctrl-y:copy-link
+pub fn parse_binding(binding: &str) -> Result<(String, FinderAction)> {
+    let (name, action) = binding.rsplit_once(':').ok_or_else(|| {
+        Error::PKMError(format!(
+            "invalid binding {:?}, expected key:action",
+            binding
+        ))
+    })?;
+
+    if key(name).is_none() {
+        return Err(Error::PKMError(format!(
+            "unknown key {:?}, expected ctrl-<c>, alt-<c> or f<n>",
+            name
+        )));
+    }
+
+    Ok((name.to_string(), action.parse()?))
+}
+
+// key converts the skim name of a key into the key skim reports
+fn key(name: &str) -> Option<Key> {
+    let single = |c: &str| {
+        let mut chars = c.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    if let Some(c) = name.strip_prefix("ctrl-") {
+        return single(c).map(Key::Ctrl);
+    }
+
+    if let Some(c) = name.strip_prefix("alt-") {
+        return single(c).map(Key::Alt);
+    }
+
+    if let Some(n) = name.strip_prefix('f') {
+        return n
+            .parse::<u8>()
+            .ok()
+            .filter(|n| (1..=12).contains(n))
+            .map(Key::F);
+    }
+
+    None
+}
+
+// CreateDaily creates the daily the selection is appended to when it doesn't
+// exist yet
+type CreateDaily<'a> = Box<dyn FnOnce() -> Result<()> + 'a>;
+
+pub struct Finder<'a, P: AsRef<Path>> {
     repo: P,
     options: SkimOptions,
-    sender: Sender<Arc<dyn SkimItem>>,
-    receiver: Receiver<Arc<dyn SkimItem>>,
+    items: Vec<Arc<dyn SkimItem>>,
+    has_diff: bool,
+    show_diff: Arc<AtomicBool>,
+    bindings: Vec<(String, FinderAction)>,
+    daily: Option<(PathBuf, CreateDaily<'a>)>,
+    source: Option<PathBuf>,
+    plain: bool,
+    theme: Option<String>,
 }
 
-impl<P: AsRef<Path>> Finder<P> {
-    pub fn new(repo: P) -> Finder<P> {
+impl<'a, P: AsRef<Path>> Finder<'a, P> {
+    pub fn new(repo: P) -> Finder<'a, P> {
         let options = SkimOptionsBuilder::default()
             .multi(true)
             .preview(Some(String::from("right")))
             .build()
             .expect("you should work");
 
-        let (sender, receiver) = unbounded();
         Finder {
             repo,
             options,
-            sender,
-            receiver,
+            items: Vec::new(),
+            has_diff: false,
+            show_diff: Arc::new(AtomicBool::new(false)),
+            bindings: Vec::new(),
+            daily: None,
+            source: None,
+            plain: false,
+            theme: None,
         }
     }
 
+    // preview_layout sets the position, size and wrapping of the preview and
+    // the key toggling it
+    pub fn preview_layout(&mut self, layout: &PreviewLayout) {
+        self.options.preview_window = layout.window();
+        if let Some(toggle) = layout.toggle.as_ref() {
+            self.options.bind.push(format!("{}:toggle-preview", toggle));
+        }
+        self.plain = layout.plain;
+    }
+
+    // bind runs the action on the selection when the key is pressed instead
+    // of opening the editor
+    pub fn bind<S: Into<String>>(&mut self, key: S, action: FinderAction) {
+        self.bindings.push((key.into(), action));
+    }
+
+    // daily sets the daily references are appended to. It is only created,
+    // with create, once something is appended to it.
+    pub fn daily<Q, F>(&mut self, daily: Q, create: F)
+    where
+        Q: Into<PathBuf>,
+        F: FnOnce() -> Result<()> + 'a,
+    {
+        self.daily = Some((daily.into(), Box::new(create)));
+    }
+
+    // source sets the note wikilinks to the selection are added to
+    pub fn source<Q: Into<PathBuf>>(&mut self, source: Option<Q>) {
+        self.source = source.map(|s| s.into());
+    }
+
+    // theme sets the theme of the previews of docs, without one the theme
+    // matches the background of the terminal
+    pub fn theme<S: Into<String>>(&mut self, theme: Option<S>) {
+        self.theme = theme.map(|t| t.into());
+    }
+
+    // header sets the line shown above the items
+    pub fn header<S: Into<String>>(&mut self, header: Option<S>) {
+        self.options.header = header.map(|h| h.into());
+    }
+
     pub fn add_fq_doc(&mut self, path: Uri) -> Result<()> {
+        self.add_fq_doc_with_preview(path, None)
+    }
+
+    // add_fq_doc_with_preview adds the fully qualified doc, previewing the provided
+    // content instead of the content of the doc when it is set
+    pub fn add_fq_doc_with_preview(&mut self, path: Uri, preview: Option<&str>) -> Result<()> {
         let path_string = path.to_string();
         let path = path_string.strip_prefix("file://").unwrap_or(&path_string);
         let path = path
@@ -48,10 +289,18 @@ impl<P: AsRef<Path>> Finder<P> {
             .unwrap_or(path);
         let path = path.strip_prefix("/").unwrap_or(path);
 
-        self.add_doc(Path::new(path))
+        self.add_doc_with_preview(Path::new(path), preview)
     }
 
     pub fn add_doc<Q: AsRef<Path>>(&mut self, path: Q) -> Result<()> {
+        self.add_doc_with_preview(path, None)
+    }
+
+    pub fn add_doc_with_preview<Q: AsRef<Path>>(
+        &mut self,
+        path: Q,
+        preview: Option<&str>,
+    ) -> Result<()> {
         let mut full_doc_path = PathBuf::new();
         full_doc_path.push(self.repo.as_ref());
         full_doc_path.push(path.as_ref());
@@ -60,6 +309,14 @@ impl<P: AsRef<Path>> Finder<P> {
         let opts = ParseOptions::gfm();
         let ast = markdown::to_mdast(&content, &opts)?;
 
+        let content = match preview {
+            Some(preview) => preview.to_string(),
+            None => {
+                let notes = NoteReader::new(self.repo.as_ref());
+                expand_embeds(&content, |id| notes.read(id))
+            }
+        };
+
         let mut title = None;
         if let Some(header) = first_node!(&ast, Node::Heading) {
             if let Some(Node::Text(header_content)) = header.children.get(0) {
@@ -70,16 +327,66 @@ impl<P: AsRef<Path>> Finder<P> {
         self.add(
             FinderItem::new(path.as_ref())
                 .with_display(title)
-                .with_syntax_preview(&content, Some("md"), None)?,
+                .with_syntax_preview(&content, Some("md"), self.theme.as_deref())?,
         )
     }
 
     pub fn add<F: Into<FinderItem>>(&mut self, item: F) -> Result<()> {
-        Ok(self.sender.send(Arc::new(item.into()))?)
+        let mut item = item.into();
+        item.show_diff = self.show_diff.clone();
+        if self.plain {
+            item.strip_ansi();
+        }
+        self.has_diff |= item.diff.is_some();
+        self.items.push(Arc::new(item));
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<SkimItemReceiver> {
+        let (sender, receiver) = unbounded();
+        for item in self.items.iter() {
+            sender.send(item.clone())?;
+        }
+        Ok(receiver)
+    }
+
+    // skim runs skim over the items. When the diff toggle key is pressed the
+    // preview is switched and skim is restarted with the same query.
+    fn skim(&mut self) -> Option<SkimOutput> {
+        for (key, _) in self.bindings.iter() {
+            self.options.expect.push(key.clone());
+        }
+
+        if self.has_diff {
+            self.options.expect.push(DIFF_TOGGLE_KEY.to_string());
+            let toggle = format!("{}: toggle diff", DIFF_TOGGLE_KEY);
+            self.options.header = Some(match self.options.header.take() {
+                Some(header) => format!("{}  {}", header, toggle),
+                None => toggle,
+            });
+        }
+
+        loop {
+            let receiver = match self.receiver() {
+                Ok(receiver) => receiver,
+                Err(err) => {
+                    log::error!("could not send items to finder: {}", err);
+                    return None;
+                }
+            };
+
+            let output = Skim::run_with(&self.options, Some(receiver))?;
+            if output.is_abort || !self.has_diff || key(DIFF_TOGGLE_KEY) != Some(output.final_key) {
+                return Some(output);
+            }
+
+            self.show_diff.fetch_xor(true, Ordering::Relaxed);
+            self.options.query = Some(output.query);
+        }
     }
 
-    pub fn select(self) -> Option<SkimOutput> {
-        Skim::run_with(&self.options, Some(self.receiver))
+    pub fn select(mut self) -> Option<SkimOutput> {
+        self.skim()
     }
 
     pub fn select_one(self) -> Option<Arc<dyn SkimItem + 'static>> {
@@ -100,43 +407,150 @@ impl<P: AsRef<Path>> Finder<P> {
         selection.selected_items.pop()
     }
 
+    // pick returns the paths of the first n items without launching skim. The
+    // items are expected to be added best match first.
+    pub fn pick(&self, n: usize) -> Vec<PathBuf> {
+        self.items
+            .iter()
+            .take(n)
+            .map(|item| PathBuf::from(item.text().as_ref()))
+            .collect()
+    }
+
+    // select_first returns the path of the best match without launching skim
+    pub fn select_first(&self) -> Option<PathBuf> {
+        self.pick(1).pop()
+    }
+
     // run runs the finder and returns if we ran the editor
     pub fn run(self) -> Result<bool> {
-        let Self {
-            repo,
-            options,
-            receiver,
-            sender: _,
-        } = self;
+        Ok(self.run_selection()?.is_some())
+    }
 
-        let selections = Skim::run_with(&options, Some(receiver));
-        let selections = match selections {
+    // run_selection runs the finder, runs the action bound to the key the
+    // selection was made with and returns the selected paths. Without a
+    // binding the selection is opened in the editor. None is returned when
+    // nothing was selected.
+    pub fn run_selection(mut self) -> Result<Option<Vec<PathBuf>>> {
+        let selections = match self.skim() {
             Some(m) => m,
-            None => return Ok(false),
+            None => return Ok(None),
         };
 
         if selections.is_abort {
-            return Ok(false);
+            return Ok(None);
         }
 
         if selections.selected_items.len() == 0 {
-            return Ok(false);
+            return Ok(None);
         }
 
-        let mut editor = Editor::new_from_env("EDITOR", repo);
+        let paths: Vec<PathBuf> = selections
+            .selected_items
+            .iter()
+            .map(|f| PathBuf::from(f.text().as_ref()))
+            .collect();
+
+        let action = self
+            .bindings
+            .iter()
+            .find(|(name, _)| key(name) == Some(selections.final_key))
+            .map(|(_, action)| *action)
+            .unwrap_or(FinderAction::Open);
+
+        self.act(action, &paths)?;
+        Ok(Some(paths))
+    }
+
+    fn act(self, action: FinderAction, paths: &[PathBuf]) -> Result<()> {
+        let ids: Vec<String> = paths.iter().map(path_to_id).collect();
+
+        match action {
+            FinderAction::Open => {
+                let mut editor = Editor::new_from_env("EDITOR", self.repo);
+                for path in paths.iter() {
+                    editor = editor.file(path)
+                }
+                editor.exec()?;
+            }
+            FinderAction::PrintPath => {
+                for path in paths {
+                    println!("{}", path.to_string_lossy());
+                }
+            }
+            FinderAction::CopyPath => copy(
+                &paths
+                    .iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )?,
+            FinderAction::CopyWikilink => copy(
+                &ids.iter()
+                    .map(|id| format!("[[{}]]", id))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )?,
+            FinderAction::AppendToDaily => {
+                let (daily, create) = self.daily.ok_or_else(|| {
+                    Error::PKMError(String::from("no daily to append the selection to"))
+                })?;
+                if !daily.exists() {
+                    create()?;
+                }
+
+                let mut daily = Zettel::new(daily)?;
+                let mut content = daily.mut_content()?;
+                for id in ids {
+                    content.append(&format!("- [[{}]]", id))?;
+                }
+                daily.sync()?;
+            }
+            FinderAction::LinkFromSource => {
+                let source = self.source.ok_or_else(|| {
+                    Error::PKMError(String::from("no note to link the selection from"))
+                })?;
 
-        for f in selections.selected_items {
-            editor = editor.file(PathBuf::from(f.text().as_ref()))
+                let mut source = Zettel::new(source)?;
+                let mut content = source.mut_content()?;
+                let links: Vec<String> = wikilinks(&content.to_string())
+                    .into_iter()
+                    .map(|link| link.id.to_lowercase())
+                    .collect();
+                for id in ids {
+                    if !links.contains(&id.to_lowercase()) {
+                        content.append(&format!("- [[{}]]", id))?;
+                    }
+                }
+                source.sync()?;
+            }
         }
 
-        editor.exec()?;
-        Ok(true)
+        Ok(())
+    }
+}
+
+// copy puts the text on the clipboard of the terminal with the OSC 52 escape
+// sequence, which works over ssh and without a clipboard tool installed. It
+// goes to the terminal itself so it never ends up in piped output.
+fn copy(text: &str) -> Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    match OpenOptions::new().write(true).open(TTY) {
+        Ok(mut tty) => tty.write_all(sequence.as_bytes())?,
+        Err(_) => {
+            let mut stderr = std::io::stderr();
+            stderr.write_all(sequence.as_bytes())?;
+            stderr.flush()?;
+        }
     }
+    Ok(())
 }
 
 pub struct FinderItem {
     path: PathBuf,
     preview: Option<ItemPreview>,
+    diff: Option<String>,
+    show_diff: Arc<AtomicBool>,
     display: Option<String>,
 }
 
@@ -145,6 +559,8 @@ impl FinderItem {
         Self {
             path: path.into(),
             preview: None,
+            diff: None,
+            show_diff: Arc::new(AtomicBool::new(false)),
             display: None,
         }
     }
@@ -159,6 +575,23 @@ impl FinderItem {
         self
     }
 
+    // with_diff_preview sets the colored diff shown instead of the preview
+    // when the diff is toggled in the finder
+    pub fn with_diff_preview(mut self, diff: Option<String>) -> Self {
+        self.diff = diff;
+        self
+    }
+
+    // strip_ansi turns the colored previews into plain text
+    fn strip_ansi(&mut self) {
+        let strip = |s: &str| ANSI_REGEX.replace_all(s, "").into_owned();
+
+        if let Some(ItemPreview::AnsiText(preview)) = self.preview.as_ref() {
+            self.preview = Some(ItemPreview::Text(strip(preview)));
+        }
+        self.diff = self.diff.as_deref().map(strip);
+    }
+
     pub fn with_syntax_preview(
         mut self,
         content: &str,
@@ -186,6 +619,12 @@ impl SkimItem for FinderItem {
     }
 
     fn preview(&self, _context: skim::PreviewContext) -> skim::ItemPreview {
+        if let Some(diff) = self.diff.as_ref() {
+            if self.show_diff.load(Ordering::Relaxed) {
+                return ItemPreview::AnsiText(diff.clone());
+            }
+        }
+
         match self.preview.as_ref() {
             Some(ip) => match ip {
                 // wish they would implement clone on ItemPreview
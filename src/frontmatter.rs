@@ -0,0 +1,205 @@
+use serde_json::Value;
+
+// FRONTMATTER_FENCE opens and closes the yaml frontmatter of a note
+const FRONTMATTER_FENCE: &str = "---";
+
+// Frontmatter is the yaml block at the start of a note. Only flat `key: value`
+// lines are understood, values are written as json which yaml understands.
+// Other lines are kept as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frontmatter {
+    lines: Vec<String>,
+}
+
+impl Frontmatter {
+    // parse reads the frontmatter of the content, it is empty when the
+    // content has none
+    pub fn parse(content: &str) -> Frontmatter {
+        let (block, _) = split_frontmatter(content);
+        Frontmatter {
+            lines: block
+                .unwrap_or_default()
+                .lines()
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // get returns the raw value of the key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|line| field(line).filter(|(k, _)| *k == key))
+            .map(|(_, value)| value)
+    }
+
+    // fields returns every key with its raw value
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| field(line))
+    }
+
+    // list returns the values of a key holding a list, written as a json or
+    // an inline yaml list. A single value is a list of one.
+    pub fn list(&self, key: &str) -> Vec<String> {
+        let value = match self.get(key) {
+            Some(value) if !value.is_empty() => value,
+            _ => return Vec::new(),
+        };
+
+        match serde_json::from_str::<Value>(value) {
+            Ok(Value::Array(values)) => values
+                .into_iter()
+                .map(|value| match value {
+                    Value::String(s) => s,
+                    value => value.to_string(),
+                })
+                .collect(),
+            Ok(Value::String(s)) => vec![s],
+            _ => value
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .map(|v| v.trim().trim_matches(['"', '\'']).to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+        }
+    }
+
+    // set replaces the value of the key, adding the key when it is missing.
+    // The indented lines of a replaced value are removed with it.
+    pub fn set<S: AsRef<str>>(&mut self, key: &str, value: S) {
+        let line = format!("{}: {}", key, value.as_ref());
+        let idx = match self
+            .lines
+            .iter()
+            .position(|l| field(l).is_some_and(|(k, _)| k == key))
+        {
+            Some(idx) => idx,
+            None => {
+                self.lines.push(line);
+                return;
+            }
+        };
+
+        self.lines[idx] = line;
+        while self
+            .lines
+            .get(idx + 1)
+            .is_some_and(|l| l.starts_with([' ', '\t', '-']))
+        {
+            self.lines.remove(idx + 1);
+        }
+    }
+
+    // render writes the frontmatter out including the fences
+    pub fn render(&self) -> String {
+        let mut frontmatter = format!("{}\n", FRONTMATTER_FENCE);
+        for line in self.lines.iter() {
+            frontmatter.push_str(line);
+            frontmatter.push('\n');
+        }
+        frontmatter.push_str(&format!("{}\n", FRONTMATTER_FENCE));
+        frontmatter
+    }
+}
+
+// field splits a top level `key: value` line
+fn field(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with([' ', '\t', '-', '#']) {
+        return None;
+    }
+
+    line.split_once(':')
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+// split_frontmatter splits the content into the frontmatter, without its
+// fences, and the body
+pub fn split_frontmatter(content: &str) -> (Option<&str>, &str) {
+    let rest = match content.strip_prefix(FRONTMATTER_FENCE).and_then(|rest| {
+        rest.strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+    }) {
+        Some(rest) => rest,
+        None => return (None, content),
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONTMATTER_FENCE {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+
+    (None, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_frontmatter_from_the_body() {
+        let (block, body) = split_frontmatter("---\ntitle: x\n---\n# body\n");
+        assert_eq!(block, Some("title: x\n"));
+        assert_eq!(body, "# body\n");
+    }
+
+    #[test]
+    fn splits_crlf_frontmatter() {
+        let (block, body) = split_frontmatter("---\r\ntitle: x\r\n---\r\nbody");
+        assert_eq!(block, Some("title: x\r\n"));
+        assert_eq!(body, "body");
+        assert_eq!(
+            Frontmatter::parse("---\r\ntitle: x\r\n---\r\n").get("title"),
+            Some("x")
+        );
+    }
+
+    #[test]
+    fn content_without_frontmatter_is_left_whole() {
+        assert_eq!(split_frontmatter("# title\n"), (None, "# title\n"));
+        assert!(Frontmatter::parse("# title\n").is_empty());
+    }
+
+    #[test]
+    fn unclosed_frontmatter_is_not_frontmatter() {
+        let content = "---\ntitle: x\n";
+        assert_eq!(split_frontmatter(content), (None, content));
+    }
+
+    #[test]
+    fn reads_lists() {
+        let frontmatter = Frontmatter::parse(
+            "---\nattendees: [\"ana\", \"bo\"]\ntags: [a, 'b']\nowner: cy\nempty:\n---\n",
+        );
+        assert_eq!(frontmatter.list("attendees"), vec!["ana", "bo"]);
+        assert_eq!(frontmatter.list("tags"), vec!["a", "b"]);
+        assert_eq!(frontmatter.list("owner"), vec!["cy"]);
+        assert!(frontmatter.list("empty").is_empty());
+        assert!(frontmatter.list("missing").is_empty());
+    }
+
+    #[test]
+    fn nested_lines_are_not_fields() {
+        let frontmatter = Frontmatter::parse("---\ntags:\n  - a\n# note: x\n---\n");
+        assert_eq!(frontmatter.fields().collect::<Vec<_>>(), vec![("tags", "")]);
+        assert_eq!(frontmatter.get("note"), None);
+    }
+
+    #[test]
+    fn set_replaces_the_value_and_its_nested_lines() {
+        let mut frontmatter = Frontmatter::parse("---\ntags:\n  - a\n  - b\ntitle: x\n---\n");
+        frontmatter.set("tags", "[\"c\"]");
+        frontmatter.set("status", "draft");
+        assert_eq!(
+            frontmatter.render(),
+            "---\ntags: [\"c\"]\ntitle: x\nstatus: draft\n---\n"
+        );
+    }
+}
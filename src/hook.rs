@@ -0,0 +1,115 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{Error, Result, Zettel};
+
+// HOOK_DIR is the directory, relative to the root directory, hooks are run
+// from
+pub const HOOK_DIR: &str = "scripts/hooks";
+
+// Hook is a point in the life of the notes a script can be run at. The script
+// is the executable within the HOOK_DIR named after the hook, with or without
+// an extension, like post-zettel-create.sh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PostZettelCreate,
+    PostDailyCreate,
+    PreIndex,
+    PostImage,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PostZettelCreate => "post-zettel-create",
+            Self::PostDailyCreate => "post-daily-create",
+            Self::PreIndex => "pre-index",
+            Self::PostImage => "post-image",
+        }
+    }
+
+    // is_pre is true for the hooks run before pkm does something, the action
+    // is stopped when they fail
+    pub fn is_pre(&self) -> bool {
+        matches!(self, Self::PreIndex)
+    }
+
+    // script is the executable run for the hook, if the repo has one
+    pub fn script<P: AsRef<Path>>(&self, root: P) -> Result<Option<PathBuf>> {
+        let dir = root.as_ref().join(HOOK_DIR);
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path.file_stem().is_some_and(|stem| stem == self.name())
+                && executable(&path)?
+            {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    // run runs the script of the hook from the root directory. The event is
+    // described by PKM_HOOK, PKM_PATH and, for notes, PKM_ID and PKM_TITLE,
+    // along with the PKM_ROOT and PKM_BIN plugins get.
+    pub fn run<P, Q>(&self, root: P, path: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let Some(script) = self.script(root.as_ref())? else {
+            return Ok(());
+        };
+
+        let path = path.as_ref();
+        let mut command = Command::new(&script);
+        command
+            .stdin(Stdio::null())
+            // what a hook prints goes to stderr, stdout is left to pkm for
+            // the paths and json scripts read
+            .stdout(std::io::stderr())
+            .stderr(Stdio::inherit())
+            .current_dir(root.as_ref())
+            .env("PKM_ROOT", root.as_ref())
+            .env("PKM_BIN", std::env::current_exe()?)
+            .env("PKM_HOOK", self.name())
+            .env("PKM_PATH", path);
+
+        if path.extension().is_some_and(|ext| ext == "md") {
+            let ztl = Zettel::new(path)?;
+            command.env("PKM_ID", ztl.id().to_string());
+            if let Some(title) = ztl.title()? {
+                command.env("PKM_TITLE", title);
+            }
+        }
+
+        log::debug!("running the {} hook {:?}", self.name(), script);
+        let status = command.status()?;
+        if !status.success() {
+            return Err(Error::PKMError(format!(
+                "the {} hook failed with {}",
+                self.name(),
+                status
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn executable(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::metadata(path)?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn executable(_path: &Path) -> Result<bool> {
+    Ok(true)
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use nanoid::nanoid;
+use sha1::{Digest, Sha1};
+use ulid::Ulid;
+
+use crate::{Error, Result};
+
+// NANOID_ALPHABET leaves out _ and - of the usual nanoid alphabet, _ separates
+// the parts of an id
+const NANOID_ALPHABET: [char; 36] = [
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
+    'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z',
+];
+const NANOID_LENGTH: usize = 21;
+
+// IDScheme decides the unique part at the end of the id of a new zettel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IDScheme {
+    // the first 8 characters of the sha1 of the time, only when asked for
+    #[default]
+    Hash,
+    // the minute the zettel was made, YYYYMMDDHHMM
+    Timestamp,
+    Ulid,
+    Nanoid,
+    // the title and tags only, no unique part at all
+    Slug,
+}
+
+impl IDScheme {
+    // always is true when every new zettel gets the unique part, not just
+    // the ones asking for a hash
+    pub fn always(&self) -> bool {
+        matches!(
+            self,
+            IDScheme::Timestamp | IDScheme::Ulid | IDScheme::Nanoid
+        )
+    }
+
+    // unique makes the unique part of an id made at the date, slugs have none
+    pub fn unique(&self, date: &DateTime<Utc>) -> Option<String> {
+        match self {
+            IDScheme::Hash => {
+                let mut hash = Sha1::new();
+                hash.update(date.to_rfc3339().as_bytes());
+                Some(hex::encode(hash.finalize())[0..8].to_string())
+            }
+            IDScheme::Timestamp => {
+                Some(date.with_timezone(&Local).format("%Y%m%d%H%M").to_string())
+            }
+            IDScheme::Ulid => Some(Ulid::from_datetime((*date).into()).to_string()),
+            IDScheme::Nanoid => Some(nanoid!(NANOID_LENGTH, &NANOID_ALPHABET)),
+            IDScheme::Slug => None,
+        }
+    }
+}
+
+// parse_id_scheme parses the name of a scheme, like ulid
+pub fn parse_id_scheme(scheme: &str) -> Result<IDScheme> {
+    match scheme.trim().to_lowercase().as_str() {
+        "hash" => Ok(IDScheme::Hash),
+        "timestamp" => Ok(IDScheme::Timestamp),
+        "ulid" => Ok(IDScheme::Ulid),
+        "nanoid" => Ok(IDScheme::Nanoid),
+        "slug" => Ok(IDScheme::Slug),
+        _ => Err(Error::PKMError(format!(
+            "invalid id scheme {:?}, expected hash, timestamp, ulid, nanoid or slug",
+            scheme
+        ))),
+    }
+}
+
+// scheme_date returns the day the part of an id was made on when it is a
+// timestamp or a ulid, so ids of every scheme keep their date
+pub fn scheme_date(part: &str) -> Option<NaiveDate> {
+    if part.len() == 12 && part.bytes().all(|b| b.is_ascii_digit()) {
+        return NaiveDate::parse_from_str(&part[..8], "%Y%m%d").ok();
+    }
+
+    // a ulid is 26 characters of crockford base32, its time is in the first
+    // 10 of them
+    if part.len() == 26 {
+        let ulid = Ulid::from_string(part).ok()?;
+        return Some(DateTime::<Local>::from(ulid.datetime()).date_naive());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_the_scheme_names() {
+        assert_eq!(parse_id_scheme(" ULID ").unwrap(), IDScheme::Ulid);
+        assert_eq!(parse_id_scheme("slug").unwrap(), IDScheme::Slug);
+        assert!(parse_id_scheme("uuid").is_err());
+    }
+
+    #[test]
+    fn timestamps_and_ulids_keep_their_date() {
+        let date = Local.with_ymd_and_hms(2024, 3, 9, 14, 30, 0).unwrap();
+        let utc = date.with_timezone(&Utc);
+
+        let timestamp = IDScheme::Timestamp.unique(&utc).unwrap();
+        assert_eq!(timestamp, "202403091430");
+        assert_eq!(scheme_date(&timestamp), Some(date.date_naive()));
+
+        let ulid = IDScheme::Ulid.unique(&utc).unwrap();
+        assert_eq!(ulid.len(), 26);
+        assert_eq!(scheme_date(&ulid), Some(date.date_naive()));
+        assert_eq!(scheme_date(&ulid.to_lowercase()), Some(date.date_naive()));
+    }
+
+    #[test]
+    fn nanoids_never_contain_the_separators() {
+        let nanoid = IDScheme::Nanoid.unique(&Utc::now()).unwrap();
+        assert_eq!(nanoid.len(), NANOID_LENGTH);
+        assert!(nanoid.chars().all(|c| NANOID_ALPHABET.contains(&c)));
+    }
+
+    #[test]
+    fn slugs_and_hashes() {
+        assert_eq!(IDScheme::Slug.unique(&Utc::now()), None);
+        assert_eq!(IDScheme::Hash.unique(&Utc::now()).unwrap().len(), 8);
+        assert!(!IDScheme::Hash.always() && IDScheme::Ulid.always());
+        assert_eq!(scheme_date("not-a-date"), None);
+    }
+}
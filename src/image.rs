@@ -4,16 +4,31 @@ use image::{
     imageops::{self, FilterType::Gaussian},
 };
 
-use crate::{Result, ZettelIDBuilder};
+use crate::{Error, Result, ZettelIDBuilder};
+use regex::Regex;
 use std::{
-    fs::{self, File},
+    fs::{self, OpenOptions},
+    io,
     path::{Path, PathBuf, StripPrefixError},
+    thread,
 };
+use tera::{Context, Tera};
 
+// DEFAULT_IMAGE_NAME_TEMPLATE names images after the hash, prefixed with the
+// slug of the name when one is given
+pub const DEFAULT_IMAGE_NAME_TEMPLATE: &str =
+    "{% if slug %}{{ slug }}_{% endif %}{{ hash }}.{{ ext }}";
+
+// IMAGE_EXTENSIONS are the files picked up when ingesting a directory
+pub(crate) const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "webp", "bmp", "tiff"];
+
+#[derive(Clone)]
 pub struct ImageBuilder {
     base: PathBuf,
     max_width: Option<u32>,
     max_height: Option<u32>,
+    name: Option<String>,
+    name_template: String,
 }
 
 impl ImageBuilder {
@@ -22,6 +37,8 @@ impl ImageBuilder {
             base: PathBuf::from(base.as_ref()),
             max_width: None,
             max_height: None,
+            name: None,
+            name_template: DEFAULT_IMAGE_NAME_TEMPLATE.to_string(),
         }
     }
 
@@ -49,6 +66,21 @@ impl ImageBuilder {
         self
     }
 
+    // name sets the name the slug in the filename is made from
+    pub fn name<S: Into<String>>(mut self, name: Option<S>) -> Self {
+        self.name = name.map(|v| v.into());
+        self
+    }
+
+    // name_template sets the tera template for the filename. The template has
+    // date, slug, hash and ext available.
+    pub fn name_template<S: Into<String>>(mut self, template: Option<S>) -> Self {
+        if let Some(template) = template {
+            self.name_template = template.into();
+        }
+        self
+    }
+
     pub fn build<P>(self, path: P) -> Result<Image>
     where
         P: AsRef<Path>,
@@ -57,6 +89,8 @@ impl ImageBuilder {
             base,
             max_width,
             max_height,
+            name,
+            name_template,
         } = self;
 
         let img = ImageReader::open(path.as_ref())?.decode()?;
@@ -87,19 +121,134 @@ impl ImageBuilder {
         // Create the directory for the thing to live in
         fs::create_dir_all(base.as_path())?; // only creates the directories, not the file
 
-        let id = ZettelIDBuilder::new().with_hash().build()?;
-        let mut id: String = id.into();
-        id.push_str(".jpg");
+        let hash: String = ZettelIDBuilder::new().with_hash().build()?.into();
+        let mut context = Context::new();
+        context.insert("date", &Local::now().format("%Y-%m-%d").to_string());
+        context.insert("slug", &name.as_deref().map(slug).unwrap_or_default());
+        context.insert("hash", &hash);
+        context.insert("ext", "jpg");
+        let filename = Tera::one_off(&name_template, &context, false)?;
 
         let mut path = PathBuf::from(base);
-        path.push(id);
+        path.push(filename.trim());
 
-        let mut image_file = File::create(path.as_path())?;
-        img.write_to(&mut image_file, image::ImageFormat::Jpeg)?;
-        image_file.sync_all()?;
+        // templates without the hash can collide, create_new never
+        // overwrites an image, even one stored by another pkm meanwhile
+        let mut image_file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(image_file) => image_file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::PKMError(format!("{:?} already exists", path)));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let written = img
+            .write_to(&mut image_file, image::ImageFormat::Jpeg)
+            .map_err(Error::from)
+            .and_then(|_| Ok(image_file.sync_all()?));
+        if let Err(err) = written {
+            let _ = fs::remove_file(&path);
+            return Err(err);
+        }
 
         Ok(Image { path })
     }
+
+    // build_all builds every image concurrently, returning the results in the
+    // order of the paths
+    pub fn build_all<P>(self, paths: &[P]) -> Vec<Result<Image>>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        let workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk = paths.len().div_ceil(workers).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = paths
+                .chunks(chunk)
+                .map(|paths| {
+                    let builder = self.clone();
+                    scope.spawn(move || {
+                        paths
+                            .iter()
+                            .map(|path| builder.clone().build(path))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("image worker panicked"))
+                .collect()
+        })
+    }
+}
+
+// image_paths expands the path into the images to ingest. A directory gives
+// the images within it, a glob like `photos/*.png` the files matching it and
+// anything else the path itself.
+pub fn image_paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let (dir, pattern) = if path.is_dir() {
+        (path, None)
+    } else if name.contains(['*', '?']) {
+        let pattern = regex::escape(&name)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".");
+        (
+            path.parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new(".")),
+            Some(Regex::new(&format!("^{}$", pattern)).expect("escaped glob must compile")),
+        )
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let matches = match &pattern {
+            Some(pattern) => path
+                .file_name()
+                .is_some_and(|name| pattern.is_match(&name.to_string_lossy())),
+            None => path.extension().is_some_and(|ext| {
+                IMAGE_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+            }),
+        };
+
+        if matches {
+            paths.push(path);
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(Error::NotFound(format!("no images in {:?}", path)));
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+// slug lowercases the name and replaces anything that isn't alphanumeric
+// with a dash
+pub(crate) fn slug(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
 }
 
 pub struct Image {
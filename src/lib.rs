@@ -1,19 +1,69 @@
+mod agenda;
+mod attachment;
+mod bundle;
+mod collection;
+mod doctor;
 mod editor;
+mod embedding;
 mod error;
+mod favorites;
+mod filters;
 mod finder;
+mod frontmatter;
+mod git;
+mod hook;
+mod id_scheme;
 mod image;
+mod link;
+mod llm;
 pub mod lsp;
 mod markdown;
+mod metrics;
 mod pkm;
+mod plugin;
+mod project;
+mod query;
+mod route;
+mod selection;
+mod serve;
+mod streak;
 mod syntax;
+mod table;
+mod template;
+mod tokenizer;
 mod zettel;
 mod zettel_index;
 
+pub use agenda::*;
+pub use attachment::*;
+pub use bundle::*;
+pub use collection::*;
+pub use doctor::*;
 pub use editor::*;
+pub use embedding::*;
 pub use error::*;
+pub use favorites::*;
+pub use filters::*;
 pub use finder::*;
+pub use frontmatter::*;
+pub use git::*;
+pub use hook::*;
+pub use id_scheme::*;
 pub use image::*;
+pub use link::*;
+pub use llm::*;
+pub use metrics::*;
 pub use pkm::*;
+pub use plugin::*;
+pub use project::*;
+pub use query::*;
+pub use route::*;
+pub use selection::*;
+pub use serve::*;
+pub use streak::*;
 pub use syntax::*;
+pub use table::*;
+pub use template::*;
+pub use tokenizer::*;
 pub use zettel::*;
 pub use zettel_index::*;
@@ -0,0 +1,393 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+use walkdir::WalkDir;
+
+use crate::split_frontmatter;
+
+// WIKILINK_REGEX matches [[id]], [[id#heading]] and [[id|display]] style links
+// capturing the id, the optional heading and the optional display text
+static WIKILINK_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\[\[([^\]\|#]+)(#[^\]\|]*)?(\|[^\]]*)?\]\]").expect("must compile")
+});
+
+// EMBED_REGEX matches ![[id]] and ![[id#heading]] embeds, capturing the id and
+// the optional heading
+static EMBED_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"!\[\[([^\]\|#]+)(#[^\]\|]*)?(\|[^\]]*)?\]\]").expect("must compile")
+});
+
+// MARKDOWN_LINK_REGEX matches markdown links and images, [text](target) and
+// ![alt](target), capturing the target
+static MARKDOWN_LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(!?\[[^\]]*\]\()([^)\s]+)(\))").expect("must compile"));
+
+// WikiLink is a single [[wikilink]] found within a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WikiLink {
+    pub id: String,
+    pub heading: Option<String>,
+    pub display: Option<String>,
+}
+
+// wikilinks returns all the wikilinks found within the content
+pub fn wikilinks(content: &str) -> Vec<WikiLink> {
+    WIKILINK_REGEX
+        .captures_iter(content)
+        .map(|cap| WikiLink {
+            id: cap[1].trim().to_string(),
+            heading: cap.get(2).map(|h| h.as_str()[1..].to_string()),
+            display: cap.get(3).map(|d| d.as_str()[1..].to_string()),
+        })
+        .collect()
+}
+
+// wikilink_lines returns the wikilinks of the content with the line, counting
+// from 1, they are on
+pub fn wikilink_lines(content: &str) -> Vec<(usize, WikiLink)> {
+    WIKILINK_REGEX
+        .captures_iter(content)
+        .map(|cap| {
+            let start = cap.get(0).expect("has match").start();
+            (
+                content[..start].matches('\n').count() + 1,
+                WikiLink {
+                    id: cap[1].trim().to_string(),
+                    heading: cap.get(2).map(|h| h.as_str()[1..].to_string()),
+                    display: cap.get(3).map(|d| d.as_str()[1..].to_string()),
+                },
+            )
+        })
+        .collect()
+}
+
+// resolve_local_link resolves the target of a markdown link or image to a
+// file, relative to the note first and the root second. Links to other sites
+// and anchors within the note are not local and resolve to None.
+pub fn resolve_local_link(root: &Path, note: &Path, link: &str) -> Option<PathBuf> {
+    let link = link_path(link)?;
+    [
+        note.parent().map(|dir| dir.join(&link)),
+        Some(root.join(&link)),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.exists())
+}
+
+// resolve_root_link resolves the target of a markdown link or image to a file
+// relative to the root only
+pub fn resolve_root_link(root: &Path, link: &str) -> Option<PathBuf> {
+    Some(root.join(link_path(link)?)).filter(|path| path.exists())
+}
+
+// link_path is the path a local link points at, without the anchor
+fn link_path(link: &str) -> Option<String> {
+    if !is_local_link(link) {
+        return None;
+    }
+
+    let link = link.split('#').next().unwrap_or(link).replace("%20", " ");
+    Some(link.trim_start_matches('/').to_string())
+}
+
+// is_local_link is true when the link points at a file rather than a site,
+// a mail address or an anchor within the note
+pub fn is_local_link(link: &str) -> bool {
+    !(link.is_empty()
+        || link.starts_with('#')
+        || link.starts_with("mailto:")
+        || link.contains("://"))
+}
+
+// rewrite_wikilinks replaces every link pointing at `old_id` with `new_id`, keeping
+// the heading and display parts intact. None is returned when nothing changed so
+// callers can skip writing the file.
+pub fn rewrite_wikilinks(content: &str, old_id: &str, new_id: &str) -> Option<String> {
+    let mut changed = false;
+    let rewritten = WIKILINK_REGEX.replace_all(content, |cap: &Captures| {
+        if cap[1].trim() != old_id {
+            return cap[0].to_string();
+        }
+
+        changed = true;
+        format!(
+            "[[{}{}{}]]",
+            new_id,
+            cap.get(2).map(|m| m.as_str()).unwrap_or(""),
+            cap.get(3).map(|m| m.as_str()).unwrap_or("")
+        )
+    });
+
+    if changed {
+        Some(rewritten.into_owned())
+    } else {
+        None
+    }
+}
+
+// image_links returns the targets of the markdown images within the content
+pub fn image_links(content: &str) -> Vec<String> {
+    MARKDOWN_LINK_REGEX
+        .captures_iter(content)
+        .filter(|cap| cap[1].starts_with('!'))
+        .map(|cap| cap[2].to_string())
+        .collect()
+}
+
+// markdown_links returns the targets of the markdown links and images within
+// the content
+pub fn markdown_links(content: &str) -> Vec<String> {
+    MARKDOWN_LINK_REGEX
+        .captures_iter(content)
+        .map(|cap| cap[2].to_string())
+        .collect()
+}
+
+// rewrite_markdown_links replaces the target of every markdown link and image
+// for which rewrite returns a new target. None is returned when nothing
+// changed so callers can skip writing the file.
+pub fn rewrite_markdown_links<F>(content: &str, mut rewrite: F) -> Option<String>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut changed = false;
+    let rewritten =
+        MARKDOWN_LINK_REGEX.replace_all(content, |cap: &Captures| match rewrite(&cap[2]) {
+            Some(target) => {
+                changed = true;
+                format!("{}{}{}", &cap[1], target, &cap[3])
+            }
+            None => cap[0].to_string(),
+        });
+
+    if changed {
+        Some(rewritten.into_owned())
+    } else {
+        None
+    }
+}
+
+// expand_embeds replaces every ![[id]] embed with the note read returns for
+// the id, ![[id#heading]] only embeds the section under the heading. Embeds
+// within the embedded notes are expanded as well, except for notes already
+// being expanded so cycles end. Embeds that can't be read are left alone.
+pub fn expand_embeds<F>(content: &str, read: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if !content.contains("![[") {
+        return content.to_string();
+    }
+
+    expand(content, &read, &mut Vec::new())
+}
+
+fn expand<F>(content: &str, read: &F, expanding: &mut Vec<String>) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    EMBED_REGEX
+        .replace_all(content, |cap: &Captures| {
+            let id = cap[1].trim();
+            if expanding.iter().any(|other| other.eq_ignore_ascii_case(id)) {
+                return cap[0].to_string();
+            }
+
+            let note = match read(id) {
+                Some(note) => note,
+                None => return cap[0].to_string(),
+            };
+            let (_, body) = split_frontmatter(&note);
+            let body = match cap.get(2) {
+                Some(heading) => match section(body, &heading.as_str()[1..]) {
+                    Some(section) => section,
+                    None => return cap[0].to_string(),
+                },
+                None => body,
+            };
+
+            expanding.push(id.to_string());
+            let expanded = expand(body.trim(), read, expanding);
+            expanding.pop();
+            expanded
+        })
+        .into_owned()
+}
+
+// section returns the heading and everything below it up to the next heading
+// of the same or a higher level
+fn section<'a>(content: &'a str, heading: &str) -> Option<&'a str> {
+    let depth = |line: &str| {
+        let hashes = line.chars().take_while(|c| *c == '#').count();
+        (hashes > 0 && line[hashes..].starts_with(' ')).then_some(hashes)
+    };
+
+    let mut offset = 0;
+    let mut start: Option<(usize, usize)> = None;
+    for line in content.split_inclusive('\n') {
+        match (start, depth(line)) {
+            (None, Some(level)) if line[level..].trim().eq_ignore_ascii_case(heading.trim()) => {
+                start = Some((offset, level));
+            }
+            (Some((begin, level)), Some(other)) if other <= level => {
+                return Some(&content[begin..offset]);
+            }
+            _ => (),
+        }
+        offset += line.len();
+    }
+
+    start.map(|(begin, _)| &content[begin..])
+}
+
+// NoteReader reads the notes within the repo by their id, ids are matched
+// case insensitively. The repo is walked once, when the first note is read,
+// so a render expanding many embeds doesn't walk it for each of them.
+pub struct NoteReader<'a> {
+    root: &'a Path,
+    paths: OnceCell<HashMap<String, PathBuf>>,
+}
+
+impl<'a> NoteReader<'a> {
+    pub fn new(root: &'a Path) -> Self {
+        Self {
+            root,
+            paths: OnceCell::new(),
+        }
+    }
+
+    pub fn read(&self, id: &str) -> Option<String> {
+        self.paths
+            .get_or_init(|| note_paths(self.root))
+            .get(&id.to_ascii_lowercase())
+            .and_then(|path| fs::read_to_string(path).ok())
+    }
+}
+
+// note_paths maps the lowercased id of every note to its path, the first
+// note found wins when ids clash
+fn note_paths(root: &Path) -> HashMap<String, PathBuf> {
+    let mut paths = HashMap::new();
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+        if let Some(id) = name.strip_suffix(".md") {
+            paths
+                .entry(id.to_string())
+                .or_insert_with(|| entry.path().to_path_buf());
+        }
+    }
+    paths
+}
+
+// unlink_wikilinks turns the links for which keep returns false into their
+// plain text, the display text when there is one and the id otherwise
+pub fn unlink_wikilinks<F>(content: &str, keep: F) -> String
+where
+    F: Fn(&str) -> bool,
+{
+    WIKILINK_REGEX
+        .replace_all(content, |cap: &Captures| {
+            let id = cap[1].trim();
+            if keep(id) {
+                return cap[0].to_string();
+            }
+
+            match cap.get(3) {
+                Some(display) => display.as_str()[1..].to_string(),
+                None => id.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+// Mention is a plain text occurrence of a term that isn't linked yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    pub range: std::ops::Range<usize>,
+    pub line: usize,
+    pub context: String,
+}
+
+// unlinked_mentions finds the whole word, case insensitive occurrences of the
+// terms that are not already within a wikilink. Overlapping mentions of
+// different terms are reported once, preferring the longest.
+pub fn unlinked_mentions(content: &str, terms: &[&str]) -> Vec<Mention> {
+    let linked: Vec<std::ops::Range<usize>> = WIKILINK_REGEX
+        .find_iter(content)
+        .map(|m| m.range())
+        .collect();
+
+    let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+    for term in terms.iter().filter(|term| !term.trim().is_empty()) {
+        let regex = match Regex::new(&format!(r"(?i)\b{}\b", regex::escape(term.trim()))) {
+            Ok(regex) => regex,
+            Err(_) => continue,
+        };
+
+        ranges.extend(
+            regex
+                .find_iter(content)
+                .map(|m| m.range())
+                .filter(|r| !linked.iter().any(|l| l.start < r.end && r.start < l.end)),
+        );
+    }
+
+    ranges.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+
+    let mut mentions: Vec<Mention> = Vec::new();
+    for range in ranges {
+        if mentions.last().is_some_and(|m| range.start < m.range.end) {
+            continue;
+        }
+
+        let line_start = content[..range.start]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let line_end = content[range.end..]
+            .find('\n')
+            .map(|i| range.end + i)
+            .unwrap_or(content.len());
+
+        mentions.push(Mention {
+            line: content[..range.start].matches('\n').count() + 1,
+            context: content[line_start..line_end].trim().to_string(),
+            range,
+        });
+    }
+
+    mentions
+}
+
+// link_mentions turns the mentions into wikilinks to the id, keeping the
+// mentioned text as the display text when it differs from the id
+pub fn link_mentions(content: &str, id: &str, mentions: &[Mention]) -> String {
+    let mut content = content.to_string();
+
+    // from the bottom up so the earlier ranges stay valid
+    let mut mentions: Vec<&Mention> = mentions.iter().collect();
+    mentions.sort_by(|a, b| b.range.start.cmp(&a.range.start));
+    for mention in mentions {
+        let text = &content[mention.range.clone()];
+        let link = if text == id {
+            format!("[[{}]]", id)
+        } else {
+            format!("[[{}|{}]]", id, text)
+        };
+        content.replace_range(mention.range.clone(), &link);
+    }
+
+    content
+}
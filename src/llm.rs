@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{Error, Result, post_json, split_frontmatter};
+
+// LLM_CONFIG_FILE is the file, relative to the root directory, configuring
+// the language model of the repo, like {"url": "http://localhost:11434/v1/chat/completions"}
+pub const LLM_CONFIG_FILE: &str = ".pkm/llm.json";
+
+// DEFAULT_LLM_MODEL is the model asked for when none is configured
+pub const DEFAULT_LLM_MODEL: &str = "llama3.2";
+
+// SUMMARY_KEY is the frontmatter key and SUMMARY_HEADING the section a
+// summary is written to
+pub const SUMMARY_KEY: &str = "summary";
+pub const SUMMARY_HEADING: &str = "Summary";
+
+// TITLE_LENGTH is the longest title taken from a reply
+const TITLE_LENGTH: usize = 80;
+
+const SUMMARIZE_PROMPT: &str = "Summarize the markdown note in two or three sentences. \
+    Reply with the summary only, without a heading or quotes.";
+const TITLE_PROMPT: &str = "Suggest a short title of at most six words for the markdown note. \
+    Reply with the title only, without quotes or punctuation at the end.";
+
+// Llm is an OpenAI compatible chat completions endpoint, nothing is sent
+// anywhere unless one is configured
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Llm {
+    pub url: String,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(skip)]
+    pub api_key: Option<String>,
+}
+
+fn default_model() -> String {
+    DEFAULT_LLM_MODEL.to_string()
+}
+
+// parse_llm parses the http or https url of a chat completions endpoint
+pub fn parse_llm(url: &str) -> Result<Llm> {
+    let url = url.trim();
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(Error::PKMError(format!(
+            "invalid llm endpoint {:?}, expected an http url like http://localhost:11434/v1/chat/completions",
+            url
+        )));
+    }
+
+    Ok(Llm {
+        url: url.to_string(),
+        model: default_model(),
+        api_key: None,
+    })
+}
+
+impl Llm {
+    // open reads the llm configured for the repo, None when the repo has none
+    pub fn open(root: &Path) -> Result<Option<Llm>> {
+        let path = root.join(LLM_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let llm: Llm = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        parse_llm(&llm.url)?;
+        Ok(Some(llm))
+    }
+
+    // with_model sets the model asked for
+    pub fn with_model<S: Into<String>>(mut self, model: Option<S>) -> Self {
+        if let Some(model) = model {
+            self.model = model.into();
+        }
+        self
+    }
+
+    // with_api_key sets the token sent as the bearer of the authorization
+    // header, hosted endpoints ask for one
+    pub fn with_api_key<S: Into<String>>(mut self, api_key: Option<S>) -> Self {
+        if let Some(api_key) = api_key {
+            self.api_key = Some(api_key.into());
+        }
+        self
+    }
+
+    // complete sends the instructions along with the content and returns the
+    // reply
+    pub fn complete(&self, instructions: &str, content: &str) -> Result<String> {
+        let body = json!({
+            "model": self.model,
+            "messages": [
+                { "role": "system", "content": instructions },
+                { "role": "user", "content": content },
+            ],
+        });
+
+        let response: Value = serde_json::from_slice(&post_json(
+            &self.url,
+            self.api_key.as_deref(),
+            &body.to_string(),
+        )?)?;
+        response["choices"][0]["message"]["content"]
+            .as_str()
+            .map(|reply| reply.trim().to_string())
+            .filter(|reply| !reply.is_empty())
+            .ok_or_else(|| Error::PKMError(format!("{} didn't reply", self.url)))
+    }
+
+    // summarize returns a short summary of the note, its frontmatter is left
+    // out
+    pub fn summarize(&self, note: &str) -> Result<String> {
+        let (_, body) = split_frontmatter(note);
+        self.complete(SUMMARIZE_PROMPT, body)
+    }
+
+    // suggest_title returns a title for the text. The reply is cut down to
+    // the words of its first line, the model doesn't always keep to the
+    // prompt and the title names a file.
+    pub fn suggest_title(&self, text: &str) -> Result<String> {
+        let title = self.complete(TITLE_PROMPT, text)?;
+        let title = title
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .replace(['/', '\\'], " ")
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let title: String = title
+            .trim_matches(['"', '\'', '`', '*', '#', ' ', '.'])
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(TITLE_LENGTH)
+            .collect();
+
+        match title.trim().is_empty() {
+            true => Err(Error::PKMError(format!(
+                "{} didn't suggest a title",
+                self.url
+            ))),
+            false => Ok(title.trim().to_string()),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{num::ParseIntError, str::Utf8Error};
+use std::{num::ParseIntError, str::Utf8Error, time::Duration};
 
 use thiserror::Error;
 
@@ -20,6 +20,15 @@ pub enum Error {
     #[error("LSP Error: {0}")]
     LSPError(String),
 
+    #[error("LSP Server Error: {0}")]
+    ServerError(#[from] super::LspServerError),
+
+    #[error("LSP does not support {0}")]
+    Unsupported(String),
+
+    #[error("LSP did not respond within {0:?}")]
+    Timeout(Duration),
+
     #[error("Could not parse int: {0}")]
     ParseIntError(#[from] ParseIntError),
 
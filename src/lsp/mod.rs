@@ -3,26 +3,39 @@ mod notification;
 mod request;
 mod response;
 mod runner_standard;
+mod workspace_edit;
 
 use std::{
+    collections::HashSet,
     path::{Path, PathBuf, absolute},
     str::FromStr,
+    time::Duration,
 };
 
 pub use error::*;
 use lsp_types::{
-    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
-    GotoDefinitionResponse, InitializeParams, PartialResultParams, Position,
-    TextDocumentIdentifier, TextDocumentPositionParams, Uri, WorkDoneProgressParams,
-    WorkspaceFolder,
-    notification::{DidCloseTextDocument, DidOpenTextDocument, Notification as _},
-    request::{GotoDefinition, Initialize, Request as rt},
+    CompletionItem, CompletionParams, CompletionResponse, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, MarkedString, OneOf,
+    PartialResultParams, Position, PublishDiagnosticsParams, RenameParams, ServerCapabilities,
+    TextDocumentIdentifier, TextDocumentPositionParams, Uri, WorkDoneProgressParams, WorkspaceEdit,
+    WorkspaceFolder, WorkspaceSymbolParams, WorkspaceSymbolResponse,
+    notification::{
+        DidCloseTextDocument, DidOpenTextDocument, Exit, Notification as _, PublishDiagnostics,
+    },
+    request::{
+        Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Initialize, Rename,
+        Request as rt, Shutdown, WorkspaceSymbolRequest,
+    },
 };
 pub use notification::*;
 pub use request::*;
 pub use response::*;
 pub use runner_standard::*;
 use serde::Serialize;
+use serde_json::Value;
+pub use workspace_edit::*;
 
 pub trait Requester {
     // send sends the request to the LSP and returns the RequestID for the request
@@ -47,23 +60,83 @@ pub trait Runner {
     #[allow(async_fn_in_trait)]
     async fn response(&mut self, req_id: RequestID) -> Result<Response>;
 
+    // close waits for the LSP to exit after it has been asked to, forcing it
+    // to stop if it takes too long
+    #[allow(async_fn_in_trait)]
+    async fn close(&mut self) -> Result<()>;
+
+    // notifications drains the notifications the LSP has sent since the last call
+    fn notifications(&mut self) -> Vec<ServerNotification>;
+
+    // next_notification waits for the next notification of the LSP, as long
+    // as a response would be waited for. None is returned once the LSP is gone.
+    #[allow(async_fn_in_trait)]
+    async fn next_notification(&mut self) -> Result<Option<ServerNotification>>;
+
     // create a sender for this implementation of the runner
     fn sender(&mut self) -> Result<Self::Sender>;
 }
 
 pub type RequestID = u32;
 
+// RetryPolicy defines how many times the initialize handshake is retried
+// when the LSP does not respond in time, and how long to wait in between
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
 pub struct LSP<R: Runner> {
     runner: R,
     sender: R::Sender,
+    capabilities: ServerCapabilities,
+}
+
+// enabled reports if a capability that is either a flag or a set of options
+// is turned on
+fn enabled<T>(capability: &Option<OneOf<bool, T>>) -> bool {
+    matches!(capability, Some(OneOf::Left(true)) | Some(OneOf::Right(_)))
 }
 
 impl<R: Runner> LSP<R> {
-    pub async fn new<P: AsRef<Path>>(mut runner: R, workspace: P) -> Result<LSP<R>> {
+    pub async fn new<P: AsRef<Path>>(runner: R, workspace: P) -> Result<LSP<R>> {
+        Self::with_options(runner, workspace, RetryPolicy::default(), None).await
+    }
+
+    // with_options creates the LSP, passing the initialization options to the
+    // server and retrying the initialize handshake according to the retry
+    // policy when it times out
+    pub async fn with_options<P: AsRef<Path>>(
+        mut runner: R,
+        workspace: P,
+        retry: RetryPolicy,
+        initialization_options: Option<Value>,
+    ) -> Result<LSP<R>> {
         let sender = runner.sender()?;
-        let mut lsp = LSP { runner, sender };
+        let mut lsp = LSP {
+            runner,
+            sender,
+            capabilities: ServerCapabilities::default(),
+        };
+        let workspace = absolute(workspace)?;
 
-        lsp.init(absolute(workspace)?).await?;
+        let mut attempt = 0;
+        loop {
+            match lsp.init(&workspace, initialization_options.clone()).await {
+                Err(Error::Timeout(after)) if attempt < retry.attempts => {
+                    attempt += 1;
+                    log::warn!(
+                        "LSP did not initialize within {:?}, retrying ({}/{})",
+                        after,
+                        attempt,
+                        retry.attempts
+                    );
+                    tokio::time::sleep(retry.backoff).await;
+                }
+                res => break res?,
+            }
+        }
 
         Ok(lsp)
     }
@@ -96,15 +169,19 @@ impl<R: Runner> LSP<R> {
     // initialize the LSP. Allow deprecated since there are parameters that are
     // deprecated but I have to define them
     #[allow(deprecated)]
-    async fn init<P: AsRef<Path>>(&mut self, workspace: P) -> Result<()> {
-        let _ = self
+    async fn init<P: AsRef<Path>>(
+        &mut self,
+        workspace: P,
+        initialization_options: Option<Value>,
+    ) -> Result<()> {
+        let result: InitializeResult = self
             .request(
                 Initialize::METHOD,
                 InitializeParams {
                     process_id: None,
                     root_path: Some(format!("file://{}", workspace.as_ref().to_string_lossy())),
                     root_uri: Some(uri_from_path(&workspace)?),
-                    initialization_options: None,
+                    initialization_options,
                     capabilities: lsp_types::ClientCapabilities::default(),
                     trace: None,
                     workspace_folders: Some(vec![WorkspaceFolder {
@@ -118,10 +195,98 @@ impl<R: Runner> LSP<R> {
                     },
                 },
             )
-            .await?;
+            .await?
+            .result()?;
+
+        log::debug!("LSP capabilities: {:?}", result.capabilities);
+        self.capabilities = result.capabilities;
         Ok(())
     }
 
+    // capabilities returns what the LSP said it supports when it initialized
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.capabilities
+    }
+
+    // require returns Error::Unsupported for the method when the LSP did not
+    // announce support for it
+    fn require(&self, method: &str, supported: bool) -> Result<()> {
+        if supported {
+            Ok(())
+        } else {
+            Err(Error::Unsupported(method.to_string()))
+        }
+    }
+
+    // close runs the shutdown sequence, asking the LSP to shutdown and then exit,
+    // so it can flush its state before the process goes away
+    pub async fn close(mut self) -> Result<()> {
+        self.request(Shutdown::METHOD, ()).await?.result::<()>()?;
+        self.notify(Exit::METHOD, ()).await?;
+        self.runner.close().await
+    }
+
+    // diagnostics drains the notifications of the LSP and returns the diagnostics
+    // that have been published since the last call. Other notifications are dropped.
+    pub fn diagnostics(&mut self) -> Vec<PublishDiagnosticsParams> {
+        self.runner
+            .notifications()
+            .into_iter()
+            .filter(|n| n.method == PublishDiagnostics::METHOD)
+            .filter_map(|n| match serde_json::from_value(n.params) {
+                Ok(params) => Some(params),
+                Err(err) => {
+                    log::debug!("LSP sent invalid diagnostics: {}", err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // published_diagnostics waits for the LSP to publish the diagnostics of
+    // every one of the opened notes and returns them. When the LSP goes quiet
+    // for longer than the timeout of the runner the diagnostics published so
+    // far are returned.
+    pub async fn published_diagnostics<P: AsRef<Path>>(
+        &mut self,
+        opened: &[P],
+    ) -> Result<Vec<PublishDiagnosticsParams>> {
+        let mut pending = HashSet::new();
+        for path in opened {
+            pending.insert(uri_from_path(path)?.as_local_path());
+        }
+
+        let mut published = Vec::new();
+        while !pending.is_empty() {
+            let notification = match self.runner.next_notification().await {
+                Ok(Some(notification)) => notification,
+                Ok(None) => break,
+                Err(Error::Timeout(after)) => {
+                    log::info!(
+                        "LSP published no diagnostics for {} notes within {:?}",
+                        pending.len(),
+                        after
+                    );
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            if notification.method != PublishDiagnostics::METHOD {
+                continue;
+            }
+
+            match serde_json::from_value::<PublishDiagnosticsParams>(notification.params) {
+                Ok(params) => {
+                    pending.remove(&params.uri.clone().as_local_path());
+                    published.push(params);
+                }
+                Err(err) => log::debug!("LSP sent invalid diagnostics: {}", err),
+            }
+        }
+
+        Ok(published)
+    }
+
     // goto_definition will return
     pub async fn goto_defintion<P: AsRef<Path>>(
         &mut self,
@@ -129,6 +294,10 @@ impl<R: Runner> LSP<R> {
         line: u32,
         character: u32,
     ) -> Result<GotoDefinitionResponse> {
+        self.require(
+            GotoDefinition::METHOD,
+            enabled(&self.capabilities.definition_provider),
+        )?;
         self.request(
             GotoDefinition::METHOD,
             GotoDefinitionParams {
@@ -150,6 +319,175 @@ impl<R: Runner> LSP<R> {
         .result()
     }
 
+    // hover returns the hover information for the position, for wikilinks this
+    // is the content of the linked note
+    pub async fn hover<P: AsRef<Path>>(
+        &mut self,
+        uri: P,
+        line: u32,
+        character: u32,
+    ) -> Result<Option<Hover>> {
+        self.require(
+            HoverRequest::METHOD,
+            !matches!(
+                self.capabilities.hover_provider,
+                None | Some(HoverProviderCapability::Simple(false))
+            ),
+        )?;
+        self.request(
+            HoverRequest::METHOD,
+            HoverParams {
+                text_document_position_params: TextDocumentPositionParams {
+                    text_document: TextDocumentIdentifier {
+                        uri: uri_from_path(uri)?,
+                    },
+                    position: Position { line, character },
+                },
+                work_done_progress_params: WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+            },
+        )
+        .await?
+        .result()
+    }
+
+    // document_symbols returns the symbols of the document, for markdown these
+    // are the headings of the note
+    pub async fn document_symbols<P: AsRef<Path>>(
+        &mut self,
+        uri: P,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        self.require(
+            DocumentSymbolRequest::METHOD,
+            enabled(&self.capabilities.document_symbol_provider),
+        )?;
+        self.request(
+            DocumentSymbolRequest::METHOD,
+            DocumentSymbolParams {
+                text_document: TextDocumentIdentifier {
+                    uri: uri_from_path(uri)?,
+                },
+                work_done_progress_params: WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+                partial_result_params: PartialResultParams {
+                    partial_result_token: None,
+                },
+            },
+        )
+        .await?
+        .result()
+    }
+
+    // workspace_symbols searches the symbols of every document in the workspace,
+    // for markdown these are the note titles and headings
+    pub async fn workspace_symbols<S: Into<String>>(
+        &mut self,
+        query: S,
+    ) -> Result<Option<WorkspaceSymbolResponse>> {
+        self.require(
+            WorkspaceSymbolRequest::METHOD,
+            enabled(&self.capabilities.workspace_symbol_provider),
+        )?;
+        self.request(
+            WorkspaceSymbolRequest::METHOD,
+            WorkspaceSymbolParams {
+                query: query.into(),
+                work_done_progress_params: WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+                partial_result_params: PartialResultParams {
+                    partial_result_token: None,
+                },
+            },
+        )
+        .await?
+        .result()
+    }
+
+    // completion asks the LSP for the completions at the position, within a
+    // wikilink these are the candidate link targets
+    pub async fn completion<P: AsRef<Path>>(
+        &mut self,
+        uri: P,
+        line: u32,
+        character: u32,
+    ) -> Result<Vec<CompletionItem>> {
+        self.require(
+            Completion::METHOD,
+            self.capabilities.completion_provider.is_some(),
+        )?;
+        let resp: Option<CompletionResponse> = self
+            .request(
+                Completion::METHOD,
+                CompletionParams {
+                    text_document_position: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: uri_from_path(uri)?,
+                        },
+                        position: Position { line, character },
+                    },
+                    work_done_progress_params: WorkDoneProgressParams {
+                        work_done_token: None,
+                    },
+                    partial_result_params: PartialResultParams {
+                        partial_result_token: None,
+                    },
+                    context: None,
+                },
+            )
+            .await?
+            .result()?;
+
+        Ok(match resp {
+            Some(CompletionResponse::Array(items)) => items,
+            Some(CompletionResponse::List(list)) => list.items,
+            None => Vec::new(),
+        })
+    }
+
+    // rename asks the LSP to rename the symbol at the position and applies the
+    // returned workspace edit to the files on disk. The applied edit is returned,
+    // None means the LSP had nothing to change.
+    pub async fn rename<P, S>(
+        &mut self,
+        uri: P,
+        line: u32,
+        character: u32,
+        new_name: S,
+    ) -> Result<Option<WorkspaceEdit>>
+    where
+        P: AsRef<Path>,
+        S: Into<String>,
+    {
+        self.require(Rename::METHOD, enabled(&self.capabilities.rename_provider))?;
+        let edit: Option<WorkspaceEdit> = self
+            .request(
+                Rename::METHOD,
+                RenameParams {
+                    text_document_position: TextDocumentPositionParams {
+                        text_document: TextDocumentIdentifier {
+                            uri: uri_from_path(uri)?,
+                        },
+                        position: Position { line, character },
+                    },
+                    new_name: new_name.into(),
+                    work_done_progress_params: WorkDoneProgressParams {
+                        work_done_token: None,
+                    },
+                },
+            )
+            .await?
+            .result()?;
+
+        if let Some(edit) = edit.as_ref() {
+            apply_workspace_edit(edit.clone())?;
+        }
+
+        Ok(edit)
+    }
+
     // open_virtual opens a virtual file, that is a made up file, by notifying the
     // LSP that it has opened a text document
     pub async fn did_open<P, S1, S2>(&mut self, uri: P, content: S1, language: S2) -> Result<()>
@@ -196,6 +534,103 @@ fn uri_from_path<P: AsRef<Path>>(p: P) -> Result<Uri> {
         .or_else(|err| Err(Error::LSPError(format!("error: {}", err.to_string()))))
 }
 
+// hover_text flattens the hover contents into a single markdown string
+pub fn hover_text(hover: Hover) -> String {
+    let marked = |marked: MarkedString| match marked {
+        MarkedString::String(s) => s,
+        MarkedString::LanguageString(ls) => format!("```{}\n{}\n```", ls.language, ls.value),
+    };
+
+    match hover.contents {
+        HoverContents::Scalar(s) => marked(s),
+        HoverContents::Array(a) => a.into_iter().map(marked).collect::<Vec<_>>().join("\n\n"),
+        HoverContents::Markup(m) => m.value,
+    }
+}
+
+// OutlineItem is a single entry in the outline of a document
+#[derive(Debug, Clone)]
+pub struct OutlineItem {
+    pub name: String,
+    pub depth: usize,
+    // the zero based line the symbol starts on
+    pub line: u32,
+}
+
+// outline flattens the document symbols into a list ordered by line. Flat responses
+// don't carry the hierarchy so every item is given a depth of 0.
+pub fn outline(symbols: DocumentSymbolResponse) -> Vec<OutlineItem> {
+    fn walk(symbols: Vec<DocumentSymbol>, depth: usize, items: &mut Vec<OutlineItem>) {
+        for symbol in symbols {
+            items.push(OutlineItem {
+                name: symbol.name,
+                depth,
+                line: symbol.range.start.line,
+            });
+
+            if let Some(children) = symbol.children {
+                walk(children, depth + 1, items);
+            }
+        }
+    }
+
+    let mut items = Vec::new();
+    match symbols {
+        DocumentSymbolResponse::Nested(symbols) => walk(symbols, 0, &mut items),
+        DocumentSymbolResponse::Flat(symbols) => {
+            for symbol in symbols {
+                items.push(OutlineItem {
+                    name: symbol.name,
+                    depth: 0,
+                    line: symbol.location.range.start.line,
+                })
+            }
+        }
+    }
+
+    items.sort_by_key(|item| item.line);
+    items
+}
+
+// SymbolLocation is a symbol found in the workspace
+#[derive(Debug, Clone)]
+pub struct SymbolLocation {
+    pub name: String,
+    pub path: PathBuf,
+    // the zero based line the symbol starts on, symbols without a range start
+    // at the top of the document
+    pub line: u32,
+}
+
+// symbol_locations flattens the workspace symbols into their locations
+pub fn symbol_locations(symbols: WorkspaceSymbolResponse) -> Vec<SymbolLocation> {
+    match symbols {
+        WorkspaceSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .map(|symbol| SymbolLocation {
+                name: symbol.name,
+                line: symbol.location.range.start.line,
+                path: symbol.location.uri.as_local_path(),
+            })
+            .collect(),
+        WorkspaceSymbolResponse::Nested(symbols) => symbols
+            .into_iter()
+            .map(|symbol| match symbol.location {
+                OneOf::Left(location) => SymbolLocation {
+                    name: symbol.name,
+                    line: location.range.start.line,
+                    path: location.uri.as_local_path(),
+                },
+                OneOf::Right(location) => SymbolLocation {
+                    name: symbol.name,
+                    line: 0,
+                    path: location.uri.as_local_path(),
+                },
+            })
+            .collect(),
+    }
+}
+
 pub trait AsLocalPath {
     fn as_local_path(self) -> PathBuf;
 }
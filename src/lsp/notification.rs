@@ -1,4 +1,5 @@
-use serde::{Serialize, ser::SerializeMap};
+use serde::{Deserialize, Serialize, ser::SerializeMap};
+use serde_json::Value;
 
 use super::Result;
 
@@ -31,3 +32,13 @@ impl<T: Serialize> Serialize for Notification<T> {
         map.end()
     }
 }
+
+// ServerNotification is a message the LSP sent without being asked, such as
+// diagnostics, log messages and progress
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServerNotification {
+    pub method: String,
+
+    #[serde(default)]
+    pub params: Value,
+}
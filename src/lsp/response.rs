@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 
-use crate::lsp::Error;
-
 use super::Result;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::value::Value;
+use thiserror::Error;
+
+// PARSE_ERROR is the JSON-RPC code for a response we could not make sense of
+pub const PARSE_ERROR: i64 = -32700;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Response {
@@ -30,7 +32,18 @@ pub enum ResponsePayload {
     #[serde(rename = "result")]
     Result(Value),
     #[serde(rename = "error")]
-    Error { code: isize, message: String },
+    Error(LspServerError),
+}
+
+// LspServerError is the JSON-RPC error object the server responds with when
+// a request fails
+#[derive(Deserialize, Serialize, Debug, Clone, Error)]
+#[error("{message} ({code})")]
+pub struct LspServerError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
 }
 
 impl Response {
@@ -41,9 +54,22 @@ impl Response {
     }
 
     pub fn result<D: DeserializeOwned>(self) -> Result<D> {
+        Ok(self.into_result()?)
+    }
+
+    // into_result returns the result of the response, or the error the server
+    // responded with. A result that does not deserialize into D is reported
+    // as a parse error.
+    pub fn into_result<D: DeserializeOwned>(self) -> std::result::Result<D, LspServerError> {
         match self.payload {
-            ResponsePayload::Result(result) => Ok(serde_json::from_value(result)?),
-            ResponsePayload::Error { code: _, message } => Err(Error::LSPError(message)),
+            ResponsePayload::Result(result) => {
+                serde_json::from_value(result).map_err(|err| LspServerError {
+                    code: PARSE_ERROR,
+                    message: err.to_string(),
+                    data: None,
+                })
+            }
+            ResponsePayload::Error(err) => Err(err),
         }
     }
 }
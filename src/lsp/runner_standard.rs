@@ -8,29 +8,50 @@ use std::{
         Arc, Mutex,
         atomic::{AtomicU32, Ordering},
     },
+    time::Duration,
 };
 
 use serde::Serialize;
 use tokio::{
     io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
     process::{Child, ChildStdin, Command},
-    sync::mpsc::{Receiver, Sender, channel},
+    sync::mpsc::{
+        Receiver, Sender, UnboundedReceiver, UnboundedSender, channel, unbounded_channel,
+    },
 };
 
 use crate::lsp::Response;
 
-use super::{Error, Notification, Request, RequestID, Requester, Result, Runner};
+use super::{
+    Error, Notification, Request, RequestID, Requester, Result, Runner, ServerNotification,
+};
+
+// DEFAULT_TIMEOUT is how long to wait for a response before giving up
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct StandardRunnerBuilder {
     cmd: Command,
+    timeout: Option<Duration>,
 }
 
 impl StandardRunnerBuilder {
     // new creates a new StandardRunnerBuilder.
     pub fn new<S: AsRef<OsStr>>(cmd: S) -> Self {
         let mut cmd = Command::new(cmd);
-        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
-        Self { cmd }
+        // never leave the LSP running after we are gone
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+        Self {
+            cmd,
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+
+    // timeout sets how long to wait for each response, None waits forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
     }
 
     // working dir set the working directory for the LSP
@@ -59,7 +80,7 @@ impl StandardRunnerBuilder {
     // a runner
     pub fn spawn(mut self) -> Result<StandardRunner> {
         let child = self.cmd.spawn()?;
-        Ok(StandardRunner::new(child))
+        Ok(StandardRunner::new(child, self.timeout))
     }
 }
 
@@ -67,15 +88,17 @@ impl StandardRunnerBuilder {
 // run as a subprocess of the application
 pub struct StandardRunner {
     responses: HashMap<u32, Response>,
-    _child: Child,
+    child: Child,
     recv: Receiver<Response>,
+    notifications: UnboundedReceiver<ServerNotification>,
     request: Arc<AtomicU32>,
     writer: Arc<Mutex<ChildStdin>>,
+    timeout: Option<Duration>,
 }
 
 impl StandardRunner {
-    fn new(mut child: Child) -> Self {
-        let (mut reader, recv) =
+    fn new(mut child: Child, timeout: Option<Duration>) -> Self {
+        let (mut reader, recv, notifications) =
             StandardRunnerReader::new(child.stdout.take().expect("stdout will be there"));
         let writer = Arc::new(Mutex::new(child.stdin.take().expect("stdin will be there")));
 
@@ -88,16 +111,15 @@ impl StandardRunner {
         StandardRunner {
             responses: HashMap::new(),
             recv,
+            notifications,
             request: Arc::new(AtomicU32::new(0)),
             writer,
-            _child: child,
+            child,
+            timeout,
         }
     }
-}
 
-impl Runner for StandardRunner {
-    type Sender = StandardRunnerWriter;
-    async fn response(&mut self, r: RequestID) -> Result<Response> {
+    async fn wait_for(&mut self, r: RequestID) -> Result<Response> {
         loop {
             match self.recv.recv().await {
                 Some(resp) => {
@@ -114,6 +136,53 @@ impl Runner for StandardRunner {
             }
         }
     }
+}
+
+impl Runner for StandardRunner {
+    type Sender = StandardRunnerWriter;
+    async fn response(&mut self, r: RequestID) -> Result<Response> {
+        // the response may have arrived while waiting on an earlier request
+        if let Some(response) = self.responses.remove(&r) {
+            return Ok(response);
+        }
+
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.wait_for(r))
+                .await
+                .map_err(|_| Error::Timeout(timeout))?,
+            None => self.wait_for(r).await,
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        match tokio::time::timeout(Duration::from_secs(2), self.child.wait()).await {
+            Ok(status) => {
+                log::debug!("LSP exited with {}", status?);
+            }
+            Err(_) => {
+                log::warn!("LSP did not exit after shutdown, killing it");
+                self.child.kill().await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn notifications(&mut self) -> Vec<ServerNotification> {
+        let mut notifications = Vec::new();
+        while let Ok(notification) = self.notifications.try_recv() {
+            notifications.push(notification);
+        }
+        notifications
+    }
+
+    async fn next_notification(&mut self) -> Result<Option<ServerNotification>> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.notifications.recv())
+                .await
+                .map_err(|_| Error::Timeout(timeout)),
+            None => Ok(self.notifications.recv().await),
+        }
+    }
 
     fn sender(&mut self) -> Result<StandardRunnerWriter> {
         Ok(StandardRunnerWriter {
@@ -123,38 +192,73 @@ impl Runner for StandardRunner {
     }
 }
 
+// Message is anything the LSP can send over stdout
+enum Message {
+    Response(Response),
+    Notification(ServerNotification),
+}
+
 struct StandardRunnerReader<R: AsyncRead + Unpin> {
     sync: Sender<Response>,
+    notify: UnboundedSender<ServerNotification>,
     reader: BufReader<R>,
 }
 
 impl<R: AsyncRead + Unpin> StandardRunnerReader<R> {
-    fn new(reader: R) -> (Self, Receiver<Response>) {
+    fn new(
+        reader: R,
+    ) -> (
+        Self,
+        Receiver<Response>,
+        UnboundedReceiver<ServerNotification>,
+    ) {
         let (sync, rec) = channel(100);
+        // notifications are unbounded so a caller that never drains them can't
+        // block the responses
+        let (notify, notifications) = unbounded_channel();
 
         (
             StandardRunnerReader {
                 sync,
+                notify,
                 reader: BufReader::new(reader),
             },
             rec,
+            notifications,
         )
     }
 
     async fn start(&mut self) -> Result<()> {
         loop {
-            // if there is a read failure of some kind we return and close the routine
-            if let Ok(res) = self.read_response().await {
-                self.sync.send(res).await?;
+            // once the LSP closed its output, after exiting, or reading from it
+            // fails we return and close the routine. A malformed message is
+            // skipped.
+            match self.read_message().await {
+                Ok(Some(Message::Response(res))) => self.sync.send(res).await?,
+                Ok(Some(Message::Notification(notification))) => {
+                    log_notification(&notification);
+                    // nobody listening for notifications is fine
+                    let _ = self.notify.send(notification);
+                }
+                Ok(None) => {
+                    log::debug!("LSP closed its output");
+                    return Ok(());
+                }
+                Err(Error::IOError(err)) => return Err(err.into()),
+                Err(err) => log::debug!("LSP could not read message: {}", err),
             }
             // if their is no reciever because it was dropped we return and close the routine
         }
     }
 
-    async fn read_response(&mut self) -> Result<Response> {
+    // read_message reads the next message of the LSP, None when its output
+    // was closed
+    async fn read_message(&mut self) -> Result<Option<Message>> {
         let mut buf = String::new();
         let mut headers = HashMap::new();
-        self.reader.read_line(&mut buf).await?;
+        if self.reader.read_line(&mut buf).await? == 0 {
+            return Ok(None);
+        }
 
         while !buf.trim_end().is_empty() {
             let (key, value) = buf
@@ -175,8 +279,37 @@ impl<R: AsyncRead + Unpin> StandardRunnerReader<R> {
         let mut body = vec![0; length];
         self.reader.read_exact(&mut body).await?;
 
-        log::debug!("LSP Recieved:\n{}", from_utf8(&body)?);
-        Ok(Response::new(headers, &body)?)
+        log::trace!("LSP Recieved:\n{}", from_utf8(&body)?);
+
+        // anything with a method was initiated by the server, requests from the
+        // server are treated as notifications since we never answer them
+        let value: serde_json::Value = serde_json::from_slice(&body)?;
+        if value.get("method").is_some() {
+            return Ok(Some(Message::Notification(serde_json::from_value(value)?)));
+        }
+
+        Ok(Some(Message::Response(Response::new(headers, &body)?)))
+    }
+}
+
+// log_notification forwards the log messages of the LSP to our logger
+fn log_notification(notification: &ServerNotification) {
+    if notification.method != "window/logMessage" && notification.method != "window/showMessage" {
+        return;
+    }
+
+    let message = notification
+        .params
+        .get("message")
+        .and_then(|m| m.as_str())
+        .unwrap_or_default();
+
+    // 1 = error, 2 = warning, 3 = info, 4 = log
+    match notification.params.get("type").and_then(|t| t.as_u64()) {
+        Some(1) => log::error!("LSP: {}", message),
+        Some(2) => log::warn!("LSP: {}", message),
+        Some(3) => log::info!("LSP: {}", message),
+        _ => log::debug!("LSP: {}", message),
     }
 }
 
@@ -201,7 +334,7 @@ impl Requester for StandardRunnerWriter {
         let req_b = serde_json::to_string(&msg)?;
 
         let req = format!("Content-Length:{}\r\n\r\n{}", req_b.len(), &req_b);
-        log::debug!("LSP Notification:\n{}", req);
+        log::trace!("LSP Notification:\n{}", req);
         let mut writer = self.writer.lock().unwrap();
         writer.write_all(req.as_bytes()).await?;
         writer.flush().await?;
@@ -220,7 +353,7 @@ impl Requester for StandardRunnerWriter {
         let req_b = serde_json::to_string(&msg)?;
 
         let req = format!("Content-Length:{}\r\n\r\n{}", req_b.len(), &req_b);
-        log::debug!("LSP Request:\n{}", req);
+        log::trace!("LSP Request:\n{}", req);
         let mut writer = self.writer.lock().unwrap();
         writer.write_all(req.as_bytes()).await?;
         writer.flush().await?;
@@ -228,3 +361,22 @@ impl Requester for StandardRunnerWriter {
         Ok(id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_reading_once_the_output_is_closed() {
+        let body =
+            r#"{"jsonrpc":"2.0","method":"window/logMessage","params":{"type":4,"message":"hi"}}"#;
+        let output = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let (mut reader, _responses, mut notifications) =
+            StandardRunnerReader::new(output.as_bytes());
+
+        reader.start().await.unwrap();
+        let notification = notifications.try_recv().unwrap();
+        assert_eq!(notification.method, "window/logMessage");
+        assert!(notifications.try_recv().is_err());
+    }
+}
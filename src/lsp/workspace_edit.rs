@@ -0,0 +1,175 @@
+use std::fs;
+use std::path::PathBuf;
+
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, Position, ResourceOp, TextEdit, Uri,
+    WorkspaceEdit,
+};
+
+use super::{AsLocalPath, Error, Result};
+
+// apply_workspace_edit writes the changes described by the workspace edit to disk.
+// Edits for files that do not exist, such as virtual documents, are skipped.
+pub fn apply_workspace_edit(edit: WorkspaceEdit) -> Result<()> {
+    if let Some(changes) = edit.changes {
+        for (uri, edits) in changes {
+            apply_text_edits(uri, edits)?;
+        }
+    }
+
+    match edit.document_changes {
+        Some(DocumentChanges::Edits(edits)) => {
+            for edit in edits {
+                apply_text_edits(edit.text_document.uri, unannotate(edit.edits))?;
+            }
+        }
+        Some(DocumentChanges::Operations(operations)) => {
+            for operation in operations {
+                match operation {
+                    DocumentChangeOperation::Edit(edit) => {
+                        apply_text_edits(edit.text_document.uri, unannotate(edit.edits))?
+                    }
+                    DocumentChangeOperation::Op(op) => apply_resource_op(op)?,
+                }
+            }
+        }
+        None => (),
+    }
+
+    Ok(())
+}
+
+// changed_paths returns the files the workspace edit changes as the path
+// before and after the edit, only renamed files have two different paths
+pub fn changed_paths(edit: &WorkspaceEdit) -> Vec<(PathBuf, PathBuf)> {
+    let same = |uri: &Uri| {
+        let path = uri.clone().as_local_path();
+        (path.clone(), path)
+    };
+
+    let mut changed: Vec<(PathBuf, PathBuf)> = edit
+        .changes
+        .iter()
+        .flat_map(|changes| changes.keys())
+        .map(same)
+        .collect();
+
+    match edit.document_changes.as_ref() {
+        Some(DocumentChanges::Edits(edits)) => {
+            changed.extend(edits.iter().map(|edit| same(&edit.text_document.uri)));
+        }
+        Some(DocumentChanges::Operations(operations)) => {
+            for operation in operations {
+                changed.push(match operation {
+                    DocumentChangeOperation::Edit(edit) => same(&edit.text_document.uri),
+                    DocumentChangeOperation::Op(ResourceOp::Create(create)) => same(&create.uri),
+                    DocumentChangeOperation::Op(ResourceOp::Rename(rename)) => (
+                        rename.old_uri.clone().as_local_path(),
+                        rename.new_uri.clone().as_local_path(),
+                    ),
+                    DocumentChangeOperation::Op(ResourceOp::Delete(delete)) => same(&delete.uri),
+                });
+            }
+        }
+        None => (),
+    }
+
+    changed
+}
+
+fn unannotate(edits: Vec<OneOf<TextEdit, lsp_types::AnnotatedTextEdit>>) -> Vec<TextEdit> {
+    edits
+        .into_iter()
+        .map(|edit| match edit {
+            OneOf::Left(edit) => edit,
+            OneOf::Right(edit) => edit.text_edit,
+        })
+        .collect()
+}
+
+fn apply_resource_op(op: ResourceOp) -> Result<()> {
+    match op {
+        ResourceOp::Create(create) => {
+            let path = create.uri.as_local_path();
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            log::debug!("LSP creating {:?}", &path);
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+        }
+        ResourceOp::Rename(rename) => {
+            let (from, to) = (
+                rename.old_uri.as_local_path(),
+                rename.new_uri.as_local_path(),
+            );
+            if !from.exists() {
+                log::warn!("LSP rename skipped, {:?} does not exist", &from);
+                return Ok(());
+            }
+            log::debug!("LSP renaming {:?} to {:?}", &from, &to);
+            fs::rename(from, to)?;
+        }
+        ResourceOp::Delete(delete) => {
+            let path = delete.uri.as_local_path();
+            log::debug!("LSP deleting {:?}", &path);
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_text_edits(uri: Uri, mut edits: Vec<TextEdit>) -> Result<()> {
+    let path = uri.as_local_path();
+    if !path.is_file() {
+        log::warn!("LSP edit skipped, {:?} does not exist", &path);
+        return Ok(());
+    }
+
+    let mut content = fs::read_to_string(&path)?;
+
+    // apply the edits from the bottom of the file up so the earlier offsets
+    // remain valid
+    edits.sort_by(|a, b| b.range.start.cmp(&a.range.start));
+    for edit in edits {
+        let start = offset(&content, edit.range.start)?;
+        let end = offset(&content, edit.range.end)?;
+        content.replace_range(start..end, &edit.new_text);
+    }
+
+    log::debug!("LSP editing {:?}", &path);
+    fs::write(path, content)?;
+    Ok(())
+}
+
+// offset converts an LSP position, which counts characters in utf-16 code units,
+// into a byte offset within the content
+fn offset(content: &str, position: Position) -> Result<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += content[line_start..]
+            .find('\n')
+            .ok_or_else(|| Error::LSPError(format!("line {} out of range", position.line)))?
+            + 1;
+    }
+
+    let line = &content[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= position.character {
+            return Ok(line_start + idx);
+        }
+        units += c.len_utf16() as u32;
+    }
+
+    Ok(line_start + line.len())
+}
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use chrono::NaiveDate;
+
+use crate::{FLEETING_TAG, INDEX_DIR, PKM, Result, ZettelID, last_commit, tasks};
+
+// VaultMetrics are the numbers worth alerting on when the note taking habit
+// or the sync of the repo breaks
+#[derive(Debug, Default)]
+pub struct VaultMetrics {
+    pub notes: u64,
+    pub zettels: u64,
+    pub fleeting: u64,
+    pub open_tasks: u64,
+    pub index_age: Option<Duration>,
+    pub last_daily: Option<NaiveDate>,
+    pub backup_age: Option<Duration>,
+}
+
+impl VaultMetrics {
+    // collect walks the repo and gathers the metrics
+    pub fn collect(pkm: &PKM) -> Result<Self> {
+        let now = SystemTime::now();
+        let age = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+        let mut metrics = Self::default();
+
+        for path in pkm.notes() {
+            metrics.notes += 1;
+            metrics.open_tasks += tasks(&fs::read_to_string(&path)?)
+                .iter()
+                .filter(|task| !task.done)
+                .count() as u64;
+
+            let id = ZettelID::from_path(&path);
+            if path.starts_with(&pkm.zettel_dir) {
+                metrics.zettels += 1;
+                if id.has_tag(FLEETING_TAG) {
+                    metrics.fleeting += 1;
+                }
+            }
+
+            if path.starts_with(&pkm.daily_dir) {
+                let date = id
+                    .tags()
+                    .find_map(|tag| NaiveDate::parse_from_str(tag, "%Y-%m-%d").ok());
+                metrics.last_daily = metrics.last_daily.max(date);
+            }
+        }
+
+        // tantivy rewrites meta.json on every commit
+        let meta = pkm.root.join(INDEX_DIR).join("meta.json");
+        if meta.is_file() {
+            metrics.index_age = Some(age(fs::metadata(&meta)?.modified()?));
+        }
+
+        metrics.backup_age = last_commit(pkm.root.as_path())?.map(age);
+        Ok(metrics)
+    }
+
+    // prometheus renders the metrics in the prometheus text format, metrics
+    // that are unknown are left out
+    pub fn prometheus(&self) -> String {
+        let mut out = format!(
+            "# TYPE pkm_notes gauge\n\
+             pkm_notes {}\n\
+             # TYPE pkm_zettels gauge\n\
+             pkm_zettels {}\n\
+             # TYPE pkm_fleeting gauge\n\
+             pkm_fleeting {}\n\
+             # TYPE pkm_open_tasks gauge\n\
+             pkm_open_tasks {}\n",
+            self.notes, self.zettels, self.fleeting, self.open_tasks
+        );
+
+        if let Some(age) = self.index_age {
+            out.push_str(&format!(
+                "# TYPE pkm_index_age_seconds gauge\npkm_index_age_seconds {}\n",
+                age.as_secs()
+            ));
+        }
+
+        if let Some(date) = self.last_daily {
+            let timestamp = date
+                .and_hms_opt(0, 0, 0)
+                .map(|dt| dt.and_utc().timestamp())
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "# TYPE pkm_last_daily_timestamp_seconds gauge\npkm_last_daily_timestamp_seconds {}\n",
+                timestamp
+            ));
+        }
+
+        if let Some(age) = self.backup_age {
+            out.push_str(&format!(
+                "# TYPE pkm_backup_age_seconds gauge\npkm_backup_age_seconds {}\n",
+                age.as_secs()
+            ));
+        }
+
+        out
+    }
+}
+
+// write_textfile writes the metrics for the textfile collector of the node
+// exporter. The file is swapped in whole so the collector never reads half
+// of it.
+pub fn write_textfile<P: AsRef<Path>>(pkm: &PKM, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, VaultMetrics::collect(pkm)?.prometheus())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
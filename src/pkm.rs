@@ -1,17 +1,68 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
 use std::path::{Path, PathBuf, absolute};
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
-use crate::lsp::{AsLocalPath, LSP, Runner, StandardRunner, StandardRunnerBuilder};
-use crate::{ImageBuilder, Result, Zettel, ZettelBuilder, ZettelIDBuilder};
-use chrono::{DateTime, Local};
+use crate::lsp::{
+    AsLocalPath, LSP, RetryPolicy, Runner, StandardRunner, StandardRunnerBuilder, changed_paths,
+};
+use crate::{
+    Agenda, AttachmentBuilder, Collection, DEFAULT_COMMIT_MESSAGE_TEMPLATE, DEFAULT_DAILY_TEMPLATE,
+    DEFAULT_PDF_TEXT_COMMAND, DEFAULT_TRANSCRIBE_COMMAND, DEFAULT_ZETTEL_TEMPLATE, EMBEDDINGS_FILE,
+    Embedder, Error, FAVORITES_FILE, FLEETING_TAG, Finder, FinderAction, Frontmatter, Hook,
+    IDScheme, INDEX_DIR, ImageBuilder, Llm, MEETING_TAG, META_TEMPLATE, PROJECT_TAG, PreviewLayout,
+    ProjectInfo, Projects, Result, Route, Task, Zettel, ZettelBuilder, ZettelID, ZettelIDBuilder,
+    commit_files, commit_message, image_links, nested_tag, open_task_items, path_to_id,
+    register_filters, register_functions, resolve_local_link, resolve_root_link,
+    rewrite_markdown_links, rewrite_wikilinks, set_highlighting_dirs, tasks, template_file,
+    toplevel, touched_files, wikilinks,
+};
+use chrono::{DateTime, Local, NaiveDate};
 use clap::ArgMatches;
+use ignore::WalkBuilder;
 use lsp_types::GotoDefinitionResponse;
+use serde_json::Value;
 use tera::{Context, Tera};
+use walkdir::WalkDir;
 
 pub const DEFAULT_IMAGE_DIR: &str = "imgs";
 pub const DEFAULT_TEMPLATE_DIR: &str = "tmpl";
 pub const DEFAULT_ZETTEL_DIR: &str = "zettels";
 pub const DEFAULT_DAILY_DIR: &str = "daily";
+pub const DEFAULT_ARCHIVE_DIR: &str = "archive";
+pub const DEFAULT_INBOX_DIR: &str = "inbox";
+pub const DEFAULT_COLLECTION_DIR: &str = "collections";
+pub const DEFAULT_ATTACHMENT_DIR: &str = "attachments";
+pub const SCRIPTS_DIR: &str = "scripts";
+// TRASH_DIR is the directory, relative to the root directory, removed notes
+// are kept in until the trash is emptied
+pub const TRASH_DIR: &str = ".trash";
+pub const GITIGNORE_FILE: &str = ".gitignore";
+// PKMIGNORE_FILE holds the paths, in the gitignore syntax, that are left out
+// of the index, the doctor checks and the links between the notes
+pub const PKMIGNORE_FILE: &str = ".pkmignore";
+pub const CHANGELOG_FILE: &str = "CHANGELOG.md";
+pub const DEFAULT_LSP_COMMAND: &str = "markdown-oxide";
+// CARRIED_OVER_HEADING is the section of the daily the open tasks of the
+// previous daily are carried over into
+pub const CARRIED_OVER_HEADING: &str = "Carried over";
+// ATTENDEES_KEY is the frontmatter key holding the attendees of a meeting
+pub const ATTENDEES_KEY: &str = "attendees";
+// PARENT_KEY is the frontmatter key linking a child note to its parent
+pub const PARENT_KEY: &str = "parent";
+// CHILDREN_HEADING is the section of a parent note its children are linked in
+pub const CHILDREN_HEADING: &str = "Children";
+// ALIASES_KEY is the frontmatter key holding the other names of a note
+pub const ALIASES_KEY: &str = "aliases";
+// TAGS_KEY is the frontmatter key holding the tags of a note
+pub const TAGS_KEY: &str = "tags";
+// ARCHIVED_KEY is the frontmatter key marking a note as archived
+pub const ARCHIVED_KEY: &str = "archived";
+// UNASSIGNED groups the meeting actions that mention none of the attendees
+pub const UNASSIGNED: &str = "unassigned";
 
 pub struct PKMBuilder {
     root: PathBuf,
@@ -19,6 +70,31 @@ pub struct PKMBuilder {
     daily_dir: Option<PathBuf>,
     image_dir: Option<PathBuf>,
     zettel_dir: Option<PathBuf>,
+    archive_dir: Option<PathBuf>,
+    inbox_dir: Option<PathBuf>,
+    collection_dir: Option<PathBuf>,
+    attachment_dir: Option<PathBuf>,
+    pdf_text_command: String,
+    transcribe_command: String,
+    lsp_timeout: Option<Duration>,
+    lsp_retry: RetryPolicy,
+    lsp_command: String,
+    lsp_args: Vec<String>,
+    lsp_init_options: Option<Value>,
+    changelog: bool,
+    auto_commit: bool,
+    commit_template: String,
+    json: bool,
+    finder_bindings: Vec<(String, FinderAction)>,
+    finder_layout: PreviewLayout,
+    theme: Option<String>,
+    routes: Vec<Route>,
+    rollover: bool,
+    id_scheme: IDScheme,
+    embedder: Option<Embedder>,
+    llm: Option<Llm>,
+    llm_model: Option<String>,
+    llm_api_key: Option<String>,
 }
 
 impl PKMBuilder {
@@ -33,6 +109,31 @@ impl PKMBuilder {
             daily_dir: None,
             image_dir: None,
             zettel_dir: None,
+            archive_dir: None,
+            inbox_dir: None,
+            collection_dir: None,
+            attachment_dir: None,
+            pdf_text_command: DEFAULT_PDF_TEXT_COMMAND.to_string(),
+            transcribe_command: DEFAULT_TRANSCRIBE_COMMAND.to_string(),
+            lsp_timeout: Some(crate::lsp::DEFAULT_TIMEOUT),
+            lsp_retry: RetryPolicy::default(),
+            lsp_command: DEFAULT_LSP_COMMAND.to_string(),
+            lsp_args: Vec::new(),
+            lsp_init_options: None,
+            changelog: false,
+            auto_commit: false,
+            commit_template: DEFAULT_COMMIT_MESSAGE_TEMPLATE.to_string(),
+            json: false,
+            finder_bindings: Vec::new(),
+            finder_layout: PreviewLayout::default(),
+            theme: None,
+            routes: Vec::new(),
+            rollover: false,
+            id_scheme: IDScheme::default(),
+            embedder: None,
+            llm: None,
+            llm_model: None,
+            llm_api_key: None,
         })
     }
 
@@ -85,11 +186,283 @@ impl PKMBuilder {
         self
     }
 
+    pub fn with_archive_dir<P>(mut self, archive_dir: Option<P>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.archive_dir = archive_dir.map(|f| {
+            let mut path = PathBuf::from(&self.root);
+            path.push(f.as_ref());
+            path
+        });
+        self
+    }
+
+    // with_attachment_dir sets the directory, relative to the root directory,
+    // files other than images are attached in
+    pub fn with_attachment_dir<P>(mut self, attachment_dir: Option<P>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.attachment_dir = attachment_dir.map(|f| self.root.join(f.as_ref()));
+        self
+    }
+
+    // with_pdf_text_command sets the command extracting the text of attached
+    // pdfs, it is run with the pdf and - and prints the text
+    pub fn with_pdf_text_command<S: Into<String>>(mut self, command: Option<S>) -> Self {
+        if let Some(command) = command {
+            self.pdf_text_command = command.into();
+        }
+        self
+    }
+
+    // with_transcribe_command sets the command transcribing audio memos, it
+    // is run with the recording and prints the transcript
+    pub fn with_transcribe_command<S: Into<String>>(mut self, command: Option<S>) -> Self {
+        if let Some(command) = command {
+            self.transcribe_command = command.into();
+        }
+        self
+    }
+
+    pub fn with_inbox_dir<P>(mut self, inbox_dir: Option<P>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.inbox_dir = inbox_dir.map(|f| {
+            let mut path = PathBuf::from(&self.root);
+            path.push(f.as_ref());
+            path
+        });
+        self
+    }
+
+    pub fn with_collection_dir<P>(mut self, collection_dir: Option<P>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.collection_dir = collection_dir.map(|f| {
+            let mut path = PathBuf::from(&self.root);
+            path.push(f.as_ref());
+            path
+        });
+        self
+    }
+
+    // with_lsp_timeout sets how long to wait on each LSP response, None
+    // waits forever
+    pub fn with_lsp_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.lsp_timeout = timeout;
+        self
+    }
+
+    // with_lsp_retry sets how the LSP initialize handshake is retried
+    pub fn with_lsp_retry(mut self, retry: RetryPolicy) -> Self {
+        self.lsp_retry = retry;
+        self
+    }
+
+    // with_lsp_command sets the markdown language server to run along with
+    // its arguments
+    pub fn with_lsp_command<S, I>(mut self, command: Option<S>, args: I) -> Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        if let Some(command) = command {
+            self.lsp_command = command.into();
+        }
+        self.lsp_args = args.into_iter().map(|arg| arg.into()).collect();
+        self
+    }
+
+    // with_lsp_init_options sets the initialization options sent to the
+    // language server
+    pub fn with_lsp_init_options(mut self, options: Option<Value>) -> Self {
+        self.lsp_init_options = options;
+        self
+    }
+
+    // with_changelog enables recording every change made through pkm in the
+    // changelog note of the repo
+    pub fn with_changelog(mut self, changelog: bool) -> Self {
+        self.changelog = changelog;
+        self
+    }
+
+    // with_auto_commit enables committing the files changed by a command
+    pub fn with_auto_commit(mut self, auto_commit: bool) -> Self {
+        self.auto_commit = auto_commit;
+        self
+    }
+
+    // with_commit_template sets the tera template of the commit messages of
+    // the auto commits and pkm repo sync
+    pub fn with_commit_template<S: Into<String>>(mut self, template: Option<S>) -> Self {
+        if let Some(template) = template {
+            self.commit_template = template.into();
+        }
+        self
+    }
+
+    // with_json makes the commands print json instead of text
+    pub fn with_json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    // with_finder_bindings sets the keys bound to actions in the finder
+    pub fn with_finder_bindings<I>(mut self, bindings: I) -> Self
+    where
+        I: IntoIterator<Item = (String, FinderAction)>,
+    {
+        self.finder_bindings = bindings.into_iter().collect();
+        self
+    }
+
+    // with_finder_layout sets how the preview is shown in the finder
+    pub fn with_finder_layout(mut self, layout: PreviewLayout) -> Self {
+        self.finder_layout = layout;
+        self
+    }
+
+    // with_theme sets the syntax highlighting theme, overriding the one
+    // matching the background of the terminal
+    pub fn with_theme<S: Into<String>>(mut self, theme: Option<S>) -> Self {
+        self.theme = theme.map(|t| t.into());
+        self
+    }
+
+    // with_routes sets the templates and directories of the zettels with a
+    // tag, the directories are relative to the root directory
+    pub fn with_routes<I>(mut self, routes: I) -> Self
+    where
+        I: IntoIterator<Item = Route>,
+    {
+        self.routes = routes
+            .into_iter()
+            .map(|mut route| {
+                route.dir = route.dir.map(|dir| self.root.join(dir));
+                route
+            })
+            .collect();
+        self
+    }
+
+    // with_rollover carries the open tasks of the previous daily over into
+    // new dailies
+    pub fn with_rollover(mut self, rollover: bool) -> Self {
+        self.rollover = rollover;
+        self
+    }
+
+    // with_id_scheme sets how the unique part of new ids is made
+    pub fn with_id_scheme(mut self, scheme: IDScheme) -> Self {
+        self.id_scheme = scheme;
+        self
+    }
+
+    // with_embedder sets what turns the notes into vectors for semantic
+    // search
+    pub fn with_embedder(mut self, embedder: Option<Embedder>) -> Self {
+        self.embedder = embedder;
+        self
+    }
+
+    // with_llm sets the chat completions endpoint summaries and titles are
+    // asked from, without one the llm of .pkm/llm.json is used if there is one
+    pub fn with_llm(mut self, llm: Option<Llm>) -> Self {
+        self.llm = llm;
+        self
+    }
+
+    // with_llm_model overrides the model asked for from the llm
+    pub fn with_llm_model<S: Into<String>>(mut self, model: Option<S>) -> Self {
+        self.llm_model = model.map(|m| m.into());
+        self
+    }
+
+    // with_llm_api_key sets the bearer token sent to the llm, it is never
+    // kept in .pkm/llm.json which is committed with the notes
+    pub fn with_llm_api_key<S: Into<String>>(mut self, api_key: Option<S>) -> Self {
+        self.llm_api_key = api_key.map(|k| k.into());
+        self
+    }
+
     pub fn parse_args(self, args: &ArgMatches) -> Self {
+        // a timeout of 0 disables the timeout
+        let timeout = args
+            .get_one::<u64>("LSP_TIMEOUT")
+            .map(|secs| Duration::from_secs(*secs))
+            .unwrap_or(crate::lsp::DEFAULT_TIMEOUT);
+        let retry = RetryPolicy {
+            attempts: args
+                .get_one::<u32>("LSP_INIT_RETRIES")
+                .copied()
+                .unwrap_or_default(),
+            backoff: Duration::from_millis(500),
+        };
+
         self.with_image_dir(args.get_one::<String>("IMG_DIR"))
             .with_tmpl_dir(args.get_one::<String>("TEMPLATE_DIR"))
             .with_daily_dir(args.get_one::<String>("DAILY_DIR"))
             .with_zettel_dir(args.get_one::<String>("ZETTEL_DIR"))
+            .with_archive_dir(args.get_one::<String>("ARCHIVE_DIR"))
+            .with_attachment_dir(args.get_one::<String>("ATTACHMENT_DIR"))
+            .with_pdf_text_command(args.get_one::<String>("PDF_TEXT_COMMAND").cloned())
+            .with_transcribe_command(args.get_one::<String>("TRANSCRIBE_COMMAND").cloned())
+            .with_inbox_dir(args.get_one::<String>("INBOX_DIR"))
+            .with_collection_dir(args.get_one::<String>("COLLECTION_DIR"))
+            .with_lsp_timeout(Some(timeout).filter(|t| !t.is_zero()))
+            .with_lsp_retry(retry)
+            .with_lsp_command(
+                args.get_one::<String>("LSP_COMMAND").cloned(),
+                args.get_many::<String>("LSP_ARGS")
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .with_lsp_init_options(args.get_one::<Value>("LSP_INIT_OPTIONS").cloned())
+            .with_changelog(args.get_flag("CHANGELOG"))
+            .with_auto_commit(args.get_flag("AUTO_COMMIT"))
+            .with_commit_template(args.get_one::<String>("COMMIT_TEMPLATE"))
+            .with_json(args.get_flag("JSON"))
+            .with_finder_bindings(
+                args.get_many::<(String, FinderAction)>("FINDER_BIND")
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .with_finder_layout(
+                PreviewLayout::default()
+                    .with_position(args.get_one::<String>("FINDER_PREVIEW"))
+                    .with_size(args.get_one::<String>("FINDER_PREVIEW_SIZE"))
+                    .with_wrap(args.get_flag("FINDER_PREVIEW_WRAP"))
+                    .with_toggle(args.get_one::<String>("FINDER_PREVIEW_TOGGLE"))
+                    .with_plain(args.get_flag("FINDER_PLAIN")),
+            )
+            .with_theme(args.get_one::<String>("THEME"))
+            .with_routes(
+                args.get_many::<Route>("ROUTE")
+                    .into_iter()
+                    .flatten()
+                    .cloned(),
+            )
+            .with_rollover(args.get_flag("ROLLOVER"))
+            .with_id_scheme(
+                args.get_one::<IDScheme>("ID_SCHEME")
+                    .copied()
+                    .unwrap_or_default(),
+            )
+            .with_embedder(
+                args.get_one::<Embedder>("EMBEDDER")
+                    .cloned()
+                    .map(|embedder| embedder.with_model(args.get_one::<String>("EMBEDDER_MODEL"))),
+            )
+            .with_llm(args.get_one::<Llm>("LLM").cloned())
+            .with_llm_model(args.get_one::<String>("LLM_MODEL"))
+            .with_llm_api_key(args.get_one::<String>("LLM_API_KEY"))
     }
 
     pub fn build(self) -> Result<PKM> {
@@ -99,6 +472,31 @@ impl PKMBuilder {
             daily_dir,
             image_dir,
             zettel_dir,
+            archive_dir,
+            inbox_dir,
+            collection_dir,
+            attachment_dir,
+            pdf_text_command,
+            transcribe_command,
+            lsp_timeout,
+            lsp_retry,
+            lsp_command,
+            lsp_args,
+            lsp_init_options,
+            changelog,
+            auto_commit,
+            commit_template,
+            json,
+            finder_bindings,
+            finder_layout,
+            theme,
+            routes,
+            rollover,
+            id_scheme,
+            embedder,
+            llm,
+            llm_model,
+            llm_api_key,
         } = self;
 
         let tmpl_dir = tmpl_dir.unwrap_or_else(|| {
@@ -108,31 +506,47 @@ impl PKMBuilder {
         });
 
         let mut tmpl = if tmpl_dir.is_dir() {
-            let mut glob = PathBuf::from(tmpl_dir);
+            let mut glob = PathBuf::from(&tmpl_dir);
             glob.push("**/*.md");
             Tera::new(glob.to_string_lossy().as_ref())?
         } else {
             Tera::default()
         };
 
+        register_filters(&mut tmpl);
+        register_functions(&mut tmpl, &root);
+
         if tmpl
             .get_template("daily.md")
             .is_err_and(|v| matches!(v.kind, tera::ErrorKind::TemplateNotFound(_)))
         {
-            tmpl.add_raw_template("daily.md", "# {{ date }}")?;
+            tmpl.add_raw_template("daily.md", DEFAULT_DAILY_TEMPLATE)?;
         }
 
         if tmpl
             .get_template("default.md")
             .is_err_and(|v| matches!(v.kind, tera::ErrorKind::TemplateNotFound(_)))
         {
-            tmpl.add_raw_template("default.md", "# {{ title }}")?;
+            tmpl.add_raw_template("default.md", DEFAULT_ZETTEL_TEMPLATE)?;
         }
         log::debug!("{:?}", tmpl);
 
+        // custom themes and syntaxes come from the user config, the repo
+        // overrides them
+        let mut highlighting_dirs = Vec::new();
+        if let Some(config) = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        {
+            highlighting_dirs.push(config.join("pkm"));
+        }
+        highlighting_dirs.push(root.join(".pkm"));
+        set_highlighting_dirs(highlighting_dirs);
+
         Ok(PKM {
             root: root.clone(),
             tmpl,
+            tmpl_dir,
             daily_dir: daily_dir
                 .unwrap_or_else(|| {
                     let mut daily = PathBuf::from(&root);
@@ -154,6 +568,50 @@ impl PKMBuilder {
                     daily
                 })
                 .into(),
+            archive_dir: archive_dir
+                .unwrap_or_else(|| {
+                    let mut archive = PathBuf::from(&root);
+                    archive.push(DEFAULT_ARCHIVE_DIR);
+                    archive
+                })
+                .into(),
+            inbox_dir: inbox_dir
+                .unwrap_or_else(|| {
+                    let mut inbox = PathBuf::from(&root);
+                    inbox.push(DEFAULT_INBOX_DIR);
+                    inbox
+                })
+                .into(),
+            collection_dir: collection_dir
+                .unwrap_or_else(|| {
+                    let mut collection = PathBuf::from(&root);
+                    collection.push(DEFAULT_COLLECTION_DIR);
+                    collection
+                })
+                .into(),
+            attachment_dir: attachment_dir.unwrap_or_else(|| root.join(DEFAULT_ATTACHMENT_DIR)),
+            pdf_text_command,
+            transcribe_command,
+            lsp_timeout,
+            lsp_retry,
+            lsp_command,
+            lsp_args,
+            lsp_init_options,
+            changelog,
+            auto_commit,
+            commit_template,
+            json,
+            finder_bindings,
+            finder_layout,
+            theme,
+            routes,
+            rollover,
+            id_scheme,
+            embedder,
+            llm,
+            llm_model,
+            llm_api_key,
+            actions: Mutex::new(Vec::new()),
         })
     }
 }
@@ -161,36 +619,344 @@ impl PKMBuilder {
 pub struct PKM {
     pub root: PathBuf,
     pub tmpl: Tera,
+    pub tmpl_dir: PathBuf,
     pub daily_dir: PathBuf,
     pub image_dir: PathBuf,
     pub zettel_dir: PathBuf,
+    pub archive_dir: PathBuf,
+    pub inbox_dir: PathBuf,
+    pub collection_dir: PathBuf,
+    pub attachment_dir: PathBuf,
+    pub pdf_text_command: String,
+    pub transcribe_command: String,
+    pub lsp_timeout: Option<Duration>,
+    pub lsp_retry: RetryPolicy,
+    pub lsp_command: String,
+    pub lsp_args: Vec<String>,
+    pub lsp_init_options: Option<Value>,
+    pub changelog: bool,
+    // auto_commit commits the files a command changed once it is done
+    pub auto_commit: bool,
+    pub commit_template: String,
+    // json is set when the output is read by scripts and editor plugins
+    pub json: bool,
+    pub finder_bindings: Vec<(String, FinderAction)>,
+    pub finder_layout: PreviewLayout,
+    pub theme: Option<String>,
+    pub routes: Vec<Route>,
+    pub rollover: bool,
+    pub id_scheme: IDScheme,
+    pub embedder: Option<Embedder>,
+    // llm is the endpoint set on the command line, the one of .pkm/llm.json
+    // is only read once a command asks for it
+    llm: Option<Llm>,
+    llm_model: Option<String>,
+    llm_api_key: Option<String>,
+    // actions are the changes recorded by the command, they make up the
+    // message of the auto commit
+    actions: Mutex<Vec<String>>,
 }
 
 impl PKM {
+    // llm returns the chat completions endpoint summaries and titles are
+    // asked from, None when the repo has none
+    pub fn llm(&self) -> Result<Option<Llm>> {
+        let llm = match self.llm.clone() {
+            Some(llm) => Some(llm),
+            None => Llm::open(&self.root)?,
+        };
+        Ok(llm.map(|llm| {
+            llm.with_model(self.llm_model.as_ref())
+                .with_api_key(self.llm_api_key.as_ref())
+        }))
+    }
+
     pub fn image(&self) -> ImageBuilder {
         ImageBuilder::new(&self.image_dir)
     }
 
+    pub fn attachment(&self) -> AttachmentBuilder {
+        AttachmentBuilder::new(&self.attachment_dir)
+    }
+
+    pub fn collection(&self, name: &str) -> Result<Collection> {
+        Collection::open(&self.collection_dir, name)
+    }
+
+    // record appends the action to the changelog note when it is enabled. The
+    // entries are grouped under a heading per day.
+    pub fn record(&self, action: &str, id: &ZettelID) -> Result<()> {
+        if self.auto_commit {
+            self.actions
+                .lock()
+                .expect("actions lock")
+                .push(format!("{} {}", action, id));
+        }
+
+        if !self.changelog {
+            return Ok(());
+        }
+
+        let mut path = self.root.clone();
+        path.push(CHANGELOG_FILE);
+
+        let mut changelog = Zettel::new(path)?;
+        if changelog.content().is_none() {
+            changelog.set_content("# Changelog\n");
+            changelog = changelog.sync()?;
+        }
+
+        let now = Local::now();
+        changelog.mut_content()?.append_to_section(
+            &now.format("%Y-%m-%d").to_string(),
+            &format!("- {} {} [[{}]]", now.format("%H:%M"), action, id),
+        )?;
+        changelog.sync()?;
+
+        Ok(())
+    }
+
+    // commit_changes commits the files the command touched since the times of
+    // the changed files were taken, the message lists the recorded actions.
+    // None is returned when nothing changed.
+    pub fn commit_changes(
+        &self,
+        before: &HashMap<PathBuf, Option<SystemTime>>,
+    ) -> Result<Option<String>> {
+        let files = touched_files(&self.root, before)?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+
+        let actions = self.actions.lock().expect("actions lock");
+        let message = commit_message(&self.root, &self.commit_template, &files, &actions)?;
+        commit_files(&self.root, &files, &message)?;
+        Ok(Some(message))
+    }
+
+    // finder creates a finder with the configured key bindings and preview
+    pub fn finder(&self) -> Result<Finder<'_, &Path>> {
+        let mut finder = Finder::new(self.root.as_path());
+        finder.preview_layout(&self.finder_layout);
+        finder.theme(self.theme.as_deref());
+        for (key, action) in self.finder_bindings.iter() {
+            finder.bind(key.clone(), *action);
+        }
+
+        if self
+            .finder_bindings
+            .iter()
+            .any(|(_, action)| *action == FinderAction::AppendToDaily)
+        {
+            // the daily is only written, with its rollover and hook, when
+            // something is appended to it
+            let now = Local::now();
+            let daily = self.daily_builder(&now)?.path().to_path_buf();
+            finder.daily(daily, move || self.daily(&now).map(|_| ()));
+        }
+
+        Ok(finder)
+    }
+
+    // new_template creates the template from the meta template, failing when
+    // the template already exists
+    pub fn new_template(&self, name: &str) -> Result<PathBuf> {
+        let file = template_file(name)?;
+        let name = file.trim_end_matches(".md");
+        let path = self.tmpl_dir.join(&file);
+
+        if path.exists() {
+            return Err(Error::PKMError(format!("{:?} already exists", path)));
+        }
+
+        let mut context = Context::new();
+        context.insert("name", name);
+        context.insert("date", &Local::now().format("%Y-%m-%d").to_string());
+
+        let mut ztl = Zettel::new(&path)?;
+        ztl.set_content(Tera::one_off(META_TEMPLATE, &context, false)?);
+        ztl.sync()?;
+
+        Ok(path)
+    }
+
+    // id starts the id of a new zettel with the id scheme of the repo
+    pub fn id<'a>(&self) -> ZettelIDBuilder<'a> {
+        ZettelIDBuilder::new().scheme(self.id_scheme)
+    }
+
     pub fn zettel(&self) -> ZettelBuilder {
-        ZettelBuilder::new(&self.zettel_dir)
+        ZettelBuilder::new(&self.zettel_dir).with_repo(&self.root)
     }
 
-    pub fn daily(&self, date: &DateTime<Local>) -> Result<Zettel> {
+    // route finds the route of the zettel, the first one matching its tags
+    pub fn route(&self, id: &ZettelID) -> Option<&Route> {
+        self.routes.iter().find(|route| route.matches(id))
+    }
+
+    // routed_zettel is zettel, with the template and the directory of the
+    // route of the id when it has one
+    pub fn routed_zettel(&self, id: &ZettelID) -> ZettelBuilder {
+        let route = self.route(id);
+        let dir = route
+            .and_then(|route| route.dir.as_ref())
+            .unwrap_or(&self.zettel_dir);
+
+        ZettelBuilder::new(dir)
+            .with_repo(&self.root)
+            .template(route.and_then(|route| route.template.as_ref()))
+    }
+
+    // create_zettel creates the zettel with the title from the template of its
+    // route, dated today
+    pub fn create_zettel(&self, title: &str) -> Result<Zettel> {
+        let id = self.id().title(Some(title), None).build()?;
         let mut context = Context::new();
-        context.insert("date", &format!("{}", date.format("%A, %B %d, %Y")));
-        let id = ZettelIDBuilder::new().date(&date).build()?;
-        ZettelBuilder::new(&self.daily_dir)
-            .with_year_month(&date)
+        context.insert("title", title);
+        let ztl = self
+            .routed_zettel(&id)
+            .with_year_month_day(&Local::now())
+            .id(&id)
+            .build(&self.tmpl, &context)?;
+        self.created("created", &ztl)?;
+        Ok(ztl)
+    }
+
+    // created records the action for the zettel that was just created and
+    // runs the post-zettel-create hook, every command creating a zettel ends
+    // with it
+    pub fn created(&self, action: &str, ztl: &Zettel) -> Result<()> {
+        self.record(action, &ztl.id())?;
+        self.hook(Hook::PostZettelCreate, ztl.path())
+    }
+
+    // daily acquires the daily of the date. With rollover the open tasks of
+    // the previous daily are carried over when the daily has to be created.
+    pub fn daily(&self, date: &DateTime<Local>) -> Result<Zettel> {
+        let context = Context::new();
+        let builder = self.daily_builder(date)?;
+        if builder.path().exists() {
+            return builder.aquire(&self.tmpl, &context);
+        }
+
+        let daily = match self.rollover {
+            true => self.rolled_over_daily(builder, date, &context)?,
+            false => builder.aquire(&self.tmpl, &context)?,
+        };
+        self.hook(Hook::PostDailyCreate, daily.path())?;
+        Ok(daily)
+    }
+
+    // script_env describes the layout of the repo to the scripts it runs, the
+    // daily of today is only named, it may not exist yet
+    pub fn script_env(&self) -> Result<Vec<(&'static str, PathBuf)>> {
+        Ok(vec![
+            ("PKM_REPO", self.root.clone()),
+            ("PKM_ZETTEL_DIR", self.zettel_dir.clone()),
+            ("PKM_DAILY_DIR", self.daily_dir.clone()),
+            ("PKM_IMG_DIR", self.image_dir.clone()),
+            ("PKM_TEMPLATE_DIR", self.tmpl_dir.clone()),
+            (
+                "PKM_TODAY_PATH",
+                self.daily_builder(&Local::now())?.path().to_path_buf(),
+            ),
+        ])
+    }
+
+    // rolled_over_daily creates the daily with the open tasks of the previous
+    // daily carried over
+    fn rolled_over_daily(
+        &self,
+        builder: ZettelBuilder,
+        date: &DateTime<Local>,
+        context: &Context,
+    ) -> Result<Zettel> {
+        let items = match self.previous_daily(date.date_naive())? {
+            Some(previous) => open_task_items(previous.content().as_deref().unwrap_or(""))?,
+            None => Vec::new(),
+        };
+
+        let mut daily = builder.build(&self.tmpl, context)?;
+        if items.is_empty() {
+            return Ok(daily);
+        }
+
+        daily
+            .mut_content()?
+            .insert_section(CARRIED_OVER_HEADING, &items.join("\n"))?;
+        daily.sync()
+    }
+
+    // hook runs the script of the hook for the path. A failing pre hook is an
+    // error, a failing post hook is only logged as what it follows is done.
+    pub fn hook<P: AsRef<Path>>(&self, hook: Hook, path: P) -> Result<()> {
+        match hook.run(&self.root, path) {
+            Err(err) if !hook.is_pre() => {
+                log::warn!("{}", err);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    // dailies returns the dailies within the daily directory with their date
+    pub fn dailies(&self) -> impl Iterator<Item = (NaiveDate, PathBuf)> {
+        WalkDir::new(self.daily_dir.as_path())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension() == Some(OsStr::new("md")))
+            .filter_map(|entry| {
+                let date = ZettelID::from_path(entry.path())
+                    .tags()
+                    .find_map(|tag| NaiveDate::parse_from_str(tag, "%Y-%m-%d").ok())?;
+                Some((date, entry.into_path()))
+            })
+    }
+
+    // previous_daily finds the latest daily before the day
+    pub fn previous_daily(&self, day: NaiveDate) -> Result<Option<Zettel>> {
+        let previous = self
+            .dailies()
+            .filter(|(date, _)| *date < day)
+            .max_by_key(|(date, _)| *date);
+
+        previous.map(|(_, path)| Zettel::new(path)).transpose()
+    }
+
+    // daily_words counts the words of the daily of every day that has one
+    pub fn daily_words(&self) -> Result<BTreeMap<NaiveDate, usize>> {
+        let mut days = BTreeMap::new();
+        for (date, path) in self.dailies() {
+            let words = fs::read_to_string(path)?.split_whitespace().count();
+            *days.entry(date).or_default() += words;
+        }
+
+        Ok(days)
+    }
+
+    fn daily_builder(&self, date: &DateTime<Local>) -> Result<ZettelBuilder> {
+        // a daily is named after its date alone, whatever the id scheme
+        let id = ZettelIDBuilder::new().date(date).build()?;
+        Ok(ZettelBuilder::new(&self.daily_dir)
+            .with_repo(&self.root)
+            .with_year_month(date)
             .id(id)
-            .template(Some("daily"))
-            .aquire(&self.tmpl, &context)
+            .template(Some("daily")))
     }
 
     pub async fn lsp(&self) -> Result<LSP<StandardRunner>> {
-        let runner = StandardRunnerBuilder::new("markdown-oxide")
+        let runner = StandardRunnerBuilder::new(&self.lsp_command)
+            .args(&self.lsp_args)
             .working_dir(self.root.as_path())
+            .timeout(self.lsp_timeout)
             .spawn()?;
-        Ok(LSP::new(runner, self.root.as_path()).await?)
+        Ok(LSP::with_options(
+            runner,
+            self.root.as_path(),
+            self.lsp_retry,
+            self.lsp_init_options.clone(),
+        )
+        .await?)
     }
 
     // resolve_path will resolve the specified zettel id. It is possible there
@@ -217,11 +983,853 @@ impl PKM {
                 .collect(),
         };
 
-        Ok(locations
+        let paths: Vec<PathBuf> = locations
             .into_iter()
             .filter(|buf| {
-                buf.starts_with(absolute(&self.zettel_dir).unwrap_or(self.zettel_dir.clone()))
+                std::iter::once(&self.zettel_dir)
+                    .chain(self.routes.iter().filter_map(|route| route.dir.as_ref()))
+                    .any(|dir| buf.starts_with(absolute(dir).unwrap_or(dir.clone())))
             })
-            .collect())
+            .collect();
+
+        // the LSP only knows the notes by their id
+        match paths.is_empty() {
+            true => Ok(self.find_by_alias(id)),
+            false => Ok(paths),
+        }
+    }
+
+    // lsp_rename asks the LSP to rename the zettel, letting it rewrite the links
+    // across the repo. The files the LSP changed are returned as their path
+    // before and after the rename, None when the LSP had nothing to rename.
+    pub async fn lsp_rename<R: Runner>(
+        &self,
+        id: &str,
+        new_id: &ZettelID,
+        lsp: &mut LSP<R>,
+    ) -> Result<Option<Vec<(PathBuf, PathBuf)>>> {
+        let path = PathBuf::from("/__rename.md");
+        lsp.did_open(&path, format!("[[{}]]", id), "markdown")
+            .await?;
+
+        // the server handles the open before the rename sent after it, so
+        // awaiting the response of the rename is all the waiting needed.
+        // Servers without rename support fall back to rewriting the links ourselves
+        let edit = match lsp.rename(&path, 0, 2, new_id.as_str()).await {
+            Err(crate::lsp::Error::Unsupported(method)) => {
+                log::debug!("LSP does not support {}", method);
+                None
+            }
+            edit => edit?,
+        };
+        lsp.did_close(&path).await?;
+
+        Ok(edit.as_ref().map(changed_paths))
+    }
+
+    // walk returns every file within the repo, skipping hidden directories
+    // like .git and .index and the paths matched by the .pkmignore files
+    pub fn walk(&self) -> impl Iterator<Item = PathBuf> {
+        WalkBuilder::new(self.root.as_path())
+            .standard_filters(false)
+            .hidden(true)
+            .add_custom_ignore_filename(PKMIGNORE_FILE)
+            .build()
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    log::error!("could not walk repo: {}", err);
+                    None
+                }
+            })
+            .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+            .map(|entry| entry.into_path())
+    }
+
+    // notes returns the path of every markdown file within the repo
+    pub fn notes(&self) -> impl Iterator<Item = PathBuf> {
+        self.walk()
+            .filter(|path| path.extension() == Some(OsStr::new("md")))
     }
+
+    // rename_zettel renames the zettel to the new id and rewrites every
+    // wikilink in the repo that pointed at the old id. The renamed zettel is
+    // returned along with the notes whose links were rewritten.
+    pub fn rename_zettel<Z: AsRef<ZettelID>>(
+        &self,
+        ztl: Zettel,
+        id: Z,
+    ) -> Result<(Zettel, Vec<PathBuf>)> {
+        let old_id = ztl.id();
+        let ztl = ztl.rename(id.as_ref())?;
+        self.record(&format!("renamed {} to", old_id), id.as_ref())?;
+
+        let mut rewritten = Vec::new();
+        for note in self.notes() {
+            let content = fs::read_to_string(&note)?;
+            if let Some(content) = rewrite_wikilinks(&content, &old_id, id.as_ref()) {
+                log::info!("rewriting links in {:?}", &note);
+                fs::write(&note, content)?;
+                rewritten.push(note);
+            }
+        }
+
+        // the zettel may have linked to itself
+        Ok((Zettel::new(ztl.path())?, rewritten))
+    }
+
+    // find_by_id resolves the id to the notes with a matching filename without
+    // needing an LSP. Ids are matched case insensitively.
+    pub fn find_by_id(&self, id: &str) -> Vec<PathBuf> {
+        self.notes()
+            .filter(|note| path_to_id(note).eq_ignore_ascii_case(id))
+            .collect()
+    }
+
+    // find_notes resolves the query to notes, by id first and then by the
+    // notes whose id or title contains every word of the query
+    pub fn find_notes(&self, query: &str) -> Vec<PathBuf> {
+        let found = self.find_by_id(query);
+        if !found.is_empty() {
+            return found;
+        }
+
+        let found = self.find_by_alias(query);
+        if !found.is_empty() {
+            return found;
+        }
+
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        self.notes()
+            .filter(|note| {
+                let ztl = match Zettel::new(note) {
+                    Ok(ztl) => ztl,
+                    Err(_) => return false,
+                };
+                let aliases = Frontmatter::parse(ztl.content().as_deref().unwrap_or(""))
+                    .list(ALIASES_KEY)
+                    .join(" ");
+                let text = format!(
+                    "{} {} {}",
+                    path_to_id(note),
+                    ztl.title().ok().flatten().unwrap_or_default(),
+                    aliases
+                )
+                .to_lowercase();
+                words.iter().all(|word| text.contains(word.as_str()))
+            })
+            .collect()
+    }
+
+    // find_by_alias resolves the alias to the notes listing it in the aliases
+    // of their frontmatter, case insensitively
+    pub fn find_by_alias(&self, alias: &str) -> Vec<PathBuf> {
+        self.notes()
+            .filter(|note| {
+                fs::read_to_string(note).is_ok_and(|content| {
+                    Frontmatter::parse(&content)
+                        .list(ALIASES_KEY)
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(alias))
+                })
+            })
+            .collect()
+    }
+
+    // link_project creates or updates the project note of the code repo the
+    // directory is in and links the repo to it
+    pub fn link_project<P: AsRef<Path>>(&self, dir: P) -> Result<Zettel> {
+        let repo = toplevel(absolute(dir.as_ref())?)?.ok_or_else(|| {
+            Error::NotFound(format!("{:?} is not within a git repo", dir.as_ref()))
+        })?;
+        let info = ProjectInfo::read(&repo)?;
+        let mut projects = Projects::open(&self.root)?;
+
+        let existing = projects
+            .get(&repo)
+            .and_then(|id| self.find_by_id(id).into_iter().next());
+        let mut created = false;
+        let mut ztl = match existing {
+            Some(path) => Zettel::new(path)?,
+            None => {
+                let id = self
+                    .id()
+                    .title(Some(&info.name), None)
+                    .tag(PROJECT_TAG)
+                    .build()?;
+                // a note of the project may exist before the repo is linked
+                let path = self
+                    .find_by_id(&id)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_else(|| self.zettel_dir.join(id.filename()));
+                let mut ztl = Zettel::new(path)?;
+                if ztl.content().is_none() {
+                    ztl.set_content(format!("# {}\n", info.name));
+                    ztl = ztl.sync()?;
+                    created = true;
+                }
+                ztl
+            }
+        };
+
+        ztl.mut_content()?
+            .upsert_marked_block("Repository", "pkm-project", &info.markdown())?;
+        let ztl = ztl.sync()?;
+
+        projects.link(repo, ztl.id().to_string());
+        projects.save()?;
+        if created {
+            self.created("created the project", &ztl)?;
+        }
+        self.record("linked the project", &ztl.id())?;
+
+        Ok(ztl)
+    }
+
+    // project returns the project note linked to the code repo the directory
+    // is in
+    pub fn project<P: AsRef<Path>>(&self, dir: P) -> Result<Option<Zettel>> {
+        let repo = match toplevel(absolute(dir.as_ref())?)? {
+            Some(repo) => repo,
+            None => return Ok(None),
+        };
+
+        let projects = Projects::open(&self.root)?;
+        match projects
+            .get(&repo)
+            .and_then(|id| self.find_by_id(id).into_iter().next())
+        {
+            Some(path) => Ok(Some(Zettel::new(path)?)),
+            None => Ok(None),
+        }
+    }
+
+    // recent returns the notes of the repo that were modified last, newest
+    // first
+    pub fn recent(&self, limit: usize) -> Result<Vec<Zettel>> {
+        let mut notes = Vec::new();
+        for note in self.notes() {
+            let ztl = Zettel::new(note)?;
+            notes.push((ztl.modified()?, ztl));
+        }
+
+        notes.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Ok(notes.into_iter().take(limit).map(|(_, ztl)| ztl).collect())
+    }
+
+    // zettels returns every zettel within the zettel directory and the
+    // directories of the routes
+    pub fn zettels(&self) -> Result<Vec<Zettel>> {
+        let mut dirs: Vec<&PathBuf> = vec![&self.zettel_dir];
+        for dir in self.routes.iter().filter_map(|route| route.dir.as_ref()) {
+            if !dirs.iter().any(|other| dir.starts_with(other)) {
+                dirs.retain(|other| !other.starts_with(dir));
+                dirs.push(dir);
+            }
+        }
+
+        dirs.into_iter()
+            .flat_map(|dir| WalkDir::new(dir.as_path()))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension() == Some(OsStr::new("md")))
+            .map(|entry| Zettel::new(entry.into_path()))
+            .collect()
+    }
+
+    // zettel_base is the directory the zettel is stored under, the zettel
+    // directory or the directory of a route
+    fn zettel_base(&self, ztl: &Zettel) -> Option<&Path> {
+        self.routes
+            .iter()
+            .filter_map(|route| route.dir.as_deref())
+            .chain(std::iter::once(self.zettel_dir.as_path()))
+            .filter(|dir| ztl.path().starts_with(dir))
+            .max_by_key(|dir| dir.components().count())
+    }
+
+    // fleeting returns the fleeting zettels older than the max age, oldest
+    // first
+    pub fn fleeting(&self, max_age: Duration) -> Result<Vec<Zettel>> {
+        let mut expired = self.older_zettels(max_age)?;
+        expired.retain(|ztl| ztl.id().has_tag(FLEETING_TAG));
+        Ok(expired)
+    }
+
+    // older_zettels returns the zettels older than the max age, oldest first.
+    // The age comes from the date of the id, or from the last modification
+    // when the id has no date or timestamp.
+    pub fn older_zettels(&self, max_age: Duration) -> Result<Vec<Zettel>> {
+        let now = SystemTime::now();
+        let mut expired = Vec::new();
+
+        for ztl in self.zettels()? {
+            let dated = ztl
+                .id()
+                .created()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .and_then(|date| date.and_local_timezone(Local).earliest())
+                .map(SystemTime::from);
+            let modified = match dated {
+                Some(date) => date,
+                None => ztl.modified()?,
+            };
+
+            if now.duration_since(modified).unwrap_or_default() > max_age {
+                expired.push((modified, ztl));
+            }
+        }
+
+        expired.sort_by_key(|(modified, _)| *modified);
+        Ok(expired.into_iter().map(|(_, ztl)| ztl).collect())
+    }
+
+    // meeting_actions gathers the open tasks of the meetings by the attendees
+    // they mention, the tasks mentioning no attendee are kept as UNASSIGNED
+    pub fn meeting_actions(&self) -> Result<BTreeMap<String, Vec<(Zettel, Task)>>> {
+        let mut actions: BTreeMap<String, Vec<(Zettel, Task)>> = BTreeMap::new();
+        for ztl in self.zettels()? {
+            if !ztl.id().has_tag(MEETING_TAG) {
+                continue;
+            }
+
+            let content = ztl.content().as_deref().unwrap_or("").to_string();
+            let attendees = Frontmatter::parse(&content).list(ATTENDEES_KEY);
+            for task in tasks(&content).into_iter().filter(|task| !task.done) {
+                let text = task.text.to_lowercase();
+                let mut mentioned: Vec<&String> = attendees
+                    .iter()
+                    .filter(|attendee| text.contains(&attendee.to_lowercase()))
+                    .collect();
+
+                let unassigned = UNASSIGNED.to_string();
+                if mentioned.is_empty() {
+                    mentioned.push(&unassigned);
+                }
+
+                for attendee in mentioned {
+                    actions
+                        .entry(attendee.clone())
+                        .or_default()
+                        .push((ztl.clone(), task.clone()));
+                }
+            }
+        }
+
+        Ok(actions)
+    }
+
+    // agenda gathers the daily, the tasks due, the meetings and the fleeting
+    // notes that are older than the review age for the date
+    pub fn agenda(&self, date: &DateTime<Local>, review_age: Duration) -> Result<Agenda> {
+        let day = date.date_naive();
+        // the agenda only reads, a daily that doesn't exist has no tasks
+        let daily = Zettel::new(self.daily_builder(date)?.path())?;
+        let todo = tasks(daily.content().as_deref().unwrap_or(""))
+            .into_iter()
+            .filter(|task| !task.done)
+            .collect();
+
+        let mut due = Vec::new();
+        for note in self.notes() {
+            if note.as_path() == daily.path() {
+                continue;
+            }
+
+            let ztl = Zettel::new(note)?;
+            for task in tasks(ztl.content().as_deref().unwrap_or("")) {
+                if task.is_due(day) {
+                    due.push((ztl.clone(), task));
+                }
+            }
+        }
+        due.sort_by_key(|(_, task)| task.due);
+
+        let date_tag = day.format("%Y-%m-%d").to_string();
+        let meetings = self
+            .zettels()?
+            .into_iter()
+            .filter(|ztl| {
+                let id = ztl.id();
+                id.has_tag(MEETING_TAG) && id.has_tag(&date_tag)
+            })
+            .collect();
+
+        Ok(Agenda {
+            date: day,
+            daily,
+            todo,
+            due,
+            meetings,
+            review: self.fleeting(review_age)?,
+        })
+    }
+
+    // promote_zettel turns a fleeting zettel into a permanent one by dropping
+    // the fleeting tag and hash from its id. A zettel routed elsewhere for
+    // being fleeting moves to the directory of its new id, pointing the
+    // markdown links at it along the way. The promoted zettel is returned
+    // along with the notes whose links were rewritten.
+    pub fn promote_zettel(&self, ztl: Zettel) -> Result<(Zettel, Vec<PathBuf>)> {
+        let id = ztl.id();
+        let mut builder = self.id().title(id.title().ok(), None);
+        for tag in id.tags().filter(|tag| *tag != FLEETING_TAG) {
+            builder = builder.tag(tag);
+        }
+        let new_id = builder.build()?;
+
+        let from = self.zettel_base(&ztl).map(Path::to_path_buf);
+        let to = self
+            .route(&new_id)
+            .and_then(|route| route.dir.clone())
+            .unwrap_or_else(|| self.zettel_dir.clone());
+
+        let (ztl, mut rewritten) = self.rename_zettel(ztl, &new_id)?;
+        match from {
+            Some(from) if from != to => {
+                let dest = to.join(ztl.rel_path(&from)?);
+                let (moved, relinked) = self.relocate_zettel(ztl, dest)?;
+                self.record("moved", &new_id)?;
+                rewritten.extend(relinked);
+                Ok((moved, rewritten))
+            }
+            _ => Ok((ztl, rewritten)),
+        }
+    }
+
+    // merge_zettel appends the content of the zettel to another zettel, points
+    // the links at the merged zettel and removes the original. The merged
+    // zettel is returned along with the notes whose links were rewritten.
+    pub fn merge_zettel(&self, ztl: Zettel, into: Zettel) -> Result<(Zettel, Vec<PathBuf>)> {
+        if ztl.path() == into.path() {
+            return Err(Error::PKMError(String::from(
+                "can not merge a zettel into itself",
+            )));
+        }
+
+        let (old_id, new_id) = (ztl.id(), into.id());
+        let mut into = into;
+        into.mut_content()?
+            .append(&format!("\n{}", ztl.content().as_deref().unwrap_or("")))?;
+        let into = into.sync()?;
+        ztl.delete()?;
+        self.record(&format!("merged {} into", old_id), &new_id)?;
+
+        let mut rewritten = Vec::new();
+        for note in self.notes() {
+            let content = fs::read_to_string(&note)?;
+            if let Some(content) = rewrite_wikilinks(&content, &old_id, &new_id) {
+                fs::write(&note, content)?;
+                rewritten.push(note);
+            }
+        }
+
+        Ok((Zettel::new(into.path())?, rewritten))
+    }
+
+    // init scaffolds the layout of the repo, files that already exist are
+    // left alone. The created paths are returned.
+    pub fn init(&self) -> Result<Vec<PathBuf>> {
+        let mut created = Vec::new();
+        for dir in [
+            &self.zettel_dir,
+            &self.daily_dir,
+            &self.tmpl_dir,
+            &self.image_dir,
+            &self.root.join(SCRIPTS_DIR),
+        ] {
+            if !dir.exists() {
+                fs::create_dir_all(dir)?;
+                created.push(dir.clone());
+            }
+        }
+
+        let files = [
+            (
+                self.tmpl_dir.join("default.md"),
+                format!("{}\n", DEFAULT_ZETTEL_TEMPLATE),
+            ),
+            (
+                self.tmpl_dir.join("daily.md"),
+                format!("{}\n", DEFAULT_DAILY_TEMPLATE),
+            ),
+            (
+                self.root.join(FAVORITES_FILE),
+                String::from("# Favorites\n\n| Favorite |\n| --- |\n"),
+            ),
+            (
+                self.root.join(GITIGNORE_FILE),
+                format!(
+                    "{0}\n{0}.new\n{0}.old\n{1}\n{2}\n",
+                    INDEX_DIR, TRASH_DIR, EMBEDDINGS_FILE
+                ),
+            ),
+        ];
+        for (path, content) in files {
+            if !path.exists() {
+                fs::write(&path, content)?;
+                created.push(path);
+            }
+        }
+
+        Ok(created)
+    }
+
+    // capture writes the text into a new note within the inbox, named after
+    // the first words of the text
+    pub fn capture(&self, text: &str) -> Result<Zettel> {
+        let title: String = text
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '-')
+            .collect();
+        let title = title
+            .split_whitespace()
+            .take(6)
+            .collect::<Vec<&str>>()
+            .join(" ");
+
+        let id = self
+            .id()
+            .title(Some(title).filter(|title| !title.is_empty()), None)
+            .with_hash()
+            .build()?;
+
+        let mut ztl = Zettel::new(self.inbox_dir.join(id.filename()))?;
+        ztl.set_content(format!("{}\n", text.trim_end()));
+        let ztl = ztl.sync()?;
+        self.created("captured", &ztl)?;
+        Ok(ztl)
+    }
+
+    // inbox returns the captures that have not been filed yet, oldest first
+    pub fn inbox(&self) -> Result<Vec<Zettel>> {
+        let mut captures = Vec::new();
+        for entry in WalkDir::new(self.inbox_dir.as_path())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension() == Some(OsStr::new("md")))
+        {
+            let ztl = Zettel::new(entry.into_path())?;
+            captures.push((ztl.modified()?, ztl));
+        }
+
+        captures.sort_by_key(|(modified, _)| *modified);
+        Ok(captures.into_iter().map(|(_, ztl)| ztl).collect())
+    }
+
+    // file_capture turns the capture into a zettel with the title, the
+    // content of the capture becomes the body below the rendered template
+    pub fn file_capture(&self, capture: Zettel, title: &str) -> Result<Zettel> {
+        let now = Local::now();
+        let id = self.id().title(Some(title), None).build()?;
+
+        let mut context = Context::new();
+        context.insert("title", title);
+        let mut ztl = self
+            .routed_zettel(&id)
+            .with_year_month_day(&now)
+            .id(&id)
+            .build(&self.tmpl, &context)?;
+
+        let rendered = ztl.content().as_deref().unwrap_or("").to_string();
+        ztl.set_content(format!(
+            "{}\n\n{}\n",
+            rendered.trim_end(),
+            capture.content().as_deref().unwrap_or("").trim_end()
+        ));
+        let ztl = ztl.sync()?;
+        capture.delete()?;
+        self.created("filed", &ztl)?;
+        Ok(ztl)
+    }
+
+    // rename_tag renames the tag in the ids and the frontmatter of every
+    // note, the tags nested under it move along. Renamed zettels have their
+    // links rewritten. The old and new paths of the changed notes are
+    // returned.
+    pub fn rename_tag(&self, tag: &str, new: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let (tag, new) = (nested_tag(tag), nested_tag(new));
+        if new.split('/').any(str::is_empty) || new.contains(['_', ' ']) {
+            return Err(Error::PKMError(format!(
+                "invalid tag {:?}, tags can't be empty or contain _ or spaces",
+                new
+            )));
+        }
+
+        let rename = |t: &str| match t.strip_prefix(tag.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                Some(format!("{}{}", new, rest))
+            }
+            _ => None,
+        };
+
+        let mut changed = Vec::new();
+        for note in self.notes().collect::<Vec<PathBuf>>() {
+            let mut ztl = Zettel::new(&note)?;
+            let mut updated = false;
+
+            let tags = Frontmatter::parse(ztl.content().as_deref().unwrap_or("")).list(TAGS_KEY);
+            if tags.iter().any(|t| rename(t).is_some()) {
+                let mut renamed: Vec<String> = Vec::new();
+                for t in tags {
+                    let t = rename(&t).unwrap_or(t);
+                    if !renamed.contains(&t) {
+                        renamed.push(t);
+                    }
+                }
+
+                ztl.mut_content()?
+                    .set_frontmatter(TAGS_KEY, &serde_json::to_string(&renamed)?)?;
+                ztl = ztl.sync()?;
+                updated = true;
+            }
+
+            if let Some(id) = ztl.id().replace_tag(&tag, &new) {
+                ztl = self.rename_zettel(ztl, &id)?.0;
+                updated = true;
+            }
+
+            if updated {
+                changed.push((note, ztl.path().to_path_buf()));
+            }
+        }
+
+        Ok(changed)
+    }
+
+    // adopt makes the zettel a child of the parent. The child links to its
+    // parent from the frontmatter, which makes the parent a backlink of the
+    // child, and the parent links to the child under its children section.
+    pub fn adopt(&self, child: Zettel, mut parent: Zettel) -> Result<Zettel> {
+        let (child_id, parent_id) = (child.id(), parent.id());
+        if child.path() == parent.path() {
+            return Err(Error::PKMError(String::from(
+                "a zettel can not be its own parent",
+            )));
+        }
+
+        let mut child = child;
+        child.mut_content()?.set_frontmatter(
+            PARENT_KEY,
+            &serde_json::to_string(&format!("[[{}]]", parent_id))?,
+        )?;
+        let child = child.sync()?;
+
+        parent
+            .mut_content()?
+            .append_to_section(CHILDREN_HEADING, &format!("- [[{}]]", child_id))?;
+        parent.sync()?;
+
+        self.record(&format!("made {} the parent of", parent_id), &child_id)?;
+        Ok(child)
+    }
+
+    // backlinks returns the notes with a wikilink to the id
+    pub fn backlinks(&self, id: &ZettelID) -> Result<Vec<PathBuf>> {
+        let mut linked = Vec::new();
+        for note in self.notes() {
+            let content = fs::read_to_string(&note)?;
+            if wikilinks(&content)
+                .iter()
+                .any(|link| link.id.eq_ignore_ascii_case(id))
+            {
+                linked.push(note);
+            }
+        }
+        Ok(linked)
+    }
+
+    // trash_zettel moves the zettel into the trash, keeping the directory
+    // structure it had within the repo so it can be restored. With images the
+    // images no other note links to are trashed along with it. The paths
+    // within the trash are returned.
+    pub fn trash_zettel(&self, ztl: Zettel, images: bool) -> Result<Vec<PathBuf>> {
+        let root = fs::canonicalize(&self.root)?;
+        let mut paths = vec![fs::canonicalize(ztl.path())?];
+        if images {
+            paths.extend(self.exclusive_images(&ztl)?);
+        }
+
+        // every destination is worked out before anything moves, so a path
+        // that can't be trashed leaves the zettel where it was
+        let moves = paths
+            .into_iter()
+            .map(|path| {
+                let to = self.root.join(TRASH_DIR).join(path.strip_prefix(&root)?);
+                Ok((path, to))
+            })
+            .collect::<Result<Vec<(PathBuf, PathBuf)>>>()?;
+
+        let mut trashed = Vec::new();
+        for (path, to) in moves {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&path, &to)?;
+            trashed.push(to);
+        }
+
+        self.record("trashed", &ztl.id())?;
+        Ok(trashed)
+    }
+
+    // exclusive_images returns the images within the repo the zettel links to
+    // that no other note links to
+    fn exclusive_images(&self, ztl: &Zettel) -> Result<Vec<PathBuf>> {
+        let root = fs::canonicalize(&self.root)?;
+        let images = |note: &Path, content: &str| -> Vec<PathBuf> {
+            image_links(content)
+                .iter()
+                .filter_map(|link| resolve_local_link(&self.root, note, link))
+                .filter(|path| path.is_file())
+                .filter_map(|path| fs::canonicalize(path).ok())
+                .filter(|path| path.starts_with(&root))
+                .collect()
+        };
+
+        let mut exclusive = images(ztl.path(), ztl.content().as_deref().unwrap_or(""));
+        for note in self.notes().filter(|note| note.as_path() != ztl.path()) {
+            if exclusive.is_empty() {
+                break;
+            }
+
+            let others = images(&note, &fs::read_to_string(&note)?);
+            exclusive.retain(|image| !others.contains(image));
+        }
+
+        exclusive.sort();
+        exclusive.dedup();
+        Ok(exclusive)
+    }
+
+    // trashed returns the files within the trash
+    pub fn trashed(&self) -> Vec<PathBuf> {
+        WalkDir::new(self.root.join(TRASH_DIR))
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect()
+    }
+
+    // restore moves the file within the trash back to where it was removed
+    // from, files that have been replaced since are not overwritten
+    pub fn restore(&self, trashed: &Path) -> Result<PathBuf> {
+        let trash = self.root.join(TRASH_DIR);
+        let to = self.root.join(trashed.strip_prefix(&trash)?);
+        if to.exists() {
+            return Err(Error::PKMError(format!(
+                "can not restore {}, it already exists",
+                to.to_string_lossy()
+            )));
+        }
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(trashed, &to)?;
+        self.record("restored", &ZettelID::from_path(&to))?;
+        Ok(to)
+    }
+
+    // empty_trash removes everything within the trash for good, returning how
+    // many files were removed
+    pub fn empty_trash(&self) -> Result<usize> {
+        let removed = self.trashed().len();
+        let trash = self.root.join(TRASH_DIR);
+        if trash.exists() {
+            fs::remove_dir_all(trash)?;
+        }
+        Ok(removed)
+    }
+
+    // archive_zettel moves the zettel into the archive directory, keeping the
+    // directory structure it had within the repo, and marks it archived in its
+    // frontmatter so searches leave it out. The notes whose links to it were
+    // rewritten are returned along with it.
+    pub fn archive_zettel(&self, mut ztl: Zettel) -> Result<(Zettel, Vec<PathBuf>)> {
+        let new = self.archive_dir.join(ztl.rel_path(&self.root)?);
+        ztl.mut_content()?.set_frontmatter(ARCHIVED_KEY, "true")?;
+
+        let (archived, rewritten) = self.relocate_zettel(ztl, new)?;
+        self.record("archived", &archived.id())?;
+        Ok((archived, rewritten))
+    }
+
+    // relocate_zettel moves the zettel to the path within the repo. The id
+    // stays the same so wikilinks keep working, markdown links to the zettel
+    // are pointed at its new path. The moved zettel is returned along with the
+    // notes whose links were rewritten.
+    pub fn relocate_zettel<P: AsRef<Path>>(
+        &self,
+        ztl: Zettel,
+        to: P,
+    ) -> Result<(Zettel, Vec<PathBuf>)> {
+        let to = to.as_ref();
+        if to.exists() {
+            return Err(Error::PKMError(format!(
+                "can not move to {}, it already exists",
+                to.to_string_lossy()
+            )));
+        }
+
+        let old = fs::canonicalize(ztl.path())?;
+        let mut rewritten = Vec::new();
+        for note in self.notes().filter(|note| note.as_path() != ztl.path()) {
+            let content = fs::read_to_string(&note)?;
+            let content = rewrite_markdown_links(&content, |link| {
+                resolve_local_link(&self.root, &note, link)
+                    .and_then(|path| fs::canonicalize(path).ok())
+                    .filter(|path| *path == old)
+                    .map(|_| root_link(&self.root, to, link))
+            });
+
+            if let Some(content) = content {
+                log::info!("rewriting links in {:?}", &note);
+                fs::write(&note, content)?;
+                rewritten.push(note);
+            }
+        }
+
+        // links relative to the zettel would break once it moves, they are
+        // pointed at the same file from the root of the repo
+        let root = fs::canonicalize(&self.root)?;
+        let content = ztl.content().as_deref().unwrap_or("").to_string();
+        let content = rewrite_markdown_links(&content, |link| {
+            let target =
+                fs::canonicalize(resolve_local_link(&self.root, ztl.path(), link)?).ok()?;
+            let from_root =
+                resolve_root_link(&self.root, link).and_then(|path| fs::canonicalize(path).ok());
+            (from_root.as_ref() != Some(&target) && target.starts_with(&root))
+                .then(|| root_link(&root, &target, link))
+        })
+        .unwrap_or(content);
+
+        let mut moved = Zettel::new(to)?;
+        moved.set_content(content);
+        let moved = moved.sync()?;
+        ztl.delete()?;
+        Ok((moved, rewritten))
+    }
+}
+
+// root_link is the link to the path from the root of the repo, keeping the
+// heading anchor of the link it replaces
+fn root_link(root: &Path, path: &Path, link: &str) -> String {
+    let anchor = link.find('#').map(|idx| &link[idx..]).unwrap_or("");
+    format!(
+        "/{}{}",
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace(' ', "%20"),
+        anchor
+    )
 }
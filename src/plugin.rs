@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use rhai::{
+    Array, Dynamic, Engine, EvalAltResult, Map, Scope, module_resolvers::DummyModuleResolver,
+};
+
+use crate::{Error, PKM, Result, ZettelID, ZettelIndex, title_similarity};
+
+// PLUGIN_DIR is the directory, relative to the root directory, plugins are
+// loaded from
+pub const PLUGIN_DIR: &str = ".pkm/plugins";
+
+// PLUGIN_EXTENSION is the extension of the plugin scripts
+pub const PLUGIN_EXTENSION: &str = "rhai";
+
+// MAX_OPERATIONS bounds how much work a plugin does, so a runaway loop ends in
+// an error instead of hanging pkm
+const MAX_OPERATIONS: u64 = 50_000_000;
+
+// SIMILAR_NAME is how alike a name must be to a command to be suggested for it
+const SIMILAR_NAME: f64 = 0.6;
+
+// Plugin is a rhai script within the plugin directory. Plugins are run as
+// subcommands of pkm, `pkm <name> [args]`, with the arguments in the args
+// array. The script is sandboxed, it can't read or write files, run commands
+// or import other scripts. It only has the API over the repo, where paths
+// are relative to the root directory:
+//
+//   create_note(title) -> path        creates a zettel from its template
+//   search(query) -> [#{uri, title}]  searches the index
+//   notes() -> [path]                 lists the notes
+//   read_note(path) -> content        reads a note
+//   write_note(path, content)         replaces the content of a note
+//
+// A plugin describes itself to pkm plugins with an ABOUT constant, like
+// `const ABOUT = "Print the open tasks";`.
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Plugin {
+    // find returns the plugin with the name, if there is one
+    pub fn find<P: AsRef<Path>>(root: P, name: &str) -> Result<Option<Plugin>> {
+        Ok(plugins(root)?.into_iter().find(|p| p.name == name))
+    }
+
+    // about is the ABOUT constant of the plugin
+    pub fn about(&self) -> Result<Option<String>> {
+        let ast = sandbox()
+            .compile_file(self.path.clone())
+            .map_err(|err| self.error(err))?;
+
+        Ok(ast
+            .iter_literal_variables(true, false)
+            .find(|(name, _, _)| *name == "ABOUT")
+            .map(|(_, _, about)| about.to_string()))
+    }
+
+    // run runs the plugin with the arguments and waits for it to finish
+    pub fn run<I, S>(&self, pkm: &Rc<PKM>, args: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut engine = sandbox();
+        register_api(&mut engine, pkm);
+
+        let args: Array = args
+            .into_iter()
+            .map(|arg| Dynamic::from(Into::<String>::into(arg)))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push_constant("args", args);
+
+        let ast = engine
+            .compile_file(self.path.clone())
+            .map_err(|err| self.error(err))?;
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| self.error(err))
+    }
+
+    fn error(&self, err: Box<EvalAltResult>) -> Error {
+        Error::PluginError(format!("{}: {}", self.name, err))
+    }
+}
+
+// sandbox is an engine without access to the file system or other scripts,
+// with limits on how deep and how long a script can run
+fn sandbox() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(64);
+    engine.set_max_expr_depths(64, 64);
+    engine.set_max_string_size(16 * 1024 * 1024);
+    engine.set_max_array_size(100_000);
+    engine.set_max_map_size(10_000);
+    engine
+}
+
+// register_api registers the functions plugins have over the repo
+fn register_api(engine: &mut Engine, pkm: &Rc<PKM>) {
+    let repo = Rc::clone(pkm);
+    engine.register_fn(
+        "create_note",
+        move |title: &str| -> std::result::Result<String, Box<EvalAltResult>> {
+            let ztl = repo.create_zettel(title).map_err(script_error)?;
+            Ok(relative(&repo, ztl.path()))
+        },
+    );
+
+    let repo = Rc::clone(pkm);
+    engine.register_fn(
+        "search",
+        move |query: &str| -> std::result::Result<Array, Box<EvalAltResult>> {
+            let index = ZettelIndex::new(repo.root.as_path()).map_err(script_error)?;
+            let docs = index
+                .doc_searcher()
+                .and_then(|searcher| searcher.find(query))
+                .map_err(script_error)?;
+
+            Ok(docs
+                .into_iter()
+                .map(|doc| {
+                    let mut hit = Map::new();
+                    for key in ["uri", "title"] {
+                        let value = doc.get(key).cloned().unwrap_or_default();
+                        hit.insert(key.into(), Dynamic::from(value));
+                    }
+                    Dynamic::from_map(hit)
+                })
+                .collect())
+        },
+    );
+
+    let repo = Rc::clone(pkm);
+    engine.register_fn("notes", move || -> Array {
+        repo.notes()
+            .map(|note| Dynamic::from(relative(&repo, &note)))
+            .collect()
+    });
+
+    let repo = Rc::clone(pkm);
+    engine.register_fn(
+        "read_note",
+        move |path: &str| -> std::result::Result<String, Box<EvalAltResult>> {
+            let path = note_path(&repo, path).map_err(script_error)?;
+            fs::read_to_string(path).map_err(|err| script_error(err.into()))
+        },
+    );
+
+    let repo = Rc::clone(pkm);
+    engine.register_fn(
+        "write_note",
+        move |path: &str, content: &str| -> std::result::Result<(), Box<EvalAltResult>> {
+            let path = note_path(&repo, path).map_err(script_error)?;
+            fs::write(&path, content).map_err(|err| script_error(err.into()))?;
+            repo.record("edited", &ZettelID::from_path(&path))
+                .map_err(script_error)
+        },
+    );
+}
+
+// note_path resolves the path of a note relative to the root, a plugin can't
+// reach outside of the repo or touch anything but notes
+fn note_path(pkm: &PKM, path: &str) -> Result<PathBuf> {
+    let rel = Path::new(path);
+    let within = rel
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !within || rel.extension().is_none_or(|ext| ext != "md") {
+        return Err(Error::PKMError(format!(
+            "{:?} is not the path of a note within the repo",
+            path
+        )));
+    }
+    Ok(pkm.root.join(rel))
+}
+
+fn relative(pkm: &PKM, path: &Path) -> String {
+    path.strip_prefix(&pkm.root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn script_error(err: Error) -> Box<EvalAltResult> {
+    err.to_string().into()
+}
+
+// plugins lists the plugins of the repo sorted by name. The name of a plugin
+// is its file name without the extension, so `tasks.rhai` runs as `pkm tasks`.
+pub fn plugins<P: AsRef<Path>>(root: P) -> Result<Vec<Plugin>> {
+    let mut dir = root.as_ref().to_path_buf();
+    dir.push(PLUGIN_DIR);
+
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut plugins = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != PLUGIN_EXTENSION) {
+            continue;
+        }
+
+        let name = path
+            .file_stem()
+            .ok_or_else(|| Error::PKMError(format!("invalid plugin {}", path.display())))?
+            .to_string_lossy()
+            .to_string();
+
+        plugins.push(Plugin { name, path });
+    }
+
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(plugins)
+}
+
+// similar_names are the names that look like a mistyped name, most alike
+// first
+pub fn similar_names<I, S>(name: &str, names: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let mut similar: Vec<(f64, String)> = names
+        .into_iter()
+        .map(Into::into)
+        .map(|candidate| (title_similarity(name, &candidate), candidate))
+        .filter(|(similarity, _)| *similarity >= SIMILAR_NAME)
+        .collect();
+    similar.sort_by(|a, b| b.0.total_cmp(&a.0));
+    similar.into_iter().map(|(_, name)| name).collect()
+}
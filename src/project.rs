@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use markdown::{ParseOptions, mdast::Node};
+
+use crate::{Error, Result, latest_tags, remote_url};
+
+// PROJECTS_FILE maps code repos to their project notes, relative to the root
+// directory. The repos are kept relative to the root directory as well, so
+// the links hold on every machine the notes are synced to.
+pub const PROJECTS_FILE: &str = ".pkm/projects.json";
+pub const PROJECT_TAG: &str = "project";
+
+// Projects are the code repos linked to project notes
+pub struct Projects {
+    root: PathBuf,
+    path: PathBuf,
+    links: BTreeMap<PathBuf, String>,
+}
+
+impl Projects {
+    // open reads the links of the repo, no file means no links
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let mut path = root.as_ref().to_path_buf();
+        path.push(PROJECTS_FILE);
+
+        let root = root.as_ref().to_path_buf();
+        let links: BTreeMap<PathBuf, String> = if path.is_file() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        // links saved before the repos were kept relative are absolute
+        let links = links
+            .into_iter()
+            .map(|(repo, id)| (relative_path(&repo, &root), id))
+            .collect();
+
+        Ok(Self { root, path, links })
+    }
+
+    // get returns the id of the project note linked to the code repo
+    pub fn get<P: AsRef<Path>>(&self, repo: P) -> Option<&str> {
+        self.links
+            .get(&relative_path(repo.as_ref(), &self.root))
+            .map(String::as_str)
+    }
+
+    // link links the code repo to the project note
+    pub fn link<P: AsRef<Path>, S: Into<String>>(&mut self, repo: P, id: S) {
+        self.links
+            .insert(relative_path(repo.as_ref(), &self.root), id.into());
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, serde_json::to_string_pretty(&self.links)?)?;
+        Ok(())
+    }
+}
+
+// ProjectInfo is the metadata of a code repo kept in its project note
+pub struct ProjectInfo {
+    pub name: String,
+    pub repo: PathBuf,
+    pub remote: Option<String>,
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+}
+
+impl ProjectInfo {
+    // read gathers the metadata of the code repo, the summary is the first
+    // paragraph of its README
+    pub fn read<P: AsRef<Path>>(repo: P) -> Result<Self> {
+        let repo = repo.as_ref().to_path_buf();
+        let name = repo
+            .file_name()
+            .ok_or_else(|| Error::PKMError(format!("{:?} has no name", repo)))?
+            .to_string_lossy()
+            .to_string();
+
+        Ok(Self {
+            remote: remote_url(&repo)?,
+            tags: latest_tags(&repo, 5)?,
+            summary: readme_summary(&repo)?,
+            name,
+            repo,
+        })
+    }
+
+    // markdown renders the metadata as the body of the project note block
+    pub fn markdown(&self) -> String {
+        let mut body = format!("- path: `{}`\n", self.repo.display());
+        if let Some(remote) = self.remote.as_ref() {
+            body.push_str(&format!("- remote: {}\n", remote));
+        }
+        if !self.tags.is_empty() {
+            body.push_str(&format!("- tags: {}\n", self.tags.join(", ")));
+        }
+        if let Some(summary) = self.summary.as_ref() {
+            body.push_str(&format!("\n{}\n", summary));
+        }
+        body.trim_end().to_string()
+    }
+}
+
+// relative_path is the absolute path relative to the base, like ../code/pkm.
+// A relative path is left as it is, as is a path on another drive.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    if !path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return path.iter().collect();
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+fn readme_summary(repo: &Path) -> Result<Option<String>> {
+    let readme = match ["README.md", "readme.md", "Readme.md"]
+        .iter()
+        .map(|name| repo.join(name))
+        .find(|path| path.is_file())
+    {
+        Some(readme) => readme,
+        None => return Ok(None),
+    };
+
+    let ast = markdown::to_mdast(&fs::read_to_string(readme)?, &ParseOptions::gfm())?;
+    Ok(ast.children().and_then(|children| {
+        children.iter().find_map(|node| match node {
+            Node::Paragraph(_) => Some(node.to_string()),
+            _ => None,
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repos_are_relative_to_the_root() {
+        let root = Path::new("/home/ana/notes");
+        assert_eq!(
+            relative_path(Path::new("/home/ana/code/pkm"), root),
+            PathBuf::from("../code/pkm")
+        );
+        assert_eq!(
+            relative_path(Path::new("/home/ana/notes/code"), root),
+            PathBuf::from("code")
+        );
+        assert_eq!(relative_path(root, root), PathBuf::from("."));
+        assert_eq!(
+            relative_path(Path::new("../code/pkm"), root),
+            PathBuf::from("../code/pkm")
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use std::ops::Bound;
+
+use chrono::{Local, NaiveDate};
+use human_date_parser::ParseResult;
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, EmptyQuery, Occur, PhraseQuery, Query, RangeQuery,
+    TermQuery,
+};
+use tantivy::schema::{Field, FieldType, IndexRecordOption, Schema};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{Index, Term};
+
+use crate::{
+    Error, FACET_FIELDS, FRONTMATTER_FIELD_PREFIX, INDEX_CONFIG_FILE, Result, facet_path,
+    frontmatter_field, index_date, nested_tag,
+};
+
+// TITLE_BOOST is how much more a match in the title counts than one in the
+// body, an alias is another title of the note so it shares the boost
+const TITLE_BOOST: f32 = 3.0;
+
+// TEXT_FIELDS are the fields a term without a field is searched in, along with
+// their boost. A section heading counts for more than a mention in the body
+// and the text of an attachment for less than the note itself.
+const TEXT_FIELDS: [(&str, f32); 5] = [
+    ("title", TITLE_BOOST),
+    ("aliases", TITLE_BOOST),
+    ("headings", 2.0),
+    ("content", 1.0),
+    ("attachments", 0.5),
+];
+
+// QUERY_FIELDS are the fields a term can be limited to, like title:rust.
+// after:2024-01-01 and before:2024-02-01 are the notes dated from and before
+// the day. The frontmatter fields of the index config can be searched as
+// well, like status:draft. Any other word with a colon, like note: or a url,
+// is searched as it is.
+const QUERY_FIELDS: [&str; 13] = [
+    "title",
+    "heading",
+    "content",
+    "alias",
+    "aliases",
+    "attachment",
+    "id",
+    "tag",
+    "kind",
+    "year",
+    "dir",
+    "after",
+    "before",
+];
+
+// SearchQuery is a parsed search, like `title:"rust" tag:meeting -tag:fleeting`.
+// Terms are and'ed together unless they are joined by OR, a term starting
+// with - or NOT is left out and parentheses group terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchQuery {
+    All,
+    Term { field: Option<String>, text: String },
+    Not(Box<SearchQuery>),
+    And(Vec<SearchQuery>),
+    Or(Vec<SearchQuery>),
+}
+
+// Token is a piece of a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    And,
+    Or,
+    Term { field: Option<String>, text: String },
+}
+
+impl SearchQuery {
+    // parse reads the query, an empty query matches every note. The fields
+    // are the frontmatter keys that are indexed, see frontmatter_keys.
+    pub fn parse(query: &str, fields: &[String]) -> Result<SearchQuery> {
+        let tokens = tokenize(query, fields)?;
+        if tokens.is_empty() {
+            return Ok(SearchQuery::All);
+        }
+
+        let mut parser = Parser { tokens, pos: 0 };
+        let parsed = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some((_, column)) => Err(invalid(format!(
+                "unexpected ) at column {}, there is no ( to close",
+                column
+            ))),
+            None => Ok(parsed),
+        }
+    }
+
+    // query turns the parsed query into a query on the index
+    pub fn query(&self, index: &Index) -> Result<Box<dyn Query>> {
+        Ok(match self {
+            SearchQuery::All => Box::new(AllQuery),
+            SearchQuery::Term { field: None, text } => {
+                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for (field, boost) in TEXT_FIELDS {
+                    clauses.push((
+                        Occur::Should,
+                        Box::new(BoostQuery::new(text_query(index, field, text)?, boost)),
+                    ));
+                }
+                Box::new(BooleanQuery::new(clauses))
+            }
+            SearchQuery::Term {
+                field: Some(field),
+                text,
+            } => field_query(index, field, text)?,
+            SearchQuery::Not(query) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, Box::new(AllQuery)),
+                (Occur::MustNot, query.query(index)?),
+            ])),
+            SearchQuery::And(queries) => {
+                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for query in queries {
+                    match query {
+                        SearchQuery::Not(query) => {
+                            clauses.push((Occur::MustNot, query.query(index)?))
+                        }
+                        query => clauses.push((Occur::Must, query.query(index)?)),
+                    }
+                }
+
+                // only leaving notes out leaves every other note
+                if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+                    clauses.push((Occur::Must, Box::new(AllQuery)));
+                }
+                Box::new(BooleanQuery::new(clauses))
+            }
+            SearchQuery::Or(queries) => {
+                let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                for query in queries {
+                    clauses.push((Occur::Should, query.query(index)?));
+                }
+                Box::new(BooleanQuery::new(clauses))
+            }
+        })
+    }
+}
+
+// frontmatter_keys are the frontmatter keys the schema indexes, like status
+pub fn frontmatter_keys(schema: &Schema) -> Vec<String> {
+    schema
+        .fields()
+        .filter_map(|(_, entry)| entry.name().strip_prefix(FRONTMATTER_FIELD_PREFIX))
+        .map(String::from)
+        .collect()
+}
+
+// field_query matches the text within the field, tags, kinds and years are
+// facets so tag:project matches project/alpha as well
+fn field_query(index: &Index, field: &str, text: &str) -> Result<Box<dyn Query>> {
+    let schema = index.schema();
+    match field {
+        "alias" | "aliases" => text_query(index, "aliases", text),
+        "title" | "content" => text_query(index, field, text),
+        "heading" => text_query(index, "headings", text),
+        "attachment" => text_query(index, "attachments", text),
+        "after" => Ok(date_query(index, Some(parse_search_date(text)?), None)),
+        "before" => Ok(date_query(index, None, Some(parse_search_date(text)?))),
+        "id" => Ok(Box::new(TermQuery::new(
+            Term::from_field_text(schema.get_field("id").expect("id not in schema"), text),
+            IndexRecordOption::Basic,
+        ))),
+        "dir" => Ok(Box::new(TermQuery::new(
+            Term::from_facet(
+                schema.get_field("dir").expect("dir not in schema"),
+                &facet_path(text),
+            ),
+            IndexRecordOption::Basic,
+        ))),
+        field if FACET_FIELDS.contains(&field) => Ok(Box::new(TermQuery::new(
+            Term::from_facet(
+                schema.get_field(field).expect("facet not in schema"),
+                &facet_path(&nested_tag(text.trim_start_matches('#'))),
+            ),
+            IndexRecordOption::Basic,
+        ))),
+        field => match schema.get_field(&frontmatter_field(field)) {
+            Ok(field) => frontmatter_query(index, field, text),
+            Err(_) => Err(unknown_field(field)),
+        },
+    }
+}
+
+// frontmatter_query matches the value of a frontmatter field, a date field
+// takes <, <=, > and >= in front of the date to match the days before or
+// after it
+fn frontmatter_query(index: &Index, field: Field, text: &str) -> Result<Box<dyn Query>> {
+    let schema = index.schema();
+    if !matches!(
+        schema.get_field_entry(field).field_type(),
+        FieldType::Date(_)
+    ) {
+        return text_query(index, schema.get_field_name(field), text);
+    }
+
+    let (op, date) = ["<=", ">=", "<", ">"]
+        .iter()
+        .find_map(|op| text.strip_prefix(op).map(|date| (*op, date)))
+        .unwrap_or(("", text));
+    let date = parse_search_date(date)?;
+    let term = |date: NaiveDate| Term::from_field_date(field, index_date(date));
+    let next = date.succ_opt().unwrap_or(date);
+    let (from, to) = match op {
+        "<" => (Bound::Unbounded, Bound::Excluded(term(date))),
+        "<=" => (Bound::Unbounded, Bound::Excluded(term(next))),
+        ">" => (Bound::Included(term(next)), Bound::Unbounded),
+        ">=" => (Bound::Included(term(date)), Bound::Unbounded),
+        _ => (Bound::Included(term(date)), Bound::Excluded(term(next))),
+    };
+    Ok(Box::new(RangeQuery::new(from, to)))
+}
+
+// date_query matches the notes dated from the first day up to, but not
+// including, the last day
+pub fn date_query(index: &Index, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Box<dyn Query> {
+    let field = index
+        .schema()
+        .get_field("date")
+        .expect("date not in schema");
+    let term = |date: NaiveDate| Term::from_field_date(field, index_date(date));
+    Box::new(RangeQuery::new(
+        from.map(term).map_or(Bound::Unbounded, Bound::Included),
+        to.map(term).map_or(Bound::Unbounded, Bound::Excluded),
+    ))
+}
+
+// parse_search_date parses a date like 2024-01-01, or one like "last week"
+pub fn parse_search_date(date: &str) -> Result<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+        return Ok(date);
+    }
+
+    Ok(
+        match human_date_parser::from_human_time(date, Local::now().naive_local())? {
+            ParseResult::DateTime(datetime) => datetime.date(),
+            ParseResult::Date(date) => date,
+            ParseResult::Time(_) => Local::now().date_naive(),
+        },
+    )
+}
+
+// text_query runs the text through the tokenizer of the field, more than one
+// token has to be found as a phrase
+fn text_query(index: &Index, field: &str, text: &str) -> Result<Box<dyn Query>> {
+    let field = index
+        .schema()
+        .get_field(field)
+        .expect("text field not in schema");
+
+    let mut analyzer = index.tokenizer_for_field(field)?;
+    let mut stream = analyzer.token_stream(text);
+    let mut terms = Vec::new();
+    while stream.advance() {
+        terms.push((
+            stream.token().position,
+            Term::from_field_text(field, &stream.token().text),
+        ));
+    }
+
+    Ok(match terms.len() {
+        0 => Box::new(EmptyQuery),
+        1 => Box::new(TermQuery::new(
+            terms.remove(0).1,
+            IndexRecordOption::WithFreqs,
+        )),
+        _ => Box::new(PhraseQuery::new_with_offset(terms)),
+    })
+}
+
+// tokenize splits the query into its tokens along with the column each one
+// starts at, a word is only a field when it names one of the QUERY_FIELDS or
+// the frontmatter fields
+fn tokenize(query: &str, fields: &[String]) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                tokens.push((Token::Open, column));
+                i += 1;
+                continue;
+            }
+            ')' => {
+                tokens.push((Token::Close, column));
+                i += 1;
+                continue;
+            }
+            '-' if chars.get(i + 1).is_some_and(|c| !c.is_whitespace()) => {
+                tokens.push((Token::Not, column));
+                i += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        // a word runs up to the next space or parenthesis, a field followed
+        // by a quote takes the quoted phrase as its text
+        let start = i;
+        while i < chars.len() && !chars[i].is_whitespace() && !matches!(chars[i], '(' | ')' | '"') {
+            i += 1;
+        }
+        let word: String = chars[start..i].iter().collect();
+
+        let (field, text) = match word.split_once(':') {
+            Some((field, text))
+                if QUERY_FIELDS.contains(&field.to_lowercase().as_str())
+                    || fields.contains(&field.to_lowercase()) =>
+            {
+                (Some(field.to_lowercase()), text.to_string())
+            }
+            _ => (None, word),
+        };
+
+        let text = if text.is_empty() && chars.get(i) == Some(&'"') {
+            let open = i + 1;
+            i += 1;
+            let start = i;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(invalid(format!(
+                    "the quote at column {} is never closed",
+                    open
+                )));
+            }
+            i += 1;
+            chars[start..i - 1].iter().collect()
+        } else if text.is_empty() && field.is_some() {
+            return Err(invalid(format!(
+                "{}: at column {} is missing a value, like {}:rust",
+                field.as_deref().unwrap_or_default(),
+                column,
+                field.as_deref().unwrap_or_default()
+            )));
+        } else {
+            text
+        };
+
+        if matches!(field.as_deref(), Some("after") | Some("before"))
+            && parse_search_date(&text).is_err()
+        {
+            return Err(invalid(format!(
+                "invalid date {:?} at column {}, expected a date like 2024-01-01 or \"last week\"",
+                text, column
+            )));
+        }
+
+        match (field.is_none(), text.as_str()) {
+            (true, "OR") => tokens.push((Token::Or, column)),
+            (true, "AND") => tokens.push((Token::And, column)),
+            (true, "NOT") => tokens.push((Token::Not, column)),
+            (_, "") | (true, "-") => (),
+            _ => tokens.push((Token::Term { field, text }, column)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Parser turns the tokens into a query, OR binds looser than the terms
+// and'ed together
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    // column is where the next token starts, or just past the last one
+    fn column(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some((_, column)) => *column,
+            None => self
+                .tokens
+                .last()
+                .map(|(_, column)| column + 1)
+                .unwrap_or(1),
+        }
+    }
+
+    fn or(&mut self) -> Result<SearchQuery> {
+        let mut queries = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            queries.push(self.and()?);
+        }
+
+        Ok(match queries.len() {
+            1 => queries.remove(0),
+            _ => SearchQuery::Or(queries),
+        })
+    }
+
+    fn and(&mut self) -> Result<SearchQuery> {
+        let mut queries = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) => break,
+                Some(Token::And) => self.pos += 1,
+                _ => queries.push(self.unary()?),
+            }
+        }
+
+        match queries.len() {
+            0 => Err(invalid(format!(
+                "expected a term at column {}",
+                self.column()
+            ))),
+            1 => Ok(queries.remove(0)),
+            _ => Ok(SearchQuery::And(queries)),
+        }
+    }
+
+    fn unary(&mut self) -> Result<SearchQuery> {
+        let column = self.column();
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Not) => match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) | Some(Token::And) => Err(invalid(
+                    format!("nothing to leave out after the - at column {}", column),
+                )),
+                _ => Ok(SearchQuery::Not(Box::new(self.unary()?))),
+            },
+            Some(Token::Open) => {
+                let query = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(invalid(format!(
+                        "the ( at column {} is never closed",
+                        column
+                    )));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            Some(Token::Term { field, text }) => Ok(SearchQuery::Term { field, text }),
+            _ => Err(invalid(format!("expected a term at column {}", column))),
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidQuery(message)
+}
+
+fn unknown_field(field: &str) -> Error {
+    invalid(format!(
+        "unknown field {:?}, expected one of {} or a frontmatter field indexed by {}",
+        field,
+        QUERY_FIELDS.join(", "),
+        INDEX_CONFIG_FILE
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Option<&str>, text: &str) -> SearchQuery {
+        SearchQuery::Term {
+            field: field.map(String::from),
+            text: String::from(text),
+        }
+    }
+
+    fn parse(query: &str) -> Result<SearchQuery> {
+        SearchQuery::parse(query, &[String::from("status")])
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(parse("  ").unwrap(), SearchQuery::All);
+    }
+
+    #[test]
+    fn terms_are_anded() {
+        assert_eq!(
+            parse("rust Tag:meeting -tag:fleeting").unwrap(),
+            SearchQuery::And(vec![
+                term(None, "rust"),
+                term(Some("tag"), "meeting"),
+                SearchQuery::Not(Box::new(term(Some("tag"), "fleeting"))),
+            ])
+        );
+        assert_eq!(
+            parse("a AND NOT b").unwrap(),
+            SearchQuery::And(vec![
+                term(None, "a"),
+                SearchQuery::Not(Box::new(term(None, "b")))
+            ])
+        );
+    }
+
+    #[test]
+    fn or_binds_looser_than_and() {
+        assert_eq!(
+            parse("a OR b c").unwrap(),
+            SearchQuery::Or(vec![
+                term(None, "a"),
+                SearchQuery::And(vec![term(None, "b"), term(None, "c")]),
+            ])
+        );
+        assert_eq!(
+            parse("(a OR b) -c").unwrap(),
+            SearchQuery::And(vec![
+                SearchQuery::Or(vec![term(None, "a"), term(None, "b")]),
+                SearchQuery::Not(Box::new(term(None, "c"))),
+            ])
+        );
+    }
+
+    #[test]
+    fn fields_take_quoted_phrases() {
+        assert_eq!(
+            parse("title:\"rust lang\"").unwrap(),
+            term(Some("title"), "rust lang")
+        );
+        assert_eq!(parse("\"rust lang\"").unwrap(), term(None, "rust lang"));
+    }
+
+    #[test]
+    fn frontmatter_fields_must_be_indexed() {
+        assert_eq!(
+            parse("status:draft").unwrap(),
+            term(Some("status"), "draft")
+        );
+        assert_eq!(
+            SearchQuery::parse("status:draft", &[]).unwrap(),
+            term(None, "status:draft")
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_searched_as_words() {
+        assert_eq!(
+            parse("note: https://example.com").unwrap(),
+            SearchQuery::And(vec![term(None, "note:"), term(None, "https://example.com")])
+        );
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        for query in ["(a", "a)", "title:", "\"rust", "a OR", "- OR a", "after:"] {
+            assert!(
+                matches!(parse(query), Err(Error::InvalidQuery(_))),
+                "{:?} should be invalid",
+                query
+            );
+        }
+    }
+}
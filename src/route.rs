@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use crate::{Error, Result, ZettelID};
+
+// Route sends the zettels with the tag to their own template and directory
+// instead of the default template and the zettel directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub tag: String,
+    pub template: Option<String>,
+    pub dir: Option<PathBuf>,
+}
+
+impl Route {
+    // matches is true when the id has the tag of the route
+    pub fn matches(&self, id: &ZettelID) -> bool {
+        id.has_tag(&self.tag)
+    }
+}
+
+// parse_route parses a route written as tag=template:dir, either the template
+// or the dir can be left out, like meeting=meeting:meetings, meeting=:meetings
+// or fleeting=fleeting
+pub fn parse_route(route: &str) -> Result<Route> {
+    let (tag, target) = route.split_once('=').ok_or_else(|| {
+        Error::PKMError(format!(
+            "invalid route {:?}, expected tag=template:dir",
+            route
+        ))
+    })?;
+
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err(Error::PKMError(format!("route {:?} has no tag", route)));
+    }
+
+    let (template, dir) = match target.split_once(':') {
+        Some((template, dir)) => (template.trim(), dir.trim()),
+        None => (target.trim(), ""),
+    };
+
+    Ok(Route {
+        tag: tag.to_string(),
+        template: Some(template)
+            .filter(|template| !template.is_empty())
+            .map(|template| template.trim_end_matches(".md").to_string()),
+        dir: Some(dir).filter(|dir| !dir.is_empty()).map(PathBuf::from),
+    })
+}
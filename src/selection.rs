@@ -0,0 +1,48 @@
+use serde_json::{Map, Value};
+
+// TITLE_LENGTH is the longest title taken from the text of a selection
+const TITLE_LENGTH: usize = 60;
+
+// Selection is text captured from another application, such as an editor or
+// a browser. The first line may be a json object describing where the text
+// came from, e.g. {"source": "firefox", "url": "https://..."}
+pub struct Selection {
+    pub meta: Map<String, Value>,
+    pub text: String,
+}
+
+impl Selection {
+    // parse splits the optional json header line from the text
+    pub fn parse(input: &str) -> Selection {
+        let (first, rest) = input.split_once('\n').unwrap_or((input, ""));
+
+        if first.trim_start().starts_with('{') {
+            if let Ok(Value::Object(meta)) = serde_json::from_str(first) {
+                return Selection {
+                    meta,
+                    text: rest.trim().to_string(),
+                };
+            }
+        }
+
+        Selection {
+            meta: Map::new(),
+            text: input.trim().to_string(),
+        }
+    }
+
+    // title is the title from the header, falling back to the first line of
+    // the text
+    pub fn title(&self) -> Option<String> {
+        if let Some(Value::String(title)) = self.meta.get("title") {
+            return Some(title.clone());
+        }
+
+        self.text
+            .lines()
+            .map(|line| line.trim_start_matches('#').trim())
+            .find(|line| !line.is_empty())
+            .map(|line| line.chars().take(TITLE_LENGTH).collect::<String>())
+            .map(|line| line.trim().to_string())
+    }
+}
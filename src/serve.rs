@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use markdown::{Options, to_html_with_options};
+use nanoid::nanoid;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::{
+    DocSearcher, Error, NoteReader, PKM, Result, VaultMetrics, Zettel, ZettelIndex, expand_embeds,
+    path_to_id,
+};
+
+// MAX_BODY_SIZE is the largest request body read, a note saved from the
+// editor is far below it
+const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+// MAX_HEAD_SIZE is the largest request line and headers read
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+// REQUEST_TIMEOUT is how long a client has to send its request, requests are
+// handled one at a time and a silent client would hold up everyone else
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// TOKEN_COOKIE keeps the edit token in the browser once it was passed in the
+// query of a request
+const TOKEN_COOKIE: &str = "pkm_token";
+
+// Server serves the notes of the repo over http. When edit mode is enabled
+// notes can also be modified from the browser by whoever has the token of the
+// run, in metrics mode nothing but the metrics is served.
+pub struct Server<'a> {
+    pkm: &'a PKM,
+    edit: bool,
+    metrics_only: bool,
+    token: String,
+}
+
+// Session is the state kept warm between requests so searches don't have to
+// reopen the index
+struct Session<'i> {
+    index: &'i ZettelIndex<&'i Path>,
+    searcher: DocSearcher<'i, &'i Path>,
+    metrics: SearchMetrics,
+}
+
+#[derive(Default)]
+struct SearchMetrics {
+    count: u64,
+    errors: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl SearchMetrics {
+    fn observe(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    // prometheus renders the metrics in the prometheus text format
+    fn prometheus(&self) -> String {
+        format!(
+            "# TYPE pkm_search_requests_total counter\n\
+             pkm_search_requests_total {}\n\
+             # TYPE pkm_search_errors_total counter\n\
+             pkm_search_errors_total {}\n\
+             # TYPE pkm_search_latency_seconds_sum counter\n\
+             pkm_search_latency_seconds_sum {}\n\
+             # TYPE pkm_search_latency_seconds_max gauge\n\
+             pkm_search_latency_seconds_max {}\n",
+            self.count,
+            self.errors,
+            self.total.as_secs_f64(),
+            self.max.as_secs_f64()
+        )
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    // header is the value of the header, the names are lowercased when read
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    // cookie is the value of the cookie sent with the request
+    fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("cookie")?
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+// Incoming is the request read from a connection, or the status it was
+// rejected with before it was read completely
+enum Incoming {
+    Request(HttpRequest),
+    Rejected(&'static str),
+}
+
+struct HttpResponse {
+    status: &'static str,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl HttpResponse {
+    fn json<S: Into<String>>(body: S) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    fn text<S: Into<String>>(body: S) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    fn html<S: Into<String>>(body: S) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    fn status(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            headers: Vec::new(),
+            body: status.to_string(),
+        }
+    }
+
+    fn redirect(location: String) -> Self {
+        Self {
+            status: "303 See Other",
+            content_type: "text/plain; charset=utf-8",
+            headers: vec![("Location", location)],
+            body: String::new(),
+        }
+    }
+}
+
+impl<'a> Server<'a> {
+    pub fn new(pkm: &'a PKM) -> Self {
+        Self {
+            pkm,
+            edit: false,
+            metrics_only: false,
+            token: nanoid!(32),
+        }
+    }
+
+    // token is the secret of this run required to edit notes, it is passed
+    // once as the token query parameter and then kept in a cookie
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    // edit enables the editing of notes from the browser
+    pub fn edit(mut self, edit: bool) -> Self {
+        self.edit = edit;
+        self
+    }
+
+    // metrics_only serves /metrics alone, for scraping by prometheus
+    pub fn metrics_only(mut self, metrics_only: bool) -> Self {
+        self.metrics_only = metrics_only;
+        self
+    }
+
+    // listen serves requests on the address until the process is stopped.
+    // Requests are handled one at a time which keeps writes to the notes and
+    // the index ordered.
+    pub async fn listen(self, addr: &str) -> Result<()> {
+        let addr = listen_addr(addr);
+        let listener = TcpListener::bind(&addr).await?;
+        log::info!("serving {:?} on http://{}", self.pkm.root, addr);
+
+        let mut metrics = SearchMetrics::default();
+        let mut pending = None;
+
+        // the index is opened once and the reader kept warm for every search,
+        // until pkm index swaps a rebuilt index in
+        loop {
+            let index = ZettelIndex::new(self.pkm.root.as_path())?;
+            let mut session = Session {
+                index: &index,
+                searcher: index.doc_searcher()?,
+                metrics,
+            };
+
+            loop {
+                let (stream, peer) = match pending.take() {
+                    Some(conn) => conn,
+                    None => listener.accept().await?,
+                };
+                if index.swapped() {
+                    log::info!("the index was rebuilt, reopening it");
+                    pending = Some((stream, peer));
+                    break;
+                }
+                if let Err(err) = self.handle(stream, &mut session).await {
+                    log::error!("could not handle request from {}: {}", peer, err);
+                }
+            }
+
+            metrics = session.metrics;
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream, session: &mut Session<'_>) -> Result<()> {
+        let incoming = match timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+            Ok(incoming) => incoming?,
+            Err(_) => Incoming::Rejected("408 Request Timeout"),
+        };
+        let resp = match incoming {
+            Incoming::Request(req) => {
+                log::debug!("{} {}", req.method, req.path);
+                match self.route(&req, session) {
+                    Ok(resp) => resp,
+                    Err(Error::NotFound(_)) => HttpResponse::status("404 Not Found"),
+                    Err(err) => {
+                        log::error!("{}", err);
+                        HttpResponse::status("500 Internal Server Error")
+                    }
+                }
+            }
+            Incoming::Rejected(status) => HttpResponse::status(status),
+        };
+
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            resp.status,
+            resp.content_type,
+            resp.body.len()
+        );
+        for (key, value) in resp.headers {
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(resp.body.as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    fn route(&self, req: &HttpRequest, session: &mut Session<'_>) -> Result<HttpResponse> {
+        let (path, query) = req.path.split_once('?').unwrap_or((&req.path, ""));
+        let path = percent_decode(path);
+        let query = parse_form(query);
+
+        let resp = match (req.method.as_str(), path.as_str()) {
+            ("GET", "/metrics") => self.metrics(session),
+            _ if self.metrics_only => Ok(HttpResponse::status("404 Not Found")),
+            ("GET", "/") => self.list(),
+            ("GET", "/search") => self.search(&query, session),
+            ("GET", p) if p.starts_with("/note/") => self.show(&p["/note/".len()..]),
+            ("GET", p) if self.edit && p.starts_with("/edit/") => {
+                if !self.authorized(req.cookie(TOKEN_COOKIE))
+                    && !self.authorized(query.get("token").map(String::as_str))
+                {
+                    return Ok(HttpResponse::status("403 Forbidden"));
+                }
+                self.editor(&p["/edit/".len()..])
+            }
+            ("POST", p) if self.edit && p.starts_with("/edit/") => {
+                let form = parse_form(&String::from_utf8_lossy(&req.body));
+                if !same_origin(req) || !self.authorized(form.get("token").map(String::as_str)) {
+                    return Ok(HttpResponse::status("403 Forbidden"));
+                }
+                self.save(&p["/edit/".len()..], &form, session)
+            }
+            _ => Ok(HttpResponse::status("404 Not Found")),
+        };
+
+        // the token passed in the query is kept in a cookie so the links
+        // between the pages don't have to carry it
+        let mut resp = resp?;
+        if self.edit && self.authorized(query.get("token").map(String::as_str)) {
+            resp.headers.push((
+                "Set-Cookie",
+                format!(
+                    "{}={}; Path=/; HttpOnly; SameSite=Strict",
+                    TOKEN_COOKIE, self.token
+                ),
+            ));
+        }
+        Ok(resp)
+    }
+
+    // authorized is true when the token is the one of this run
+    fn authorized(&self, token: Option<&str>) -> bool {
+        // compared in constant time so the token can't be guessed byte by
+        // byte from the response times
+        token.is_some_and(|token| {
+            token.len() == self.token.len()
+                && token
+                    .bytes()
+                    .zip(self.token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        })
+    }
+
+    // metrics are the metrics of the repo, collected fresh for every scrape,
+    // followed by those of the searches served
+    fn metrics(&self, session: &Session<'_>) -> Result<HttpResponse> {
+        let mut body = VaultMetrics::collect(self.pkm)?.prometheus();
+        body.push_str(&session.metrics.prometheus());
+        Ok(HttpResponse::text(body))
+    }
+
+    // note_path resolves the relative path of a note, refusing anything that
+    // would escape the repo
+    fn note_path(&self, rel: &str) -> Result<PathBuf> {
+        let rel = Path::new(rel);
+        if rel.extension().is_none_or(|ext| ext != "md")
+            || rel.components().any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(Error::NotFound(format!("{:?} is not a note", rel)));
+        }
+
+        let mut path = self.pkm.root.clone();
+        path.push(rel);
+        if !path.is_file() {
+            return Err(Error::NotFound(format!("{:?} is not a note", rel)));
+        }
+        Ok(path)
+    }
+
+    fn list(&self) -> Result<HttpResponse> {
+        let mut notes: Vec<String> = self
+            .pkm
+            .notes()
+            .filter_map(|note| {
+                note.strip_prefix(&self.pkm.root)
+                    .ok()
+                    .map(|rel| rel.to_string_lossy().to_string())
+            })
+            .collect();
+        notes.sort();
+
+        let mut body = String::from("<ul>\n");
+        for note in notes {
+            body.push_str(&format!(
+                "<li><a href=\"/note/{0}\">{1}</a></li>\n",
+                percent_encode(&note),
+                escape_html(&note)
+            ));
+        }
+        body.push_str("</ul>");
+
+        Ok(HttpResponse::html(page("pkm", &body)))
+    }
+
+    // search runs the query against the warm searcher and returns the hits as
+    // json, recording how long it took
+    fn search(
+        &self,
+        params: &HashMap<String, String>,
+        session: &mut Session<'_>,
+    ) -> Result<HttpResponse> {
+        let query = params.get("q").map(String::as_str).unwrap_or_default();
+
+        let start = Instant::now();
+        let docs = session.searcher.find(query);
+        session.metrics.observe(start.elapsed());
+
+        match docs {
+            Ok(docs) => Ok(HttpResponse::json(serde_json::to_string(&docs)?)),
+            Err(err) => {
+                session.metrics.errors += 1;
+                Err(err)
+            }
+        }
+    }
+
+    fn show(&self, rel: &str) -> Result<HttpResponse> {
+        let ztl = Zettel::new(self.note_path(rel)?)?;
+        let content = ztl.content().map(|c| c.to_string()).unwrap_or_default();
+        let notes = NoteReader::new(&self.pkm.root);
+        let content = expand_embeds(&content, |id| notes.read(id));
+        let mut body = to_html_with_options(&content, &Options::gfm())?;
+
+        if self.edit {
+            body.push_str(&format!(
+                "\n<p><a href=\"/edit/{}\">edit</a></p>",
+                percent_encode(rel)
+            ));
+        }
+
+        Ok(HttpResponse::html(page(rel, &body)))
+    }
+
+    fn editor(&self, rel: &str) -> Result<HttpResponse> {
+        let ztl = Zettel::new(self.note_path(rel)?)?;
+        let content = ztl.content().map(|c| c.to_string()).unwrap_or_default();
+
+        let body = format!(
+            "<form method=\"post\" action=\"/edit/{0}\">\n\
+             <input type=\"hidden\" name=\"token\" value=\"{2}\">\n\
+             <textarea name=\"content\" rows=\"40\" style=\"width:100%;font-family:monospace\">{1}</textarea>\n\
+             <p><button type=\"submit\">save</button> <a href=\"/note/{0}\">cancel</a></p>\n\
+             </form>",
+            percent_encode(rel),
+            escape_html(&content),
+            self.token
+        );
+
+        Ok(HttpResponse::html(page(rel, &body)))
+    }
+
+    // save writes the posted content to the note and reindexes it
+    fn save(
+        &self,
+        rel: &str,
+        form: &HashMap<String, String>,
+        session: &mut Session<'_>,
+    ) -> Result<HttpResponse> {
+        let path = self.note_path(rel)?;
+        let content = form
+            .get("content")
+            .ok_or_else(|| Error::PKMError(String::from("missing content in form")))?;
+
+        let mut ztl = Zettel::new(&path)?;
+        // browsers submit textareas with CRLF line endings
+        ztl.set_content(content.replace("\r\n", "\n"));
+        let ztl = ztl.sync()?;
+
+        let mut writer = session.index.doc_indexer()?;
+        writer.update(&path_to_id(ztl.path()), ztl.path())?;
+        writer.commit()?;
+        session.searcher.reload()?;
+        self.pkm.record("edited", &ztl.id())?;
+
+        Ok(HttpResponse::redirect(format!(
+            "/note/{}",
+            percent_encode(rel)
+        )))
+    }
+}
+
+// listen_addr completes an address like :8080 to the loopback interface, an
+// interface like 0.0.0.0 has to be named to be reachable from other machines
+fn listen_addr(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => addr.to_string(),
+    }
+}
+
+// same_origin is false when the request was sent by a page of another site,
+// the Origin browsers send has to name the Host the request was sent to.
+// Requests without an Origin don't come from a browser form.
+fn same_origin(req: &HttpRequest) -> bool {
+    match (req.header("origin"), req.header("host")) {
+        (None, _) => true,
+        (Some(origin), Some(host)) => origin
+            .split_once("://")
+            .is_some_and(|(_, origin_host)| origin_host.eq_ignore_ascii_case(host)),
+        (Some(_), None) => false,
+    }
+}
+
+// read_request reads the request from the stream. It is rejected unread when
+// its head is larger than MAX_HEAD_SIZE or its body larger than
+// MAX_BODY_SIZE.
+async fn read_request(stream: &mut TcpStream) -> Result<Incoming> {
+    let mut reader = BufReader::new(stream).take(MAX_HEAD_SIZE);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        if !line.ends_with('\n') && reader.limit() == 0 {
+            return Ok(Incoming::Rejected("431 Request Header Fields Too Large"));
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = match headers.get("content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| Error::PKMError(String::from("invalid content-length")))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Ok(Incoming::Rejected("413 Payload Too Large"));
+    }
+
+    // the limit only applies to the head
+    let mut reader = reader.into_inner();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Incoming::Request(HttpRequest {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<p><a href=\"/\">index</a></p>\n{}\n</body></html>",
+        escape_html(title),
+        body
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// parse_form parses an application/x-www-form-urlencoded body
+fn parse_form(body: &str) -> HashMap<String, String> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            (
+                percent_decode(&key.replace('+', " ")),
+                percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest {
+            method: String::from("POST"),
+            path: String::from("/edit/note.md"),
+            headers: headers
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn rejects_posts_from_other_sites() {
+        assert!(same_origin(&request(&[("host", "127.0.0.1:8080")])));
+        assert!(same_origin(&request(&[
+            ("host", "127.0.0.1:8080"),
+            ("origin", "http://127.0.0.1:8080")
+        ])));
+        assert!(!same_origin(&request(&[
+            ("host", "127.0.0.1:8080"),
+            ("origin", "https://evil.example")
+        ])));
+        assert!(!same_origin(&request(&[
+            ("host", "127.0.0.1:8080"),
+            ("origin", "null")
+        ])));
+        assert!(!same_origin(&request(&[(
+            "origin",
+            "http://127.0.0.1:8080"
+        )])));
+    }
+
+    #[test]
+    fn reads_cookies() {
+        let req = request(&[("cookie", "theme=dark; pkm_token=abc")]);
+        assert_eq!(req.cookie(TOKEN_COOKIE), Some("abc"));
+        assert_eq!(req.cookie("missing"), None);
+    }
+
+    #[test]
+    fn parses_forms() {
+        let form = parse_form("content=%23+Title%0A&token=abc");
+        assert_eq!(form.get("content").map(String::as_str), Some("# Title\n"));
+        assert_eq!(form.get("token").map(String::as_str), Some("abc"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+// HEATMAP_LEVELS are the cells of the heatmap, from no entry to the days with
+// the most written
+const HEATMAP_LEVELS: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+// Streaks are the runs of consecutive days with a daily
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Streaks {
+    // current is the run ending today, or yesterday when today has no daily
+    // yet
+    pub current: usize,
+    pub longest: usize,
+}
+
+// streaks counts the runs of consecutive days within the days that have an
+// entry
+pub fn streaks(days: &BTreeMap<NaiveDate, usize>, today: NaiveDate) -> Streaks {
+    let mut streaks = Streaks::default();
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for day in days.keys().filter(|day| **day <= today) {
+        run = match previous {
+            Some(previous) if *day - previous == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        streaks.longest = streaks.longest.max(run);
+        previous = Some(*day);
+    }
+
+    if previous.is_some_and(|last| today - last <= Duration::days(1)) {
+        streaks.current = run;
+    }
+
+    streaks
+}
+
+// heatmap renders the weeks up to today with a row for every weekday, the
+// cells darken with the amount written on the day
+pub fn heatmap(days: &BTreeMap<NaiveDate, usize>, today: NaiveDate, weeks: usize) -> String {
+    let weeks = weeks.max(1);
+    let start = today
+        - Duration::days(today.weekday().num_days_from_monday() as i64)
+        - Duration::weeks(weeks as i64 - 1);
+    let most = days
+        .range(start..=today)
+        .map(|(_, words)| *words)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    // the month labels sit above the week the month starts in
+    let mut header: Vec<char> = vec![' '; 4 + 2 * weeks];
+    let mut free = 0;
+    for week in 0..weeks {
+        let monday = start + Duration::weeks(week as i64);
+        let sunday = monday + Duration::days(6);
+        let column = 4 + 2 * week;
+        if (monday.day() == 1 || sunday.month() != monday.month()) && column >= free {
+            let first = if monday.day() == 1 { monday } else { sunday };
+            for (i, c) in first.format("%b").to_string().chars().enumerate() {
+                if let Some(cell) = header.get_mut(column + i) {
+                    *cell = c;
+                }
+            }
+            free = column + 4;
+        }
+    }
+
+    let mut map = format!("{}\n", header.iter().collect::<String>().trim_end());
+    for (row, name) in ["Mon", "", "Wed", "", "Fri", "", "Sun"].iter().enumerate() {
+        map.push_str(&format!("{:<4}", name));
+        for week in 0..weeks {
+            let day = start + Duration::weeks(week as i64) + Duration::days(row as i64);
+            if day > today {
+                break;
+            }
+
+            let level = match days.get(&day) {
+                Some(words) => 1 + (words * 3).div_ceil(most).min(3),
+                None => 0,
+            };
+            map.push(HEATMAP_LEVELS[level.min(HEATMAP_LEVELS.len() - 1)]);
+            map.push(' ');
+        }
+        map = map.trim_end().to_string();
+        map.push('\n');
+    }
+
+    map
+}
@@ -1,4 +1,12 @@
 use crate::{Error, Result};
+use std::{
+    env,
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{LazyLock, OnceLock},
+};
 use syntect::{
     easy::HighlightLines,
     highlighting::{Style, ThemeSet},
@@ -6,18 +14,214 @@ use syntect::{
     util::{LinesWithEndings, as_24_bit_terminal_escaped},
 };
 
+pub const DARK_THEME: &str = "Solarized (dark)";
+pub const LIGHT_THEME: &str = "Solarized (light)";
+
+// THEME_DIR and SYNTAX_DIR are the directories within a highlighting dir the
+// .tmTheme and .sublime-syntax files are loaded from
+pub const THEME_DIR: &str = "themes";
+pub const SYNTAX_DIR: &str = "syntax";
+
+// HIGHLIGHTING_DIRS are the directories custom themes and syntaxes are loaded
+// from, they are set once when pkm starts
+static HIGHLIGHTING_DIRS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+// set_highlighting_dirs sets the directories custom themes and syntaxes are
+// loaded from. Only the first call has an effect.
+pub fn set_highlighting_dirs<I, P>(dirs: I)
+where
+    I: IntoIterator<Item = P>,
+    P: Into<PathBuf>,
+{
+    let _ = HIGHLIGHTING_DIRS.set(dirs.into_iter().map(|d| d.into()).collect());
+}
+
+// highlighting_subdirs returns the subdirectory of every highlighting dir
+// that exists
+fn highlighting_subdirs(subdir: &str) -> Vec<PathBuf> {
+    HIGHLIGHTING_DIRS
+        .get()
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join(subdir))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+// load_syntax_set loads the default syntaxes and the custom ones
+fn load_syntax_set() -> SyntaxSet {
+    let dirs = highlighting_subdirs(SYNTAX_DIR);
+    if dirs.is_empty() {
+        return SyntaxSet::load_defaults_newlines();
+    }
+
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    for dir in dirs {
+        if let Err(err) = builder.add_from_folder(&dir, true) {
+            log::warn!("could not load syntaxes from {}: {}", dir.display(), err);
+        }
+    }
+    builder.build()
+}
+
+// load_theme_set loads the default themes and the custom ones, a custom theme
+// is named after its file
+fn load_theme_set() -> ThemeSet {
+    let mut theme_set = ThemeSet::load_defaults();
+    for dir in highlighting_subdirs(THEME_DIR) {
+        if let Err(err) = theme_set.add_from_folder(&dir) {
+            log::warn!("could not load themes from {}: {}", dir.display(), err);
+        }
+    }
+    theme_set
+}
+
+// BACKGROUND is detected once, querying the terminal is slow
+static BACKGROUND: LazyLock<Background> = LazyLock::new(Background::detect);
+
+// Background is the brightness of the terminal background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+impl Background {
+    // detect finds the background of the terminal. PKM_BACKGROUND (dark or
+    // light) wins, then the answer of the terminal to the OSC 11 query, then
+    // COLORFGBG. Anything we can't tell is dark.
+    pub fn detect() -> Background {
+        if let Ok(background) = env::var("PKM_BACKGROUND") {
+            match background.to_lowercase().as_str() {
+                "light" => return Background::Light,
+                "dark" => return Background::Dark,
+                _ => log::warn!("PKM_BACKGROUND should be dark or light, not {}", background),
+            }
+        }
+
+        if let Some(background) = query_background() {
+            return background;
+        }
+
+        // COLORFGBG is "fg;bg" with the ansi color number of the background last
+        if let Some(bg) = env::var("COLORFGBG")
+            .ok()
+            .and_then(|v| v.rsplit(';').next().and_then(|bg| bg.parse::<u8>().ok()))
+        {
+            return match bg {
+                7 | 9..=15 => Background::Light,
+                _ => Background::Dark,
+            };
+        }
+
+        Background::Dark
+    }
+
+    // theme returns the default theme for the background
+    pub fn theme(&self) -> &'static str {
+        match self {
+            Background::Dark => DARK_THEME,
+            Background::Light => LIGHT_THEME,
+        }
+    }
+}
+
+// query_background asks the terminal for its background color with OSC 11.
+// The terminal is put in raw mode with a read timeout so terminals that don't
+// answer don't hang us.
+fn query_background() -> Option<Background> {
+    let mut tty = File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+
+    let saved = stty(&tty, &["-g"])?;
+    stty(&tty, &["raw", "-echo", "min", "0", "time", "1"])?;
+
+    let mut response = Vec::new();
+    if tty
+        .write_all(b"\x1b]11;?\x07")
+        .and_then(|_| tty.flush())
+        .is_ok()
+    {
+        let mut buf = [0; 64];
+        while let Ok(n) = tty.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+            if response.ends_with(b"\x07") || response.ends_with(b"\x1b\\") {
+                break;
+            }
+        }
+    }
+
+    stty(&tty, &[saved.as_str()]);
+    parse_background(&String::from_utf8_lossy(&response))
+}
+
+// stty runs stty against the terminal, returning its output
+fn stty(tty: &File, args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(tty.try_clone().ok()?))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// parse_background parses the answer to OSC 11, `rgb:RRRR/GGGG/BBBB`, into
+// the background by its luminance
+fn parse_background(response: &str) -> Option<Background> {
+    let rgb = &response[response.find("rgb:")? + 4..];
+    let channel = |c: &str| -> Option<f64> {
+        let hex: String = c.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+        let max = 16f64.powi(hex.len() as i32) - 1.0;
+        Some(u32::from_str_radix(&hex, 16).ok()? as f64 / max)
+    };
+
+    let mut channels = rgb.split('/');
+    let r = channel(channels.next()?)?;
+    let g = channel(channels.next()?)?;
+    let b = channel(channels.next()?)?;
+
+    if 0.2126 * r + 0.7152 * g + 0.0722 * b > 0.5 {
+        Some(Background::Light)
+    } else {
+        Some(Background::Dark)
+    }
+}
+
+// SYNTAX_SET and THEME_SET are loaded once per process on first use, after
+// the highlighting dirs are set
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(load_syntax_set);
+static THEME_SET: LazyLock<ThemeSet> = LazyLock::new(load_theme_set);
+
 pub struct Highlighting<'a> {
-    syntax_set: SyntaxSet,
-    theme_set: ThemeSet,
+    syntax_set: &'a SyntaxSet,
+    theme_set: &'a ThemeSet,
     syntax: Option<&'a str>,
     theme: Option<&'a str>,
 }
 
 impl<'a> Highlighting<'a> {
+    // new highlights with the syntaxes and themes shared by the process
     pub fn new() -> Self {
+        Self::with_sets(&SYNTAX_SET, &THEME_SET)
+    }
+
+    // with_sets highlights with the provided syntaxes and themes
+    pub fn with_sets(syntax_set: &'a SyntaxSet, theme_set: &'a ThemeSet) -> Self {
         Self {
-            syntax_set: SyntaxSet::load_defaults_newlines(),
-            theme_set: ThemeSet::load_defaults(),
+            syntax_set,
+            theme_set,
             syntax: None,
             theme: None,
         }
@@ -28,6 +232,8 @@ impl<'a> Highlighting<'a> {
         self
     }
 
+    // theme sets the theme, without one the theme matches the background of
+    // the terminal
     pub fn theme(mut self, theme: Option<&'a str>) -> Self {
         self.theme = theme;
         self
@@ -45,16 +251,17 @@ impl<'a> Highlighting<'a> {
             .find_syntax_by_extension(syntax.unwrap_or("md"))
             .ok_or_else(|| Error::NotFound(String::from("could not find extension")))?;
 
+        let theme = theme.unwrap_or_else(|| BACKGROUND.theme());
         let theme = theme_set
             .themes
-            .get(theme.unwrap_or("Solarized (dark)"))
-            .ok_or_else(|| Error::NotFound(String::from("could not find theme")))?;
+            .get(theme)
+            .ok_or_else(|| Error::NotFound(format!("could not find theme {}", theme)))?;
 
         let mut highligher = HighlightLines::new(syntax, theme);
         let mut s = String::new();
         for line in LinesWithEndings::from(text) {
             // LinesWithEndings enables use of newlines mode
-            let ranges: Vec<(Style, &str)> = highligher.highlight_line(line, &syntax_set).unwrap();
+            let ranges: Vec<(Style, &str)> = highligher.highlight_line(line, syntax_set).unwrap();
             let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
             s.push_str(&escaped);
         }
@@ -0,0 +1,145 @@
+use serde_json::Value;
+
+use crate::{Error, Result};
+
+// Table is a simple table of strings that can be rendered as a GFM table
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    // from_csv parses comma separated values, the first record is the header.
+    // Quoted fields may contain commas, newlines and "" escaped quotes.
+    pub fn from_csv(content: &str) -> Result<Table> {
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = content.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                ('"', true) => quoted = false,
+                ('"', false) if field.is_empty() => quoted = true,
+                (',', false) => record.push(std::mem::take(&mut field)),
+                ('\r', false) => (),
+                ('\n', false) => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (c, _) => field.push(c),
+            }
+        }
+
+        if quoted {
+            return Err(Error::PKMError(String::from("unterminated quote in csv")));
+        }
+
+        if !field.is_empty() || !record.is_empty() {
+            record.push(field);
+            records.push(record);
+        }
+
+        let mut records = records
+            .into_iter()
+            .filter(|r| !(r.len() == 1 && r[0].is_empty()));
+        let headers = records
+            .next()
+            .ok_or_else(|| Error::PKMError(String::from("csv has no header")))?;
+
+        Ok(Table {
+            headers,
+            rows: records.collect(),
+        })
+    }
+
+    // from_json parses an array of objects, where the keys become the headers,
+    // or an array of arrays, where the first array is the header
+    pub fn from_json(content: &str) -> Result<Table> {
+        let value: Value = serde_json::from_str(content)?;
+        let items = value
+            .as_array()
+            .ok_or_else(|| Error::PKMError(String::from("json table must be an array")))?;
+
+        let mut table = Table::default();
+        if items.iter().all(Value::is_array) {
+            let mut items = items.iter().filter_map(Value::as_array);
+            if let Some(headers) = items.next() {
+                table.headers = headers.iter().map(cell).collect();
+            }
+            table.rows = items.map(|row| row.iter().map(cell).collect()).collect();
+            return Ok(table);
+        }
+
+        for item in items {
+            let object = item.as_object().ok_or_else(|| {
+                Error::PKMError(String::from(
+                    "json table rows must all be objects or arrays",
+                ))
+            })?;
+
+            for key in object.keys() {
+                if !table.headers.contains(key) {
+                    table.headers.push(key.clone());
+                }
+            }
+        }
+
+        table.rows = items
+            .iter()
+            .filter_map(Value::as_object)
+            .map(|object| {
+                table
+                    .headers
+                    .iter()
+                    .map(|header| object.get(header).map(cell).unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+
+        Ok(table)
+    }
+
+    // to_markdown renders the table as a GFM table
+    pub fn to_markdown(&self) -> String {
+        let width = self
+            .rows
+            .iter()
+            .map(Vec::len)
+            .chain(std::iter::once(self.headers.len()))
+            .max()
+            .unwrap_or(0);
+
+        let row = |cells: &[String]| {
+            let mut line = String::from("|");
+            for i in 0..width {
+                line.push(' ');
+                line.push_str(&escape(cells.get(i).map(String::as_str).unwrap_or("")));
+                line.push_str(" |");
+            }
+            line
+        };
+
+        let mut lines = vec![row(&self.headers), format!("|{}", " --- |".repeat(width))];
+        lines.extend(self.rows.iter().map(|r| row(r)));
+        lines.join("\n")
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+fn escape(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', "<br>")
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use tera::Tera;
+use tera::ast::{Expr, ExprVal, FunctionCall, Node};
+
+use crate::{Error, Result};
+
+// DEFAULT_DAILY_TEMPLATE and DEFAULT_ZETTEL_TEMPLATE are used when the
+// template dir doesn't have a daily.md or default.md
+pub const DEFAULT_DAILY_TEMPLATE: &str = "# {{ long_date }}";
+pub const DEFAULT_ZETTEL_TEMPLATE: &str = "# {{ title }}";
+
+// META_TEMPLATE is the template new templates are made from, it has the name
+// of the new template and the date available
+pub const META_TEMPLATE: &str = "---
+type: {{ name }}
+created: {{ date }}
+---
+{% raw %}# {{ title }}
+{% if daily %}
+[[{{ daily }}]]
+{% endif %}{% endraw %}
+";
+
+// template_file is the file of a new template, the name is kept to a file
+// within the template dir, like meeting or meeting.md
+pub fn template_file(name: &str) -> Result<String> {
+    let name = name.trim();
+    let name = name.strip_suffix(".md").unwrap_or(name);
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::PKMError(format!(
+            "invalid template name {:?}, expected a name like meeting",
+            name
+        )));
+    }
+
+    Ok(format!("{}.md", name))
+}
+
+// TemplateInfo describes a template usable with --template
+pub struct TemplateInfo {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub source: String,
+    pub variables: Vec<String>,
+}
+
+impl TemplateInfo {
+    // from_tera reads the template, name is the --template value without .md
+    pub fn from_tera(tera: &Tera, name: &str) -> Result<Self> {
+        let file = format!("{}.md", name.strip_suffix(".md").unwrap_or(name));
+        let template = tera
+            .get_template(&file)
+            .map_err(|_| Error::NotFound(format!("no template named {}", name)))?;
+
+        let path = template.path.as_ref().map(PathBuf::from);
+        let source = match (path.as_ref(), file.as_str()) {
+            (Some(path), _) => fs::read_to_string(path)?,
+            (None, "daily.md") => DEFAULT_DAILY_TEMPLATE.to_string(),
+            (None, "default.md") => DEFAULT_ZETTEL_TEMPLATE.to_string(),
+            (None, _) => String::new(),
+        };
+
+        Ok(Self {
+            name: file.trim_end_matches(".md").to_string(),
+            variables: template_variables(&template.ast),
+            path,
+            source,
+        })
+    }
+}
+
+// templates returns every template of tera sorted by name
+pub fn templates(tera: &Tera) -> Result<Vec<TemplateInfo>> {
+    let mut names: Vec<&str> = tera.get_template_names().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| TemplateInfo::from_tera(tera, name))
+        .collect()
+}
+
+// template_variables returns the variables the template expects from its
+// context, leaving out the ones it defines itself with set and for
+pub fn template_variables(ast: &[Node]) -> Vec<String> {
+    let mut variables = BTreeSet::new();
+    nodes_variables(ast, &mut Vec::new(), &mut variables);
+    variables.into_iter().collect()
+}
+
+// nodes_variables adds the variables the nodes use, locals are the names set
+// or looped over up to the nodes
+fn nodes_variables(nodes: &[Node], locals: &mut Vec<String>, variables: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            Node::VariableBlock(_, expr) => expr_variables(expr, locals, variables),
+            Node::Set(_, set) => {
+                expr_variables(&set.value, locals, variables);
+                locals.push(set.key.clone());
+            }
+            Node::FilterSection(_, section, _) => {
+                call_variables(&section.filter, locals, variables);
+                nodes_variables(&section.body, locals, variables);
+            }
+            Node::Block(_, block, _) => nodes_variables(&block.body, locals, variables),
+            Node::Forloop(_, forloop, _) => {
+                expr_variables(&forloop.container, locals, variables);
+                let mut scope = locals.clone();
+                scope.extend(forloop.key.iter().cloned());
+                scope.push(forloop.value.clone());
+                scope.push(String::from("loop"));
+                nodes_variables(&forloop.body, &mut scope, variables);
+                if let Some(body) = forloop.empty_body.as_ref() {
+                    nodes_variables(body, &mut locals.clone(), variables);
+                }
+            }
+            Node::If(condition, _) => {
+                for (_, expr, body) in condition.conditions.iter() {
+                    expr_variables(expr, locals, variables);
+                    nodes_variables(body, locals, variables);
+                }
+                if let Some((_, body)) = condition.otherwise.as_ref() {
+                    nodes_variables(body, locals, variables);
+                }
+            }
+            // macros only see their arguments, never the context
+            _ => (),
+        }
+    }
+}
+
+fn expr_variables(expr: &Expr, locals: &[String], variables: &mut BTreeSet<String>) {
+    match &expr.val {
+        ExprVal::Ident(ident) => add_variable(ident, locals, variables),
+        ExprVal::Math(math) => {
+            expr_variables(&math.lhs, locals, variables);
+            expr_variables(&math.rhs, locals, variables);
+        }
+        ExprVal::Logic(logic) => {
+            expr_variables(&logic.lhs, locals, variables);
+            expr_variables(&logic.rhs, locals, variables);
+        }
+        ExprVal::In(within) => {
+            expr_variables(&within.lhs, locals, variables);
+            expr_variables(&within.rhs, locals, variables);
+        }
+        ExprVal::Test(test) => {
+            add_variable(&test.ident, locals, variables);
+            for arg in test.args.iter() {
+                expr_variables(arg, locals, variables);
+            }
+        }
+        ExprVal::MacroCall(call) => {
+            for arg in call.args.values() {
+                expr_variables(arg, locals, variables);
+            }
+        }
+        ExprVal::FunctionCall(call) => call_variables(call, locals, variables),
+        ExprVal::Array(exprs) => {
+            for expr in exprs {
+                expr_variables(expr, locals, variables);
+            }
+        }
+        ExprVal::StringConcat(concat) => {
+            for value in concat.values.iter() {
+                if let ExprVal::Ident(ident) = value {
+                    add_variable(ident, locals, variables);
+                }
+            }
+        }
+        _ => (),
+    }
+
+    for filter in expr.filters.iter() {
+        call_variables(filter, locals, variables);
+    }
+}
+
+fn call_variables(call: &FunctionCall, locals: &[String], variables: &mut BTreeSet<String>) {
+    for arg in call.args.values() {
+        expr_variables(arg, locals, variables);
+    }
+}
+
+// add_variable adds the name the identifier starts with, ztl.title uses ztl
+fn add_variable(ident: &str, locals: &[String], variables: &mut BTreeSet<String>) {
+    let name = ident.split(['.', '[']).next().unwrap_or(ident);
+    if name != "__tera_context" && !locals.iter().any(|local| local == name) {
+        variables.insert(name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tera::Template;
+
+    fn variables(source: &str) -> Vec<String> {
+        template_variables(&Template::new("test", None, source).unwrap().ast)
+    }
+
+    #[test]
+    fn finds_the_variables_of_expressions_and_conditions() {
+        assert_eq!(variables(META_TEMPLATE), vec!["date", "name"]);
+        assert_eq!(
+            variables(
+                "{{ ztl.title | default(value=fallback) }}{% if not daily %}{{ now() }}{% endif %}"
+            ),
+            vec!["daily", "fallback", "ztl"]
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_the_template_defines() {
+        assert_eq!(
+            variables(
+                "{% set heading = title | upper %}# {{ heading }}\n\
+                 {% for a in attendees %}- {{ a }} {{ loop.index }}{% endfor %}\
+                 {% for k, v in meta %}{{ k }}: {{ v }}{% endfor %}"
+            ),
+            vec!["attendees", "meta", "title"]
+        );
+    }
+
+    #[test]
+    fn loop_variables_stay_in_the_loop() {
+        assert_eq!(
+            variables("{% for a in attendees %}{{ a }}{% endfor %}{{ a }}"),
+            vec!["a", "attendees"]
+        );
+    }
+
+    #[test]
+    fn template_names_stay_in_the_template_dir() {
+        assert_eq!(template_file("meeting").unwrap(), "meeting.md");
+        assert_eq!(template_file("meeting.md").unwrap(), "meeting.md");
+        assert_eq!(template_file("v1.2").unwrap(), "v1.2.md");
+        for name in ["", ".md", "../notes", "a/b", "a\\b", ".hidden", ".."] {
+            assert!(template_file(name).is_err(), "{:?} should be invalid", name);
+        }
+    }
+
+    #[test]
+    fn raw_blocks_have_no_variables() {
+        assert!(variables("{% raw %}{{ title }}{% endraw %}").is_empty());
+    }
+}
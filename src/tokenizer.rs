@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tantivy::Index;
+use tantivy::tokenizer::{
+    Language, LowerCaser, NgramTokenizer, RawTokenizer, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    TextAnalyzer,
+};
+
+use crate::{Error, Result};
+
+// INDEX_CONFIG_FILE is the file, relative to the root directory, configuring
+// how the notes are indexed, like {"tokenizers": {"content": "ngram"}}
+pub const INDEX_CONFIG_FILE: &str = ".pkm/index.json";
+
+// INDEX_SETTINGS_FILE is kept within the index and holds the hash of the
+// config the index was built with
+pub const INDEX_SETTINGS_FILE: &str = "pkm-settings.hash";
+
+// DEFAULT_TOKENIZER stems english words so rust matches rusting. It keeps
+// the name indexes were built with before the language could be set, so they
+// still fit the schema. Any other language is stemmed by the stem tokenizer.
+pub const DEFAULT_TOKENIZER: &str = "en_stem";
+
+// DEFAULT_LANGUAGE is the language words are stemmed in
+pub const DEFAULT_LANGUAGE: &str = "english";
+
+// LANGUAGES are the languages tantivy can stem
+pub const LANGUAGES: [&str; 18] = [
+    "arabic",
+    "danish",
+    "dutch",
+    "english",
+    "finnish",
+    "french",
+    "german",
+    "greek",
+    "hungarian",
+    "italian",
+    "norwegian",
+    "portuguese",
+    "romanian",
+    "russian",
+    "spanish",
+    "swedish",
+    "tamil",
+    "turkish",
+];
+
+// TOKENIZED_FIELDS are the text fields the tokenizer can be picked for
+pub const TOKENIZED_FIELDS: [&str; 5] = ["title", "headings", "content", "aliases", "attachments"];
+
+// TOKENIZERS are the tokenizers a field can use. stem stems the words in the
+// language of the config and en_stem always in english, default splits on
+// punctuation and lowercases, simple does the same keeping long words, raw
+// keeps the text whole, whitespace only splits on whitespace and ngram
+// indexes every one and two character sequence, which works for text without
+// spaces like Japanese or Chinese. Built with the lindera feature, lindera
+// splits Japanese into words with the IPADIC dictionary.
+pub const TOKENIZERS: [&str; 8] = [
+    "stem",
+    "en_stem",
+    "default",
+    "simple",
+    "raw",
+    "whitespace",
+    "ngram",
+    "lindera",
+];
+
+// FRONTMATTER_FIELD_PREFIX is put in front of the frontmatter keys within the
+// schema, so a key like date doesn't clash with the fields of the index
+pub const FRONTMATTER_FIELD_PREFIX: &str = "fm_";
+
+// FieldKind is how a frontmatter field is indexed. A keyword matches its whole
+// value ignoring case, like status:draft, a text is searched like the content
+// and a date matches the day or, like due:<2024-02-01, the days before or
+// after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Keyword,
+    Text,
+    Date,
+}
+
+// IndexConfig picks the tokenizer of the text fields, the language words are
+// stemmed in and the frontmatter fields that are indexed. The text fields
+// that aren't configured use the DEFAULT_TOKENIZER.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexConfig {
+    #[serde(default)]
+    pub tokenizers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default = "default_fields")]
+    pub fields: BTreeMap<String, FieldKind>,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            tokenizers: BTreeMap::new(),
+            language: None,
+            fields: default_fields(),
+        }
+    }
+}
+
+// default_fields are the frontmatter fields indexed unless the config says
+// otherwise, the aliases are always indexed
+fn default_fields() -> BTreeMap<String, FieldKind> {
+    BTreeMap::from([
+        (String::from("status"), FieldKind::Keyword),
+        (String::from("project"), FieldKind::Keyword),
+        (String::from("due"), FieldKind::Date),
+    ])
+}
+
+// frontmatter_field is the name of the field the frontmatter key is indexed
+// in
+pub fn frontmatter_field(key: &str) -> String {
+    format!("{}{}", FRONTMATTER_FIELD_PREFIX, key)
+}
+
+// is_field_key is true for the keys that can name a frontmatter field in the
+// config and in queries, like status or due_date
+pub fn is_field_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+// parse_language parses the name of a language tantivy can stem, like german
+pub fn parse_language(language: &str) -> Result<Language> {
+    let language = match language.trim().to_lowercase().as_str() {
+        "arabic" => Language::Arabic,
+        "danish" => Language::Danish,
+        "dutch" => Language::Dutch,
+        "english" => Language::English,
+        "finnish" => Language::Finnish,
+        "french" => Language::French,
+        "german" => Language::German,
+        "greek" => Language::Greek,
+        "hungarian" => Language::Hungarian,
+        "italian" => Language::Italian,
+        "norwegian" => Language::Norwegian,
+        "portuguese" => Language::Portuguese,
+        "romanian" => Language::Romanian,
+        "russian" => Language::Russian,
+        "spanish" => Language::Spanish,
+        "swedish" => Language::Swedish,
+        "tamil" => Language::Tamil,
+        "turkish" => Language::Turkish,
+        language => {
+            return Err(Error::PKMError(format!(
+                "can't stem {:?}, expected one of {}",
+                language,
+                LANGUAGES.join(", ")
+            )));
+        }
+    };
+    Ok(language)
+}
+
+// parse_tokenizer checks the tokenizer is one of the TOKENIZERS
+pub fn parse_tokenizer(tokenizer: &str) -> Result<String> {
+    let tokenizer = tokenizer.trim().to_lowercase();
+    if tokenizer == "lindera" && !cfg!(feature = "lindera") {
+        return Err(Error::PKMError(String::from(
+            "pkm was built without the lindera feature, use ngram for Japanese, Chinese or Korean notes",
+        )));
+    }
+
+    if !TOKENIZERS.contains(&tokenizer.as_str()) {
+        return Err(Error::PKMError(format!(
+            "unknown tokenizer {:?}, expected one of {}",
+            tokenizer,
+            TOKENIZERS.join(", ")
+        )));
+    }
+
+    Ok(tokenizer)
+}
+
+impl IndexConfig {
+    // open reads the index config of the repo, no file means every field
+    // uses the DEFAULT_TOKENIZER
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let path = root.as_ref().join(INDEX_CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        let config: IndexConfig = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        for (field, tokenizer) in config.tokenizers.iter() {
+            if !TOKENIZED_FIELDS.contains(&field.as_str()) {
+                return Err(Error::PKMError(format!(
+                    "{} sets the tokenizer of {:?}, expected one of {}",
+                    INDEX_CONFIG_FILE,
+                    field,
+                    TOKENIZED_FIELDS.join(", ")
+                )));
+            }
+            parse_tokenizer(tokenizer)?;
+        }
+        if let Some(language) = config.language.as_ref() {
+            parse_language(language)?;
+        }
+        if let Some(key) = config.fields.keys().find(|key| !is_field_key(key)) {
+            return Err(Error::PKMError(format!(
+                "{} indexes the frontmatter field {:?}, keys are lowercase letters, digits, _ and -",
+                INDEX_CONFIG_FILE, key
+            )));
+        }
+
+        Ok(config)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, root: P) -> Result<()> {
+        let path = root.as_ref().join(INDEX_CONFIG_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // with_language sets the language words are stemmed in
+    pub fn with_language<S: Into<String>>(mut self, language: Option<S>) -> Self {
+        if let Some(language) = language {
+            self.language = Some(language.into().trim().to_lowercase());
+        }
+        self
+    }
+
+    // language is the language words are stemmed in
+    pub fn language(&self) -> Result<Language> {
+        parse_language(self.language.as_deref().unwrap_or(DEFAULT_LANGUAGE))
+    }
+
+    // hash changes whenever the settings change. The schema only names the
+    // tokenizers, so an index built with other settings is found by its hash.
+    pub fn hash(&self) -> Result<String> {
+        let settings = serde_json::to_string(&(
+            TOKENIZED_FIELDS
+                .iter()
+                .map(|field| self.tokenizer(field))
+                .collect::<Vec<String>>(),
+            self.language.as_deref().unwrap_or(DEFAULT_LANGUAGE),
+            &self.fields,
+        ))?;
+        Ok(hex::encode(Sha1::digest(settings.as_bytes())))
+    }
+
+    // tokenizer is the name of the tokenizer of the field
+    pub fn tokenizer(&self, field: &str) -> String {
+        self.tokenizers
+            .get(field)
+            .map(|tokenizer| tokenizer.trim().to_lowercase())
+            .unwrap_or_else(|| match self.language.as_deref() {
+                Some(language) if language != DEFAULT_LANGUAGE => String::from("stem"),
+                _ => DEFAULT_TOKENIZER.to_string(),
+            })
+    }
+}
+
+// register_tokenizers adds the tokenizers tantivy doesn't come with to the
+// index, it has to happen every time the index is opened
+pub fn register_tokenizers(index: &Index, config: &IndexConfig) -> Result<()> {
+    index.tokenizers().register(
+        "keyword",
+        TextAnalyzer::builder(RawTokenizer::default())
+            .filter(LowerCaser)
+            .build(),
+    );
+    index.tokenizers().register(
+        "stem",
+        TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(Stemmer::new(config.language()?))
+            .build(),
+    );
+    index.tokenizers().register(
+        "simple",
+        TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(LowerCaser)
+            .build(),
+    );
+    index.tokenizers().register(
+        "ngram",
+        TextAnalyzer::builder(NgramTokenizer::new(1, 2, false)?)
+            .filter(LowerCaser)
+            .build(),
+    );
+    #[cfg(feature = "lindera")]
+    index.tokenizers().register(
+        "lindera",
+        TextAnalyzer::builder(lindera_tokenizer::LinderaTokenizer::new()?)
+            .filter(LowerCaser)
+            .build(),
+    );
+    Ok(())
+}
+
+// lindera_tokenizer adapts the lindera segmenter to a tantivy tokenizer
+#[cfg(feature = "lindera")]
+mod lindera_tokenizer {
+    use std::borrow::Cow;
+
+    use lindera::dictionary::load_dictionary;
+    use lindera::mode::Mode;
+    use lindera::segmenter::Segmenter;
+    use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+
+    use crate::{Error, Result};
+
+    // DICTIONARY is the dictionary built into lindera with the embed-ipadic
+    // feature
+    const DICTIONARY: &str = "embedded://ipadic";
+
+    #[derive(Clone)]
+    pub struct LinderaTokenizer {
+        segmenter: Segmenter,
+    }
+
+    impl LinderaTokenizer {
+        pub fn new() -> Result<Self> {
+            let dictionary = load_dictionary(DICTIONARY)
+                .map_err(|err| Error::PKMError(format!("can't load {}: {}", DICTIONARY, err)))?;
+            Ok(Self {
+                segmenter: Segmenter::new(Mode::Normal, dictionary, None),
+            })
+        }
+    }
+
+    pub struct LinderaTokenStream {
+        tokens: Vec<Token>,
+        index: usize,
+    }
+
+    impl Tokenizer for LinderaTokenizer {
+        type TokenStream<'a> = LinderaTokenStream;
+
+        fn token_stream<'a>(&'a mut self, text: &'a str) -> LinderaTokenStream {
+            // text lindera can't segment isn't indexed rather than failing
+            // the whole note
+            let tokens = self
+                .segmenter
+                .segment(Cow::Borrowed(text))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|token| !token.surface.trim().is_empty())
+                .enumerate()
+                .map(|(position, token)| Token {
+                    offset_from: token.byte_start,
+                    offset_to: token.byte_end,
+                    position,
+                    text: token.surface.into_owned(),
+                    position_length: 1,
+                })
+                .collect();
+            LinderaTokenStream { tokens, index: 0 }
+        }
+    }
+
+    impl TokenStream for LinderaTokenStream {
+        fn advance(&mut self) -> bool {
+            self.index += 1;
+            self.index <= self.tokens.len()
+        }
+
+        fn token(&self) -> &Token {
+            &self.tokens[self.index - 1]
+        }
+
+        fn token_mut(&mut self) -> &mut Token {
+            &mut self.tokens[self.index - 1]
+        }
+    }
+}
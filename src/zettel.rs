@@ -1,27 +1,38 @@
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::fmt::Display;
 use std::io::Write;
 use std::ops::Deref;
 use std::path::StripPrefixError;
+use std::time::SystemTime;
 use std::{
     fs::{self, File},
     path::{Path, PathBuf},
 };
 
-use chrono::{DateTime, Datelike, Local};
+use chrono::{DateTime, Datelike, Local, NaiveDate};
 use clap::ArgMatches;
 use convert_case::{Case, Casing};
+use markdown::ParseOptions;
+use markdown::mdast::Node;
 use regex::Regex;
-use sha1::{Digest, Sha1};
 use tera::{Context, Tera};
 
-use crate::{Error, Result};
+use crate::{Error, Frontmatter, IDScheme, Result, path_to_id, scheme_date, split_frontmatter};
+
+pub const MEETING_TAG: &str = "meeting";
+pub const FLEETING_TAG: &str = "fleeting";
+
+// ID_TAG_SEPARATOR separates the levels of a nested tag within an id. Ids are
+// filenames, so the tag project/alpha is written as project.alpha.
+pub const ID_TAG_SEPARATOR: char = '.';
 
 // ZettelBuilder is used to set the attributes of a zettel and make
 // it into an actual file
 pub struct ZettelBuilder {
     path: PathBuf,
     tmpl_name: String,
+    date: DateTime<Local>,
+    repo: Option<PathBuf>,
 }
 
 impl ZettelBuilder {
@@ -32,19 +43,38 @@ impl ZettelBuilder {
         Self {
             path: PathBuf::from(repo.as_ref()),
             tmpl_name: "default".into(),
+            date: Local::now(),
+            repo: None,
         }
     }
 
+    // with_repo sets the root of the repo, its name is the repo variable of
+    // the template
+    pub fn with_repo<P: AsRef<Path>>(mut self, repo: P) -> Self {
+        self.repo = Some(PathBuf::from(repo.as_ref()));
+        self
+    }
+
+    // with_date sets the date the date variables of the template are from
+    pub fn with_date(mut self, date: &DateTime<Local>) -> Self {
+        self.date = *date;
+        self
+    }
+
+    // with_year_month_day will add a [year]/[month]/[day] directory chain to
+    // the path, the date is used for the template as well
     pub fn with_year_month_day(mut self, current_date: &DateTime<Local>) -> Self {
+        self.date = *current_date;
         self.path.push(format!("{:02}", current_date.year()));
         self.path.push(format!("{:02}", current_date.month()));
         self.path.push(format!("{:02}", current_date.day()));
         self
     }
 
-    // push_year_month will add a [year]/[month]/[day] directory chain to the
-    // path
+    // with_year_month will add a [year]/[month] directory chain to the path,
+    // the date is used for the template as well
     pub fn with_year_month(mut self, current_date: &DateTime<Local>) -> Self {
+        self.date = *current_date;
         self.path.push(format!("{:02}", current_date.year()));
         self.path.push(format!("{:02}", current_date.month()));
         self
@@ -69,6 +99,11 @@ impl ZettelBuilder {
         self
     }
 
+    // path is where the zettel is built
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
     // open will open the zettel that is defined in the path
     pub fn open(self) -> Result<Zettel> {
         if self.path.exists() {
@@ -100,25 +135,58 @@ impl ZettelBuilder {
         T: Borrow<Tera>,
         C: Borrow<Context>,
     {
+        let mut standard = self.standard_context();
         let Self {
             path,
             mut tmpl_name,
+            ..
         } = self;
 
         tmpl_name.push_str(".md");
 
+        // the variables of the caller win over the standard ones
+        standard.extend(context.borrow().clone());
+
         let mut ztl = Zettel::new(path)?;
-        ztl.content = Some(tmpls.borrow().render(&tmpl_name, context.borrow())?);
+        ztl.content = Some(tmpls.borrow().render(&tmpl_name, &standard)?);
         ztl.sync()
     }
+
+    // standard_context holds the variables every template can use: date,
+    // long_date, like Monday, January 01, 2024, time, iso_week, weekday, repo
+    // and id
+    fn standard_context(&self) -> Context {
+        let mut context = Context::new();
+        let week = self.date.iso_week();
+        context.insert("date", &self.date.format("%Y-%m-%d").to_string());
+        context.insert("long_date", &self.date.format("%A, %B %d, %Y").to_string());
+        context.insert("time", &self.date.format("%H:%M").to_string());
+        context.insert("iso_week", &format!("{}-W{:02}", week.year(), week.week()));
+        context.insert("weekday", &self.date.format("%A").to_string());
+
+        let repo = self
+            .repo
+            .as_ref()
+            .and_then(|repo| std::path::absolute(repo).ok());
+        if let Some(name) = repo.as_ref().and_then(|repo| repo.file_name()) {
+            context.insert("repo", &name.to_string_lossy());
+        }
+
+        if self.path.extension().is_some_and(|ext| ext == "md") {
+            context.insert("id", &path_to_id(&self.path));
+        }
+
+        context
+    }
 }
 
 // ZettelIDBuilder helps build an id
 pub struct ZettelIDBuilder<'a> {
     title: Option<String>,
-    tags: Vec<&'a str>,
+    tags: Vec<Cow<'a, str>>,
     date: Option<String>,
     hash: Option<String>,
+    scheme: IDScheme,
 }
 
 // ZettelFileNameBuilder helps you build a filename for the zettel that is coherent and sensible
@@ -129,14 +197,21 @@ impl<'a> ZettelIDBuilder<'a> {
             tags: Vec::new(),
             date: None,
             hash: None,
+            scheme: IDScheme::default(),
         }
     }
 
+    // scheme sets how the unique part at the end of the id is made
+    pub fn scheme(mut self, scheme: IDScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
     pub fn title<S>(mut self, title: Option<S>, case: Option<Case>) -> Self
     where
         S: AsRef<str>,
     {
-        self.title = title.map(|v| v.as_ref().replace('\n', "").replace('\r', ""));
+        self.title = title.map(|v| id_title(v.as_ref()));
         if let Some(case) = case {
             self.title = self.title.map(|v| v.to_case(case))
         }
@@ -144,20 +219,20 @@ impl<'a> ZettelIDBuilder<'a> {
         self
     }
 
-    // meeting will put "meeting" at the beginning of the id
+    // tag adds the tag after the title, nested tags like project/alpha are
+    // written as project.alpha
     pub fn tag(mut self, prefix: &'a str) -> Self {
-        self.tags.push(prefix);
+        self.tags.push(match prefix.contains('/') {
+            true => Cow::Owned(id_tag(prefix)),
+            false => Cow::Borrowed(prefix),
+        });
         self
     }
 
-    // filename_with_hash will create a filename with the following nomenclature
-    // [title_as_snakecase]-[8_char_hash].md
+    // with_hash puts the unique part of the id scheme at the end of the id,
+    // by default the first 8 characters of a hash
     pub fn with_hash(mut self) -> Self {
-        let current_date = chrono::Utc::now();
-        let mut hash = Sha1::new();
-        hash.update(current_date.to_rfc3339().as_bytes());
-        let hash = hex::encode(hash.finalize()).to_string();
-        self.hash = Some(hash);
+        self.hash = self.scheme.unique(&chrono::Utc::now());
         self
     }
 
@@ -186,12 +261,12 @@ impl<'a> ZettelIDBuilder<'a> {
         }
 
         if let Some(true) = args.get_one::<bool>("MEETING") {
-            this = this.tag("meeting");
+            this = this.tag(MEETING_TAG);
             this = this.date(&date)
         }
 
         if let Some(true) = args.get_one::<bool>("FLEETING") {
-            this = this.tag("fleeting");
+            this = this.tag(FLEETING_TAG);
             this = this.with_hash();
         }
 
@@ -203,8 +278,13 @@ impl<'a> ZettelIDBuilder<'a> {
     }
 
     // to_string builds the id as a string in the following order
-    // [fleeting]-[meeting]-[YYYY-MM-DD]-[title snake case]-[hash]
-    pub fn build(self) -> Result<ZettelID> {
+    // [fleeting]-[meeting]-[YYYY-MM-DD]-[title snake case]-[hash]. Schemes
+    // that always have a unique part get one when none was asked for.
+    pub fn build(mut self) -> Result<ZettelID> {
+        if self.scheme.always() && self.hash.is_none() {
+            self = self.with_hash();
+        }
+
         let mut parts = Vec::new();
 
         let Self {
@@ -212,14 +292,15 @@ impl<'a> ZettelIDBuilder<'a> {
             tags,
             date,
             hash,
+            ..
         } = self;
 
         if let Some(title) = title.as_ref() {
             parts.push(title.as_str())
         }
 
-        for tag in tags {
-            parts.push(tag)
+        for tag in tags.iter() {
+            parts.push(tag.as_ref())
         }
 
         if let Some(date) = date.as_ref() {
@@ -227,7 +308,7 @@ impl<'a> ZettelIDBuilder<'a> {
         }
 
         if let Some(hash) = hash.as_ref() {
-            parts.push(&hash[0..8])
+            parts.push(hash)
         }
 
         let id = parts.join("_");
@@ -271,10 +352,32 @@ impl Display for ZettelID {
 }
 
 impl ZettelID {
+    // from_path creates the id from the filename of a zettel
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ZettelID {
+        ZettelID(path_to_id(path))
+    }
+
     pub fn filename(&self) -> String {
         format!("{}.md", **self)
     }
 
+    // with_title returns a new id with the title part swapped out for the
+    // provided title. Ids without a title are returned with the title prepended
+    pub fn with_title(&self, title: &str) -> Result<ZettelID> {
+        let title = title.replace('\n', "").replace('\r', "").replace('/', "-");
+
+        if title.trim().is_empty() {
+            return Err(Error::InvalidZettelID(String::from(
+                "zettel title can not be empty",
+            )));
+        }
+
+        match self.title() {
+            Ok(old) => Ok(ZettelID(format!("{}{}", title, &self.0[old.len()..]))),
+            Err(_) => Ok(ZettelID(title)),
+        }
+    }
+
     fn parts(&self) -> ZettelIDIter<'_> {
         ZettelIDIter::new(self)
     }
@@ -307,10 +410,59 @@ impl ZettelID {
             .next()
     }
 
+    // replace_tag returns the id with the tag swapped for the new tag, the tag
+    // is dropped when the id already has the new one. None is returned when
+    // the id doesn't have the tag.
+    // The tags nested under the tag are moved under the new tag.
+    pub fn replace_tag(&self, tag: &str, new: &str) -> Option<ZettelID> {
+        let (tag, new) = (id_tag(tag), id_tag(new));
+        let mut replaced = false;
+        let mut parts: Vec<String> = Vec::new();
+        for part in self.parts() {
+            let part = match part {
+                ZettelIDPart::Tag(t) => match t.strip_prefix(tag.as_str()) {
+                    Some(rest) if rest.is_empty() || rest.starts_with(ID_TAG_SEPARATOR) => {
+                        replaced = true;
+                        format!("{}{}", new, rest)
+                    }
+                    _ => t.to_string(),
+                },
+                ZettelIDPart::Title(t) | ZettelIDPart::Hash(t) => t.to_string(),
+            };
+
+            // the tag is dropped when the id already has the new one
+            if !parts.contains(&part) {
+                parts.push(part);
+            }
+        }
+
+        match replaced {
+            true => Some(ZettelID(parts.join("_"))),
+            false => None,
+        }
+    }
+
+    // created returns the day the id was made on, from its date or from the
+    // unique part when it is a timestamp or a ulid
+    pub fn created(&self) -> Option<NaiveDate> {
+        self.tags().chain(self.hash()).find_map(|part| {
+            NaiveDate::parse_from_str(part, "%Y-%m-%d")
+                .ok()
+                .or_else(|| scheme_date(part))
+        })
+    }
+
     pub fn tag(&self, tag: &str) -> Option<&str> {
+        let tag = id_tag(tag);
         self.tags().filter(|t| *t == tag).next()
     }
 
+    // nested_tags returns the tags of the id in their nested form, like
+    // project/alpha
+    pub fn nested_tags(&self) -> impl Iterator<Item = String> {
+        self.tags().map(nested_tag)
+    }
+
     pub fn has_tag(&self, tag: &str) -> bool {
         self.tag(tag).is_some()
     }
@@ -324,6 +476,29 @@ impl ZettelID {
     }
 }
 
+// id_title makes a title safe to name a file after. Titles come from stdin,
+// templates and language models, so path separators become dashes and leading
+// dots are dropped, a title can't reach outside of the repo or hide the note.
+pub fn id_title(title: &str) -> String {
+    title
+        .replace(['\n', '\r'], "")
+        .replace(['/', '\\'], "-")
+        .trim_start_matches(['.', ' '])
+        .to_string()
+}
+
+// id_tag turns a nested tag into the form it is written in within an id,
+// project/alpha into project.alpha
+pub fn id_tag(tag: &str) -> String {
+    tag.replace('/', &ID_TAG_SEPARATOR.to_string())
+}
+
+// nested_tag turns a tag of an id into its nested form, project.alpha into
+// project/alpha
+pub fn nested_tag(tag: &str) -> String {
+    tag.replace(ID_TAG_SEPARATOR, "/")
+}
+
 struct ZettelIDIter<'a> {
     title: bool,
     id: &'a ZettelID,
@@ -417,6 +592,68 @@ impl Zettel {
         &self.path
     }
 
+    // modified returns the last time the zettel was modified on disk
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(fs::metadata(&self.path)?.modified()?)
+    }
+
+    // id returns the zettel id based on the filename
+    pub fn id(&self) -> ZettelID {
+        ZettelID::from_path(&self.path)
+    }
+
+    // title returns the text of the first H1 within the zettel
+    pub fn title(&self) -> Result<Option<String>> {
+        Ok(self.h1()?.map(|(title, _)| title))
+    }
+
+    // h1 returns the text of the first H1 along with its byte range
+    fn h1(&self) -> Result<Option<(String, std::ops::Range<usize>)>> {
+        let content = match self.content.as_ref() {
+            Some(content) => content,
+            None => return Ok(None),
+        };
+
+        let ast = markdown::to_mdast(content, &ParseOptions::gfm())?;
+        Ok(ast.children().and_then(|children| {
+            children.iter().find_map(|node| match node {
+                Node::Heading(heading) if heading.depth == 1 => heading
+                    .position
+                    .as_ref()
+                    .map(|pos| (node.to_string(), pos.start.offset..pos.end.offset)),
+                _ => None,
+            })
+        }))
+    }
+
+    // set_title rewrites the first H1 of the zettel to the title, adding one
+    // to the top of the document if it doesn't exist. The zettel is synced to disk.
+    pub fn set_title(mut self, title: &str) -> Result<Self> {
+        let heading = format!("# {}", title);
+        let h1 = self.h1()?;
+        let content = self.content.get_or_insert_with(String::new);
+
+        match h1 {
+            Some((_, range)) => content.replace_range(range, &heading),
+            None => content.insert_str(0, &format!("{}\n\n", heading)),
+        }
+
+        self.sync()
+    }
+
+    // rename moves the zettel to a new id within the same directory
+    pub fn rename<Z: AsRef<ZettelID>>(self, id: Z) -> Result<Self> {
+        let mut path = self.path.clone();
+        path.set_file_name(id.as_ref().filename());
+
+        if path.exists() {
+            return Err(Error::PKMError(format!("{:?} already exists", path)));
+        }
+
+        fs::rename(&self.path, &path)?;
+        Zettel::new(path)
+    }
+
     // content returns the underlying content of the zettel
     pub fn content<'a>(&'a self) -> Option<ZettelContent<'a>> {
         self.content
@@ -424,6 +661,12 @@ impl Zettel {
             .map(|content| ZettelContent { child: content })
     }
 
+    // set_content replaces the in-memory content of the zettel, call sync to
+    // write it to disk
+    pub fn set_content<S: Into<String>>(&mut self, content: S) {
+        self.content = Some(content.into());
+    }
+
     pub fn mut_content<'a>(&'a mut self) -> Result<MutZettelContent<'a>> {
         let content = fs::read_to_string(&self.path)?;
         let child = self.content.insert(content);
@@ -482,6 +725,43 @@ pub struct ZettelContent<'a> {
     child: &'a String,
 }
 
+// Heading is a heading of a document with the line it starts on, counting
+// from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heading {
+    pub depth: u8,
+    pub text: String,
+    pub line: usize,
+}
+
+impl<'a> ZettelContent<'a> {
+    // headings returns every heading of the document in order, including the
+    // ones nested within lists or quotes
+    pub fn headings(&self) -> Result<Vec<Heading>> {
+        let ast = markdown::to_mdast(self.child, &ParseOptions::gfm())?;
+        let mut headings = Vec::new();
+        let mut check: Vec<&Node> = vec![&ast];
+        while let Some(node) = check.pop() {
+            if let Node::Heading(h) = node {
+                if let Some(pos) = h.position.as_ref() {
+                    headings.push(Heading {
+                        depth: h.depth,
+                        text: node.to_string(),
+                        line: pos.start.line,
+                    });
+                }
+                continue;
+            }
+
+            if let Some(children) = node.children() {
+                check.extend(children.iter().rev());
+            }
+        }
+
+        Ok(headings)
+    }
+}
+
 impl<'a> Deref for ZettelContent<'a> {
     type Target = str;
 
@@ -503,6 +783,120 @@ impl<'a> MutZettelContent<'a> {
         self.child.push_str(child);
         Ok(())
     }
+
+    // append_to_section appends the child to the end of the section under the
+    // heading, creating the section at the end of the document if it is missing
+    pub fn append_to_section(&mut self, heading: &str, child: &str) -> Result<()> {
+        let ast = markdown::to_mdast(self.child, &ParseOptions::gfm())?;
+        let headings: Vec<(u8, String, usize)> = ast
+            .children()
+            .map(|children| {
+                children
+                    .iter()
+                    .filter_map(|node| match node {
+                        Node::Heading(h) => h
+                            .position
+                            .as_ref()
+                            .map(|pos| (h.depth, node.to_string(), pos.start.offset)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let idx = match headings
+            .iter()
+            .position(|(_, text, _)| text.trim() == heading)
+        {
+            Some(idx) => idx,
+            None => {
+                if !self.child.is_empty() {
+                    self.append("\n")?;
+                }
+                self.append(&format!("## {}\n", heading))?;
+                return self.append(child);
+            }
+        };
+
+        let depth = headings[idx].0;
+        let section_end = headings[idx + 1..]
+            .iter()
+            .find(|(d, _, _)| *d <= depth)
+            .map(|(_, _, start)| *start)
+            .unwrap_or(self.child.len());
+
+        // insert after the last content of the section, leaving the blank lines
+        // before the next heading in place
+        let insert_at = self.child[..section_end].trim_end().len();
+        self.child.insert_str(insert_at, &format!("\n{}", child));
+        if !self.child.ends_with('\n') {
+            self.child.push('\n');
+        }
+        Ok(())
+    }
+
+    // insert_section inserts a new section with the heading right below the
+    // title of the document, or at the start when it has no title
+    pub fn insert_section(&mut self, heading: &str, body: &str) -> Result<()> {
+        let ast = markdown::to_mdast(self.child, &ParseOptions::gfm())?;
+        let title_end = ast.children().and_then(|children| {
+            children.iter().find_map(|node| match node {
+                Node::Heading(h) if h.depth == 1 => h.position.as_ref().map(|pos| pos.end.offset),
+                _ => None,
+            })
+        });
+
+        let section = format!("## {}\n{}\n", heading, body.trim_end());
+        match title_end {
+            Some(end) => {
+                let rest = self.child[end..].trim_start_matches('\n').to_string();
+                self.child.truncate(end);
+                self.child.push_str("\n\n");
+                self.child.push_str(&section);
+                if !rest.is_empty() {
+                    self.child.push('\n');
+                    self.child.push_str(&rest);
+                }
+            }
+            None => {
+                let rest = std::mem::take(self.child);
+                self.child.push_str(&section);
+                if !rest.trim().is_empty() {
+                    self.child.push('\n');
+                    self.child.push_str(&rest);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // set_frontmatter sets the key of the frontmatter, creating the
+    // frontmatter when the document has none
+    pub fn set_frontmatter(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut frontmatter = Frontmatter::parse(self.child);
+        frontmatter.set(key, value);
+        let (_, body) = split_frontmatter(self.child);
+        *self.child = format!("{}{}", frontmatter.render(), body);
+        Ok(())
+    }
+
+    // upsert_marked_block replaces the body between the markers of the named
+    // block. When the block doesn't exist yet it is appended to the section
+    // under the heading.
+    pub fn upsert_marked_block(&mut self, heading: &str, name: &str, body: &str) -> Result<()> {
+        let start = format!("<!-- {} -->", name);
+        let end = format!("<!-- /{} -->", name);
+        let block = format!("{}\n{}\n{}", start, body, end);
+
+        if let Some(s) = self.child.find(&start) {
+            if let Some(e) = self.child[s..].find(&end) {
+                self.child.replace_range(s..s + e + end.len(), &block);
+                return Ok(());
+            }
+        }
+
+        self.append_to_section(heading, &format!("\n{}", block))
+    }
 }
 
 impl<'a> ToString for MutZettelContent<'a> {
@@ -514,11 +908,22 @@ impl<'a> ToString for MutZettelContent<'a> {
 pub struct ZettelReference<'a> {
     id: &'a ZettelID,
     prefix: &'a str,
+    heading: Option<&'a str>,
 }
 
 impl<'a> ZettelReference<'a> {
     pub fn new(id: &'a ZettelID, prefix: &'a str) -> ZettelReference<'a> {
-        ZettelReference { id, prefix }
+        ZettelReference {
+            id,
+            prefix,
+            heading: None,
+        }
+    }
+
+    // heading anchors the reference to a heading within the zettel
+    pub fn heading(mut self, heading: Option<&'a str>) -> Self {
+        self.heading = heading;
+        self
     }
 }
 
@@ -533,6 +938,31 @@ impl<'a> ZettelReference<'a> {
 
 impl From<ZettelReference<'_>> for String {
     fn from(value: ZettelReference<'_>) -> Self {
-        format!("- {} [[{}]]", value.prefix, value.id)
+        match value.heading {
+            Some(heading) => format!("- {} [[{}#{}]]", value.prefix, value.id, heading),
+            None => format!("- {} [[{}]]", value.prefix, value.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_cannot_leave_the_repo() {
+        let id = ZettelIDBuilder::new()
+            .title(Some("../../etc/passwd"), None)
+            .build()
+            .unwrap();
+        assert_eq!(id.as_str(), "-..-etc-passwd");
+        assert!(!id.contains('/'));
+    }
+
+    #[test]
+    fn title_drops_leading_dots_and_newlines() {
+        assert_eq!(id_title(".hidden\nnote"), "hiddennote");
+        assert_eq!(id_title("a\\b/c"), "a-b-c");
+        assert_eq!(id_title(".."), "");
     }
 }
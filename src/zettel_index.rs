@@ -1,15 +1,66 @@
 use std::collections::HashMap;
 use std::fs::{self, read_to_string};
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::{Error, Result, first_node};
+use crate::{
+    ALIASES_KEY, ARCHIVED_KEY, Error, FLEETING_TAG, FieldKind, Frontmatter, INDEX_SETTINGS_FILE,
+    IndexConfig, MEETING_TAG, Result, SearchQuery, TAGS_KEY, ZettelID, attachment_text, date_query,
+    first_node, frontmatter_field, frontmatter_keys, nested_tag, register_tokenizers,
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Utc};
 use markdown::ParseOptions;
 use markdown::mdast::Node;
-use tantivy::collector::TopDocs;
+use regex::Regex;
+use tantivy::Order;
+use tantivy::collector::{Count, DocSetCollector, FacetCollector, FacetCounts, TopDocs};
 use tantivy::directory::MmapDirectory;
-use tantivy::query::QueryParser;
-use tantivy::schema::{IndexRecordOption, SchemaBuilder, TextFieldIndexing, TextOptions, Value};
-use tantivy::{Index, IndexReader, IndexWriter, TantivyDocument, doc};
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, MoreLikeThisQuery, Occur, Query, TermQuery, TermSetQuery,
+};
+use tantivy::schema::{
+    DateOptions, Facet, FacetOptions, Field, IndexRecordOption, OwnedValue, Schema, SchemaBuilder,
+    TextFieldIndexing, TextOptions, Value,
+};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{
+    DocAddress, DocId, Index, IndexReader, IndexWriter, Score, Searcher, SegmentReader,
+    TantivyDocument, Term, doc,
+};
+
+// FACET_FIELDS are the fields search results can be drilled down by
+pub const FACET_FIELDS: [&str; 4] = ["tag", "kind", "year", "dir"];
+
+// INDEX_DIR is the directory, relative to the root directory, the search
+// index is kept in
+pub const INDEX_DIR: &str = ".index";
+
+// STAGING_INDEX_DIR holds the index a full rebuild is filling
+const STAGING_INDEX_DIR: &str = ".index.new";
+
+// GENERATION_FILE is kept within the index and changes with every full
+// rebuild, so an index kept open can tell it was swapped out
+const GENERATION_FILE: &str = "pkm-generation";
+
+// META_FILE is where tantivy keeps the segments that make up the index
+const META_FILE: &str = "meta.json";
+
+// RECENCY_WEIGHT is how much a note edited just now is boosted over an
+// ancient one, the boost halves every RECENCY_HALF_LIFE_DAYS
+const RECENCY_WEIGHT: f32 = 0.5;
+const RECENCY_HALF_LIFE_DAYS: f32 = 90.0;
+
+// DATE_KEYS are the frontmatter keys a note is dated by when its id has no
+// date
+const DATE_KEYS: [&str; 2] = ["date", "created"];
+
+static DATE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("^[0-9]{4}-(0[0-9]|1[0-2])-([0-2][0-9]|3[01])$").expect("must compile")
+});
 
 pub fn path_to_id<P>(path: P) -> String
 where
@@ -25,7 +76,10 @@ where
 
 pub struct ZettelIndex<P: AsRef<Path>> {
     parent: P,
+    dir: PathBuf,
     index: Index,
+    config: IndexConfig,
+    generation: Option<String>,
 }
 
 impl<P: AsRef<Path>> ZettelIndex<P> {
@@ -33,57 +87,146 @@ impl<P: AsRef<Path>> ZettelIndex<P> {
     where
         P: AsRef<Path>,
     {
-        let mut schema = SchemaBuilder::new();
-
-        schema.add_text_field(
-            "title",
-            TextOptions::default().set_stored().set_indexing_options(
-                TextFieldIndexing::default()
-                    .set_index_option(IndexRecordOption::WithFreqsAndPositions)
-                    .set_tokenizer("en_stem"),
-            ),
-        );
+        let index_dir = dir.as_ref().join(INDEX_DIR);
 
-        schema.add_text_field(
-            "content",
-            TextOptions::default().set_indexing_options(
-                TextFieldIndexing::default()
-                    .set_index_option(IndexRecordOption::WithFreqsAndPositions)
-                    .set_tokenizer("en_stem"),
-            ),
-        );
+        // create the directory if it doesn't exist
+        if !fs::exists(index_dir.as_path())? {
+            fs::create_dir(index_dir.as_path())?;
+        }
 
-        schema.add_text_field(
-            "uri",
-            TextOptions::default().set_stored().set_indexing_options(
-                TextFieldIndexing::default()
-                    .set_index_option(IndexRecordOption::default())
-                    .set_tokenizer("raw"),
-            ),
-        );
+        let config = IndexConfig::open(dir.as_ref())?;
+        let hash = config.hash()?;
+        let settings = index_dir.join(INDEX_SETTINGS_FILE);
+        check_settings(settings.as_path(), &hash)?;
 
-        schema.add_text_field(
-            "id",
-            TextOptions::default().set_stored().set_indexing_options(
-                TextFieldIndexing::default()
-                    .set_index_option(IndexRecordOption::default())
-                    .set_tokenizer("raw"),
-            ),
-        );
+        let directory = MmapDirectory::open(index_dir.as_path())?;
+        let index = match Index::open_or_create(directory, schema(&config)) {
+            Err(tantivy::TantivyError::SchemaError(_)) => {
+                return Err(outdated("the index was built with another schema"));
+            }
+            index => index?,
+        };
+        register_tokenizers(&index, &config)?;
+        if !settings.is_file() {
+            fs::write(settings.as_path(), &hash)?;
+        }
 
-        let mut index_dir = PathBuf::new();
-        index_dir.push(dir.as_ref());
-        index_dir.push(".index");
+        Ok(Self {
+            index,
+            parent: dir,
+            generation: fs::read_to_string(index_dir.join(GENERATION_FILE)).ok(),
+            dir: index_dir,
+            config,
+        })
+    }
 
-        // create the directory if it doesn't exist
+    // open opens the existing index without creating or changing anything, for
+    // the readers that shouldn't touch the repo like the shell completions
+    pub fn open(dir: P) -> Result<Self> {
+        let index_dir = dir.as_ref().join(INDEX_DIR);
+        let config = IndexConfig::open(dir.as_ref())?;
+        check_settings(
+            index_dir.join(INDEX_SETTINGS_FILE).as_path(),
+            &config.hash()?,
+        )?;
+
+        let index = Index::open(MmapDirectory::open(index_dir.as_path())?)?;
+        if index.schema() != schema(&config) {
+            return Err(outdated("the index was built with another schema"));
+        }
+        register_tokenizers(&index, &config)?;
+
+        Ok(Self {
+            index,
+            parent: dir,
+            generation: fs::read_to_string(index_dir.join(GENERATION_FILE)).ok(),
+            dir: index_dir,
+            config,
+        })
+    }
+
+    // staging creates an empty index next to the current one. A full rebuild
+    // fills it and swaps it in, so searches keep using the current index while
+    // it runs and a crashed rebuild leaves the current index untouched.
+    pub fn staging(dir: P) -> Result<Self> {
+        let staging_dir = dir.as_ref().join(STAGING_INDEX_DIR);
+
+        // left behind by a rebuild that never finished
+        if fs::exists(staging_dir.as_path())? {
+            fs::remove_dir_all(staging_dir.as_path())?;
+        }
+        fs::create_dir(staging_dir.as_path())?;
+
+        let config = IndexConfig::open(dir.as_ref())?;
+        let index = Index::create_in_dir(staging_dir.as_path(), schema(&config))?;
+        register_tokenizers(&index, &config)?;
+        fs::write(staging_dir.join(INDEX_SETTINGS_FILE), config.hash()?)?;
+        let generation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
+        fs::write(staging_dir.join(GENERATION_FILE), &generation)?;
+        Ok(Self {
+            index,
+            parent: dir,
+            dir: staging_dir,
+            config,
+            generation: Some(generation),
+        })
+    }
+
+    // swap replaces the current index with the staged one, the documents of
+    // the staged index have to be committed first. The segments of the staged
+    // index have names of their own and are moved next to the current ones,
+    // then meta.json is replaced. That one rename swaps the indexes, readers
+    // see either the old or the new index whole and a crash leaves one intact.
+    pub fn swap(self) -> Result<()> {
+        let Self {
+            parent, dir, index, ..
+        } = self;
+        let root = parent.as_ref();
+        if dir != root.join(STAGING_INDEX_DIR) {
+            return Err(Error::PKMError(String::from(
+                "only a staged index can be swapped in",
+            )));
+        }
+        drop(index);
+
+        let index_dir = root.join(INDEX_DIR);
         if !fs::exists(index_dir.as_path())? {
-            fs::create_dir(index_dir.as_path())?;
+            fs::rename(dir.as_path(), index_dir.as_path())?;
+            return Ok(());
+        }
+
+        let old = index_files(index_dir.as_path())?;
+        let mut moved = Vec::new();
+        for name in index_files(dir.as_path())? {
+            if name != META_FILE {
+                fs::rename(dir.join(&name), index_dir.join(&name))?;
+                moved.push(name);
+            }
         }
+        fs::rename(dir.join(META_FILE), index_dir.join(META_FILE))?;
+        moved.push(String::from(META_FILE));
 
-        let index_dir = MmapDirectory::open(index_dir.as_path())?;
-        let index = Index::open_or_create(index_dir, schema.build())?;
+        // readers of the old index keep the files they have open, files that
+        // are still mapped can't be removed on every platform
+        for name in old.iter().filter(|name| !moved.contains(name)) {
+            if let Err(err) = fs::remove_file(index_dir.join(name)) {
+                log::debug!("could not remove the old index file {}: {}", name, err);
+            }
+        }
+        fs::remove_dir_all(dir.as_path())?;
+
+        Ok(())
+    }
 
-        Ok(Self { index, parent: dir })
+    // swapped is true once a full rebuild swapped another index in, an index
+    // that is kept open has to be opened again to see it
+    pub fn swapped(&self) -> bool {
+        let current = self.parent.as_ref().join(INDEX_DIR).join(GENERATION_FILE);
+        fs::read_to_string(current).ok() != self.generation
     }
 
     pub fn doc_indexer<'a>(&'a self) -> Result<DocIndexer<'a, P>> {
@@ -97,58 +240,827 @@ impl<P: AsRef<Path>> ZettelIndex<P> {
         Ok(DocSearcher {
             index: self,
             reader: self.index.reader()?,
+            limit: 10,
+            include_archived: false,
+            since: None,
+            until: None,
+            recency: true,
+            sort: SearchSort::default(),
         })
     }
 }
 
+// index_files are the names of the files of the index, tantivy's lock files
+// belong to the directory and are left out
+fn index_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && !name.starts_with(".tantivy-") {
+            files.push(name);
+        }
+    }
+    Ok(files)
+}
+
+// needs_rebuild is true for the errors of an index that no longer fits the
+// config, only a full rebuild fixes those
+pub fn needs_rebuild(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::IndexError(tantivy::TantivyError::SchemaError(_))
+    )
+}
+
+// check_settings fails when the index was built with other tokenizer settings
+// than the config. An index without settings predates them and is kept.
+fn check_settings(settings: &Path, hash: &str) -> Result<()> {
+    if settings.is_file() && fs::read_to_string(settings)?.trim() != hash {
+        return Err(outdated("the index settings changed"));
+    }
+    Ok(())
+}
+
+// outdated is the error of an index that doesn't fit the config anymore. The
+// index is never thrown away here, `pkm index` rebuilds it next to the old one.
+fn outdated(reason: &str) -> Error {
+    Error::IndexError(tantivy::TantivyError::SchemaError(format!(
+        "{}, run `pkm index` to rebuild the index",
+        reason
+    )))
+}
+
+// FacetCount is the number of hits within a facet, like tag:project (7)
+#[derive(Debug, Clone)]
+pub struct FacetCount {
+    pub field: String,
+    pub value: String,
+    pub count: u64,
+}
+
+// SearchResults are the top hits of a search along with the facet counts of
+// every hit
+pub struct SearchResults {
+    pub docs: Vec<HashMap<String, String>>,
+    pub facets: Vec<FacetCount>,
+}
+
+// parse_facet_filter parses a field:value filter, like tag:project/alpha, into
+// the facet field and the facet
+fn parse_facet_filter(schema: &Schema, filter: &str) -> Result<(Field, Facet)> {
+    let (field, value) = filter
+        .split_once(':')
+        .filter(|(field, _)| FACET_FIELDS.contains(field))
+        .ok_or_else(|| {
+            Error::PKMError(format!(
+                "invalid facet {:?}, expected one of {} followed by :value",
+                filter,
+                FACET_FIELDS.join(", ")
+            ))
+        })?;
+
+    let field = schema
+        .get_field(field)
+        .expect("facet fields are part of the schema");
+    Ok((field, facet_path(value)))
+}
+
+// facet_path turns a value like project/alpha into its facet
+pub fn facet_path(value: &str) -> Facet {
+    Facet::from_path(value.split('/').filter(|part| !part.is_empty()))
+}
+
+// schema is the schema of the index, the text fields use the tokenizers of
+// the config. A changed tokenizer changes the schema, so the index is rebuilt.
+fn schema(config: &IndexConfig) -> Schema {
+    let mut schema = SchemaBuilder::new();
+
+    schema.add_text_field(
+        "title",
+        TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer(&config.tokenizer("title")),
+        ),
+    );
+
+    // the text of the h2 and h3 headings, the sections of the note
+    schema.add_text_field(
+        "headings",
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer(&config.tokenizer("headings")),
+        ),
+    );
+
+    schema.add_text_field(
+        "content",
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer(&config.tokenizer("content")),
+        ),
+    );
+
+    schema.add_text_field(
+        "aliases",
+        TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer(&config.tokenizer("aliases")),
+        ),
+    );
+
+    // the text extracted from the attachments the note links to
+    schema.add_text_field(
+        "attachments",
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                .set_tokenizer(&config.tokenizer("attachments")),
+        ),
+    );
+
+    schema.add_text_field(
+        "uri",
+        TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::default())
+                .set_tokenizer("raw"),
+        ),
+    );
+
+    schema.add_text_field(
+        "id",
+        TextOptions::default().set_stored().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_index_option(IndexRecordOption::default())
+                .set_tokenizer("raw"),
+        ),
+    );
+
+    for (key, kind) in config.fields.iter() {
+        let name = frontmatter_field(key);
+        match kind {
+            FieldKind::Keyword => schema.add_text_field(
+                &name,
+                TextOptions::default().set_stored().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_index_option(IndexRecordOption::Basic)
+                        .set_tokenizer("keyword"),
+                ),
+            ),
+            FieldKind::Text => schema.add_text_field(
+                &name,
+                TextOptions::default().set_stored().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+                        .set_tokenizer(&config.tokenizer("content")),
+                ),
+            ),
+            FieldKind::Date => schema.add_date_field(&name, DateOptions::default().set_indexed()),
+        };
+    }
+
+    schema.add_date_field("date", DateOptions::default().set_indexed().set_fast());
+    schema.add_date_field("modified", DateOptions::default().set_fast());
+
+    for field in FACET_FIELDS {
+        schema.add_facet_field(field, FacetOptions::default());
+    }
+
+    schema.build()
+}
+
+// SearchSort is the order of the hits of a search. Modified and created are
+// newest first, title is alphabetical.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchSort {
+    #[default]
+    Relevance,
+    Modified,
+    Created,
+    Title,
+}
+
+// parse_search_sort parses relevance, modified, created or title
+pub fn parse_search_sort(sort: &str) -> Result<SearchSort> {
+    match sort.trim().to_lowercase().as_str() {
+        "relevance" => Ok(SearchSort::Relevance),
+        "modified" => Ok(SearchSort::Modified),
+        "created" => Ok(SearchSort::Created),
+        "title" => Ok(SearchSort::Title),
+        sort => Err(Error::PKMError(format!(
+            "unknown sort {:?}, expected relevance, modified, created or title",
+            sort
+        ))),
+    }
+}
+
 pub struct DocSearcher<'a, P: AsRef<Path>> {
     index: &'a ZettelIndex<P>,
     reader: IndexReader,
+    limit: usize,
+    include_archived: bool,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    recency: bool,
+    sort: SearchSort,
 }
 
 impl<'a, P: AsRef<Path>> DocSearcher<'a, P> {
+    // limit sets how many hits a search returns, 10 by default
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    // include_archived keeps the archived notes in the results, they are left
+    // out by default unless filtered for with kind:archived
+    pub fn include_archived(mut self, include: bool) -> Self {
+        self.include_archived = include;
+        self
+    }
+
+    // since leaves out the notes dated before the day
+    pub fn since(mut self, since: Option<NaiveDate>) -> Self {
+        self.since = since;
+        self
+    }
+
+    // until leaves out the notes dated after the day
+    pub fn until(mut self, until: Option<NaiveDate>) -> Self {
+        self.until = until;
+        self
+    }
+
+    // recency ranks recently edited notes above older ones that match as well,
+    // on by default
+    pub fn recency(mut self, recency: bool) -> Self {
+        self.recency = recency;
+        self
+    }
+
+    // sort sets the order of the hits, by relevance by default. The created
+    // date is the date of the id, the frontmatter or the file.
+    pub fn sort(mut self, sort: SearchSort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    // reload picks up the latest commit right away instead of waiting for the
+    // reader to notice it
+    pub fn reload(&self) -> Result<()> {
+        self.reader.reload()?;
+        Ok(())
+    }
+
     pub fn find(&self, query: &str) -> Result<Vec<HashMap<String, String>>> {
-        let title_field = self
+        Ok(self.search(query, &[])?.docs)
+    }
+
+    // search finds the documents matching the query that are within every
+    // facet filter, counting the facets of the hits. Facets are counted one
+    // level below the filter of their field so results can be drilled into.
+    pub fn search(&self, query: &str, filters: &[String]) -> Result<SearchResults> {
+        let schema = self.index.index.schema();
+        let (query, roots) = self.filtered_query(query, filters)?;
+
+        let collector = |name: &str| {
+            let field = schema.get_field(name).expect("facet not part of schema");
+            let mut collector = FacetCollector::for_field(name);
+            collector.add_facet(roots.get(&field).cloned().unwrap_or_else(Facet::root));
+            collector
+        };
+
+        let searcher = self.reader.searcher();
+
+        let recency = self.recency;
+        let now = Utc::now().timestamp();
+        let top_docs =
+            TopDocs::with_limit(self.limit).tweak_score(move |segment: &SegmentReader| {
+                let modified = segment
+                    .fast_fields()
+                    .date("modified")
+                    .ok()
+                    .filter(|_| recency);
+                move |doc: DocId, score: Score| match modified.as_ref().and_then(|m| m.first(doc)) {
+                    Some(modified) => score * recency_boost(now, modified.into_timestamp_secs()),
+                    None => score,
+                }
+            });
+
+        let newest = |field: &'static str| {
+            TopDocs::with_limit(self.limit)
+                .order_by_fast_field::<tantivy::DateTime>(field, Order::Desc)
+        };
+        let hits: Vec<(f32, DocAddress)> = match self.sort {
+            SearchSort::Relevance => searcher.search(&query, &top_docs)?,
+            // every match is loaded so the titles are ordered across all of
+            // them, not only the most relevant ones
+            SearchSort::Title => without_score(
+                searcher
+                    .search(&query, &DocSetCollector)?
+                    .into_iter()
+                    .map(|address| ((), address))
+                    .collect(),
+            ),
+            SearchSort::Modified => without_score(searcher.search(&query, &newest("modified"))?),
+            SearchSort::Created => without_score(searcher.search(&query, &newest("date"))?),
+        };
+
+        let (tags, kinds, years, dirs) = searcher.search(
+            &query,
+            &(
+                collector("tag"),
+                collector("kind"),
+                collector("year"),
+                collector("dir"),
+            ),
+        )?;
+
+        let mut facets = Vec::new();
+        for (name, counts) in FACET_FIELDS.iter().zip([tags, kinds, years, dirs]) {
+            let field = schema.get_field(name).expect("facet not part of schema");
+            facets.extend(facet_counts(
+                name,
+                &counts,
+                roots.get(&field).cloned().unwrap_or_else(Facet::root),
+            ));
+        }
+
+        let mut docs = stored_docs(&searcher, hits);
+        if self.sort == SearchSort::Title {
+            docs.sort_by_cached_key(|doc| doc.get("title").map(|title| title.to_lowercase()));
+            docs.truncate(self.limit);
+        }
+        Ok(SearchResults { docs, facets })
+    }
+
+    // tags counts the documents of every tag, nested tags are listed after
+    // their parent. Siblings are ordered most used first.
+    pub fn tags(&self) -> Result<Vec<FacetCount>> {
+        let searcher = self.reader.searcher();
+
+        // facets are only counted one level below the facet they are
+        // collected for
+        let children = |parent: &Facet| -> Result<Vec<(Facet, u64)>> {
+            let mut collector = FacetCollector::for_field("tag");
+            collector.add_facet(parent.clone());
+            let counts = searcher.search(&AllQuery, &collector)?;
+
+            let mut children: Vec<(Facet, u64)> = counts
+                .get(parent.clone())
+                .map(|(facet, count)| (facet.clone(), count))
+                .collect();
+            children.sort_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+            Ok(children)
+        };
+
+        let mut tags = Vec::new();
+        let mut stack = children(&Facet::root())?;
+        while let Some((facet, count)) = stack.pop() {
+            stack.extend(children(&facet)?);
+            tags.push(FacetCount {
+                field: String::from("tag"),
+                value: facet.to_path_string().trim_start_matches('/').to_string(),
+                count,
+            });
+        }
+        Ok(tags)
+    }
+
+    // areas counts the documents in each top level directory of the repo,
+    // most used first
+    pub fn areas(&self) -> Result<Vec<FacetCount>> {
+        let searcher = self.reader.searcher();
+        let mut collector = FacetCollector::for_field("dir");
+        collector.add_facet(Facet::root());
+        let counts = searcher.search(&AllQuery, &collector)?;
+
+        let mut areas: Vec<FacetCount> = counts
+            .get(Facet::root())
+            .map(|(facet, count)| FacetCount {
+                field: String::from("dir"),
+                value: facet.to_path_string().trim_start_matches('/').to_string(),
+                count,
+            })
+            .collect();
+        areas.sort_by(|a, b| b.count.cmp(&a.count).then(a.value.cmp(&b.value)));
+        Ok(areas)
+    }
+
+    // indexed returns when every indexed note was modified by its uri, in
+    // seconds, so an update only has to index the notes changed since
+    pub fn indexed(&self) -> Result<HashMap<String, i64>> {
+        let searcher = self.reader.searcher();
+        let uri = self
             .index
             .index
             .schema()
-            .get_field("title")
-            .expect("title not part of schema");
-
-        let parser = QueryParser::for_index(
-            &self.index.index,
-            vec![
-                title_field,
-                self.index
-                    .index
-                    .schema()
-                    .get_field("content")
-                    .expect("content not part of schema"),
-            ],
-        );
+            .get_field("uri")
+            .expect("uri not in schema");
 
-        let query = parser.parse_query(query)?;
+        let mut indexed = HashMap::new();
+        for (ord, segment) in searcher.segment_readers().iter().enumerate() {
+            let modified = segment.fast_fields().date("modified")?;
+            for doc in segment.doc_ids_alive() {
+                let stored: TantivyDocument = searcher.doc(DocAddress::new(ord as u32, doc))?;
+                if let Some(value) = stored.get_first(uri).and_then(|value| value.as_str()) {
+                    let secs = modified
+                        .first(doc)
+                        .map(|modified| modified.into_timestamp_secs())
+                        .unwrap_or_default();
+                    indexed.insert(value.to_string(), secs);
+                }
+            }
+        }
+        Ok(indexed)
+    }
 
+    // uris returns the uri of every indexed document
+    pub fn uris(&self) -> Result<Vec<String>> {
         let searcher = self.reader.searcher();
-        let searcher_ref = &searcher;
+        let hits = searcher
+            .search(&AllQuery, &DocSetCollector)?
+            .into_iter()
+            .map(|address| (0.0, address))
+            .collect();
 
-        let docs = searcher.search(&query, &TopDocs::with_limit(10))?;
-        // this is so fucking ugly
-        Ok(docs
+        Ok(stored_docs(&searcher, hits)
             .into_iter()
-            .map(|v| searcher_ref.doc::<TantivyDocument>(v.1))
-            .filter_map(|v| v.ok())
-            .map(|v| {
-                let mut map: HashMap<String, String> = HashMap::new();
-                for (key, value) in v.field_values() {
-                    let key = searcher_ref.schema().get_field_name(key);
-                    let value = value.as_str().expect("they are all strings");
+            .filter_map(|mut doc| doc.remove("uri"))
+            .collect())
+    }
+
+    // random picks one of the documents within every facet filter at random,
+    // without reading the other documents
+    pub fn random(&self, filters: &[String]) -> Result<Option<HashMap<String, String>>> {
+        let (query, _) = self.filtered_query("", filters)?;
+        let searcher = self.reader.searcher();
+
+        let count = searcher.search(&query, &Count)?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        // the keys of RandomState are random for every process
+        let offset = RandomState::new().build_hasher().finish() as usize % count;
+        let docs = searcher.search(&query, &TopDocs::with_limit(1).and_offset(offset))?;
+        Ok(stored_docs(&searcher, docs).into_iter().next())
+    }
+
+    // filtered_query matches the query within every facet filter, returning
+    // the facet of every filtered field as well
+    fn filtered_query(
+        &self,
+        query: &str,
+        filters: &[String],
+    ) -> Result<(BooleanQuery, HashMap<Field, Facet>)> {
+        let schema = self.index.index.schema();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::Must,
+            SearchQuery::parse(query, &frontmatter_keys(&schema))?.query(&self.index.index)?,
+        )];
+
+        if self.since.is_some() || self.until.is_some() {
+            clauses.push((
+                Occur::Must,
+                date_query(
+                    &self.index.index,
+                    self.since,
+                    self.until.and_then(|until| until.succ_opt()),
+                ),
+            ));
+        }
+
+        let mut roots: HashMap<Field, Facet> = HashMap::new();
+        for filter in filters {
+            let (field, facet) = parse_facet_filter(&schema, filter)?;
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_facet(field, &facet),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+            roots.insert(field, facet);
+        }
+
+        let kind = schema.get_field("kind").expect("kind not part of schema");
+        let archived = facet_path(ARCHIVED_KEY);
+        if !self.include_archived && roots.get(&kind) != Some(&archived) {
+            clauses.push((
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_facet(kind, &archived),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        Ok((BooleanQuery::new(clauses), roots))
+    }
+
+    // within returns the documents of the uris that are within every facet
+    // filter and the bounds of the searcher, in the order of the uris
+    pub fn within(
+        &self,
+        uris: &[String],
+        filters: &[String],
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.index.index.schema();
+        let uri = schema.get_field("uri").expect("uri not in schema");
+        let (filtered, _) = self.filtered_query("", filters)?;
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, Box::new(filtered) as Box<dyn Query>),
+            (
+                Occur::Must,
+                Box::new(TermSetQuery::new(
+                    uris.iter().map(|value| Term::from_field_text(uri, value)),
+                )),
+            ),
+        ]);
+
+        let searcher = self.reader.searcher();
+        let hits = searcher.search(&query, &DocSetCollector)?;
+        let mut docs = stored_docs(
+            &searcher,
+            without_score(hits.into_iter().map(|address| ((), address)).collect()),
+        );
+        docs.sort_by_key(|doc| {
+            doc.get("uri")
+                .and_then(|value| uris.iter().position(|uri| uri == value))
+        });
+        Ok(docs)
+    }
+
+    // similar finds the documents that are about the same thing as the text.
+    // The most frequent terms of the text are searched for, each weighted by
+    // how often it occurs.
+    pub fn similar(&self, text: &str, limit: usize) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.index.index.schema();
+        let fields = [
+            schema.get_field("title").expect("title not part of schema"),
+            schema
+                .get_field("content")
+                .expect("content not part of schema"),
+        ];
+
+        let mut analyzer = self.index.index.tokenizer_for_field(fields[1])?;
+        let mut stream = analyzer.token_stream(text);
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        while stream.advance() {
+            let token = &stream.token().text;
+            // short tokens are mostly stop words
+            if token.chars().count() > 2 {
+                *counts.entry(token.clone()).or_default() += 1;
+            }
+        }
+
+        let mut terms: Vec<(String, usize)> = counts.into_iter().collect();
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        terms.truncate(25);
+
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        for (term, count) in terms {
+            for field in fields {
+                clauses.push((
+                    Occur::Should,
+                    Box::new(BoostQuery::new(
+                        Box::new(TermQuery::new(
+                            Term::from_field_text(field, &term),
+                            IndexRecordOption::WithFreqs,
+                        )),
+                        count as f32,
+                    )),
+                ));
+            }
+        }
+
+        let searcher = self.reader.searcher();
+        let docs = searcher.search(&BooleanQuery::new(clauses), &TopDocs::with_limit(limit))?;
+        Ok(stored_docs(&searcher, docs))
+    }
+
+    // more_like finds the documents sharing the most significant terms with
+    // the content of the note, leaving out the note itself
+    pub fn more_like(
+        &self,
+        id: &str,
+        content: &str,
+        limit: usize,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let schema = self.index.index.schema();
+        let content_field = schema
+            .get_field("content")
+            .expect("content not part of schema");
+        let id_field = schema.get_field("id").expect("id not part of schema");
+
+        let like = MoreLikeThisQuery::builder()
+            .with_min_doc_frequency(1)
+            .with_min_term_frequency(1)
+            .with_min_word_length(3)
+            .with_document_fields(vec![(
+                content_field,
+                vec![OwnedValue::Str(content.to_string())],
+            )]);
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, Box::new(like)),
+            (
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(id_field, id),
+                    IndexRecordOption::Basic,
+                )),
+            ),
+        ]);
+
+        let searcher = self.reader.searcher();
+        let docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+        Ok(stored_docs(&searcher, docs))
+    }
+}
+
+// recency_boost is what the score of a note last modified at the time is
+// multiplied by, it halves every half life down to nothing
+fn recency_boost(now: i64, modified: i64) -> f32 {
+    let age = (now - modified).max(0) as f32 / 86_400.0;
+    1.0 + RECENCY_WEIGHT * 0.5f32.powf(age / RECENCY_HALF_LIFE_DAYS)
+}
+
+// without_score drops the values the hits were ordered by, keeping the order
+fn without_score<T>(hits: Vec<(T, DocAddress)>) -> Vec<(f32, DocAddress)> {
+    hits.into_iter()
+        .map(|(_, address)| (0.0, address))
+        .collect()
+}
+
+// stored_docs loads the stored fields of the hits
+fn stored_docs(searcher: &Searcher, hits: Vec<(f32, DocAddress)>) -> Vec<HashMap<String, String>> {
+    // this is so fucking ugly
+    hits.into_iter()
+        .map(|v| searcher.doc::<TantivyDocument>(v.1))
+        .filter_map(|v| v.ok())
+        .map(|v| {
+            let mut map: HashMap<String, String> = HashMap::new();
+            for (key, value) in v.field_values() {
+                // facets are only for counting
+                if let Some(value) = value.as_str() {
+                    let key = searcher.schema().get_field_name(key);
                     map.insert(String::from(key), String::from(value));
                 }
-                map
-            })
-            .collect())
+            }
+            map
+        })
+        .collect()
+}
+
+fn facet_counts(field: &str, counts: &FacetCounts, root: Facet) -> Vec<FacetCount> {
+    counts
+        .top_k(root, 5)
+        .into_iter()
+        .map(|(facet, count)| FacetCount {
+            field: field.to_string(),
+            value: facet.to_path_string().trim_start_matches('/').to_string(),
+            count,
+        })
+        .collect()
+}
+
+// index_date is the value a day is indexed as, midnight in utc so days compare
+// the same in every timezone
+pub fn index_date(date: NaiveDate) -> tantivy::DateTime {
+    tantivy::DateTime::from_timestamp_secs(date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+// note_date is the day the note is from. That is the date of its id, the date
+// or created key of its frontmatter or else the day the file was last modified.
+fn note_date(id: &ZettelID, path: &Path, frontmatter: &Frontmatter) -> Result<NaiveDate> {
+    if let Some(date) = id.created() {
+        return Ok(date);
+    }
+
+    let date = DATE_KEYS
+        .iter()
+        .filter_map(|key| frontmatter.get(key))
+        .find_map(frontmatter_date);
+    match date {
+        Some(date) => Ok(date),
+        None => Ok(DateTime::<Local>::from(fs::metadata(path)?.modified()?).date_naive()),
+    }
+}
+
+// frontmatter_date reads the day of a frontmatter value like 2024-01-01 or
+// "2024-01-01T10:00"
+fn frontmatter_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim_matches(['"', '\'']);
+    NaiveDate::parse_from_str(value.get(..10)?, "%Y-%m-%d").ok()
+}
+
+// facets returns the tag, kind, year and dir facets of the zettel. The tags
+// come from the id and the frontmatter, nested tags become nested facets so
+// tag:project matches project/alpha as well. The year is the year of the
+// date of the note. Archived zettels are of the archived kind whatever their
+// id. The dir is the directory the note is in relative to the root, so
+// dir:daily matches the dailies within their date directories as well.
+fn facets(
+    id: &ZettelID,
+    uri: &Path,
+    date: NaiveDate,
+    frontmatter: &Frontmatter,
+) -> Vec<(&'static str, Facet)> {
+    let parts: Vec<&str> = id.split('_').collect();
+    let date_part = parts.iter().find(|part| DATE_REGEX.is_match(part));
+    let archived = frontmatter.get(ARCHIVED_KEY) == Some("true");
+
+    let mut tags: Vec<String> = id
+        .tags()
+        .filter(|tag| !DATE_REGEX.is_match(tag))
+        .map(nested_tag)
+        .collect();
+    for tag in frontmatter.list(TAGS_KEY) {
+        let tag = tag.trim_start_matches('#').to_string();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    let mut facets = Vec::new();
+    for tag in tags {
+        facets.push(("tag", facet_path(&tag)));
+    }
+
+    let kind = if archived {
+        ARCHIVED_KEY
+    } else if id.has_tag(MEETING_TAG) {
+        MEETING_TAG
+    } else if id.has_tag(FLEETING_TAG) {
+        FLEETING_TAG
+    } else if parts.len() == 1 && date_part.is_some() {
+        "daily"
+    } else {
+        "zettel"
+    };
+    facets.push(("kind", facet_path(kind)));
+
+    facets.push(("year", facet_path(&date.year().to_string())));
+
+    let dir: Vec<String> = uri
+        .parent()
+        .into_iter()
+        .flat_map(|dir| dir.iter())
+        .map(|part| part.to_string_lossy().to_string())
+        .collect();
+    if !dir.is_empty() {
+        facets.push(("dir", Facet::from_path(dir)));
     }
+
+    facets
+}
+
+// section_headings returns the text of the h2 and h3 headings of the note
+fn section_headings(ast: &Node) -> Vec<String> {
+    let mut headings = Vec::new();
+    let mut check: Vec<&Node> = vec![ast];
+    while let Some(node) = check.pop() {
+        match node {
+            Node::Heading(heading) if heading.depth == 2 || heading.depth == 3 => {
+                headings.push(node.to_string());
+            }
+            _ => {
+                if let Some(children) = node.children() {
+                    check.extend(children.iter().rev());
+                }
+            }
+        }
+    }
+    headings
+}
+
+// IndexProgress is how far along process_all is
+#[derive(Debug, Clone)]
+pub struct IndexProgress {
+    pub processed: usize,
+    pub errors: usize,
+    pub total: usize,
+    pub elapsed: Duration,
+}
+
+// IndexReport sums up process_all, the skipped notes come with the reason,
+// like a missing title
+#[derive(Debug, Clone)]
+pub struct IndexReport {
+    pub indexed: usize,
+    pub skipped: Vec<(PathBuf, String)>,
+    pub elapsed: Duration,
 }
 
 pub struct DocIndexer<'a, P: AsRef<Path>> {
@@ -163,12 +1075,73 @@ impl<'a, P: AsRef<Path>> DocIndexer<'a, P> {
     }
 
     pub fn process<Q>(&mut self, id: &str, doc: Q) -> Result<()>
+    where
+        Q: AsRef<Path>,
+    {
+        let document = self.document(id, doc)?;
+        self.writer.add_document(document)?;
+        Ok(())
+    }
+
+    // process_all indexes the notes with as many jobs reading and parsing
+    // them, the documents are all added to the one writer. The progress is
+    // reported after every note. A note that can't be indexed is skipped and
+    // listed in the report.
+    pub fn process_all<F>(&mut self, docs: &[PathBuf], jobs: usize, progress: F) -> IndexReport
+    where
+        P: Sync,
+        F: Fn(&IndexProgress) + Sync,
+    {
+        let start = Instant::now();
+        let jobs = jobs.clamp(1, docs.len().max(1));
+        let processed = AtomicUsize::new(0);
+        let skipped = Mutex::new(Vec::new());
+
+        let indexer: &Self = self;
+        thread::scope(|scope| {
+            for job in 0..jobs {
+                let (processed, skipped, progress) = (&processed, &skipped, &progress);
+                scope.spawn(move || {
+                    for doc in docs.iter().skip(job).step_by(jobs) {
+                        let added = indexer
+                            .document(&path_to_id(doc), doc)
+                            .and_then(|document| {
+                                indexer.writer.add_document(document).map_err(Error::from)
+                            });
+
+                        let mut skipped = skipped.lock().expect("poisoned");
+                        if let Err(err) = added {
+                            skipped.push((doc.clone(), err.to_string()));
+                        }
+                        progress(&IndexProgress {
+                            processed: processed.fetch_add(1, Ordering::SeqCst) + 1,
+                            errors: skipped.len(),
+                            total: docs.len(),
+                            elapsed: start.elapsed(),
+                        });
+                    }
+                });
+            }
+        });
+
+        let skipped = skipped.into_inner().expect("poisoned");
+        IndexReport {
+            indexed: docs.len() - skipped.len(),
+            skipped,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    // document reads and parses the note into the document to index
+    fn document<Q>(&self, id: &str, doc: Q) -> Result<TantivyDocument>
     where
         Q: AsRef<Path>,
     {
         let mut full_doc_path = PathBuf::new();
         full_doc_path.push(self.index.parent.as_ref());
         full_doc_path.push(doc.as_ref());
+        let uri = self.uri(doc.as_ref());
+        log::trace!("indexing {:?}", full_doc_path);
         let content = read_to_string(full_doc_path.as_path())?;
 
         let opts = ParseOptions::gfm();
@@ -186,16 +1159,95 @@ impl<'a, P: AsRef<Path>> DocIndexer<'a, P> {
             String::from("Title must be supplied"),
         )))?;
 
-        self.writer.add_document(doc!(
+        let headings = section_headings(&ast).join("\n");
+        let frontmatter = Frontmatter::parse(&content);
+        let attachments = attachment_text(self.index.parent.as_ref(), &full_doc_path, &content);
+        let mut document = doc!(
             self.writer.index().schema().get_field("title").expect("title not in schema") => title,
+            self.writer.index().schema().get_field("headings").expect("headings not in schema")  => headings,
             self.writer.index().schema().get_field("content").expect("content not in schema")  => content,
-            self.writer.index().schema().get_field("uri").expect("uri not in schema")  => *doc.as_ref().to_string_lossy(),
+            self.writer.index().schema().get_field("uri").expect("uri not in schema")  => *uri.to_string_lossy(),
             self.writer.index().schema().get_field("id").expect("id not in schema")  => id,
-        ))?;
+            self.writer.index().schema().get_field("aliases").expect("aliases not in schema")  => frontmatter.list(ALIASES_KEY).join("\n"),
+            self.writer.index().schema().get_field("attachments").expect("attachments not in schema")  => attachments,
+        );
+
+        let schema = self.writer.index().schema();
+        let id = ZettelID::from_path(&full_doc_path);
+        let date = note_date(&id, &full_doc_path, &frontmatter)?;
+        document.add_date(
+            schema.get_field("date").expect("date not in schema"),
+            index_date(date),
+        );
+        let modified = DateTime::<Utc>::from(fs::metadata(&full_doc_path)?.modified()?);
+        document.add_date(
+            schema
+                .get_field("modified")
+                .expect("modified not in schema"),
+            tantivy::DateTime::from_timestamp_secs(modified.timestamp()),
+        );
+        for (key, kind) in self.index.config.fields.iter() {
+            let field = schema
+                .get_field(&frontmatter_field(key))
+                .expect("frontmatter field not in schema");
+            match kind {
+                FieldKind::Date => {
+                    if let Some(date) = frontmatter.get(key).and_then(frontmatter_date) {
+                        document.add_date(field, index_date(date));
+                    }
+                }
+                FieldKind::Keyword | FieldKind::Text => {
+                    for value in frontmatter.list(key) {
+                        document.add_text(field, value);
+                    }
+                }
+            }
+        }
+        for (field, facet) in facets(&id, uri, date, &frontmatter) {
+            document.add_facet(schema.get_field(field).expect("facet not in schema"), facet);
+        }
+
+        Ok(document)
+    }
+
+    // uri is the path of the note relative to the root, the one key that is
+    // unique to a document. Ids are only unique within their directory.
+    fn uri<'b>(&self, doc: &'b Path) -> &'b Path {
+        doc.strip_prefix(self.index.parent.as_ref()).unwrap_or(doc)
+    }
+
+    // remove drops the document indexed from the path, relative to the root
+    // or not. A note that moved has to be removed at the path it had.
+    pub fn remove<Q>(&mut self, doc: Q) -> Result<()>
+    where
+        Q: AsRef<Path>,
+    {
+        let field = self
+            .writer
+            .index()
+            .schema()
+            .get_field("uri")
+            .expect("uri not in schema");
 
+        // indexes built before the uris were relative hold the full path
+        let full_path = self.index.parent.as_ref().join(doc.as_ref());
+        for uri in [self.uri(doc.as_ref()), full_path.as_path()] {
+            self.writer
+                .delete_term(Term::from_field_text(field, &uri.to_string_lossy()));
+        }
         Ok(())
     }
 
+    // update replaces the indexed document of the note with the current
+    // content on disk
+    pub fn update<Q>(&mut self, id: &str, doc: Q) -> Result<()>
+    where
+        Q: AsRef<Path>,
+    {
+        self.remove(doc.as_ref())?;
+        self.process(id, doc)
+    }
+
     pub fn commit(mut self) -> Result<()> {
         self.writer.commit()?;
         Ok(())